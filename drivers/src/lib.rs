@@ -47,6 +47,18 @@ pub struct Info {
     pub port_capacity: u16,
     /// Turn all interfaces bound to the server
     pub interfaces: Vec<Interface>,
+    /// The number of the permissions and the channels of all allocations
+    #[serde(default)]
+    pub relay_paths: usize,
+    /// The number of the requests in flight
+    #[serde(default)]
+    pub in_flight: usize,
+    /// The new work is shed by the overloaded server
+    #[serde(default)]
+    pub overloaded: bool,
+    /// The number of the relayed packets dropped over the router queue
+    #[serde(default)]
+    pub router_dropped: u64,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -55,6 +67,9 @@ pub struct Session {
     pub username: String,
     /// The password used in session authentication
     pub password: String,
+    /// The tenant tag given by the observer when the session was authenticated
+    #[serde(default)]
+    pub tenant: Option<String>,
     /// Channel numbers that have been assigned to the session
     pub channels: Vec<u16>,
     /// Port numbers that have been assigned to the session
@@ -66,6 +81,9 @@ pub struct Session {
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Statistics {
+    /// The tenant tag of the session
+    #[serde(default)]
+    pub tenant: Option<String>,
     /// Number of bytes received in the current session
     pub received_bytes: u64,
     /// The number of bytes sent by the current session
@@ -78,13 +96,9 @@ pub struct Statistics {
     pub error_pkts: u64,
 }
 
-impl<'a> Display for SessionAddr {
+impl Display for SessionAddr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            format!("address={}&interface={}", self.address, self.interface)
-        )
+        write!(f, "address={}&interface={}", self.address, self.interface)
     }
 }
 
@@ -114,6 +128,46 @@ impl<T> Message<T> {
     }
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct Reservation {
+    /// The token given to the client in the RESERVATION-TOKEN attribute
+    pub token: u64,
+    /// The reserved port
+    pub port: u16,
+    /// The remaining lifetime of the reservation, in seconds
+    pub lifetime: u32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Channel {
+    /// The channel number
+    pub number: u16,
+    /// The relayed transport address of the peer the channel is bound to
+    pub peer: SocketAddr,
+    /// The remaining lifetime of the binding, in seconds
+    pub lifetime: u32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Released {
+    released: usize,
+}
+
+/// The network policies of the turn server, the networks are in the CIDR
+/// notation and an empty list allows all networks.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Policies {
+    /// The networks of the clients whose binding requests are answered.
+    pub binding_allow: Vec<String>,
+    /// The networks of the clients whose binding requests are dropped.
+    pub binding_deny: Vec<String>,
+    /// The networks of the peers that the clients can create permissions for.
+    pub peer_allow: Vec<String>,
+    /// The networks of the peers that the clients cannot create permissions
+    /// for.
+    pub peer_deny: Vec<String>,
+}
+
 /// The controller of the turn server is used to control the server and obtain
 /// server information through the HTTP interface
 pub struct Controller {
@@ -189,6 +243,115 @@ impl Controller {
         )
         .await
     }
+
+    /// Get the channel bindings of the session.
+    pub async fn get_channels(&self, query: &SessionAddr) -> Option<Message<Vec<Channel>>> {
+        Message::from_res(
+            self.client
+                .get(format!("{}/session/channels?{}", self.server, query))
+                .send()
+                .await
+                .ok()?,
+            |res| async { res.json().await.ok() },
+        )
+        .await
+    }
+
+    /// Remove the channel binding of the session, the permission of the peer
+    /// is kept until it expires.
+    pub async fn remove_channel(&self, query: &SessionAddr, number: u16) -> Option<Message<bool>> {
+        Message::from_res(
+            self.client
+                .delete(format!(
+                    "{}/session/channel?{}&number={}",
+                    self.server, query, number
+                ))
+                .send()
+                .await
+                .ok()?,
+            |res| async move { Some(res.status() == StatusCode::OK) },
+        )
+        .await
+    }
+
+    /// Get the ports held in reserve by the reservation tokens of the even
+    /// port allocations, which have not been redeemed yet.
+    pub async fn get_reservations(&self) -> Option<Message<Vec<Reservation>>> {
+        Message::from_res(
+            self.client
+                .get(format!("{}/reservations", self.server))
+                .send()
+                .await
+                .ok()?,
+            |res| async { res.json().await.ok() },
+        )
+        .await
+    }
+
+    /// Release the reserved ports whose reservation has expired right away,
+    /// returns the number of the released ports.
+    pub async fn prune_reservations(&self) -> Option<Message<usize>> {
+        Message::from_res(
+            self.client
+                .delete(format!("{}/reservations", self.server))
+                .send()
+                .await
+                .ok()?,
+            |res| async { res.json::<Released>().await.ok().map(|it| it.released) },
+        )
+        .await
+    }
+
+    /// Replace the network policies of the turn server without a restart.
+    /// Only the new decisions use the replaced policies, the existing
+    /// permissions and channels are kept.
+    pub async fn reload_policy(&self, policies: &Policies) -> Option<Message<bool>> {
+        Message::from_res(
+            self.client
+                .put(format!("{}/policy", self.server))
+                .json(policies)
+                .send()
+                .await
+                .ok()?,
+            |res| async move { Some(res.status() == StatusCode::OK) },
+        )
+        .await
+    }
+
+    /// Drain the listener of the turn server for maintenance, the new
+    /// allocations on the listener are redirected to the alternate server,
+    /// or rejected if there is none. The existing allocations are kept.
+    pub async fn drain_listener(
+        &self,
+        interface: SocketAddr,
+        alternate: Option<SocketAddr>,
+    ) -> Option<Message<bool>> {
+        let mut url = format!("{}/listener/drain?interface={}", self.server, interface);
+        if let Some(alternate) = alternate {
+            url.push_str(&format!("&alternate={}", alternate));
+        }
+
+        Message::from_res(self.client.put(url).send().await.ok()?, |res| async move {
+            Some(res.status() == StatusCode::OK)
+        })
+        .await
+    }
+
+    /// Take new allocations on the drained listener again.
+    pub async fn resume_listener(&self, interface: SocketAddr) -> Option<Message<bool>> {
+        Message::from_res(
+            self.client
+                .delete(format!(
+                    "{}/listener/drain?interface={}",
+                    self.server, interface
+                ))
+                .send()
+                .await
+                .ok()?,
+            |res| async move { Some(res.status() == StatusCode::OK) },
+        )
+        .await
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -337,6 +500,15 @@ pub enum Events {
         username: String,
         lifetime: u32,
     },
+    /// session evicted
+    ///
+    /// Triggered when the session is closed by force to reclaim its
+    /// resources, such as when its connection is evicted by a server at
+    /// capacity. The closed event follows.
+    Evicted {
+        session: SessionAddr,
+        username: String,
+    },
     /// session closed
     ///
     /// Triggered when the session leaves from the turn. Possible reasons: the
@@ -15,6 +15,15 @@ pub enum Transport {
     UDP = 0x11000000,
 }
 
+/// The change flags of the CHANGE-REQUEST attribute.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Change {
+    /// Send the response from a different IP address.
+    pub ip: bool,
+    /// Send the response from a different port.
+    pub port: bool,
+}
+
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum IpFamily {
@@ -411,6 +420,7 @@ pub enum AttrKind {
     #[default]
     Unknown = 0x0000,
     MappedAddress = 0x0001,
+    ChangeRequest = 0x0003,
     UserName = 0x0006,
     MessageIntegrity = 0x0008,
     ErrorCode = 0x0009,
@@ -429,14 +439,18 @@ pub enum AttrKind {
     ReservationToken = 0x0022,
     Priority = 0x0024,
     UseCandidate = 0x0025,
+    ResponsePort = 0x0027,
     AdditionalAddressFamily = 0x8000,
     AddressErrorCode = 0x8001,
     Icmp = 0x8004,
     Software = 0x8022,
+    AlternateServer = 0x8023,
     Fingerprint = 0x8028,
     IceControlled = 0x8029,
     IceControlling = 0x802A,
     ResponseOrigin = 0x802B,
+    OtherAddress = 0x802C,
+    MobilityTicket = 0x8030,
 }
 
 /// dyn stun/turn message attribute.
@@ -585,8 +599,38 @@ impl<'a> Attribute<'a> for Nonce {
 /// [RFC3629] sequence of fewer than 128 characters (which can be as long
 /// as 509 when encoding them and as long as 763 bytes when decoding
 /// them).
+///
+/// The longer values are truncated to the first 127 characters when
+/// encoding, the value is padded like any other attribute.
+///
+/// # Test
+///
+/// ```
+/// use bytes::BytesMut;
+/// use mycrl_stun::attribute::*;
+/// use mycrl_stun::*;
+///
+/// let software = "é".repeat(1000);
+/// let mut bytes = BytesMut::with_capacity(1500);
+/// let mut message = MessageWriter::new(Method::Binding(Kind::Request), &[0u8; 12], &mut bytes);
+/// message.append::<Software>(&software);
+/// message.flush(None).unwrap();
+///
+/// // 127 characters of 2 bytes, padded to 256 bytes.
+/// assert_eq!(&bytes[22..24], &254u16.to_be_bytes());
+/// assert_eq!(bytes.len(), 20 + 4 + 256);
+///
+/// let mut attributes = Attributes::default();
+/// let message = MessageReader::decode(&bytes, &mut attributes).unwrap();
+/// assert_eq!(message.get::<Software>(), Some(&software[..254]));
+/// ```
 pub struct Software;
 
+impl Software {
+    /// The maximum number of characters of the value.
+    pub const MAX_CHARS: usize = 127;
+}
+
 impl<'a> Attribute<'a> for Software {
     type Error = StunError;
     type Item = &'a str;
@@ -594,7 +638,13 @@ impl<'a> Attribute<'a> for Software {
     const KIND: AttrKind = AttrKind::Software;
 
     fn encode(value: Self::Item, bytes: &mut BytesMut, _: &'a [u8]) {
-        bytes.put(value.as_bytes());
+        let size = value
+            .char_indices()
+            .nth(Self::MAX_CHARS)
+            .map(|(index, _)| index)
+            .unwrap_or(value.len());
+
+        bytes.put(&value.as_bytes()[..size]);
     }
 
     fn decode(bytes: &'a [u8], _: &'a [u8]) -> Result<Self::Item, Self::Error> {
@@ -804,6 +854,163 @@ impl<'a> Attribute<'a> for ResponseOrigin {
     }
 }
 
+/// [RFC5780]: https://datatracker.ietf.org/doc/html/rfc5780
+///
+/// The OTHER-ADDRESS attribute is used in Binding Responses.  It informs
+/// the client of the source IP address and port that would be used if
+/// the client requested the "change IP" and "change port" behavior.
+/// OTHER-ADDRESS MUST NOT be inserted into a Binding Response unless the
+/// server has a second IP address.
+///
+/// OTHER-ADDRESS uses the same attribute type and format as
+/// CHANGED-ADDRESS from RFC 3489.
+pub struct OtherAddress;
+
+impl<'a> Attribute<'a> for OtherAddress {
+    type Error = StunError;
+    type Item = SocketAddr;
+
+    const KIND: AttrKind = AttrKind::OtherAddress;
+
+    fn encode(value: Self::Item, bytes: &mut BytesMut, token: &'a [u8]) {
+        Addr::encode(&value, token, bytes, false)
+    }
+
+    fn decode(bytes: &'a [u8], token: &'a [u8]) -> Result<Self::Item, Self::Error> {
+        Addr::decode(bytes, token, false)
+    }
+}
+
+/// [RFC8489]: https://datatracker.ietf.org/doc/html/rfc8489#section-14.15
+///
+/// The alternate server represents an alternate transport address
+/// identifying a different STUN server that the STUN client should try.
+/// It is encoded in the same way as MAPPED-ADDRESS, and thus refers to a
+/// single server by IP address.
+///
+/// # Test
+///
+/// ```
+/// use bytes::BytesMut;
+/// use mycrl_stun::attribute::*;
+/// use std::net::SocketAddr;
+///
+/// let addr = "192.0.2.1:3478".parse::<SocketAddr>().unwrap();
+/// let mut bytes = BytesMut::new();
+/// AlternateServer::encode(addr, &mut bytes, &[0u8; 12]);
+/// assert_eq!(&bytes[..], &[0x00, 0x01, 0x0D, 0x96, 192, 0, 2, 1]);
+/// assert_eq!(AlternateServer::decode(&bytes, &[0u8; 12]).unwrap(), addr);
+/// ```
+pub struct AlternateServer;
+
+impl<'a> Attribute<'a> for AlternateServer {
+    type Error = StunError;
+    type Item = SocketAddr;
+
+    const KIND: AttrKind = AttrKind::AlternateServer;
+
+    fn encode(value: Self::Item, bytes: &mut BytesMut, token: &'a [u8]) {
+        Addr::encode(&value, token, bytes, false)
+    }
+
+    fn decode(bytes: &'a [u8], token: &'a [u8]) -> Result<Self::Item, Self::Error> {
+        Addr::decode(bytes, token, false)
+    }
+}
+
+/// [RFC5780]: https://datatracker.ietf.org/doc/html/rfc5780
+///
+/// The CHANGE-REQUEST attribute contains two flags to control the IP
+/// address and port that the server uses to send the response.  These
+/// flags are called the "change IP" and "change port" flags.  The
+/// CHANGE-REQUEST attribute is allowed only in the Binding Request.  The
+/// "change IP" and "change port" flags are useful for determining the
+/// current filtering behavior of a NAT.
+///
+/// # Test
+///
+/// ```
+/// use bytes::BytesMut;
+/// use mycrl_stun::attribute::*;
+///
+/// let mut bytes = BytesMut::new();
+/// let change = Change { ip: false, port: true };
+///
+/// ChangeRequest::encode(change, &mut bytes, &[]);
+/// assert_eq!(&bytes[..], &[0x00, 0x00, 0x00, 0x02]);
+/// assert_eq!(ChangeRequest::decode(&bytes, &[]).unwrap(), change);
+///
+/// let change = ChangeRequest::decode(&[0x00, 0x00, 0x00, 0x06], &[]).unwrap();
+/// assert_eq!(change, Change { ip: true, port: true });
+/// assert!(ChangeRequest::decode(&[0x00, 0x00], &[]).is_err());
+/// ```
+pub struct ChangeRequest;
+
+impl<'a> Attribute<'a> for ChangeRequest {
+    type Error = StunError;
+    type Item = Change;
+
+    const KIND: AttrKind = AttrKind::ChangeRequest;
+
+    fn encode(value: Self::Item, bytes: &mut BytesMut, _: &'a [u8]) {
+        bytes.put_u32(if value.ip { 0x04 } else { 0x00 } | if value.port { 0x02 } else { 0x00 })
+    }
+
+    fn decode(bytes: &'a [u8], _: &'a [u8]) -> Result<Self::Item, Self::Error> {
+        let flags = u32::from_be_bytes(bytes.try_into()?);
+        Ok(Change {
+            ip: flags & 0x04 != 0,
+            port: flags & 0x02 != 0,
+        })
+    }
+}
+
+/// [RFC5780]: https://datatracker.ietf.org/doc/html/rfc5780
+///
+/// The RESPONSE-PORT attribute contains a port.  The RESPONSE-PORT
+/// attribute can be present in the Binding Request and indicates which
+/// port the Binding Response will be sent to.  For servers which support
+/// the RESPONSE-PORT attribute, the Binding Response MUST be transmitted
+/// to the source IP address of the Binding Request and the port
+/// contained in RESPONSE-PORT.  It is used in tests such as Section 4.6.
+/// When not present, the server sends the Binding Response to the source
+/// IP address and port of the Binding Request.  The server MUST NOT
+/// process RESPONSE-PORT on TCP/TLS connections.
+///
+/// The port is followed by 2 bytes of padding.
+///
+/// # Test
+///
+/// ```
+/// use bytes::BytesMut;
+/// use mycrl_stun::attribute::*;
+///
+/// let mut bytes = BytesMut::new();
+///
+/// ResponsePort::encode(3479, &mut bytes, &[]);
+/// assert_eq!(&bytes[..], &[0x0D, 0x97, 0x00, 0x00]);
+/// assert_eq!(ResponsePort::decode(&bytes, &[]).unwrap(), 3479);
+/// assert!(ResponsePort::decode(&[0x0D, 0x97], &[]).is_err());
+/// ```
+pub struct ResponsePort;
+
+impl<'a> Attribute<'a> for ResponsePort {
+    type Error = StunError;
+    type Item = u16;
+
+    const KIND: AttrKind = AttrKind::ResponsePort;
+
+    fn encode(value: Self::Item, bytes: &mut BytesMut, _: &'a [u8]) {
+        bytes.put_u16(value);
+        bytes.put_u16(0);
+    }
+
+    fn decode(bytes: &'a [u8], _: &'a [u8]) -> Result<Self::Item, Self::Error> {
+        let bytes: [u8; 4] = bytes.try_into()?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+}
+
 /// The following error codes, along with their recommended reason
 /// phrases, are defined:
 ///
@@ -842,6 +1049,14 @@ impl<'a> Attribute<'a> for ResponseOrigin {
 ///      The client should retry, using the NONCE provided in the
 ///      response.
 ///      
+/// 446  Connection Already Exists: The Connect request was rejected,
+///      because the allocation already has a connection to the peer
+///      (RFC 6062).
+///
+/// 447  Connection Timeout or Failure: The server could not establish
+///      the TCP connection to the peer, the connection attempt failed or
+///      timed out (RFC 6062).
+///
 /// 500  Server Error: The server has suffered a temporary error.  The
 ///      client should try again.
 const fn errno(code: u16) -> u16 {
@@ -855,6 +1070,7 @@ pub enum ErrorKind {
     BadRequest = errno(400),
     Unauthorized = errno(401),
     Forbidden = errno(403),
+    MobilityForbidden = errno(405),
     UnknownAttribute = errno(420),
     AllocationMismatch = errno(437),
     StaleNonce = errno(438),
@@ -862,6 +1078,8 @@ pub enum ErrorKind {
     WrongCredentials = errno(441),
     UnsupportedTransportAddress = errno(442),
     PeerAddressFamilyMismatch = errno(443),
+    ConnectionAlreadyExists = errno(446),
+    ConnectionTimeoutOrFailure = errno(447),
     AllocationQuotaReached = errno(486),
     ServerError = errno(500),
     InsufficientCapacity = errno(508),
@@ -998,6 +1216,9 @@ impl From<ErrorKind> for &'static str {
     ///
     /// let err: &'static str = ErrorKind::TryAlternate.into();
     /// assert_eq!(err, "Try Alternate");
+    ///
+    /// let err: &'static str = ErrorKind::ConnectionTimeoutOrFailure.into();
+    /// assert_eq!(err, "Connection Timeout or Failure");
     /// ```
     #[rustfmt::skip]
     fn from(val: ErrorKind) -> Self {
@@ -1006,6 +1227,7 @@ impl From<ErrorKind> for &'static str {
             ErrorKind::BadRequest => "Bad Request",
             ErrorKind::Unauthorized => "Unauthorized",
             ErrorKind::Forbidden => "Forbidden",
+            ErrorKind::MobilityForbidden => "Mobility Forbidden",
             ErrorKind::UnknownAttribute => "Unknown Attribute",
             ErrorKind::AllocationMismatch => "Allocation Mismatch",
             ErrorKind::StaleNonce => "Stale Nonce",
@@ -1016,6 +1238,8 @@ impl From<ErrorKind> for &'static str {
             ErrorKind::ServerError => "Server Error",
             ErrorKind::InsufficientCapacity => "Insufficient Capacity",
             ErrorKind::PeerAddressFamilyMismatch => "Peer Address Family Mismatch",
+            ErrorKind::ConnectionAlreadyExists => "Connection Already Exists",
+            ErrorKind::ConnectionTimeoutOrFailure => "Connection Timeout or Failure",
         }
     }
 }
@@ -1179,7 +1403,8 @@ impl<'a> Attribute<'a> for ChannelNumber {
     }
 
     fn decode(bytes: &'a [u8], _: &'a [u8]) -> Result<Self::Item, Self::Error> {
-        Ok(u16::from_be_bytes(bytes[..2].try_into()?))
+        let bytes = bytes.get(..2).ok_or(StunError::InvalidInput)?;
+        Ok(u16::from_be_bytes(bytes.try_into()?))
     }
 }
 
@@ -1300,6 +1525,43 @@ impl<'a> Attribute<'a> for ReservationToken {
     }
 }
 
+/// [RFC8016]: https://datatracker.ietf.org/doc/html/rfc8016
+///
+/// The MOBILITY-TICKET attribute is used to retain an allocation on the TURN
+/// server when the client changes its address. The client includes an empty
+/// ticket in the Allocate request to ask for mobility, and the server returns
+/// the ticket in the success responses of the Allocate and Refresh requests.
+/// The client sends the last ticket in a Refresh request from its new
+/// address to move the allocation. The value is opaque to the client.
+///
+/// # Test
+///
+/// ```
+/// use bytes::BytesMut;
+/// use mycrl_stun::attribute::*;
+///
+/// let mut bytes = BytesMut::new();
+/// MobilityTicket::encode(&[1, 2, 3, 4], &mut bytes, &[]);
+/// assert_eq!(&bytes[..], &[1, 2, 3, 4]);
+/// assert_eq!(MobilityTicket::decode(&bytes, &[]).unwrap(), &[1, 2, 3, 4]);
+/// ```
+pub struct MobilityTicket;
+
+impl<'a> Attribute<'a> for MobilityTicket {
+    type Error = StunError;
+    type Item = &'a [u8];
+
+    const KIND: AttrKind = AttrKind::MobilityTicket;
+
+    fn encode(value: Self::Item, bytes: &mut BytesMut, _: &'a [u8]) {
+        bytes.put(value);
+    }
+
+    fn decode(bytes: &'a [u8], _: &'a [u8]) -> Result<Self::Item, Self::Error> {
+        Ok(bytes)
+    }
+}
+
 /// This attribute allows the client to request that the port in the relayed
 /// transport address be even, and (optionally) that the server reserve the
 /// next-higher port number.  The value portion of this attribute is 1 byte
@@ -1317,7 +1579,7 @@ impl<'a> Attribute<'a> for EvenPort {
     }
 
     fn decode(bytes: &'a [u8], _: &'a [u8]) -> Result<Self::Item, Self::Error> {
-        Ok(bytes[0] == 0b10000000)
+        Ok(bytes.first().ok_or(StunError::InvalidInput)? == &0b10000000)
     }
 }
 
@@ -1339,7 +1601,7 @@ impl<'a> Attribute<'a> for RequestedAddressFamily {
     }
 
     fn decode(bytes: &'a [u8], _: &'a [u8]) -> Result<Self::Item, Self::Error> {
-        IpFamily::try_from(bytes[0])
+        IpFamily::try_from(*bytes.first().ok_or(StunError::InvalidInput)?)
     }
 }
 
@@ -1361,7 +1623,7 @@ impl<'a> Attribute<'a> for AdditionalAddressFamily {
     }
 
     fn decode(bytes: &'a [u8], _: &'a [u8]) -> Result<Self::Item, Self::Error> {
-        IpFamily::try_from(bytes[0])
+        IpFamily::try_from(*bytes.first().ok_or(StunError::InvalidInput)?)
     }
 }
 
@@ -74,8 +74,8 @@ impl ChannelData<'_> {
             return Err(StunError::InvalidInput);
         }
 
-        let mut size = (u16::from_be_bytes(bytes[2..4].try_into()?) + 4) as usize;
-        if is_tcp && (size % 4) > 0 {
+        let mut size = u16::from_be_bytes(bytes[2..4].try_into()?) as usize + 4;
+        if is_tcp && !size.is_multiple_of(4) {
             size += 4 - (size % 4);
         }
 
@@ -132,6 +132,24 @@ impl<'a> TryFrom<&'a [u8]> for ChannelData<'a> {
     /// let ret = ChannelData::try_from(&bytes[..]).unwrap();
     /// assert_eq!(ret.number, 16384);
     /// assert_eq!(ret.bytes, &data[..]);
+    ///
+    /// // Over tcp the message is padded to a multiple of 4 bytes, the padding
+    /// // is not part of the application data.
+    /// for len in 1..4 {
+    ///     ChannelData {
+    ///         number: 16384,
+    ///         bytes: &data[..len],
+    ///     }
+    ///     .encode(&mut bytes);
+    ///
+    ///     bytes.put_bytes(0, 4 - len);
+    ///
+    ///     let size = ChannelData::message_size(&bytes[..], true).unwrap();
+    ///     assert_eq!(size, 8);
+    ///
+    ///     let ret = ChannelData::try_from(&bytes[..size]).unwrap();
+    ///     assert_eq!(ret.bytes, &data[..len]);
+    /// }
     /// ```
     fn try_from(bytes: &'a [u8]) -> Result<Self, Self::Error> {
         if bytes.len() < 4 {
@@ -149,7 +167,7 @@ impl<'a> TryFrom<&'a [u8]> for ChannelData<'a> {
         }
 
         Ok(Self {
-            bytes: &bytes[4..],
+            bytes: &bytes[4..4 + size],
             number,
         })
     }
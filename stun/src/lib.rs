@@ -69,6 +69,8 @@ pub enum StunError {
     NotIntegrity,
     #[error("IntegrityFailed")]
     IntegrityFailed,
+    #[error("FingerprintFailed")]
+    FingerprintFailed,
     #[error("NotCookie")]
     NotCookie,
     #[error("UnknownMethod")]
@@ -132,14 +134,14 @@ pub enum Method {
 
 impl Method {
     pub fn is_error(&self) -> bool {
-        match self {
+        matches!(
+            self,
             Method::Binding(Kind::Error)
-            | Method::Refresh(Kind::Error)
-            | Method::Allocate(Kind::Error)
-            | Method::CreatePermission(Kind::Error)
-            | Method::ChannelBind(Kind::Error) => true,
-            _ => false,
-        }
+                | Method::Refresh(Kind::Error)
+                | Method::Allocate(Kind::Error)
+                | Method::CreatePermission(Kind::Error)
+                | Method::ChannelBind(Kind::Error)
+        )
     }
 }
 
@@ -287,6 +289,49 @@ impl From<Method> for u16 {
     }
 }
 
+/// The message type of a method that is not implemented.
+///
+/// The message type interleaves the method number with the two class bits,
+/// so that a request of an unknown method can still be answered with an
+/// error response of the same method.
+///
+/// # Test
+///
+/// ```
+/// use mycrl_stun::*;
+///
+/// // The Connect request and indication of the TCP allocations (RFC 6062).
+/// let request = UnknownMethod(0x000a);
+/// assert!(request.is_request());
+/// assert_eq!(request.method(), 0x00a);
+/// assert_eq!(request.error(), 0x011a);
+///
+/// let indication = UnknownMethod(0x001c);
+/// assert!(!indication.is_request());
+/// assert_eq!(indication.method(), 0x00c);
+///
+/// assert_eq!(UnknownMethod(0x3eef).method(), 0xfff);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnknownMethod(pub u16);
+
+impl UnknownMethod {
+    /// The method number, without the class bits.
+    pub fn method(&self) -> u16 {
+        ((self.0 & 0x3e00) >> 2) | ((self.0 & 0x00e0) >> 1) | (self.0 & 0x000f)
+    }
+
+    /// Check if the message is a request, only the requests are answered.
+    pub fn is_request(&self) -> bool {
+        self.0 & 0x0110 == 0
+    }
+
+    /// The message type of the error response of the method.
+    pub fn error(&self) -> u16 {
+        self.0 | 0x0110
+    }
+}
+
 #[derive(Debug)]
 pub enum Payload<'a> {
     Message(MessageReader<'a>),
@@ -329,7 +374,6 @@ impl Attributes {
             .iter()
             .filter(move |(k, _)| k == kind)
             .map(|(_, v)| v)
-            .into_iter()
     }
 
     pub fn clear(&mut self) {
@@ -343,6 +387,76 @@ impl Attributes {
 pub struct Decoder(Attributes);
 
 impl Decoder {
+    /// Check if the packet is a classic stun binding request.
+    ///
+    /// [rfc3489](https://tools.ietf.org/html/rfc3489)
+    ///
+    /// The classic stun messages have no magic cookie, the first 32 bits of the
+    /// 128 bits transaction id take its place.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_stun::*;
+    ///
+    /// let binding = [
+    ///     0x00, 0x01, 0x00, 0x00, 0x21, 0x12, 0xa4, 0x42, 0x71, 0x66, 0x46, 0x31,
+    ///     0x2b, 0x59, 0x79, 0x65, 0x56, 0x69, 0x32, 0x72,
+    /// ];
+    ///
+    /// let legacy = [
+    ///     0x00, 0x01, 0x00, 0x00, 0x01, 0x02, 0x03, 0x04, 0x71, 0x66, 0x46, 0x31,
+    ///     0x2b, 0x59, 0x79, 0x65, 0x56, 0x69, 0x32, 0x72,
+    /// ];
+    ///
+    /// let truncated = [
+    ///     0x00, 0x01, 0x00, 0x08, 0x01, 0x02, 0x03, 0x04, 0x71, 0x66, 0x46, 0x31,
+    ///     0x2b, 0x59, 0x79, 0x65, 0x56, 0x69, 0x32, 0x72,
+    /// ];
+    ///
+    /// assert!(Decoder::is_legacy_binding(&legacy));
+    /// assert!(!Decoder::is_legacy_binding(&binding));
+    /// assert!(!Decoder::is_legacy_binding(&truncated));
+    /// assert!(!Decoder::is_legacy_binding(&legacy[..8]));
+    /// ```
+    pub fn is_legacy_binding(bytes: &[u8]) -> bool {
+        bytes.len() >= 20
+            && bytes[..2] == [0x00, 0x01]
+            && bytes[4..8] != message::COOKIE
+            && u16::from_be_bytes([bytes[2], bytes[3]]) as usize + 20 <= bytes.len()
+    }
+
+    /// Get the message type of a stun message whose method is not
+    /// implemented.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_stun::*;
+    ///
+    /// let mut connect = [
+    ///     0x00, 0x0a, 0x00, 0x00, 0x21, 0x12, 0xa4, 0x42, 0x71, 0x66, 0x46, 0x31,
+    ///     0x2b, 0x59, 0x79, 0x65, 0x56, 0x69, 0x32, 0x72,
+    /// ];
+    ///
+    /// assert_eq!(Decoder::unknown_method(&connect), Some(UnknownMethod(0x000a)));
+    /// assert_eq!(Decoder::unknown_method(&connect[..8]), None);
+    ///
+    /// connect[1] = 0x01;
+    /// assert_eq!(Decoder::unknown_method(&connect), None);
+    /// ```
+    pub fn unknown_method(bytes: &[u8]) -> Option<UnknownMethod> {
+        if bytes.len() < 20 || bytes[0] >> 6 != 0 || bytes[4..8] != message::COOKIE {
+            return None;
+        }
+
+        let kind = u16::from_be_bytes([bytes[0], bytes[1]]);
+        match Method::try_from(kind) {
+            Ok(_) => None,
+            Err(_) => Some(UnknownMethod(kind)),
+        }
+    }
+
     /// # Test
     ///
     /// ```
@@ -383,6 +497,50 @@ impl Decoder {
         })
     }
 
+    /// Check if the packet looks like a stun message or channel data.
+    ///
+    /// A stun message must carry the magic cookie, and channel data must use
+    /// a channel number in the range 0x4000 - 0x7FFF. This allows the stun
+    /// traffic to be distinguished from other traffic multiplexed on the
+    /// same port, such as RTP.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_stun::*;
+    ///
+    /// let binding = [
+    ///     0x00, 0x01, 0x00, 0x00, 0x21, 0x12, 0xa4, 0x42, 0x71, 0x66, 0x46, 0x31,
+    ///     0x2b, 0x59, 0x79, 0x65, 0x56, 0x69, 0x32, 0x72,
+    /// ];
+    ///
+    /// let no_cookie = [
+    ///     0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x71, 0x66, 0x46, 0x31,
+    ///     0x2b, 0x59, 0x79, 0x65, 0x56, 0x69, 0x32, 0x72,
+    /// ];
+    ///
+    /// let rtp = [
+    ///     0x80, 0x6f, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x12, 0x34, 0x56, 0x78,
+    /// ];
+    ///
+    /// assert!(Decoder::is_stun(&binding));
+    /// assert!(Decoder::is_stun(&[0x40, 0x00, 0x00, 0x00]));
+    /// assert!(!Decoder::is_stun(&no_cookie));
+    /// assert!(!Decoder::is_stun(&rtp));
+    /// assert!(!Decoder::is_stun(&[0x00, 0x01]));
+    /// ```
+    pub fn is_stun(bytes: &[u8]) -> bool {
+        if bytes.len() < 4 {
+            return false;
+        }
+
+        match bytes[0] >> 6 {
+            0 => bytes.len() >= 20 && bytes[4..8] == message::COOKIE,
+            1 => true,
+            _ => false,
+        }
+    }
+
     /// # Test
     ///
     /// ```
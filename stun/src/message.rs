@@ -4,11 +4,11 @@ use std::convert::TryFrom;
 
 use super::{
     attribute::{AttrKind, Attribute, MessageIntegrity},
-    util, Attributes, Method, StunError,
+    util, Attributes, Method, StunError, UnknownMethod,
 };
 
 const ZOER_BUF: [u8; 10] = [0u8; 10];
-const COOKIE: [u8; 4] = 0x2112A442u32.to_be_bytes();
+pub(crate) const COOKIE: [u8; 4] = 0x2112A442u32.to_be_bytes();
 
 /// (username, password, realm)
 type Digest = [u8; 16];
@@ -18,7 +18,7 @@ pub struct MessageWriter<'a> {
     pub bytes: &'a mut BytesMut,
 }
 
-impl<'a, 'b> MessageWriter<'a> {
+impl<'a> MessageWriter<'a> {
     pub fn new(method: Method, token: &'a [u8; 12], bytes: &'a mut BytesMut) -> Self {
         unsafe { bytes.set_len(0) }
         bytes.put_u16(method.into());
@@ -59,6 +59,78 @@ impl<'a, 'b> MessageWriter<'a> {
         Self { bytes, token }
     }
 
+    /// create a message of the classic stun.
+    ///
+    /// [rfc3489](https://tools.ietf.org/html/rfc3489)
+    ///
+    /// The classic stun messages have no magic cookie, the transaction id is
+    /// 128 bits instead. This is only for answering the legacy clients, the
+    /// XOR attributes cannot be decoded by them.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use bytes::BytesMut;
+    /// use mycrl_stun::attribute::MappedAddress;
+    /// use mycrl_stun::*;
+    ///
+    /// let token = [
+    ///     0x01, 0x02, 0x03, 0x04, 0x72, 0x6d, 0x49, 0x42, 0x72, 0x52, 0x64, 0x48,
+    ///     0x57, 0x62, 0x4b, 0x2b,
+    /// ];
+    ///
+    /// let result = [
+    ///     0x01u8, 0x01, 0x00, 0x0c, 0x01, 0x02, 0x03, 0x04, 0x72, 0x6d, 0x49,
+    ///     0x42, 0x72, 0x52, 0x64, 0x48, 0x57, 0x62, 0x4b, 0x2b, 0x00, 0x01, 0x00,
+    ///     0x08, 0x00, 0x01, 0x1f, 0x90, 0x7f, 0x00, 0x00, 0x01,
+    /// ];
+    ///
+    /// let mut buf = BytesMut::new();
+    /// let mut message = MessageWriter::legacy(Method::Binding(Kind::Response), &token, &mut buf);
+    /// message.append::<MappedAddress>("127.0.0.1:8080".parse().unwrap());
+    /// message.flush(None).unwrap();
+    /// assert_eq!(&buf[..], &result[..]);
+    /// ```
+    pub fn legacy(method: Method, token: &'a [u8; 16], bytes: &'a mut BytesMut) -> Self {
+        unsafe { bytes.set_len(0) }
+        bytes.put_u16(method.into());
+        bytes.put_u16(0);
+        bytes.put(token.as_slice());
+        Self {
+            token: &token[4..],
+            bytes,
+        }
+    }
+
+    /// create the error response of a request whose method is not
+    /// implemented.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use bytes::BytesMut;
+    /// use mycrl_stun::*;
+    ///
+    /// let mut buf = BytesMut::new();
+    /// let mut message = MessageWriter::unknown_error(UnknownMethod(0x000a), &[0u8; 12], &mut buf);
+    /// message.flush(None).unwrap();
+    ///
+    /// assert_eq!(&buf[..8], &[0x01, 0x1a, 0x00, 0x00, 0x21, 0x12, 0xa4, 0x42]);
+    /// assert_eq!(Decoder::unknown_method(&buf), Some(UnknownMethod(0x011a)));
+    /// ```
+    pub fn unknown_error(
+        method: UnknownMethod,
+        token: &'a [u8; 12],
+        bytes: &'a mut BytesMut,
+    ) -> Self {
+        unsafe { bytes.set_len(0) }
+        bytes.put_u16(method.error());
+        bytes.put_u16(0);
+        bytes.put(&COOKIE[..]);
+        bytes.put(token.as_slice());
+        Self { bytes, token }
+    }
+
     /// append attribute.
     ///
     /// append attribute to message attribute list.
@@ -151,6 +223,19 @@ impl<'a, 'b> MessageWriter<'a> {
     ///     )))
     ///     .unwrap();
     /// assert_eq!(&buf[..], &result);
+    ///
+    /// // The MessageIntegrity is followed by the Fingerprint, the integrity is
+    /// // computed without the Fingerprint and both are validated on decoding.
+    /// let mut attributes = Attributes::default();
+    /// let message = MessageReader::decode(&buf[..], &mut attributes).unwrap();
+    /// assert!(message.get::<attribute::Fingerprint>().is_some());
+    /// assert!(message
+    ///     .integrity(&util::long_term_credential_digest(
+    ///         "panda",
+    ///         "panda",
+    ///         "raspberry",
+    ///     ))
+    ///     .is_ok());
     /// ```
     pub fn flush(&mut self, digest: Option<&Digest>) -> Result<(), StunError> {
         // write attribute list size.
@@ -251,6 +336,27 @@ pub struct MessageReader<'a> {
 }
 
 impl<'a> MessageReader<'a> {
+    /// get the source bytes of the message.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_stun::attribute::*;
+    /// use mycrl_stun::*;
+    ///
+    /// let buffer = [
+    ///     0x00u8, 0x01, 0x00, 0x00, 0x21, 0x12, 0xa4, 0x42, 0x72, 0x6d, 0x49,
+    ///     0x42, 0x72, 0x52, 0x64, 0x48, 0x57, 0x62, 0x4b, 0x2b,
+    /// ];
+    ///
+    /// let mut attributes = Attributes::default();
+    /// let message = MessageReader::decode(&buffer[..], &mut attributes).unwrap();
+    /// assert_eq!(message.as_bytes(), &buffer[..]);
+    /// ```
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
     /// get attribute.
     ///
     /// get attribute from message attribute list.
@@ -302,8 +408,52 @@ impl<'a> MessageReader<'a> {
         self.attributes
             .get_all(&T::KIND)
             .map(|it| T::decode(&self.bytes[it.clone()], self.token))
-            .filter(|it| it.is_ok())
-            .map(|it| it.unwrap())
+            .filter_map(|it| it.ok())
+    }
+
+    /// get the value of the first attribute of the type.
+    ///
+    /// The attributes that are not supported are skipped by the decoder, this
+    /// is the way to read them, such as the vendor specific attributes.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_stun::attribute::*;
+    /// use mycrl_stun::*;
+    ///
+    /// let buffer = [
+    ///     0x00u8, 0x01, 0x00, 0x08, 0x21, 0x12, 0xa4, 0x42, 0x72, 0x6d, 0x49,
+    ///     0x42, 0x72, 0x52, 0x64, 0x48, 0x57, 0x62, 0x4b, 0x2b, 0xc0, 0x01,
+    ///     0x00, 0x03, 0x61, 0x62, 0x63, 0x00,
+    /// ];
+    ///
+    /// let mut attributes = Attributes::default();
+    /// let message = MessageReader::decode(&buffer[..], &mut attributes).unwrap();
+    /// assert_eq!(message.get_raw(0xc001), Some(&b"abc"[..]));
+    /// assert_eq!(message.get_raw(0xc002), None);
+    /// ```
+    pub fn get_raw(&self, kind: u16) -> Option<&'a [u8]> {
+        let bytes = self.bytes;
+        let mut offset = 20;
+
+        while bytes.len() >= offset + 4 {
+            let key = u16::from_be_bytes([bytes[offset], bytes[offset + 1]]);
+            let size = u16::from_be_bytes([bytes[offset + 2], bytes[offset + 3]]) as usize;
+
+            offset += 4;
+            if bytes.len() < offset + size {
+                return None;
+            }
+
+            if key == kind {
+                return Some(&bytes[offset..offset + size]);
+            }
+
+            offset += size + util::pad_size(size);
+        }
+
+        None
     }
 
     /// check MessageReaderIntegrity attribute.
@@ -339,8 +489,36 @@ impl<'a> MessageReader<'a> {
     ///     ))
     ///     .is_ok();
     /// assert!(result);
+    ///
+    /// // The sample request of RFC 5769 section 2.1, which contains both the
+    /// // MessageIntegrity and Fingerprint attributes and uses the short-term
+    /// // credential.
+    /// let buffer = [
+    ///     0x00u8, 0x01, 0x00, 0x58, 0x21, 0x12, 0xa4, 0x42, 0xb7, 0xe7, 0xa7,
+    ///     0x01, 0xbc, 0x34, 0xd6, 0x86, 0xfa, 0x87, 0xdf, 0xae, 0x80, 0x22, 0x00,
+    ///     0x10, 0x53, 0x54, 0x55, 0x4e, 0x20, 0x74, 0x65, 0x73, 0x74, 0x20, 0x63,
+    ///     0x6c, 0x69, 0x65, 0x6e, 0x74, 0x00, 0x24, 0x00, 0x04, 0x6e, 0x00, 0x01,
+    ///     0xff, 0x80, 0x29, 0x00, 0x08, 0x93, 0x2f, 0xf9, 0xb1, 0x51, 0x26, 0x3b,
+    ///     0x36, 0x00, 0x06, 0x00, 0x09, 0x65, 0x76, 0x74, 0x6a, 0x3a, 0x68, 0x36,
+    ///     0x76, 0x59, 0x20, 0x20, 0x20, 0x00, 0x08, 0x00, 0x14, 0x9a, 0xea, 0xa7,
+    ///     0x0c, 0xbf, 0xd8, 0xcb, 0x56, 0x78, 0x1e, 0xf2, 0xb5, 0xb2, 0xd3, 0xf2,
+    ///     0x49, 0xc1, 0xb5, 0x71, 0xa2, 0x80, 0x28, 0x00, 0x04, 0xe5, 0x7a, 0x3b,
+    ///     0xcf,
+    /// ];
+    ///
+    /// let mut attributes = Attributes::default();
+    /// let message = MessageReader::decode(&buffer[..], &mut attributes).unwrap();
+    /// assert!(message.integrity(b"VOkJxbRl1RmTxUk/WvJxBt").is_ok());
+    /// assert!(message.integrity(b"VOkJxbRl1RmTxUk/WvJxBT").is_err());
+    ///
+    /// // A corrupted fingerprint is rejected.
+    /// let mut corrupted = buffer;
+    /// corrupted[107] ^= 0x01;
+    ///
+    /// let mut attributes = Attributes::default();
+    /// assert!(MessageReader::decode(&corrupted[..], &mut attributes).is_err());
     /// ```
-    pub fn integrity(&self, digest: &Digest) -> Result<(), StunError> {
+    pub fn integrity(&self, digest: &[u8]) -> Result<(), StunError> {
         if self.bytes.is_empty() || self.valid_offset < 20 {
             return Err(StunError::InvalidInput);
         }
@@ -363,8 +541,9 @@ impl<'a> MessageReader<'a> {
         let hmac_output = util::hmac_sha1(digest, &body)?.into_bytes();
         let hmac_buf = hmac_output.as_slice();
 
-        // Compare local and original attribute.
-        if integrity != hmac_buf {
+        // Compare local and original attribute, in constant time so that the
+        // time taken does not leak the expected digest.
+        if !util::constant_time_eq(integrity, hmac_buf) {
             return Err(StunError::IntegrityFailed);
         }
 
@@ -387,6 +566,17 @@ impl<'a> MessageReader<'a> {
     /// let message = MessageReader::decode(&buffer[..], &mut attributes).unwrap();
     /// assert_eq!(message.method, Method::Binding(Kind::Request));
     /// assert!(message.get::<UserName>().is_none());
+    ///
+    /// // The padding of the last attribute is missing.
+    /// let buffer: [u8; 26] = [
+    ///     0x00, 0x09, 0x00, 0x06, 0x21, 0x12, 0xa4, 0x42, 0x72, 0x6d, 0x49, 0x42,
+    ///     0x72, 0x52, 0x64, 0x48, 0x57, 0x62, 0x4b, 0x2b, 0x00, 0x0c, 0x00, 0x02,
+    ///     0x40, 0x00,
+    /// ];
+    ///
+    /// let mut attributes = Attributes::default();
+    /// let message = MessageReader::decode(&buffer[..], &mut attributes).unwrap();
+    /// assert_eq!(message.get::<ChannelNumber>(), Some(0x4000));
     /// ```
     pub fn decode(
         bytes: &'a [u8],
@@ -405,12 +595,12 @@ impl<'a> MessageReader<'a> {
         // check fixed magic cookie
         // check if the message size is overflow
         let method = Method::try_from(u16::from_be_bytes(bytes[..2].try_into()?))?;
-        let size = u16::from_be_bytes(bytes[2..4].try_into()?) as usize + 20;
+        let message_size = u16::from_be_bytes(bytes[2..4].try_into()?) as usize + 20;
         if bytes[4..8] != COOKIE[..] {
             return Err(StunError::NotCookie);
         }
 
-        if count_size < size {
+        if count_size < message_size {
             return Err(StunError::InvalidInput);
         }
 
@@ -421,7 +611,9 @@ impl<'a> MessageReader<'a> {
         loop {
             // if the buf length is not long enough to continue,
             // jump out of the loop.
-            if count_size - offset < 4 {
+            // the padding of the last attribute may be missing, so the offset
+            // can be past the end of the buffer.
+            if count_size < offset + 4 {
                 break;
             }
 
@@ -445,7 +637,7 @@ impl<'a> MessageReader<'a> {
 
             // check if the attribute length has overflowed.
             offset += 4;
-            if count_size - offset < size {
+            if count_size < offset + size {
                 break;
             }
 
@@ -459,12 +651,34 @@ impl<'a> MessageReader<'a> {
                 offset += util::pad_size(size);
             }
 
-            // skip the attributes that are not supported.
+            // skip the attributes that are not supported, the unknown
+            // comprehension-optional attributes (0x8000-0xFFFF) must be
+            // ignored, and so are the unknown comprehension-required ones, as
+            // the processors only read the attributes they understand.
             let attrkind = match AttrKind::try_from(key) {
                 Err(_) => continue,
                 Ok(a) => a,
             };
 
+            // The attributes following the MessageIntegrity attribute are not
+            // protected by it, so they are ignored, with the exception of the
+            // Fingerprint attribute.
+            let start = range.start - 4;
+            if find_integrity && start != valid_offset as usize && attrkind != AttrKind::Fingerprint
+            {
+                continue;
+            }
+
+            // The Fingerprint attribute is the last attribute of the message, and
+            // the CRC covers the message up to the attribute, the length
+            // field of the header already includes the attribute.
+            if attrkind == AttrKind::Fingerprint
+                && (range.end != message_size
+                    || bytes[range.clone()] != util::fingerprint(&bytes[..start]).to_be_bytes())
+            {
+                return Err(StunError::FingerprintFailed);
+            }
+
             // get attribute body
             // insert attribute to attributes list.
             attributes.append(attrkind, range);
@@ -497,6 +711,6 @@ impl<'a> MessageReader<'a> {
             return Err(StunError::InvalidInput);
         }
 
-        Ok((u16::from_be_bytes(buf[2..4].try_into()?) + 20) as usize)
+        Ok(u16::from_be_bytes(buf[2..4].try_into()?) as usize + 20)
     }
 }
@@ -1,3 +1,5 @@
+use std::borrow::Cow;
+
 use crc::{Crc, CRC_32_ISO_HDLC};
 use hmac::{digest::CtOutput, Hmac, Mac};
 use md5::{Digest, Md5};
@@ -30,19 +32,53 @@ pub fn pad_size(size: usize) -> usize {
 ///
 /// > key = MD5(username ":" OpaqueString(realm) ":" OpaqueString(password))
 ///
+/// The username, the realm and the password are prepared with SASLprep, as
+/// RFC 8489 §9.2.2 and §14.3 require of the three of them, so that the
+/// clients with non-ASCII credentials derive the same key whatever the
+/// Unicode normalization of their input is. The strings that SASLprep
+/// rejects, for example those with control characters, have no prepared
+/// form that the client could have used either, they are hashed as is so
+/// that a key can still be derived for them.
+///
+/// Since 2.0 the credentials are prepared, the keys of the non-ASCII
+/// credentials differ from the keys of 1.x, which hashed the raw bytes.
+///
 /// ```
+/// use mycrl_stun::util::long_term_credential_digest;
+///
 /// let buffer = [
 ///     0x3eu8, 0x2f, 0x79, 0x1e, 0x1f, 0x14, 0xd1, 0x73, 0xfc, 0x91, 0xff,
 ///     0x2f, 0x59, 0xb5, 0x0f, 0xd1,
 /// ];
 ///
-/// let key =
-///     mycrl_stun::util::long_term_credential_digest("panda", "panda", "raspberry");
+/// let key = long_term_credential_digest("panda", "panda", "raspberry");
 /// assert_eq!(key, buffer);
+///
+/// // MD5("user:realm:p\u{e4}ss")
+/// let buffer = [
+///     0x26u8, 0x12, 0xa2, 0x32, 0x60, 0xfa, 0xea, 0x53, 0xf7, 0x82, 0xe7,
+///     0xe8, 0x0a, 0x4e, 0x3c, 0x2f,
+/// ];
+///
+/// // precomposed, decomposed and with a soft hyphen, which is mapped to nothing.
+/// for password in ["p\u{e4}ss", "pa\u{308}ss", "p\u{e4}\u{ad}ss"] {
+///     let key = long_term_credential_digest("user", password, "realm");
+///     assert_eq!(key, buffer);
+/// }
+///
+/// // The prepared and the unprepared inputs derive the same key.
+/// assert_eq!(
+///     long_term_credential_digest("u\u{308}ser", "pa\u{308}ss", "realm"),
+///     long_term_credential_digest("\u{fc}ser", "p\u{e4}ss", "realm"),
+/// );
 /// ```
 pub fn long_term_credential_digest(username: &str, password: &str, realm: &str) -> [u8; 16] {
+    let username = stringprep::saslprep(username).unwrap_or(Cow::Borrowed(username));
+    let realm = stringprep::saslprep(realm).unwrap_or(Cow::Borrowed(realm));
+    let password = stringprep::saslprep(password).unwrap_or(Cow::Borrowed(password));
+
     let mut hasher = Md5::new();
-    hasher.update([username, realm, password].join(":"));
+    hasher.update([&*username, &realm, &password].join(":"));
     hasher.finalize().into()
 }
 
@@ -89,6 +125,33 @@ pub fn hmac_sha1(key: &[u8], source: &[&[u8]]) -> Result<CtOutput<Hmac<sha1::Sha
     }
 }
 
+/// Compare two byte slices in constant time.
+///
+/// The comparison does not stop at the first differing byte, so that the
+/// time taken does not leak how much of a secret value was guessed right.
+///
+/// # Test
+///
+/// ```
+/// use mycrl_stun::util::constant_time_eq;
+///
+/// assert!(constant_time_eq(b"secret", b"secret"));
+/// assert!(!constant_time_eq(b"secret", b"secreT"));
+/// assert!(!constant_time_eq(b"secret", b"secrets"));
+/// ```
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let diff = a
+        .iter()
+        .zip(b)
+        .fold(0u8, |diff, (x, y)| std::hint::black_box(diff | (x ^ y)));
+
+    diff == 0
+}
+
 /// CRC32 Fingerprint.
 ///
 /// # Test
@@ -1,35 +1,55 @@
 #[cfg(test)]
 mod tests {
-    use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
+    use std::{
+        collections::HashMap,
+        net::{IpAddr, SocketAddr},
+        path::Path,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::{Duration, Instant},
+    };
 
-    use anyhow::{ensure, Result};
+    use anyhow::{bail, ensure, Context, Result};
     use async_trait::async_trait;
     use base64::{prelude::BASE64_STANDARD, Engine};
-    use bytes::BytesMut;
+    use bytes::{BufMut, BytesMut};
     use stun::{
         attribute::{
-            ChannelNumber, Data, ErrorCode, ErrorKind, Lifetime, MappedAddress, Nonce, Realm,
-            ReqeestedTransport, ResponseOrigin, Transport, UserName, XorMappedAddress,
-            XorPeerAddress, XorRelayedAddress,
+            AlternateServer, Change, ChangeRequest, ChannelNumber, Data, ErrorCode, ErrorKind,
+            EvenPort, Lifetime, MappedAddress, MobilityTicket, Nonce, OtherAddress, Realm,
+            ReqeestedTransport, ReservationToken, ResponseOrigin, ResponsePort, Software,
+            Transport, UserName, XorMappedAddress, XorPeerAddress, XorRelayedAddress,
         },
+        util::long_term_credential_digest,
         ChannelData, Decoder, Kind, MessageReader, MessageWriter, Method, Payload,
     };
     use turn_driver::{
-        start_hooks_server, Controller, Events, Hooks, SessionAddr, Transport as DriverTransport,
+        start_hooks_server, Controller, Events, Hooks, Policies, SessionAddr,
+        Transport as DriverTransport,
     };
 
     use once_cell::sync::Lazy;
     use rand::seq::SliceRandom;
     use tokio::{
-        net::UdpSocket,
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpStream, UdpSocket, UnixStream},
         time::{sleep, timeout},
     };
 
     use turn_server::{
-        config::{Api, Auth, Config, Interface, Log, Transport as TurnTransport, Turn},
+        config::{
+            Api, Auth, Config, Interface, Listener, Log, PortChange, RelayFamily, StandardPorts,
+            Transport as TurnTransport, Turn, UnixInterface, WebSocketInterface,
+        },
         startup,
+        statistics::{prometheus::render_openmetrics, Statistics, Stats},
+        websocket,
     };
 
+    use turn::memory::MemoryTransport;
+
     static TOKEN: Lazy<[u8; 12]> = Lazy::new(|| {
         let mut rng = rand::thread_rng();
         let mut token = [0u8; 12];
@@ -38,74 +58,204 @@ mod tests {
     });
 
     pub async fn create_turn_server(bind: SocketAddr, auth: Auth, api: Api) -> Result<()> {
+        create_turn_server_with_config(Config {
+            log: Log::default(),
+            turn: Turn {
+                realm: "localhost".to_string(),
+                interfaces: vec![Interface {
+                    transport: TurnTransport::UDP,
+                    other_address: None,
+                    relay_addresses: Vec::new(),
+                    listener: Default::default(),
+                    external: bind,
+                    bind,
+                }],
+                ..Default::default()
+            },
+            auth,
+            api,
+        })
+        .await
+    }
+
+    pub async fn create_turn_server_with_config(config: Config) -> Result<()> {
         tokio::spawn(async move {
-            startup(Arc::new(Config {
-                log: Log::default(),
-                turn: Turn {
-                    realm: "localhost".to_string(),
-                    interfaces: vec![Interface {
-                        transport: TurnTransport::UDP,
-                        external: bind,
-                        bind,
-                    }],
-                },
-                auth,
-                api,
-            }))
-            .await
-            .unwrap();
+            startup(Arc::new(config)).await.unwrap();
         });
 
         sleep(Duration::from_secs(3)).await;
         Ok(())
     }
 
+    enum Socket {
+        Udp(UdpSocket),
+        Tcp(TcpStream, BytesMut),
+        /// The messages are framed with the address of the client.
+        Unix(UnixStream, BytesMut, SocketAddr),
+    }
+
     struct Operationer {
         decoder: Decoder,
-        socket: UdpSocket,
+        socket: Socket,
         recv_bytes: [u8; 1500],
         send_bytes: BytesMut,
     }
 
     impl Operationer {
         async fn new(server: SocketAddr) -> Result<Self> {
-            let socket = UdpSocket::bind("127.0.0.1:0").await?;
+            Self::new_bind(
+                server,
+                if server.is_ipv4() {
+                    "127.0.0.1:0"
+                } else {
+                    "[::1]:0"
+                }
+                .parse()?,
+            )
+            .await
+        }
+
+        async fn new_bind(server: SocketAddr, bind: SocketAddr) -> Result<Self> {
+            let socket = UdpSocket::bind(bind).await?;
             socket.connect(server).await?;
 
-            Ok(Self {
+            Ok(Self::with_socket(Socket::Udp(socket)))
+        }
+
+        async fn new_tcp(server: SocketAddr) -> Result<Self> {
+            let socket = TcpStream::connect(server).await?;
+            Ok(Self::with_socket(Socket::Tcp(
+                socket,
+                BytesMut::with_capacity(4096),
+            )))
+        }
+
+        async fn new_unix(path: &Path, client: SocketAddr) -> Result<Self> {
+            let socket = UnixStream::connect(path).await?;
+            Ok(Self::with_socket(Socket::Unix(
+                socket,
+                BytesMut::with_capacity(4096),
+                client,
+            )))
+        }
+
+        fn with_socket(socket: Socket) -> Self {
+            Self {
                 send_bytes: BytesMut::with_capacity(1500),
                 decoder: Decoder::default(),
                 recv_bytes: [0u8; 1500],
                 socket,
-            })
+            }
         }
 
         fn local_addr(&self) -> Result<SocketAddr> {
-            Ok(self.socket.local_addr()?)
+            Ok(match &self.socket {
+                Socket::Udp(socket) => socket.local_addr()?,
+                Socket::Tcp(socket, _) => socket.local_addr()?,
+                Socket::Unix(_, _, client) => *client,
+            })
         }
 
-        fn create_message(&mut self, method: Method) -> MessageWriter {
+        fn create_message(&mut self, method: Method) -> MessageWriter<'_> {
             MessageWriter::new(method, &TOKEN, &mut self.send_bytes)
         }
 
         fn create_channel_data(&mut self, number: u16, bytes: &[u8]) {
             ChannelData { number, bytes }.encode(&mut self.send_bytes);
+
+            // The channel data must be padded to a multiple of 4 bytes over tcp.
+            if let Socket::Tcp(..) = self.socket {
+                let pad = self.send_bytes.len() % 4;
+                if pad > 0 {
+                    self.send_bytes.put_bytes(0, 4 - pad);
+                }
+            }
         }
 
-        async fn send(&self) -> Result<()> {
-            self.socket.send(&self.send_bytes).await?;
+        async fn send(&mut self) -> Result<()> {
+            match &mut self.socket {
+                Socket::Udp(socket) => {
+                    socket.send(&self.send_bytes).await?;
+                }
+                Socket::Tcp(socket, _) => socket.write_all(&self.send_bytes).await?,
+                Socket::Unix(socket, _, client) => {
+                    let mut frame = BytesMut::with_capacity(self.send_bytes.len() + 21);
+                    frame.put_u16(0);
+                    match client.ip() {
+                        IpAddr::V4(ip) => {
+                            frame.put_u8(4);
+                            frame.put_u16(client.port());
+                            frame.put_slice(&ip.octets());
+                        }
+                        IpAddr::V6(ip) => {
+                            frame.put_u8(6);
+                            frame.put_u16(client.port());
+                            frame.put_slice(&ip.octets());
+                        }
+                    }
+
+                    frame.put_slice(&self.send_bytes);
+
+                    let size = (frame.len() - 2) as u16;
+                    frame[..2].copy_from_slice(&size.to_be_bytes());
+                    socket.write_all(&frame).await?
+                }
+            }
+
             Ok(())
         }
 
-        async fn read_message(&mut self) -> Result<MessageReader> {
-            let size = timeout(
-                Duration::from_secs(1),
-                self.socket.recv(&mut self.recv_bytes),
-            )
-            .await??;
+        /// Receive a message, over tcp the stream is split into messages, and
+        /// the padding of the channel data is kept.
+        async fn recv(&mut self) -> Result<usize> {
+            match &mut self.socket {
+                Socket::Udp(socket) => Ok(socket.recv(&mut self.recv_bytes).await?),
+                Socket::Tcp(socket, buffer) => loop {
+                    if buffer.len() >= 4 {
+                        let size = Decoder::message_size(buffer, true)?;
+                        if size <= buffer.len() {
+                            self.recv_bytes[..size].copy_from_slice(&buffer.split_to(size));
+                            return Ok(size);
+                        }
+                    }
+
+                    if socket.read_buf(buffer).await? == 0 {
+                        return Err(anyhow::anyhow!("tcp socket closed"));
+                    }
+                },
+                Socket::Unix(socket, buffer, client) => loop {
+                    if buffer.len() >= 2 {
+                        let size = u16::from_be_bytes([buffer[0], buffer[1]]) as usize + 2;
+                        if size <= buffer.len() {
+                            let frame = buffer.split_to(size);
+                            let (ip, offset) = match frame[2] {
+                                4 => (IpAddr::from(<[u8; 4]>::try_from(&frame[5..9])?), 9),
+                                _ => (IpAddr::from(<[u8; 16]>::try_from(&frame[5..21])?), 21),
+                            };
+
+                            let port = u16::from_be_bytes([frame[3], frame[4]]);
+                            ensure!(SocketAddr::new(ip, port) == *client);
+
+                            let size = frame.len() - offset;
+                            self.recv_bytes[..size].copy_from_slice(&frame[offset..]);
+                            return Ok(size);
+                        }
+                    }
+
+                    if socket.read_buf(buffer).await? == 0 {
+                        return Err(anyhow::anyhow!("unix socket closed"));
+                    }
+                },
+            }
+        }
+
+        async fn read_message(&mut self) -> Result<MessageReader<'_>> {
+            let size = timeout(Duration::from_secs(1), self.recv()).await??;
 
             if let Payload::Message(message) = self.decoder.decode(&self.recv_bytes[..size])? {
-                if message.token != TOKEN.as_slice() {
+                // The indications of the server are new transactions, only the
+                // responses carry the token of the request.
+                if message.method != Method::DataIndication && message.token != TOKEN.as_slice() {
                     Err(anyhow::anyhow!("Message token does not match"))
                 } else {
                     Ok(message)
@@ -115,16 +265,15 @@ mod tests {
             }
         }
 
-        async fn read_channel_data(&mut self) -> Result<ChannelData> {
-            let size = timeout(
-                Duration::from_secs(1),
-                self.socket.recv(&mut self.recv_bytes),
-            )
-            .await??;
+        async fn read_channel_data(&mut self) -> Result<ChannelData<'_>> {
+            let size = timeout(Duration::from_secs(1), self.recv()).await??;
+            let is_udp = matches!(self.socket, Socket::Udp(_));
 
             if let Payload::ChannelData(channel_data) =
                 self.decoder.decode(&self.recv_bytes[..size])?
             {
+                // The channel data relayed over udp never carries the tcp padding.
+                ensure!(!is_udp || size == channel_data.bytes.len() + 4);
                 Ok(channel_data)
             } else {
                 Err(anyhow::anyhow!("payload not a channel data"))
@@ -161,8 +310,61 @@ mod tests {
             })
         }
 
+        pub async fn new_tcp(server: SocketAddr, credentials: Credentials) -> Result<Self> {
+            Ok(Self {
+                operationer: Operationer::new_tcp(server).await?,
+                state: State::default(),
+                credentials,
+                server,
+            })
+        }
+
+        /// Replace the tcp connection of the client with a new connection, the
+        /// credentials and the nonce of the client are kept.
+        pub async fn reconnect_tcp(&mut self) -> Result<()> {
+            self.operationer = Operationer::new_tcp(self.server).await?;
+            Ok(())
+        }
+
+        /// Replace the udp socket of the client with a new socket, as after a
+        /// NAT rebinding, the credentials and the nonce of the client are kept.
+        pub async fn reconnect_udp(&mut self) -> Result<()> {
+            self.operationer = Operationer::new(self.server).await?;
+            Ok(())
+        }
+
+        /// Create a udp client bound to the local address.
+        pub async fn new_bind(
+            server: SocketAddr,
+            bind: SocketAddr,
+            credentials: Credentials,
+        ) -> Result<Self> {
+            Ok(Self {
+                operationer: Operationer::new_bind(server, bind).await?,
+                state: State::default(),
+                credentials,
+                server,
+            })
+        }
+
+        /// Create a client behind a local proxy, which forwards the messages of
+        /// the client over the unix domain socket.
+        pub async fn new_unix(
+            path: &Path,
+            client: SocketAddr,
+            server: SocketAddr,
+            credentials: Credentials,
+        ) -> Result<Self> {
+            Ok(Self {
+                operationer: Operationer::new_unix(path, client).await?,
+                state: State::default(),
+                credentials,
+                server,
+            })
+        }
+
         pub fn local_addr(&self) -> Result<SocketAddr> {
-            Ok(self.operationer.local_addr()?)
+            self.operationer.local_addr()
         }
 
         pub async fn binding(&mut self) -> Result<()> {
@@ -185,13 +387,12 @@ mod tests {
             Ok(())
         }
 
-        pub async fn allocate(&mut self) -> Result<u16> {
+        pub async fn binding_auth(&mut self) -> Result<()> {
             {
                 {
                     let mut message = self
                         .operationer
-                        .create_message(Method::Allocate(Kind::Request));
-                    message.append::<ReqeestedTransport>(Transport::UDP);
+                        .create_message(Method::Binding(Kind::Request));
                     message.flush(None)?;
 
                     self.operationer.send().await?;
@@ -199,7 +400,7 @@ mod tests {
 
                 let message = self.operationer.read_message().await?;
 
-                ensure!(message.method == Method::Allocate(Kind::Error));
+                ensure!(message.method == Method::Binding(Kind::Error));
                 ensure!(message.get::<ErrorCode>().unwrap().code == ErrorKind::Unauthorized as u16);
 
                 self.state.nonce = message.get::<Nonce>().unwrap().to_string();
@@ -211,11 +412,121 @@ mod tests {
                 );
             }
 
+            {
+                let mut message = self
+                    .operationer
+                    .create_message(Method::Binding(Kind::Request));
+                message.append::<UserName>(&self.credentials.username);
+                message.append::<Realm>(&self.state.realm);
+                message.append::<Nonce>(&self.state.nonce);
+                message.flush(Some(&self.state.digest))?;
+
+                self.operationer.send().await?;
+            }
+
+            let local_addr = self.operationer.local_addr()?;
+            let message = self.operationer.read_message().await?;
+
+            ensure!(message.method == Method::Binding(Kind::Response));
+            message.integrity(&self.state.digest)?;
+
+            ensure!(message.get::<XorMappedAddress>() == Some(local_addr));
+            Ok(())
+        }
+
+        pub async fn binding_software(&mut self, software: Option<&str>) -> Result<Option<String>> {
+            {
+                let mut message = self
+                    .operationer
+                    .create_message(Method::Binding(Kind::Request));
+                if let Some(software) = software {
+                    message.append::<Software>(software);
+                }
+
+                message.flush(None)?;
+
+                self.operationer.send().await?;
+            }
+
+            let message = self.operationer.read_message().await?;
+
+            ensure!(message.method == Method::Binding(Kind::Response));
+            Ok(message.get::<Software>().map(|it| it.to_string()))
+        }
+
+        pub async fn binding_other_address(&mut self) -> Result<Option<SocketAddr>> {
+            {
+                let mut message = self
+                    .operationer
+                    .create_message(Method::Binding(Kind::Request));
+                message.flush(None)?;
+
+                self.operationer.send().await?;
+            }
+
+            let message = self.operationer.read_message().await?;
+
+            ensure!(message.method == Method::Binding(Kind::Response));
+            Ok(message.get::<OtherAddress>())
+        }
+
+        /// Get the challenge of the server, returns the error code if the
+        /// request is rejected with another error.
+        async fn allocate_challenge(&mut self) -> Result<Result<(), u16>> {
+            {
+                let mut message = self
+                    .operationer
+                    .create_message(Method::Allocate(Kind::Request));
+                message.append::<ReqeestedTransport>(Transport::UDP);
+                message.flush(None)?;
+
+                self.operationer.send().await?;
+            }
+
+            let message = self.operationer.read_message().await?;
+
+            ensure!(message.method == Method::Allocate(Kind::Error));
+
+            let code = message.get::<ErrorCode>().unwrap().code;
+            if code != ErrorKind::Unauthorized as u16 {
+                return Ok(Err(code));
+            }
+
+            self.state.nonce = message.get::<Nonce>().unwrap().to_string();
+            self.state.realm = message.get::<Realm>().unwrap().to_string();
+            self.state.digest = stun::util::long_term_credential_digest(
+                &self.credentials.username,
+                &self.credentials.password,
+                &self.state.realm,
+            );
+
+            Ok(Ok(()))
+        }
+
+        /// Allocate with the EVEN-PORT or the RESERVATION-TOKEN attribute,
+        /// returns the error code if the allocation is rejected.
+        pub async fn allocate_reservation(
+            &mut self,
+            even_port: Option<bool>,
+            token: Option<u64>,
+        ) -> Result<Result<(u16, Option<u64>), u16>> {
+            if let Err(code) = self.allocate_challenge().await? {
+                return Ok(Err(code));
+            }
+
             {
                 let mut message = self
                     .operationer
                     .create_message(Method::Allocate(Kind::Request));
                 message.append::<ReqeestedTransport>(Transport::UDP);
+                if let Some(reserve) = even_port {
+                    message.append::<EvenPort>(reserve);
+                }
+
+                if let Some(token) = token {
+                    message.append::<ReservationToken>(token);
+                }
+
                 message.append::<UserName>(&self.credentials.username);
                 message.append::<Realm>(&self.state.realm);
                 message.append::<Nonce>(&self.state.nonce);
@@ -224,26 +535,104 @@ mod tests {
                 self.operationer.send().await?;
             }
 
-            let local_addr = self.operationer.local_addr()?;
             let message = self.operationer.read_message().await?;
+            if message.method == Method::Allocate(Kind::Error) {
+                return Ok(Err(message.get::<ErrorCode>().unwrap().code));
+            }
 
             ensure!(message.method == Method::Allocate(Kind::Response));
             message.integrity(&self.state.digest)?;
 
             let relay = message.get::<XorRelayedAddress>().unwrap();
+            Ok(Ok((relay.port(), message.get::<ReservationToken>())))
+        }
+
+        pub async fn allocate(&mut self) -> Result<u16> {
+            let relay = self.allocate_relay().await?;
 
             ensure!(relay.ip() == self.server.ip());
+
+            Ok(relay.port())
+        }
+
+        /// Allocate and return the relayed address, which may be any of the
+        /// relay addresses of the server.
+        pub async fn allocate_relay(&mut self) -> Result<SocketAddr> {
+            if let Err(code) = self.allocate_challenge().await? {
+                bail!("allocate rejected: code={}", code);
+            }
+
+            {
+                let mut message = self
+                    .operationer
+                    .create_message(Method::Allocate(Kind::Request));
+                message.append::<ReqeestedTransport>(Transport::UDP);
+                message.append::<UserName>(&self.credentials.username);
+                message.append::<Realm>(&self.state.realm);
+                message.append::<Nonce>(&self.state.nonce);
+                message.flush(Some(&self.state.digest))?;
+
+                self.operationer.send().await?;
+            }
+
+            let local_addr = self.operationer.local_addr()?;
+            let message = self.operationer.read_message().await?;
+
+            ensure!(message.method == Method::Allocate(Kind::Response));
+            message.integrity(&self.state.digest)?;
+
+            let relay = message.get::<XorRelayedAddress>().unwrap();
+
             ensure!(message.get::<XorMappedAddress>() == Some(local_addr));
             ensure!(message.get::<Lifetime>() == Some(600));
 
-            Ok(relay.port())
+            Ok(relay)
         }
 
-        pub async fn create_permission(&mut self, port: u16) -> Result<()> {
+        /// Send the request without the credential, the response must be the
+        /// challenge carrying both the realm and the nonce.
+        pub async fn challenge(&mut self, method: Method, port: u16) -> Result<()> {
+            let mut peer = self.server;
+            peer.set_port(port);
+
             {
-                let mut peer = self.server.clone();
-                peer.set_port(port);
+                let mut message = self.operationer.create_message(method);
+                match method {
+                    Method::Allocate(_) => {
+                        message.append::<ReqeestedTransport>(Transport::UDP);
+                    }
+                    Method::CreatePermission(_) => {
+                        message.append::<XorPeerAddress>(peer);
+                    }
+                    Method::ChannelBind(_) => {
+                        message.append::<ChannelNumber>(0x4000);
+                        message.append::<XorPeerAddress>(peer);
+                    }
+                    _ => (),
+                }
 
+                message.flush(None)?;
+                self.operationer.send().await?;
+            }
+
+            let message = self.operationer.read_message().await?;
+
+            ensure!(message.method.is_error());
+            ensure!(message.get::<ErrorCode>().unwrap().code == ErrorKind::Unauthorized as u16);
+            ensure!(message.get::<Realm>() == Some(self.state.realm.as_str()));
+            ensure!(message.get::<Nonce>() == Some(self.state.nonce.as_str()));
+            Ok(())
+        }
+
+        pub async fn create_permission(&mut self, port: u16) -> Result<()> {
+            let mut peer = self.server;
+            peer.set_port(port);
+
+            self.create_permission_peer(peer).await
+        }
+
+        pub async fn create_permission_peer(&mut self, peer: SocketAddr) -> Result<()> {
+            {
                 let mut message = self
                     .operationer
                     .create_message(Method::CreatePermission(Kind::Request));
@@ -266,7 +655,7 @@ mod tests {
 
         pub async fn channel_bind(&mut self, port: u16, channel: u16) -> Result<()> {
             {
-                let mut peer = self.server.clone();
+                let mut peer = self.server;
                 peer.set_port(port);
 
                 let mut message = self
@@ -314,10 +703,69 @@ mod tests {
             Ok(())
         }
 
+        /// Refresh with a nonce that is no longer valid, the response must be
+        /// the 438 (Stale Nonce) error carrying a fresh nonce, which is used
+        /// by the subsequent requests.
+        pub async fn refresh_stale_nonce(&mut self, lifetime: u32) -> Result<()> {
+            {
+                let mut message = self
+                    .operationer
+                    .create_message(Method::Refresh(Kind::Request));
+                message.append::<Lifetime>(lifetime);
+                message.append::<UserName>(&self.credentials.username);
+                message.append::<Realm>(&self.state.realm);
+                message.append::<Nonce>(&self.state.nonce);
+                message.flush(Some(&self.state.digest))?;
+
+                self.operationer.send().await?;
+            }
+
+            let message = self.operationer.read_message().await?;
+
+            ensure!(message.method == Method::Refresh(Kind::Error));
+            ensure!(message.get::<ErrorCode>().unwrap().code == ErrorKind::StaleNonce as u16);
+            ensure!(message.get::<Realm>() == Some(self.state.realm.as_str()));
+
+            let nonce = message.get::<Nonce>().unwrap();
+            ensure!(nonce != self.state.nonce);
+
+            self.state.nonce = nonce.to_string();
+            Ok(())
+        }
+
+        /// Send a message of a method that the server does not implement, and
+        /// get the message type of the response, if there is one.
+        pub async fn unknown_method(&mut self, kind: u16) -> Result<Option<u16>> {
+            {
+                let bytes = &mut self.operationer.send_bytes;
+                bytes.clear();
+                bytes.put_u16(kind);
+                bytes.put_u16(0);
+                bytes.put_u32(0x2112a442);
+                bytes.put_slice(TOKEN.as_slice());
+
+                self.operationer.send().await?;
+            }
+
+            let size = match timeout(Duration::from_millis(500), self.operationer.recv()).await {
+                Ok(size) => size?,
+                Err(_) => return Ok(None),
+            };
+
+            let bytes = &self.operationer.recv_bytes[..size];
+            ensure!(size >= 20 && bytes[8..20] == TOKEN[..]);
+
+            Ok(Some(u16::from_be_bytes([bytes[0], bytes[1]])))
+        }
+
         pub async fn send_indication(&mut self, port: u16, data: &[u8]) -> Result<()> {
-            let mut peer = self.server.clone();
+            let mut peer = self.server;
             peer.set_port(port);
 
+            self.send_indication_peer(peer, data).await
+        }
+
+        pub async fn send_indication_peer(&mut self, peer: SocketAddr, data: &[u8]) -> Result<()> {
             let mut message = self.operationer.create_message(Method::SendIndication);
             message.append::<XorPeerAddress>(peer);
             message.append::<Data>(data);
@@ -328,13 +776,18 @@ mod tests {
         }
 
         pub async fn recv_indication(&mut self) -> Result<(u16, &[u8])> {
-            let message = self.operationer.read_message().await?;
+            let (peer, data) = self.recv_indication_peer().await?;
+            Ok((peer.port(), data))
+        }
+
+        pub async fn recv_indication_peer(&mut self) -> Result<(SocketAddr, &[u8])> {
+            let message = self.operationer.read_message().await?;
 
             ensure!(message.method == Method::DataIndication);
 
             let peer = message.get::<XorPeerAddress>().unwrap();
             let data = message.get::<Data>().unwrap();
-            Ok((peer.port(), data))
+            Ok((peer, data))
         }
 
         pub async fn send_channel_data(&mut self, channel: u16, data: &[u8]) -> Result<()> {
@@ -423,6 +876,7 @@ mod tests {
                     let session = get_session(session, username.to_string()).await;
                     assert!(session.expires >= *lifetime && session.expires <= lifetime + 10);
                 }
+                Events::Evicted { .. } => (),
                 Events::Closed { session, .. } => {
                     assert!(self.0.get_session(session).await.is_none());
                 }
@@ -430,6 +884,31 @@ mod tests {
         }
     }
 
+    /// Records the maximum number of the concurrent password requests.
+    #[derive(Default, Clone)]
+    struct ConcurrencyHooks {
+        current: Arc<AtomicUsize>,
+        max: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl Hooks for ConcurrencyHooks {
+        async fn auth(
+            &self,
+            _addr: &SessionAddr,
+            _username: &str,
+            _realm: &str,
+            _nonce: &str,
+        ) -> Option<&str> {
+            let current = self.current.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max.fetch_max(current, Ordering::SeqCst);
+
+            sleep(Duration::from_millis(50)).await;
+            self.current.fetch_sub(1, Ordering::SeqCst);
+            Some("hooks")
+        }
+    }
+
     #[tokio::test]
     async fn turn_static_auth_secret_testing() -> Result<()> {
         create_turn_server(
@@ -437,10 +916,12 @@ mod tests {
             Auth {
                 static_auth_secret: Some("static_auth_secret".to_string()),
                 static_credentials: HashMap::with_capacity(1),
+                ..Default::default()
             },
             Api {
                 bind: "127.0.0.1:3001".parse()?,
                 hooks: None,
+                ..Default::default()
             },
         )
         .await?;
@@ -459,327 +940,4064 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn turn_server_testing() -> Result<()> {
-        let controller = Arc::new(Controller::new("http://127.0.0.1:3000")?);
-        {
-            tokio::spawn(start_hooks_server(
-                "127.0.0.1:8088".parse()?,
-                HooksImpl(controller.clone()),
-            ));
+    async fn turn_static_auth_secret_timestamp_testing() -> Result<()> {
+        let bind = "127.0.0.1:3517".parse()?;
+        create_turn_server_with_config(Config {
+            log: Log::default(),
+            turn: Turn {
+                realm: "localhost".to_string(),
+                interfaces: vec![Interface {
+                    transport: TurnTransport::UDP,
+                    other_address: None,
+                    relay_addresses: Vec::new(),
+                    listener: Default::default(),
+                    external: bind,
+                    bind,
+                }],
+                ..Default::default()
+            },
+            auth: Auth {
+                static_auth_secret: Some("static_auth_secret".to_string()),
+                verify_timestamp: true,
+                ..Default::default()
+            },
+            api: Api {
+                bind: "127.0.0.1:3034".parse()?,
+                hooks: None,
+                ..Default::default()
+            },
+        })
+        .await?;
 
-            sleep(Duration::from_secs(3)).await;
-        }
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs();
+
+        let credentials = |username: String| -> Result<Credentials> {
+            Ok(Credentials {
+                password: encode_password(&username, "static_auth_secret")?,
+                username,
+            })
+        };
+
+        // The credential is valid until the timestamp of the username.
+        let mut turn = TurnClient::new(bind, credentials(format!("{}:user", now + 3600))?).await?;
+        turn.allocate().await?;
+
+        // The expired credential and the username without a timestamp are
+        // rejected, although their password is derived from the secret.
+        let mut turn = TurnClient::new(bind, credentials(format!("{}:user", now - 60))?).await?;
+        assert!(turn.allocate().await.is_err());
+
+        let mut turn = TurnClient::new(bind, credentials("user".to_string())?).await?;
+        assert!(turn.allocate().await.is_err());
+        Ok(())
+    }
 
+    #[tokio::test]
+    async fn turn_binding_software_testing() -> Result<()> {
         create_turn_server(
-            "127.0.0.1:3478".parse()?,
-            Auth {
-                static_auth_secret: None,
-                static_credentials: {
-                    let mut it = HashMap::with_capacity(1);
-                    it.insert(
-                        "static_credentials".to_string(),
-                        "static_credentials".to_string(),
-                    );
-                    it
-                },
-            },
-            {
-                let mut api = Api::default();
-                api.hooks = Some("http://127.0.0.1:8088".to_string());
-                api
+            "127.0.0.1:3480".parse()?,
+            Auth::default(),
+            Api {
+                bind: "127.0.0.1:3002".parse()?,
+                hooks: None,
+                ..Default::default()
             },
         )
         .await?;
 
+        let bind = "127.0.0.1:3481".parse()?;
+        create_turn_server_with_config(Config {
+            log: Log::default(),
+            turn: Turn {
+                realm: "localhost".to_string(),
+                interfaces: vec![Interface {
+                    transport: TurnTransport::UDP,
+                    other_address: None,
+                    relay_addresses: Vec::new(),
+                    listener: Default::default(),
+                    external: bind,
+                    bind,
+                }],
+                echo_software: true,
+                ..Default::default()
+            },
+            auth: Auth::default(),
+            api: Api {
+                bind: "127.0.0.1:3003".parse()?,
+                hooks: None,
+                ..Default::default()
+            },
+        })
+        .await?;
+
+        let credentials = || Credentials {
+            username: "software".to_string(),
+            password: "software".to_string(),
+        };
+
         {
-            let info = controller.get_info().await.unwrap().payload;
-            assert_eq!(info.port_allocated, 0);
-            assert_eq!(info.port_capacity, 16383);
+            let mut turn = TurnClient::new("127.0.0.1:3480".parse()?, credentials()).await?;
+            assert!(turn.binding_software(None).await?.is_some());
+            assert!(turn.binding_software(Some("client")).await?.is_some());
+        }
 
-            let interface = info.interfaces.get(0).unwrap();
-            assert_eq!(interface.bind, "127.0.0.1:3478".parse()?);
-            assert_eq!(interface.external, "127.0.0.1:3478".parse()?);
-            assert_eq!(interface.transport, DriverTransport::UDP);
+        {
+            let mut turn = TurnClient::new(bind, credentials()).await?;
+            assert!(turn.binding_software(None).await?.is_none());
+            assert!(turn.binding_software(Some("client")).await?.is_some());
         }
 
-        let mut turn_1 = TurnClient::new(
-            "127.0.0.1:3478".parse()?,
-            Credentials {
-                username: "static_credentials".to_string(),
-                password: "static_credentials".to_string(),
-            },
-        )
-        .await?;
+        Ok(())
+    }
 
-        let mut turn_2 = TurnClient::new(
-            "127.0.0.1:3478".parse()?,
-            Credentials {
-                username: "static_credentials".to_string(),
-                password: "static_credentials".to_string(),
+    #[tokio::test]
+    async fn turn_binding_auth_testing() -> Result<()> {
+        let auth = || Auth {
+            static_auth_secret: None,
+            static_credentials: {
+                let mut it = HashMap::with_capacity(1);
+                it.insert("binding".to_string(), "binding".to_string());
+                it
             },
-        )
-        .await?;
+            ..Default::default()
+        };
 
-        let mut turn_3 = TurnClient::new(
-            "127.0.0.1:3478".parse()?,
-            Credentials {
-                username: "hooks".to_string(),
-                password: "hooks".to_string(),
+        let credentials = || Credentials {
+            username: "binding".to_string(),
+            password: "binding".to_string(),
+        };
+
+        create_turn_server(
+            "127.0.0.1:3482".parse()?,
+            auth(),
+            Api {
+                bind: "127.0.0.1:3004".parse()?,
+                hooks: None,
+                ..Default::default()
             },
         )
         .await?;
 
-        let mut turn_4 = TurnClient::new(
-            "127.0.0.1:3478".parse()?,
-            Credentials {
-                username: "hooks".to_string(),
-                password: "hooks".to_string(),
+        let bind = "127.0.0.1:3483".parse()?;
+        create_turn_server_with_config(Config {
+            log: Log::default(),
+            turn: Turn {
+                realm: "localhost".to_string(),
+                interfaces: vec![Interface {
+                    transport: TurnTransport::UDP,
+                    other_address: None,
+                    relay_addresses: Vec::new(),
+                    listener: Default::default(),
+                    external: bind,
+                    bind,
+                }],
+                binding_require_auth: true,
+                ..Default::default()
             },
-        )
+            auth: auth(),
+            api: Api {
+                bind: "127.0.0.1:3005".parse()?,
+                hooks: None,
+                ..Default::default()
+            },
+        })
         .await?;
 
         {
-            turn_1.binding().await?;
-            turn_2.binding().await?;
-            turn_3.binding().await?;
+            let mut turn = TurnClient::new("127.0.0.1:3482".parse()?, credentials()).await?;
+            turn.binding().await?;
+            assert!(turn.binding_auth().await.is_err());
+        }
+
+        {
+            let mut turn = TurnClient::new(bind, credentials()).await?;
+            assert!(turn.binding().await.is_err());
         }
 
+        {
+            let mut turn = TurnClient::new(bind, credentials()).await?;
+            turn.binding_auth().await?;
+            turn.allocate().await?;
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_inactivity_timeout_testing() -> Result<()> {
+        let bind = "127.0.0.1:3484".parse()?;
+        create_turn_server_with_config(Config {
+            log: Log::default(),
+            turn: Turn {
+                realm: "localhost".to_string(),
+                interfaces: vec![Interface {
+                    transport: TurnTransport::UDP,
+                    other_address: None,
+                    relay_addresses: Vec::new(),
+                    listener: Default::default(),
+                    external: bind,
+                    bind,
+                }],
+                inactivity_timeout: Some(2),
+                ..Default::default()
+            },
+            auth: Auth {
+                static_auth_secret: None,
+                static_credentials: {
+                    let mut it = HashMap::with_capacity(1);
+                    it.insert("inactivity".to_string(), "inactivity".to_string());
+                    it
+                },
+                ..Default::default()
+            },
+            api: Api {
+                bind: "127.0.0.1:3006".parse()?,
+                hooks: None,
+                ..Default::default()
+            },
+        })
+        .await?;
+
+        let credentials = || Credentials {
+            username: "inactivity".to_string(),
+            password: "inactivity".to_string(),
+        };
+
+        let mut turn_1 = TurnClient::new(bind, credentials()).await?;
+        let mut turn_2 = TurnClient::new(bind, credentials()).await?;
+
         let turn_1_port = turn_1.allocate().await?;
         let turn_2_port = turn_2.allocate().await?;
-        let turn_3_port = turn_3.allocate().await?;
-        let turn_4_port = turn_4.allocate().await?;
 
-        assert!(turn_1.allocate().await.is_err());
-        assert!(turn_2.allocate().await.is_err());
-        assert!(turn_3.allocate().await.is_err());
-        assert!(turn_4.allocate().await.is_err());
+        turn_1.create_permission(turn_2_port).await?;
+        turn_2.create_permission(turn_1_port).await?;
+        turn_1.refresh(600).await?;
+        turn_2.refresh(600).await?;
+
+        sleep(Duration::from_secs(3)).await;
 
         {
-            turn_1.create_permission(turn_2_port).await?;
-            turn_1.create_permission(turn_3_port).await?;
-            turn_1.create_permission(turn_4_port).await?;
-            turn_1.channel_bind(turn_2_port, 0x4000).await?;
-            turn_1.channel_bind(turn_3_port, 0x4001).await?;
-            turn_1.channel_bind(turn_4_port, 0x4002).await?;
-            turn_1.refresh(600).await?;
+            let data = "keep alive".as_bytes();
+            turn_1.send_indication(turn_2_port, data).await?;
+            turn_2.recv_indication().await?;
+        }
 
-            turn_2.create_permission(turn_1_port).await?;
-            turn_2.create_permission(turn_3_port).await?;
-            turn_2.channel_bind(turn_1_port, 0x4000).await?;
-            turn_2.channel_bind(turn_3_port, 0x4002).await?;
-            turn_2.refresh(600).await?;
+        turn_1.refresh(600).await?;
+        assert!(turn_2.refresh(600).await.is_err());
+        Ok(())
+    }
 
-            turn_3.create_permission(turn_1_port).await?;
-            turn_3.create_permission(turn_2_port).await?;
-            turn_3.channel_bind(turn_1_port, 0x4001).await?;
-            turn_3.channel_bind(turn_2_port, 0x4002).await?;
-            turn_3.refresh(600).await?;
+    #[tokio::test]
+    async fn turn_other_address_testing() -> Result<()> {
+        let primary: SocketAddr = "127.0.0.1:3485".parse()?;
+        let alternate: SocketAddr = "127.0.0.1:3486".parse()?;
+        let api: SocketAddr = "127.0.0.1:3007".parse()?;
+        let config = |other_address| Config {
+            log: Log::default(),
+            turn: Turn {
+                realm: "localhost".to_string(),
+                interfaces: vec![
+                    Interface {
+                        transport: TurnTransport::UDP,
+                        other_address: Some(other_address),
+                        relay_addresses: Vec::new(),
+                        listener: Default::default(),
+                        external: primary,
+                        bind: primary,
+                    },
+                    Interface {
+                        transport: TurnTransport::UDP,
+                        other_address: None,
+                        relay_addresses: Vec::new(),
+                        listener: Default::default(),
+                        external: alternate,
+                        bind: alternate,
+                    },
+                ],
+                ..Default::default()
+            },
+            auth: Auth::default(),
+            api: Api {
+                bind: api,
+                hooks: None,
+                ..Default::default()
+            },
+        };
 
-            turn_4.create_permission(turn_1_port).await?;
-            turn_4.channel_bind(turn_1_port, 0x4002).await?;
-            turn_4.refresh(600).await?;
+        assert!(startup(Arc::new(config("127.0.0.1:3487".parse()?)))
+            .await
+            .is_err());
+
+        create_turn_server_with_config(config(alternate)).await?;
 
-            assert!(turn_1.channel_bind(turn_2_port, 0x4000).await.is_err());
-            assert!(turn_1.channel_bind(turn_3_port, 0x4001).await.is_err());
-            assert!(turn_1.channel_bind(turn_4_port, 0x4002).await.is_err());
-            assert!(turn_2.channel_bind(turn_1_port, 0x4000).await.is_err());
-            assert!(turn_2.channel_bind(turn_3_port, 0x4002).await.is_err());
-            assert!(turn_3.channel_bind(turn_1_port, 0x4001).await.is_err());
-            assert!(turn_3.channel_bind(turn_2_port, 0x4002).await.is_err());
-            assert!(turn_4.channel_bind(turn_1_port, 0x4002).await.is_err());
+        let credentials = || Credentials {
+            username: "other".to_string(),
+            password: "other".to_string(),
+        };
+
+        {
+            let mut turn = TurnClient::new(primary, credentials()).await?;
+            assert_eq!(turn.binding_other_address().await?, Some(alternate));
         }
 
         {
-            let data = "1 forwards to 2,3,4 channel data".as_bytes();
-            turn_1.send_channel_data(0x4000, data).await?;
-            let ret = turn_2.recv_channel_data().await?;
-            assert_eq!(ret.0, 0x4000);
-            assert_eq!(ret.1, data);
+            let mut turn = TurnClient::new(alternate, credentials()).await?;
+            assert_eq!(turn.binding_other_address().await?, None);
+        }
 
-            turn_1.send_channel_data(0x4001, data).await?;
-            let ret = turn_3.recv_channel_data().await?;
-            assert_eq!(ret.0, 0x4001);
-            assert_eq!(ret.1, data);
+        Ok(())
+    }
 
-            turn_1.send_channel_data(0x4002, data).await?;
-            let ret = turn_4.recv_channel_data().await?;
-            assert_eq!(ret.0, 0x4002);
-            assert_eq!(ret.1, data);
-        }
+    #[tokio::test]
+    async fn turn_standard_interfaces_testing() -> Result<()> {
+        let bind: SocketAddr = "127.0.0.1:3488".parse()?;
+        create_turn_server_with_config(Config {
+            log: Log::default(),
+            turn: Turn {
+                realm: "localhost".to_string(),
+                interfaces: Interface::standard(
+                    bind.ip(),
+                    bind.ip(),
+                    StandardPorts {
+                        udp: bind.port(),
+                        tcp: bind.port(),
+                    },
+                ),
+                ..Default::default()
+            },
+            auth: Auth::default(),
+            api: Api {
+                bind: "127.0.0.1:3008".parse()?,
+                hooks: None,
+                ..Default::default()
+            },
+        })
+        .await?;
 
-        {
-            let data = "2 forwards to 1,3 channel data".as_bytes();
-            turn_2.send_channel_data(0x4000, data).await?;
-            let ret = turn_1.recv_channel_data().await?;
-            assert_eq!(ret.0, 0x4000);
-            assert_eq!(ret.1, data);
-            assert!(turn_3.recv_channel_data().await.is_err());
-            assert!(turn_4.recv_channel_data().await.is_err());
+        let mut client = TurnClient::new(
+            bind,
+            Credentials {
+                username: "user".to_string(),
+                password: "user".to_string(),
+            },
+        )
+        .await?;
 
-            turn_2.send_channel_data(0x4002, data).await?;
-            let ret = turn_3.recv_channel_data().await?;
-            assert_eq!(ret.0, 0x4002);
-            assert_eq!(ret.1, data);
-            assert!(turn_1.recv_channel_data().await.is_err());
-            assert!(turn_4.recv_channel_data().await.is_err());
+        client.binding().await?;
+        Ok(())
+    }
 
-            turn_2.send_channel_data(0x4001, data).await?;
-            assert!(turn_1.recv_channel_data().await.is_err());
-            assert!(turn_3.recv_channel_data().await.is_err());
-            assert!(turn_4.recv_channel_data().await.is_err());
+    #[tokio::test]
+    async fn turn_dual_stack_relay_testing() -> Result<()> {
+        let v4: SocketAddr = "127.0.0.1:3489".parse()?;
+        let v6: SocketAddr = "[::1]:3489".parse()?;
+        create_turn_server_with_config(Config {
+            log: Log::default(),
+            turn: Turn {
+                realm: "localhost".to_string(),
+                interfaces: [v4, v6]
+                    .into_iter()
+                    .map(|it| Interface {
+                        transport: TurnTransport::UDP,
+                        other_address: None,
+                        relay_addresses: Vec::new(),
+                        listener: Default::default(),
+                        external: it,
+                        bind: it,
+                    })
+                    .collect(),
+                ..Default::default()
+            },
+            auth: Auth {
+                static_auth_secret: None,
+                static_credentials: {
+                    let mut it = HashMap::with_capacity(1);
+                    it.insert("user".to_string(), "user".to_string());
+                    it
+                },
+                ..Default::default()
+            },
+            api: Api {
+                bind: "127.0.0.1:3009".parse()?,
+                hooks: None,
+                ..Default::default()
+            },
+        })
+        .await?;
+
+        let mut clients = Vec::with_capacity(4);
+        for server in [v4, v4, v6, v6] {
+            clients.push(
+                TurnClient::new(
+                    server,
+                    Credentials {
+                        username: "user".to_string(),
+                        password: "user".to_string(),
+                    },
+                )
+                .await?,
+            );
+        }
+
+        let mut ports = Vec::with_capacity(4);
+        for client in clients.iter_mut() {
+            ports.push(client.allocate().await?);
         }
 
+        // The peer must use the family of the relay of the client, and the
+        // family of the relay of the peer itself.
+        let v4_relay = SocketAddr::new(v4.ip(), ports[0]);
+        let v6_relay = SocketAddr::new(v6.ip(), ports[2]);
+        assert!(clients[2].create_permission_peer(v4_relay).await.is_err());
+        assert!(clients[0].create_permission_peer(v6_relay).await.is_err());
+        assert!(clients[2]
+            .create_permission_peer(SocketAddr::new(v6.ip(), ports[0]))
+            .await
+            .is_err());
+
+        clients[1].create_permission_peer(v4_relay).await?;
+        clients[3].create_permission_peer(v6_relay).await?;
+
         {
-            let data = "3 forwards to 1,2 channel data".as_bytes();
-            turn_3.send_channel_data(0x4001, data).await?;
-            let ret = turn_1.recv_channel_data().await?;
-            assert_eq!(ret.0, 0x4001);
+            let data = "relay to the ipv4 peer".as_bytes();
+            clients[0]
+                .send_indication_peer(SocketAddr::new(v4.ip(), ports[1]), data)
+                .await?;
+            let ret = clients[1].recv_indication().await?;
+            assert_eq!(ret.0, ports[0]);
             assert_eq!(ret.1, data);
-            assert!(turn_2.recv_channel_data().await.is_err());
-            assert!(turn_4.recv_channel_data().await.is_err());
+        }
 
-            turn_3.send_channel_data(0x4002, data).await?;
-            let ret = turn_2.recv_channel_data().await?;
-            assert_eq!(ret.0, 0x4002);
+        {
+            let data = "relay to the ipv6 peer".as_bytes();
+            clients[2]
+                .send_indication_peer(SocketAddr::new(v6.ip(), ports[3]), data)
+                .await?;
+            let ret = clients[3].recv_indication().await?;
+            assert_eq!(ret.0, ports[2]);
             assert_eq!(ret.1, data);
-            assert!(turn_1.recv_channel_data().await.is_err());
-            assert!(turn_4.recv_channel_data().await.is_err());
 
-            turn_3.send_channel_data(0x4000, data).await?;
-            assert!(turn_2.recv_channel_data().await.is_err());
-            assert!(turn_1.recv_channel_data().await.is_err());
-            assert!(turn_4.recv_channel_data().await.is_err());
+            clients[0]
+                .send_indication_peer(SocketAddr::new(v4.ip(), ports[3]), data)
+                .await?;
+            assert!(clients[3].recv_indication().await.is_err());
         }
 
-        {
-            let data = "4 forwards to 1 channel data".as_bytes();
-            turn_4.send_channel_data(0x4002, data).await?;
-            let ret = turn_1.recv_channel_data().await?;
-            assert_eq!(ret.0, 0x4002);
-            assert_eq!(ret.1, data);
-            assert!(turn_2.recv_channel_data().await.is_err());
-            assert!(turn_3.recv_channel_data().await.is_err());
+        Ok(())
+    }
 
-            turn_4.send_channel_data(0x4000, data).await?;
-            assert!(turn_1.recv_channel_data().await.is_err());
-            assert!(turn_2.recv_channel_data().await.is_err());
-            assert!(turn_3.recv_channel_data().await.is_err());
+    #[tokio::test]
+    async fn turn_tcp_channel_data_padding_testing() -> Result<()> {
+        let bind: SocketAddr = "127.0.0.1:3490".parse()?;
+        create_turn_server_with_config(Config {
+            log: Log::default(),
+            turn: Turn {
+                realm: "localhost".to_string(),
+                interfaces: [TurnTransport::UDP, TurnTransport::TCP]
+                    .into_iter()
+                    .map(|transport| Interface {
+                        other_address: None,
+                        relay_addresses: Vec::new(),
+                        listener: Default::default(),
+                        external: bind,
+                        transport,
+                        bind,
+                    })
+                    .collect(),
+                ..Default::default()
+            },
+            auth: Auth {
+                static_auth_secret: None,
+                static_credentials: {
+                    let mut it = HashMap::with_capacity(1);
+                    it.insert("user".to_string(), "user".to_string());
+                    it
+                },
+                ..Default::default()
+            },
+            api: Api {
+                bind: "127.0.0.1:3010".parse()?,
+                hooks: None,
+                ..Default::default()
+            },
+        })
+        .await?;
 
-            turn_4.send_channel_data(0x4001, data).await?;
-            assert!(turn_1.recv_channel_data().await.is_err());
-            assert!(turn_2.recv_channel_data().await.is_err());
-            assert!(turn_3.recv_channel_data().await.is_err());
-        }
+        let credentials = || Credentials {
+            username: "user".to_string(),
+            password: "user".to_string(),
+        };
 
-        {
-            let data = "1 forwards to 2,3,4".as_bytes();
-            turn_1.send_indication(turn_2_port, data).await?;
-            let ret = turn_2.recv_indication().await?;
-            assert_eq!(ret.0, turn_1_port);
-            assert_eq!(ret.1, data);
+        let mut udp = TurnClient::new(bind, credentials()).await?;
+        let mut tcp = TurnClient::new_tcp(bind, credentials()).await?;
 
-            turn_1.send_indication(turn_3_port, data).await?;
-            let ret = turn_3.recv_indication().await?;
-            assert_eq!(ret.0, turn_1_port);
+        let udp_port = udp.allocate().await?;
+        let tcp_port = tcp.allocate().await?;
+
+        udp.create_permission(tcp_port).await?;
+        udp.channel_bind(tcp_port, 0x4000).await?;
+        tcp.create_permission(udp_port).await?;
+        tcp.channel_bind(udp_port, 0x4000).await?;
+
+        // The lengths that require 3, 2, 1 and no bytes of padding.
+        for data in ["12345", "123456", "1234567", "12345678"] {
+            let data = data.as_bytes();
+
+            udp.send_channel_data(0x4000, data).await?;
+            let ret = tcp.recv_channel_data().await?;
+            assert_eq!(ret.0, 0x4000);
             assert_eq!(ret.1, data);
 
-            turn_1.send_indication(turn_4_port, data).await?;
-            let ret = turn_4.recv_indication().await?;
-            assert_eq!(ret.0, turn_1_port);
+            tcp.send_channel_data(0x4000, data).await?;
+            let ret = udp.recv_channel_data().await?;
+            assert_eq!(ret.0, 0x4000);
             assert_eq!(ret.1, data);
         }
 
-        {
-            let data = "2 forwards to 1,3".as_bytes();
-            turn_2.send_indication(turn_1_port, data).await?;
-            let ret = turn_1.recv_indication().await?;
-            assert_eq!(ret.0, turn_2_port);
-            assert_eq!(ret.1, data);
+        Ok(())
+    }
 
-            turn_2.send_indication(turn_3_port, data).await?;
-            let ret = turn_3.recv_indication().await?;
-            assert_eq!(ret.0, turn_2_port);
-            assert_eq!(ret.1, data);
+    #[tokio::test]
+    async fn turn_tcp_disconnect_grace_testing() -> Result<()> {
+        let bind: SocketAddr = "127.0.0.1:3503".parse()?;
+        create_turn_server_with_config(Config {
+            log: Log::default(),
+            turn: Turn {
+                realm: "localhost".to_string(),
+                interfaces: [TurnTransport::UDP, TurnTransport::TCP]
+                    .into_iter()
+                    .map(|transport| Interface {
+                        other_address: None,
+                        relay_addresses: Vec::new(),
+                        listener: Default::default(),
+                        external: bind,
+                        transport,
+                        bind,
+                    })
+                    .collect(),
+                tcp_disconnect_grace: Some(10),
+                ..Default::default()
+            },
+            auth: Auth {
+                static_auth_secret: None,
+                static_credentials: {
+                    let mut it = HashMap::with_capacity(1);
+                    it.insert("grace".to_string(), "grace".to_string());
+                    it
+                },
+                ..Default::default()
+            },
+            api: Api {
+                bind: "127.0.0.1:3023".parse()?,
+                hooks: None,
+                ..Default::default()
+            },
+        })
+        .await?;
 
-            turn_2.send_indication(turn_4_port, data).await?;
-            assert!(turn_4.recv_indication().await.is_err());
+        let credentials = || Credentials {
+            username: "grace".to_string(),
+            password: "grace".to_string(),
+        };
+
+        let mut udp = TurnClient::new(bind, credentials()).await?;
+        let mut tcp = TurnClient::new_tcp(bind, credentials()).await?;
+
+        let udp_port = udp.allocate().await?;
+        let tcp_port = tcp.allocate().await?;
+
+        udp.create_permission(tcp_port).await?;
+        udp.channel_bind(tcp_port, 0x4000).await?;
+        tcp.create_permission(udp_port).await?;
+        tcp.channel_bind(udp_port, 0x4000).await?;
+
+        // The network blip, the client reconnects from another port and
+        // takes over the allocation with its first authenticated request.
+        tcp.reconnect_tcp().await?;
+        sleep(Duration::from_millis(500)).await;
+
+        tcp.refresh_stale_nonce(600).await?;
+        tcp.refresh(600).await?;
+
+        let data = b"reclaimed";
+        udp.send_channel_data(0x4000, data).await?;
+        let ret = tcp.recv_channel_data().await?;
+        assert_eq!(ret.0, 0x4000);
+        assert_eq!(ret.1, data);
+
+        tcp.send_channel_data(0x4000, data).await?;
+        let ret = udp.recv_channel_data().await?;
+        assert_eq!(ret.0, 0x4000);
+        assert_eq!(ret.1, data);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_unknown_method_testing() -> Result<()> {
+        let bind: SocketAddr = "127.0.0.1:3504".parse()?;
+        create_turn_server(
+            bind,
+            Auth {
+                static_auth_secret: None,
+                static_credentials: Default::default(),
+                ..Default::default()
+            },
+            Api {
+                bind: "127.0.0.1:3024".parse()?,
+                hooks: None,
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        let credentials = Credentials {
+            username: "unknown".to_string(),
+            password: "unknown".to_string(),
+        };
+
+        let metric = &turn_server::statistics::prometheus::METRICS.unknown_methods;
+        let count = metric.get();
+
+        // The Connect request is answered with an error of the same method, the
+        // ConnectionAttempt indication is discarded.
+        let mut turn = TurnClient::new(bind, credentials).await?;
+        assert_eq!(turn.unknown_method(0x000a).await?, Some(0x011a));
+        assert_eq!(turn.unknown_method(0x001c).await?, None);
+
+        // The counter is global to the process, the other tests may count their
+        // unknown methods in parallel.
+        assert!(metric.get() >= count + 2);
+
+        turn.binding().await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_relay_dropped_testing() -> Result<()> {
+        let bind: SocketAddr = "127.0.0.1:3516".parse()?;
+        create_turn_server(
+            bind,
+            Auth {
+                static_auth_secret: None,
+                static_credentials: {
+                    let mut it = HashMap::with_capacity(1);
+                    it.insert("user".to_string(), "user".to_string());
+                    it
+                },
+                ..Default::default()
+            },
+            Api {
+                bind: "127.0.0.1:3033".parse()?,
+                hooks: None,
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        let credentials = || Credentials {
+            username: "user".to_string(),
+            password: "user".to_string(),
+        };
+
+        let mut turn = TurnClient::new(bind, credentials()).await?;
+        let mut peer = TurnClient::new(bind, credentials()).await?;
+
+        let port = turn.allocate().await?;
+        let peer_port = peer.allocate().await?;
+        peer.create_permission(port).await?;
+
+        let metric = &turn_server::statistics::prometheus::METRICS.permission_dropped;
+        let count = metric.get();
+
+        // The client has no permission for the peer, the data is dropped and
+        // counted.
+        peer.send_indication(port, b"dropped").await?;
+        assert!(turn.recv_indication().await.is_err());
+        assert!(metric.get() > count);
+
+        turn.create_permission(peer_port).await?;
+        peer.send_indication(port, b"relayed").await?;
+        let ret = turn.recv_indication().await?;
+        assert_eq!(ret.0, peer_port);
+        assert_eq!(ret.1, b"relayed");
+        Ok(())
+    }
+
+    /// Check the text against the OpenMetrics text format, every sample
+    /// belongs to the family declared before it, the counters have the
+    /// `_total` suffix and the text is terminated by `# EOF`.
+    fn parse_openmetrics(text: &str) -> Result<HashMap<String, f64>> {
+        let mut samples = HashMap::new();
+        let mut family: Option<(String, String)> = None;
+        let mut lines = text.lines().peekable();
+
+        while let Some(line) = lines.next() {
+            if line == "# EOF" {
+                ensure!(
+                    lines.peek().is_none() && text.ends_with("# EOF\n"),
+                    "text after eof"
+                );
+                return Ok(samples);
+            }
+
+            if let Some(it) = line.strip_prefix("# TYPE ") {
+                let (name, kind) = it.split_once(' ').context("invalid type")?;
+                ensure!(
+                    ["counter", "gauge"].contains(&kind),
+                    "invalid type: {}",
+                    kind
+                );
+                ensure!(!name.ends_with("_total"), "invalid family: {}", name);
+                family = Some((name.to_string(), kind.to_string()));
+            } else if let Some(it) = line.strip_prefix("# HELP ") {
+                let (name, _) = it.split_once(' ').context("invalid help")?;
+                ensure!(
+                    family.as_ref().map(|(it, _)| it.as_str()) == Some(name),
+                    "help before type"
+                );
+            } else {
+                let (name, kind) = family.as_ref().context("sample before type")?;
+                let (series, value) = line.rsplit_once(' ').context("invalid sample")?;
+                let metric = series.split('{').next().unwrap();
+                let expected = if kind == "counter" {
+                    format!("{}_total", name)
+                } else {
+                    name.clone()
+                };
+                ensure!(metric == expected, "invalid sample: {}", line);
+
+                if let Some(labels) = series.strip_prefix(metric) {
+                    if !labels.is_empty() {
+                        let labels = labels.strip_prefix('{').and_then(|it| it.strip_suffix('}'));
+                        for label in labels.context("invalid labels")?.split(',') {
+                            let (_, value) = label.split_once('=').context("invalid label")?;
+                            ensure!(
+                                value.len() >= 2 && value.starts_with('"') && value.ends_with('"')
+                            );
+                        }
+                    }
+                }
+
+                samples.insert(series.to_string(), value.parse::<f64>()?);
+            }
         }
 
-        {
-            let data = "3 forwards to 1,2".as_bytes();
-            turn_3.send_indication(turn_1_port, data).await?;
-            let ret = turn_1.recv_indication().await?;
-            assert_eq!(ret.0, turn_3_port);
-            assert_eq!(ret.1, data);
+        bail!("missing eof")
+    }
 
-            turn_3.send_indication(turn_2_port, data).await?;
-            let ret = turn_2.recv_indication().await?;
-            assert_eq!(ret.0, turn_3_port);
-            assert_eq!(ret.1, data);
+    #[tokio::test]
+    async fn turn_openmetrics_testing() -> Result<()> {
+        let bind: SocketAddr = "127.0.0.1:3510".parse()?;
+        create_turn_server(
+            bind,
+            Auth {
+                static_auth_secret: None,
+                static_credentials: Default::default(),
+                ..Default::default()
+            },
+            Api {
+                bind: "127.0.0.1:3029".parse()?,
+                hooks: None,
+                ..Default::default()
+            },
+        )
+        .await?;
 
-            turn_3.send_indication(turn_4_port, data).await?;
-            assert!(turn_4.recv_indication().await.is_err());
+        let credentials = Credentials {
+            username: "unknown".to_string(),
+            password: "unknown".to_string(),
+        };
+
+        let samples = parse_openmetrics(&render_openmetrics())?;
+        let count = |samples: &HashMap<String, f64>, series: &str| {
+            samples.get(series).copied().unwrap_or(0.0)
+        };
+        let binding = count(
+            &samples,
+            "responses_total{method=\"binding\",result=\"success\"}",
+        );
+        let unknown = count(
+            &samples,
+            "responses_total{method=\"unknown\",result=\"error\"}",
+        );
+
+        let mut turn = TurnClient::new(bind, credentials).await?;
+        turn.binding().await?;
+        assert_eq!(turn.unknown_method(0x000a).await?, Some(0x011a));
+
+        // The metrics are global to the process, the other tests running in
+        // parallel count their responses too, so the counters are only known
+        // to have grown by at least the responses of this test.
+        let samples = parse_openmetrics(&render_openmetrics())?;
+        assert!(samples.contains_key("allocated"));
+        assert!(samples.contains_key("relayed_bytes_total"));
+        assert!(samples.contains_key("bandwidth_dropped_total"));
+        assert!(samples.contains_key("queue_dropped_total"));
+        assert!(
+            count(
+                &samples,
+                "responses_total{method=\"binding\",result=\"success\"}"
+            ) >= binding + 1.0
+        );
+
+        assert!(
+            count(
+                &samples,
+                "responses_total{method=\"unknown\",result=\"error\"}"
+            ) >= unknown + 1.0
+        );
+
+        // The series are labeled by the method and the result, never by the client.
+        assert!(samples.keys().all(|it| !it.contains("127.0.0.1")));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_response_egress_testing() -> Result<()> {
+        let listeners: [SocketAddr; 2] = ["127.0.0.1:3505".parse()?, "127.0.0.2:3505".parse()?];
+        create_turn_server_with_config(Config {
+            log: Log::default(),
+            turn: Turn {
+                realm: "localhost".to_string(),
+                interfaces: listeners
+                    .iter()
+                    .map(|it| Interface {
+                        transport: TurnTransport::UDP,
+                        other_address: None,
+                        relay_addresses: Vec::new(),
+                        listener: Default::default(),
+                        external: *it,
+                        bind: *it,
+                    })
+                    .collect(),
+                ..Default::default()
+            },
+            auth: Auth::default(),
+            api: Api {
+                bind: "127.0.0.1:3025".parse()?,
+                hooks: None,
+                ..Default::default()
+            },
+        })
+        .await?;
+
+        // A single unconnected socket talks to both listeners, so the source
+        // address of each response is not filtered by the kernel.
+        let socket = UdpSocket::bind("127.0.0.1:0").await?;
+        let mut bytes = BytesMut::with_capacity(1500);
+        for listener in listeners {
+            MessageWriter::new(Method::Binding(Kind::Request), &TOKEN, &mut bytes).flush(None)?;
+            socket.send_to(&bytes, listener).await?;
         }
 
-        {
-            let data = "4 forwards to 1".as_bytes();
-            turn_4.send_indication(turn_1_port, data).await?;
-            let ret = turn_1.recv_indication().await?;
-            assert_eq!(ret.0, turn_4_port);
-            assert_eq!(ret.1, data);
+        let mut buf = [0u8; 1500];
+        let mut origins = Vec::with_capacity(2);
+        for _ in listeners {
+            let (size, source) =
+                timeout(Duration::from_secs(5), socket.recv_from(&mut buf)).await??;
 
-            turn_4.send_indication(turn_3_port, data).await?;
-            assert!(turn_3.recv_indication().await.is_err());
+            let mut decoder = Decoder::default();
+            if let Payload::Message(message) = decoder.decode(&buf[..size])? {
+                ensure!(message.method == Method::Binding(Kind::Response));
+                ensure!(message.get::<ResponseOrigin>() == Some(source));
+            } else {
+                unreachable!()
+            }
+
+            origins.push(source);
         }
 
-        {
-            let info = controller.get_info().await.unwrap().payload;
-            assert_eq!(info.port_allocated, 4);
-            assert_eq!(info.port_capacity, 16383);
+        origins.sort();
+        assert_eq!(origins, listeners);
 
-            let interface = info.interfaces.get(0).unwrap();
-            assert_eq!(interface.bind, "127.0.0.1:3478".parse()?);
-            assert_eq!(interface.external, "127.0.0.1:3478".parse()?);
-            assert_eq!(interface.transport, DriverTransport::UDP);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_change_request_testing() -> Result<()> {
+        // The four addresses of the RFC 5780 setup, each one has the diagonal
+        // as the alternate address. The last listener has no alternate address.
+        let primary: SocketAddr = "127.0.0.1:3511".parse()?;
+        let alternate: SocketAddr = "127.0.0.2:3512".parse()?;
+        let port: SocketAddr = "127.0.0.1:3512".parse()?;
+        let ip: SocketAddr = "127.0.0.2:3511".parse()?;
+        let single: SocketAddr = "127.0.0.1:3513".parse()?;
+
+        create_turn_server_with_config(Config {
+            log: Log::default(),
+            turn: Turn {
+                realm: "localhost".to_string(),
+                interfaces: [
+                    (primary, Some(alternate)),
+                    (alternate, Some(primary)),
+                    (port, Some(ip)),
+                    (ip, Some(port)),
+                    (single, None),
+                ]
+                .into_iter()
+                .map(|(it, other)| Interface {
+                    transport: TurnTransport::UDP,
+                    other_address: other,
+                    relay_addresses: Vec::new(),
+                    listener: Default::default(),
+                    external: it,
+                    bind: it,
+                })
+                .collect(),
+                ..Default::default()
+            },
+            auth: Auth::default(),
+            api: Api {
+                bind: "127.0.0.1:3030".parse()?,
+                hooks: None,
+                ..Default::default()
+            },
+        })
+        .await?;
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await?;
+        let mut bytes = BytesMut::with_capacity(1500);
+        let mut buf = [0u8; 1500];
+
+        let cases = [
+            (
+                primary,
+                Change {
+                    ip: false,
+                    port: false,
+                },
+                primary,
+            ),
+            (
+                primary,
+                Change {
+                    ip: false,
+                    port: true,
+                },
+                port,
+            ),
+            (
+                primary,
+                Change {
+                    ip: true,
+                    port: false,
+                },
+                ip,
+            ),
+            (
+                primary,
+                Change {
+                    ip: true,
+                    port: true,
+                },
+                alternate,
+            ),
+            (
+                port,
+                Change {
+                    ip: true,
+                    port: true,
+                },
+                ip,
+            ),
+        ];
+
+        for (listener, change, origin) in cases {
+            {
+                let mut message =
+                    MessageWriter::new(Method::Binding(Kind::Request), &TOKEN, &mut bytes);
+                message.append::<ChangeRequest>(change);
+                message.flush(None)?;
+            }
+
+            socket.send_to(&bytes, listener).await?;
+
+            let (size, source) =
+                timeout(Duration::from_secs(5), socket.recv_from(&mut buf)).await??;
+            assert_eq!(source, origin);
+
+            let mut decoder = Decoder::default();
+            if let Payload::Message(message) = decoder.decode(&buf[..size])? {
+                assert_eq!(message.method, Method::Binding(Kind::Response));
+                assert_eq!(message.get::<ResponseOrigin>(), Some(origin));
+                assert_eq!(
+                    message.get::<OtherAddress>(),
+                    Some(if listener == primary { alternate } else { ip })
+                );
+                assert_eq!(
+                    message.get::<XorMappedAddress>(),
+                    Some(socket.local_addr()?)
+                );
+            } else {
+                unreachable!()
+            }
         }
 
+        // The listener without an alternate address cannot change the origin.
         {
-            turn_1.refresh(0).await?;
-            turn_2.refresh(0).await?;
-            turn_3.refresh(0).await?;
+            let mut message =
+                MessageWriter::new(Method::Binding(Kind::Request), &TOKEN, &mut bytes);
+            message.append::<ChangeRequest>(Change {
+                ip: false,
+                port: true,
+            });
+            message.flush(None)?;
         }
 
-        assert!(controller
-            .get_session(&SessionAddr {
-                address: turn_1.local_addr()?,
-                interface: "127.0.0.1:3478".parse()?,
-            })
-            .await
-            .is_none());
+        socket.send_to(&bytes, single).await?;
 
-        assert!(controller
-            .get_session(&SessionAddr {
-                address: turn_2.local_addr()?,
-                interface: "127.0.0.1:3478".parse()?,
-            })
-            .await
-            .is_none());
+        let (size, source) = timeout(Duration::from_secs(5), socket.recv_from(&mut buf)).await??;
+        assert_eq!(source, single);
 
-        assert!(controller
-            .get_session(&SessionAddr {
-                address: turn_3.local_addr()?,
-                interface: "127.0.0.1:3478".parse()?,
-            })
-            .await
-            .is_none());
+        let mut decoder = Decoder::default();
+        if let Payload::Message(message) = decoder.decode(&buf[..size])? {
+            assert_eq!(message.method, Method::Binding(Kind::Error));
+            assert_eq!(
+                message.get::<ErrorCode>().map(|it| it.code),
+                Some(ErrorKind::UnknownAttribute as u16)
+            );
+        } else {
+            unreachable!()
+        }
 
-        assert!(controller
-            .get_session(&SessionAddr {
-                address: turn_4.local_addr()?,
-                interface: "127.0.0.1:3478".parse()?,
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_coalesced_messages_testing() -> Result<()> {
+        let bind: SocketAddr = "127.0.0.1:3509".parse()?;
+        create_turn_server(
+            bind,
+            Auth::default(),
+            Api {
+                bind: "127.0.0.1:3028".parse()?,
+                hooks: None,
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        // An unpadded channel data and two binding requests in a single
+        // datagram, followed by a truncated header that is not answered. The
+        // channel data has no allocation, it is dropped.
+        let tokens = [[1u8; 12], [2u8; 12]];
+        let mut datagram = BytesMut::with_capacity(1500);
+        datagram.extend_from_slice(&[0x40, 0x00, 0x00, 0x01, 0xff]);
+        for token in &tokens {
+            let mut bytes = BytesMut::with_capacity(1500);
+            MessageWriter::new(Method::Binding(Kind::Request), token, &mut bytes).flush(None)?;
+            datagram.extend_from_slice(&bytes);
+        }
+
+        datagram.extend_from_slice(&[0x00, 0x01, 0xff, 0xf0]);
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await?;
+        socket.send_to(&datagram, bind).await?;
+
+        let mut buf = [0u8; 1500];
+        for token in &tokens {
+            let size = timeout(Duration::from_secs(5), socket.recv(&mut buf)).await??;
+
+            let mut decoder = Decoder::default();
+            if let Payload::Message(message) = decoder.decode(&buf[..size])? {
+                ensure!(message.method == Method::Binding(Kind::Response));
+                ensure!(message.token == token);
+            } else {
+                unreachable!()
+            }
+        }
+
+        ensure!(timeout(Duration::from_millis(500), socket.recv(&mut buf))
+            .await
+            .is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_websocket_testing() -> Result<()> {
+        let bind: SocketAddr = "127.0.0.1:3506".parse()?;
+        let ws: SocketAddr = "127.0.0.1:3507".parse()?;
+        create_turn_server_with_config(Config {
+            log: Log::default(),
+            turn: Turn {
+                realm: "localhost".to_string(),
+                interfaces: vec![Interface {
+                    transport: TurnTransport::UDP,
+                    other_address: None,
+                    relay_addresses: Vec::new(),
+                    listener: Default::default(),
+                    external: bind,
+                    bind,
+                }],
+                websocket_interfaces: vec![WebSocketInterface {
+                    bind: ws,
+                    external: bind,
+                }],
+                ..Default::default()
+            },
+            auth: Auth::default(),
+            api: Api {
+                bind: "127.0.0.1:3026".parse()?,
+                hooks: None,
+                ..Default::default()
+            },
+        })
+        .await?;
+
+        let mut socket = TcpStream::connect(ws).await?;
+        socket
+            .write_all(
+                b"GET /turn HTTP/1.1\r\n\
+                Host: localhost\r\n\
+                Upgrade: websocket\r\n\
+                Connection: Upgrade\r\n\
+                Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                Sec-WebSocket-Version: 13\r\n\r\n",
+            )
+            .await?;
+
+        let mut buf = Vec::with_capacity(4096);
+        while !buf.windows(4).any(|it| it == b"\r\n\r\n") {
+            ensure!(timeout(Duration::from_secs(5), socket.read_buf(&mut buf)).await?? > 0);
+        }
+
+        let response = String::from_utf8(buf.clone())?;
+        ensure!(response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        ensure!(response.contains(&format!(
+            "Sec-WebSocket-Accept: {}\r\n",
+            websocket::accept_key("dGhlIHNhbXBsZSBub25jZQ==")
+        )));
+
+        // The binding request in a masked binary frame of the client.
+        let mut bytes = BytesMut::with_capacity(1500);
+        MessageWriter::new(Method::Binding(Kind::Request), &TOKEN, &mut bytes).flush(None)?;
+
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let mut frame = vec![0x82, 0x80 | bytes.len() as u8];
+        frame.extend_from_slice(&mask);
+        frame.extend(bytes.iter().enumerate().map(|(i, it)| it ^ mask[i % 4]));
+        socket.write_all(&frame).await?;
+
+        // The response is an unmasked binary frame of the server.
+        let mut header = [0u8; 2];
+        timeout(Duration::from_secs(5), socket.read_exact(&mut header)).await??;
+        ensure!(header[0] == 0x82 && header[1] < 126);
+
+        let mut message = vec![0u8; header[1] as usize];
+        socket.read_exact(&mut message).await?;
+
+        let mut decoder = Decoder::default();
+        if let Payload::Message(message) = decoder.decode(&message)? {
+            ensure!(message.method == Method::Binding(Kind::Response));
+            ensure!(message.token == TOKEN.as_slice());
+            ensure!(message.get::<XorMappedAddress>() == Some(socket.local_addr()?));
+        } else {
+            unreachable!()
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_proxy_protocol_testing() -> Result<()> {
+        let bind: SocketAddr = "127.0.0.1:3514".parse()?;
+        create_turn_server_with_config(Config {
+            log: Log::default(),
+            turn: Turn {
+                realm: "localhost".to_string(),
+                interfaces: vec![Interface {
+                    transport: TurnTransport::TCP,
+                    other_address: None,
+                    relay_addresses: Vec::new(),
+                    listener: Listener {
+                        proxy_protocol: true,
+                        ..Default::default()
+                    },
+                    external: bind,
+                    bind,
+                }],
+                ..Default::default()
+            },
+            auth: Auth::default(),
+            api: Api {
+                bind: "127.0.0.1:3031".parse()?,
+                hooks: None,
+                ..Default::default()
+            },
+        })
+        .await?;
+
+        let binding = |header: &[u8]| {
+            let header = header.to_vec();
+            async move {
+                let mut socket = TcpStream::connect(bind).await?;
+
+                // The header and the binding request are sent in one segment, the
+                // request must be left in the stream after the header.
+                let mut bytes = BytesMut::with_capacity(1500);
+                bytes.put(&header[..]);
+
+                let mut message = BytesMut::with_capacity(1500);
+                MessageWriter::new(Method::Binding(Kind::Request), &TOKEN, &mut message)
+                    .flush(None)?;
+                bytes.put(&message[..]);
+                socket.write_all(&bytes).await?;
+
+                let mut header = [0u8; 20];
+                timeout(Duration::from_secs(5), socket.read_exact(&mut header)).await??;
+
+                let size = u16::from_be_bytes([header[2], header[3]]) as usize;
+                let mut message = header.to_vec();
+                message.resize(20 + size, 0);
+                socket.read_exact(&mut message[20..]).await?;
+
+                let mut decoder = Decoder::default();
+                if let Payload::Message(message) = decoder.decode(&message)? {
+                    ensure!(message.method == Method::Binding(Kind::Response));
+                    Ok((message.get::<XorMappedAddress>(), socket.local_addr()?))
+                } else {
+                    bail!("not a stun message")
+                }
+            }
+        };
+
+        // PROXY TCP4 203.0.113.7:5000 -> 127.0.0.1:3514, the mapped address is
+        // the source address of the header.
+        let mut header = b"\r\n\r\n\0\r\nQUIT\n\x21\x11\x00\x0C".to_vec();
+        header.extend_from_slice(&[203, 0, 113, 7, 127, 0, 0, 1, 0x13, 0x88, 0x0D, 0xBA]);
+
+        let (mapped, _) = binding(&header).await?;
+        ensure!(mapped == Some("203.0.113.7:5000".parse()?));
+
+        // The LOCAL command of the health checks keeps the address of the
+        // connection.
+        let (mapped, local_addr) = binding(b"\r\n\r\n\0\r\nQUIT\n\x20\x00\x00\x00").await?;
+        ensure!(mapped == Some(local_addr));
+
+        // A connection without the header is closed, the unread bytes of the
+        // request reset it.
+        let mut socket = TcpStream::connect(bind).await?;
+        socket.write_all(&[0u8; 20]).await?;
+
+        let mut buf = [0u8; 20];
+        let ret = timeout(Duration::from_secs(5), socket.read(&mut buf)).await?;
+        ensure!(matches!(ret, Ok(0) | Err(_)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_tcp_accept_rate_testing() -> Result<()> {
+        let bind: SocketAddr = "127.0.0.1:3508".parse()?;
+        create_turn_server_with_config(Config {
+            log: Log::default(),
+            turn: Turn {
+                realm: "localhost".to_string(),
+                interfaces: vec![Interface {
+                    transport: TurnTransport::TCP,
+                    other_address: None,
+                    relay_addresses: Vec::new(),
+                    listener: Default::default(),
+                    external: bind,
+                    bind,
+                }],
+                tcp_accept_rate: Some(2),
+                ..Default::default()
+            },
+            auth: Auth::default(),
+            api: Api {
+                bind: "127.0.0.1:3027".parse()?,
+                hooks: None,
+                ..Default::default()
+            },
+        })
+        .await?;
+
+        // Let the first window of the limit elapse, so that all connections fall
+        // into the window started by the first of them.
+        sleep(Duration::from_millis(1100)).await;
+
+        let mut sockets = Vec::with_capacity(5);
+        for _ in 0..5 {
+            sockets.push(TcpStream::connect(bind).await?);
+        }
+
+        let mut bytes = BytesMut::with_capacity(1500);
+        MessageWriter::new(Method::Binding(Kind::Request), &TOKEN, &mut bytes).flush(None)?;
+
+        // The shed connections are closed by the server without a response.
+        let mut responded = 0;
+        for mut socket in sockets {
+            let mut buf = [0u8; 1500];
+            if socket.write_all(&bytes).await.is_ok()
+                && matches!(
+                    timeout(Duration::from_secs(5), socket.read(&mut buf)).await?,
+                    Ok(size) if size > 0
+                )
+            {
+                responded += 1;
+            }
+        }
+
+        assert_eq!(responded, 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_proxy_protocol_accept_rate_testing() -> Result<()> {
+        let bind: SocketAddr = "127.0.0.1:3521".parse()?;
+        create_turn_server_with_config(Config {
+            log: Log::default(),
+            turn: Turn {
+                realm: "localhost".to_string(),
+                interfaces: vec![Interface {
+                    transport: TurnTransport::TCP,
+                    other_address: None,
+                    relay_addresses: Vec::new(),
+                    listener: Listener {
+                        proxy_protocol: true,
+                        ..Default::default()
+                    },
+                    external: bind,
+                    bind,
+                }],
+                tcp_accept_rate: Some(2),
+                ..Default::default()
+            },
+            auth: Auth::default(),
+            api: Api {
+                bind: "127.0.0.1:3038".parse()?,
+                hooks: None,
+                ..Default::default()
+            },
+        })
+        .await?;
+
+        sleep(Duration::from_millis(1100)).await;
+
+        let mut sockets = Vec::with_capacity(5);
+        for _ in 0..5 {
+            sockets.push(TcpStream::connect(bind).await?);
+        }
+
+        // The connections over the rate are closed at once, without waiting for
+        // their PROXY header, the admitted ones are still waiting for it.
+        let mut closed = 0;
+        for mut socket in sockets {
+            let mut buf = [0u8; 20];
+            if let Ok(ret) = timeout(Duration::from_secs(1), socket.read(&mut buf)).await {
+                ensure!(matches!(ret, Ok(0) | Err(_)));
+                closed += 1;
+            }
+        }
+
+        assert_eq!(closed, 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_control_plane_testing() -> Result<()> {
+        let bind: SocketAddr = "127.0.0.1:3491".parse()?;
+        create_turn_server_with_config(Config {
+            log: Log::default(),
+            turn: Turn {
+                realm: "localhost".to_string(),
+                interfaces: vec![Interface {
+                    transport: TurnTransport::UDP,
+                    other_address: None,
+                    relay_addresses: Vec::new(),
+                    listener: Default::default(),
+                    external: bind,
+                    bind,
+                }],
+                control_plane_threads: Some(1),
+                ..Default::default()
+            },
+            auth: Auth {
+                static_auth_secret: None,
+                static_credentials: {
+                    let mut it = HashMap::with_capacity(1);
+                    it.insert("user".to_string(), "user".to_string());
+                    it
+                },
+                ..Default::default()
+            },
+            api: Api {
+                bind: "127.0.0.1:3011".parse()?,
+                hooks: None,
+                ..Default::default()
+            },
+        })
+        .await?;
+
+        let credentials = || Credentials {
+            username: "user".to_string(),
+            password: "user".to_string(),
+        };
+
+        let mut turn_1 = TurnClient::new(bind, credentials()).await?;
+        let mut turn_2 = TurnClient::new(bind, credentials()).await?;
+
+        // The requests are answered by the control plane, and the relayed data
+        // is forwarded by the main runtime.
+        turn_1.binding().await?;
+        let turn_1_port = turn_1.allocate().await?;
+        let turn_2_port = turn_2.allocate().await?;
+
+        turn_1.create_permission(turn_2_port).await?;
+        turn_1.channel_bind(turn_2_port, 0x4000).await?;
+        turn_2.create_permission(turn_1_port).await?;
+        turn_2.channel_bind(turn_1_port, 0x4000).await?;
+
+        let data = "control plane".as_bytes();
+        turn_1.send_channel_data(0x4000, data).await?;
+        let ret = turn_2.recv_channel_data().await?;
+        assert_eq!(ret.1, data);
+
+        turn_2.send_indication(turn_1_port, data).await?;
+        let ret = turn_1.recv_indication().await?;
+        assert_eq!(ret.0, turn_2_port);
+        assert_eq!(ret.1, data);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_pin_relay_testing() -> Result<()> {
+        let bind: SocketAddr = "127.0.0.1:3492".parse()?;
+        create_turn_server_with_config(Config {
+            log: Log::default(),
+            turn: Turn {
+                realm: "localhost".to_string(),
+                interfaces: vec![Interface {
+                    transport: TurnTransport::TCP,
+                    other_address: None,
+                    relay_addresses: Vec::new(),
+                    listener: Default::default(),
+                    external: bind,
+                    bind,
+                }],
+                max_connections: Some(3),
+                connection_idle: 0,
+                pin_relay: true,
+                ..Default::default()
+            },
+            auth: Auth {
+                static_auth_secret: None,
+                static_credentials: {
+                    let mut it = HashMap::with_capacity(1);
+                    it.insert("user".to_string(), "user".to_string());
+                    it
+                },
+                ..Default::default()
+            },
+            api: Api {
+                bind: "127.0.0.1:3012".parse()?,
+                hooks: None,
+                ..Default::default()
+            },
+        })
+        .await?;
+
+        let credentials = || Credentials {
+            username: "user".to_string(),
+            password: "user".to_string(),
+        };
+
+        let mut turn_1 = TurnClient::new_tcp(bind, credentials()).await?;
+        let mut turn_2 = TurnClient::new_tcp(bind, credentials()).await?;
+
+        let turn_1_port = turn_1.allocate().await?;
+        let turn_2_port = turn_2.allocate().await?;
+
+        turn_1.create_permission(turn_2_port).await?;
+        turn_2.create_permission(turn_1_port).await?;
+
+        // The relayed data always comes from the same relay address.
+        for i in 0..10u8 {
+            let data = [i; 32];
+            turn_2.send_indication(turn_1_port, &data).await?;
+            let ret = turn_1.recv_indication().await?;
+            assert_eq!(ret.0, turn_2_port);
+            assert_eq!(ret.1, data);
+        }
+
+        // The pool is full, the connection past the cap reclaims the slot of the
+        // idle connection without an allocation, the pinned relays survive.
+        let mut turn_3 = TurnClient::new_tcp(bind, credentials()).await?;
+        turn_3.binding().await?;
+
+        let mut turn_4 = TurnClient::new_tcp(bind, credentials()).await?;
+        turn_4.allocate().await?;
+        assert!(turn_3.binding().await.is_err());
+
+        turn_2.send_indication(turn_1_port, &[1; 32]).await?;
+        let ret = turn_1.recv_indication().await?;
+        assert_eq!(ret.0, turn_2_port);
+
+        // The connection is unpinned when its allocation is closed, so it is
+        // reclaimed like any other idle connection.
+        turn_1.refresh(0).await?;
+
+        let mut turn_5 = TurnClient::new_tcp(bind, credentials()).await?;
+        turn_5.allocate().await?;
+        assert!(turn_1.binding().await.is_err());
+
+        turn_2.binding().await?;
+        turn_4.binding().await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_auth_failure_delay_testing() -> Result<()> {
+        let bind: SocketAddr = "127.0.0.1:3493".parse()?;
+        create_turn_server_with_config(Config {
+            log: Log::default(),
+            turn: Turn {
+                realm: "localhost".to_string(),
+                interfaces: vec![Interface {
+                    transport: TurnTransport::UDP,
+                    other_address: None,
+                    relay_addresses: Vec::new(),
+                    listener: Default::default(),
+                    external: bind,
+                    bind,
+                }],
+                auth_failure_delay: Some(300),
+                auth_failure_jitter: 100,
+                ..Default::default()
+            },
+            auth: Auth {
+                static_auth_secret: None,
+                static_credentials: {
+                    let mut it = HashMap::with_capacity(1);
+                    it.insert("user".to_string(), "user".to_string());
+                    it
+                },
+                ..Default::default()
+            },
+            api: Api {
+                bind: "127.0.0.1:3013".parse()?,
+                hooks: None,
+                ..Default::default()
+            },
+        })
+        .await?;
+
+        let credentials = |username: &str, password: &str| Credentials {
+            username: username.to_string(),
+            password: password.to_string(),
+        };
+
+        // The unknown username and the bad password both fail after the delay,
+        // the response still arrives before the read timeout of the client.
+        for (username, password) in [("user", "bad"), ("unknown", "user")] {
+            let mut turn = TurnClient::new(bind, credentials(username, password)).await?;
+
+            let instant = Instant::now();
+            assert!(turn.allocate().await.is_err());
+
+            let elapsed = instant.elapsed();
+            assert!(elapsed >= Duration::from_millis(300), "{:?}", elapsed);
+            assert!(elapsed < Duration::from_secs(1), "{:?}", elapsed);
+        }
+
+        let mut turn = TurnClient::new(bind, credentials("user", "user")).await?;
+
+        let instant = Instant::now();
+        turn.allocate().await?;
+        assert!(instant.elapsed() < Duration::from_millis(300));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_unix_interface_testing() -> Result<()> {
+        let bind: SocketAddr = "127.0.0.1:3494".parse()?;
+        let path = std::env::temp_dir().join(format!("turn-server-{}.sock", std::process::id()));
+        create_turn_server_with_config(Config {
+            log: Log::default(),
+            turn: Turn {
+                realm: "localhost".to_string(),
+                interfaces: vec![Interface {
+                    transport: TurnTransport::UDP,
+                    other_address: None,
+                    relay_addresses: Vec::new(),
+                    listener: Default::default(),
+                    external: bind,
+                    bind,
+                }],
+                unix_interfaces: vec![UnixInterface {
+                    path: path.clone(),
+                    external: bind,
+                }],
+                ..Default::default()
+            },
+            auth: Auth {
+                static_auth_secret: None,
+                static_credentials: {
+                    let mut it = HashMap::with_capacity(1);
+                    it.insert("user".to_string(), "user".to_string());
+                    it
+                },
+                ..Default::default()
+            },
+            api: Api {
+                bind: "127.0.0.1:3014".parse()?,
+                hooks: None,
+                ..Default::default()
+            },
+        })
+        .await?;
+
+        let credentials = || Credentials {
+            username: "user".to_string(),
+            password: "user".to_string(),
+        };
+
+        // The clients behind the proxy are identified by the address in the
+        // frame header.
+        let mut turn_1 =
+            TurnClient::new_unix(&path, "203.0.113.5:40000".parse()?, bind, credentials()).await?;
+        let mut turn_2 = TurnClient::new(bind, credentials()).await?;
+
+        turn_1.binding().await?;
+
+        let turn_1_port = turn_1.allocate().await?;
+        let turn_2_port = turn_2.allocate().await?;
+
+        turn_1.create_permission(turn_2_port).await?;
+        turn_2.create_permission(turn_1_port).await?;
+
+        // The relayed data between the unix and the udp clients.
+        for i in 0..10u8 {
+            let data = [i; 32];
+            turn_2.send_indication(turn_1_port, &data).await?;
+            let ret = turn_1.recv_indication().await?;
+            assert_eq!(ret.0, turn_2_port);
+            assert_eq!(ret.1, data);
+
+            turn_1.send_indication(turn_2_port, &data).await?;
+            let ret = turn_2.recv_indication().await?;
+            assert_eq!(ret.0, turn_1_port);
+            assert_eq!(ret.1, data);
+        }
+
+        let _ = std::fs::remove_file(&path);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_binding_policy_testing() -> Result<()> {
+        let bind: SocketAddr = "127.0.0.1:3495".parse()?;
+        create_turn_server_with_config(Config {
+            log: Log::default(),
+            turn: Turn {
+                realm: "localhost".to_string(),
+                interfaces: vec![Interface {
+                    transport: TurnTransport::UDP,
+                    other_address: None,
+                    relay_addresses: Vec::new(),
+                    listener: Default::default(),
+                    external: bind,
+                    bind,
+                }],
+                binding_allow: vec!["127.0.0.0/8".to_string()],
+                binding_deny: vec!["127.0.0.2".to_string()],
+                ..Default::default()
+            },
+            auth: Auth {
+                static_auth_secret: None,
+                static_credentials: Default::default(),
+                ..Default::default()
+            },
+            api: Api {
+                bind: "127.0.0.1:3015".parse()?,
+                hooks: None,
+                ..Default::default()
+            },
+        })
+        .await?;
+
+        let credentials = || Credentials {
+            username: "user".to_string(),
+            password: "user".to_string(),
+        };
+
+        // The denied client gets no response at all.
+        let mut turn = TurnClient::new_bind(bind, "127.0.0.2:0".parse()?, credentials()).await?;
+        assert!(turn.binding().await.is_err());
+
+        let mut turn = TurnClient::new_bind(bind, "127.0.0.3:0".parse()?, credentials()).await?;
+        turn.binding().await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_challenge_testing() -> Result<()> {
+        create_turn_server(
+            "127.0.0.1:3496".parse()?,
+            Auth {
+                static_auth_secret: None,
+                static_credentials: {
+                    let mut it = HashMap::with_capacity(1);
+                    it.insert("user".to_string(), "user".to_string());
+                    it
+                },
+                ..Default::default()
+            },
+            Api {
+                bind: "127.0.0.1:3016".parse()?,
+                hooks: None,
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        let mut turn = TurnClient::new(
+            "127.0.0.1:3496".parse()?,
+            Credentials {
+                username: "user".to_string(),
+                password: "user".to_string(),
+            },
+        )
+        .await?;
+
+        let port = turn.allocate().await?;
+
+        // Every processor challenges the requests without the credential
+        // with the same realm and nonce.
+        for method in [
+            Method::Allocate(Kind::Request),
+            Method::CreatePermission(Kind::Request),
+            Method::ChannelBind(Kind::Request),
+            Method::Refresh(Kind::Request),
+        ] {
+            turn.challenge(method, port).await?;
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_hooks_concurrency_testing() -> Result<()> {
+        let hooks = ConcurrencyHooks::default();
+        {
+            tokio::spawn(start_hooks_server("127.0.0.1:8089".parse()?, hooks.clone()));
+            sleep(Duration::from_secs(1)).await;
+        }
+
+        // Each tcp connection is processed concurrently.
+        let bind: SocketAddr = "127.0.0.1:3497".parse()?;
+        create_turn_server_with_config(Config {
+            log: Log::default(),
+            turn: Turn {
+                realm: "localhost".to_string(),
+                interfaces: vec![Interface {
+                    transport: TurnTransport::TCP,
+                    other_address: None,
+                    relay_addresses: Vec::new(),
+                    listener: Default::default(),
+                    external: bind,
+                    bind,
+                }],
+                ..Default::default()
+            },
+            auth: Auth {
+                static_auth_secret: None,
+                static_credentials: Default::default(),
+                ..Default::default()
+            },
+            api: Api {
+                bind: "127.0.0.1:3017".parse()?,
+                hooks: Some("http://127.0.0.1:8089".to_string()),
+                hooks_concurrency: Some(2),
+                ..Default::default()
+            },
+        })
+        .await?;
+
+        let mut tasks = Vec::with_capacity(8);
+        for _ in 0..8 {
+            tasks.push(tokio::spawn(async move {
+                let mut turn = TurnClient::new_tcp(
+                    bind,
+                    Credentials {
+                        username: "hooks".to_string(),
+                        password: "hooks".to_string(),
+                    },
+                )
+                .await?;
+
+                turn.allocate().await
+            }));
+        }
+
+        // All the requests are answered, but never more than two of them
+        // reach the hooks service at the same time.
+        for task in tasks {
+            task.await??;
+        }
+
+        assert_eq!(hooks.max.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_channels_api_testing() -> Result<()> {
+        let bind: SocketAddr = "127.0.0.1:3515".parse()?;
+        create_turn_server(
+            bind,
+            Auth {
+                static_auth_secret: None,
+                static_credentials: {
+                    let mut it = HashMap::with_capacity(1);
+                    it.insert("user".to_string(), "user".to_string());
+                    it
+                },
+                ..Default::default()
+            },
+            Api {
+                bind: "127.0.0.1:3032".parse()?,
+                hooks: None,
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        let credentials = || Credentials {
+            username: "user".to_string(),
+            password: "user".to_string(),
+        };
+
+        let mut turn = TurnClient::new(bind, credentials()).await?;
+        let mut peer = TurnClient::new(bind, credentials()).await?;
+
+        turn.allocate().await?;
+        let peer_port = peer.allocate().await?;
+        turn.create_permission(peer_port).await?;
+        turn.channel_bind(peer_port, 0x4000).await?;
+
+        let addr = SessionAddr {
+            address: turn.local_addr()?,
+            interface: bind,
+        };
+
+        let controller = Controller::new("http://127.0.0.1:3032")?;
+        let channels = controller.get_channels(&addr).await.unwrap().payload;
+        assert_eq!(channels.len(), 1);
+        assert_eq!(channels[0].number, 0x4000);
+        assert_eq!(channels[0].peer, SocketAddr::new(bind.ip(), peer_port));
+        assert!(channels[0].lifetime <= 600);
+
+        assert_eq!(
+            controller
+                .remove_channel(&addr, 0x4000)
+                .await
+                .map(|it| it.payload),
+            Some(true)
+        );
+
+        assert_eq!(
+            controller
+                .remove_channel(&addr, 0x4000)
+                .await
+                .map(|it| it.payload),
+            Some(false)
+        );
+
+        assert!(controller
+            .get_channels(&addr)
+            .await
+            .unwrap()
+            .payload
+            .is_empty());
+
+        // The permission is kept, the peer can be bound to another channel.
+        turn.channel_bind(peer_port, 0x4001).await?;
+        let channels = controller.get_channels(&addr).await.unwrap().payload;
+        assert_eq!(channels.len(), 1);
+        assert_eq!(channels[0].number, 0x4001);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_relay_family_testing() -> Result<()> {
+        let bind: SocketAddr = "127.0.0.1:3498".parse()?;
+
+        // The address of the ipv6 interface is not available on this host,
+        // the interface is skipped instead of failing the startup.
+        let unavailable: SocketAddr = "[2001:db8::1]:3498".parse()?;
+        create_turn_server_with_config(Config {
+            log: Log::default(),
+            turn: Turn {
+                realm: "localhost".to_string(),
+                interfaces: [unavailable, bind]
+                    .into_iter()
+                    .map(|bind| Interface {
+                        transport: TurnTransport::UDP,
+                        other_address: None,
+                        relay_addresses: Vec::new(),
+                        listener: Default::default(),
+                        external: bind,
+                        bind,
+                    })
+                    .collect(),
+                relay_family: RelayFamily::Ipv4,
+                ..Default::default()
+            },
+            auth: Auth {
+                static_auth_secret: None,
+                static_credentials: {
+                    let mut it = HashMap::with_capacity(1);
+                    it.insert("user".to_string(), "user".to_string());
+                    it
+                },
+                ..Default::default()
+            },
+            api: Api {
+                bind: "127.0.0.1:3018".parse()?,
+                hooks: None,
+                ..Default::default()
+            },
+        })
+        .await?;
+
+        let mut turn = TurnClient::new(
+            bind,
+            Credentials {
+                username: "user".to_string(),
+                password: "user".to_string(),
+            },
+        )
+        .await?;
+
+        turn.allocate().await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_reservation_testing() -> Result<()> {
+        let bind: SocketAddr = "127.0.0.1:3499".parse()?;
+        create_turn_server(
+            bind,
+            Auth {
+                static_auth_secret: None,
+                static_credentials: {
+                    let mut it = HashMap::with_capacity(1);
+                    it.insert("user".to_string(), "user".to_string());
+                    it
+                },
+                ..Default::default()
+            },
+            Api {
+                bind: "127.0.0.1:3019".parse()?,
+                hooks: None,
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        let credentials = || Credentials {
+            username: "user".to_string(),
+            password: "user".to_string(),
+        };
+
+        let mut turn = TurnClient::new(bind, credentials()).await?;
+        let (port, token) = turn.allocate_reservation(Some(true), None).await?.unwrap();
+        assert_eq!(port % 2, 0);
+
+        let controller = Controller::new("http://127.0.0.1:3019")?;
+        let reservations = controller.get_reservations().await.unwrap().payload;
+        assert_eq!(reservations.len(), 1);
+        assert_eq!(Some(reservations[0].token), token);
+        assert_eq!(reservations[0].port, port + 1);
+        assert!(reservations[0].lifetime <= 30);
+
+        // The reserved port is redeemed only once.
+        let mut turn = TurnClient::new(bind, credentials()).await?;
+        let res = turn.allocate_reservation(None, token).await?;
+        assert_eq!(res, Ok((port + 1, None)));
+        assert!(controller
+            .get_reservations()
+            .await
+            .unwrap()
+            .payload
+            .is_empty());
+        assert_eq!(
+            controller.prune_reservations().await.map(|it| it.payload),
+            Some(0)
+        );
+
+        let mut turn = TurnClient::new(bind, credentials()).await?;
+        let res = turn.allocate_reservation(None, token).await?;
+        assert_eq!(res, Err(ErrorKind::InsufficientCapacity as u16));
+
+        // The token and the even port cannot be requested together.
+        let res = turn.allocate_reservation(Some(false), token).await?;
+        assert_eq!(res, Err(ErrorKind::BadRequest as u16));
+
+        let res = turn.allocate_reservation(Some(false), None).await?;
+        assert_eq!(res.map(|(port, token)| (port % 2, token)), Ok((0, None)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_stale_nonce_testing() -> Result<()> {
+        let bind = "127.0.0.1:3500".parse()?;
+        create_turn_server_with_config(Config {
+            log: Log::default(),
+            turn: Turn {
+                realm: "localhost".to_string(),
+                interfaces: vec![Interface {
+                    transport: TurnTransport::UDP,
+                    other_address: None,
+                    relay_addresses: Vec::new(),
+                    listener: Default::default(),
+                    external: bind,
+                    bind,
+                }],
+                nonce_lifetime: 2,
+                ..Default::default()
+            },
+            auth: Auth {
+                static_auth_secret: None,
+                static_credentials: {
+                    let mut it = HashMap::with_capacity(1);
+                    it.insert("nonce".to_string(), "nonce".to_string());
+                    it
+                },
+                ..Default::default()
+            },
+            api: Api {
+                bind: "127.0.0.1:3020".parse()?,
+                hooks: None,
+                ..Default::default()
+            },
+        })
+        .await?;
+
+        let mut turn = TurnClient::new(
+            bind,
+            Credentials {
+                username: "nonce".to_string(),
+                password: "nonce".to_string(),
+            },
+        )
+        .await?;
+
+        // Inside the window the nonce is accepted.
+        turn.allocate().await?;
+        turn.refresh(600).await?;
+
+        // Outside the window the nonce is stale, and the fresh nonce of the
+        // error response is accepted.
+        sleep(Duration::from_secs(4)).await;
+        turn.refresh_stale_nonce(600).await?;
+        turn.refresh(600).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_relay_addresses_testing() -> Result<()> {
+        let bind = "127.0.0.1:3501".parse()?;
+        create_turn_server_with_config(Config {
+            log: Log::default(),
+            turn: Turn {
+                realm: "localhost".to_string(),
+                interfaces: vec![Interface {
+                    transport: TurnTransport::UDP,
+                    other_address: None,
+                    relay_addresses: vec!["127.0.0.2".parse()?, "127.0.0.3".parse()?],
+                    listener: Default::default(),
+                    external: bind,
+                    bind,
+                }],
+                ..Default::default()
+            },
+            auth: Auth {
+                static_auth_secret: None,
+                static_credentials: {
+                    let mut it = HashMap::with_capacity(1);
+                    it.insert("relay".to_string(), "relay".to_string());
+                    it
+                },
+                ..Default::default()
+            },
+            api: Api {
+                bind: "127.0.0.1:3021".parse()?,
+                hooks: None,
+                ..Default::default()
+            },
+        })
+        .await?;
+
+        let credentials = || Credentials {
+            username: "relay".to_string(),
+            password: "relay".to_string(),
+        };
+
+        let mut clients = Vec::with_capacity(4);
+        let mut relays = Vec::with_capacity(4);
+        for _ in 0..4 {
+            let mut turn = TurnClient::new(bind, credentials()).await?;
+            relays.push(turn.allocate_relay().await?);
+            clients.push(turn);
+        }
+
+        // The relayed addresses rotate across the addresses of the interface.
+        assert_eq!(
+            relays
+                .iter()
+                .map(|it| it.ip().to_string())
+                .collect::<Vec<_>>(),
+            ["127.0.0.1", "127.0.0.2", "127.0.0.3", "127.0.0.1"]
+        );
+
+        // The permissions and the relayed data use the assigned addresses.
+        let (turn_1, turn_2) = clients.split_at_mut(2);
+        let (turn_1, turn_2) = (&mut turn_1[1], &mut turn_2[0]);
+        turn_1.create_permission_peer(relays[2]).await?;
+        turn_2.create_permission_peer(relays[1]).await?;
+
+        let data = [7u8; 32];
+        turn_1.send_indication_peer(relays[2], &data).await?;
+        let ret = turn_2.recv_indication_peer().await?;
+        assert_eq!(ret.0, relays[1]);
+        assert_eq!(ret.1, data);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_reload_policy_testing() -> Result<()> {
+        let bind = "127.0.0.1:3502".parse()?;
+        create_turn_server_with_config(Config {
+            log: Log::default(),
+            turn: Turn {
+                realm: "localhost".to_string(),
+                interfaces: vec![Interface {
+                    transport: TurnTransport::UDP,
+                    other_address: None,
+                    relay_addresses: Vec::new(),
+                    listener: Default::default(),
+                    external: bind,
+                    bind,
+                }],
+                ..Default::default()
+            },
+            auth: Auth {
+                static_auth_secret: None,
+                static_credentials: {
+                    let mut it = HashMap::with_capacity(1);
+                    it.insert("policy".to_string(), "policy".to_string());
+                    it
+                },
+                ..Default::default()
+            },
+            api: Api {
+                bind: "127.0.0.1:3022".parse()?,
+                hooks: None,
+                ..Default::default()
+            },
+        })
+        .await?;
+
+        let credentials = || Credentials {
+            username: "policy".to_string(),
+            password: "policy".to_string(),
+        };
+
+        let mut clients = Vec::with_capacity(3);
+        let mut relays = Vec::with_capacity(3);
+        for _ in 0..3 {
+            let mut turn = TurnClient::new(bind, credentials()).await?;
+            relays.push(turn.allocate_relay().await?);
+            clients.push(turn);
+        }
+
+        clients[0].create_permission_peer(relays[1]).await?;
+        clients[1].create_permission_peer(relays[0]).await?;
+
+        let controller = Controller::new("http://127.0.0.1:3022")?;
+        let reload = |peer_deny: Vec<String>| {
+            let controller = &controller;
+            async move {
+                controller
+                    .reload_policy(&Policies {
+                        peer_deny,
+                        ..Default::default()
+                    })
+                    .await
+                    .map(|it| it.payload)
+            }
+        };
+
+        assert_eq!(reload(vec!["127.0.0.1/99".to_string()]).await, Some(false));
+        assert_eq!(reload(vec!["127.0.0.0/8".to_string()]).await, Some(true));
+
+        // The new permissions are rejected, the existing permissions are kept.
+        assert!(clients[0].create_permission_peer(relays[2]).await.is_err());
+
+        let data = [7u8; 32];
+        clients[0].send_indication_peer(relays[1], &data).await?;
+        let ret = clients[1].recv_indication_peer().await?;
+        assert_eq!(ret.0, relays[0]);
+        assert_eq!(ret.1, data);
+
+        assert_eq!(reload(Vec::new()).await, Some(true));
+        clients[0].create_permission_peer(relays[2]).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_server_testing() -> Result<()> {
+        let controller = Arc::new(Controller::new("http://127.0.0.1:3000")?);
+        {
+            tokio::spawn(start_hooks_server(
+                "127.0.0.1:8088".parse()?,
+                HooksImpl(controller.clone()),
+            ));
+
+            sleep(Duration::from_secs(3)).await;
+        }
+
+        create_turn_server(
+            "127.0.0.1:3478".parse()?,
+            Auth {
+                static_auth_secret: None,
+                static_credentials: {
+                    let mut it = HashMap::with_capacity(1);
+                    it.insert(
+                        "static_credentials".to_string(),
+                        "static_credentials".to_string(),
+                    );
+                    it
+                },
+                ..Default::default()
+            },
+            Api {
+                hooks: Some("http://127.0.0.1:8088".to_string()),
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        {
+            let info = controller.get_info().await.unwrap().payload;
+            assert_eq!(info.port_allocated, 0);
+            assert_eq!(info.port_capacity, 16383);
+            assert_eq!(info.relay_paths, 0);
+            assert_eq!(info.in_flight, 0);
+            assert!(!info.overloaded);
+            assert_eq!(info.router_dropped, 0);
+
+            let interface = info.interfaces.first().unwrap();
+            assert_eq!(interface.bind, "127.0.0.1:3478".parse()?);
+            assert_eq!(interface.external, "127.0.0.1:3478".parse()?);
+            assert_eq!(interface.transport, DriverTransport::UDP);
+        }
+
+        let mut turn_1 = TurnClient::new(
+            "127.0.0.1:3478".parse()?,
+            Credentials {
+                username: "static_credentials".to_string(),
+                password: "static_credentials".to_string(),
+            },
+        )
+        .await?;
+
+        let mut turn_2 = TurnClient::new(
+            "127.0.0.1:3478".parse()?,
+            Credentials {
+                username: "static_credentials".to_string(),
+                password: "static_credentials".to_string(),
+            },
+        )
+        .await?;
+
+        let mut turn_3 = TurnClient::new(
+            "127.0.0.1:3478".parse()?,
+            Credentials {
+                username: "hooks".to_string(),
+                password: "hooks".to_string(),
+            },
+        )
+        .await?;
+
+        let mut turn_4 = TurnClient::new(
+            "127.0.0.1:3478".parse()?,
+            Credentials {
+                username: "hooks".to_string(),
+                password: "hooks".to_string(),
+            },
+        )
+        .await?;
+
+        {
+            turn_1.binding().await?;
+            turn_2.binding().await?;
+            turn_3.binding().await?;
+        }
+
+        let turn_1_port = turn_1.allocate().await?;
+        let turn_2_port = turn_2.allocate().await?;
+        let turn_3_port = turn_3.allocate().await?;
+        let turn_4_port = turn_4.allocate().await?;
+
+        assert!(turn_1.allocate().await.is_err());
+        assert!(turn_2.allocate().await.is_err());
+        assert!(turn_3.allocate().await.is_err());
+        assert!(turn_4.allocate().await.is_err());
+
+        {
+            turn_1.create_permission(turn_2_port).await?;
+            turn_1.create_permission(turn_3_port).await?;
+            turn_1.create_permission(turn_4_port).await?;
+            turn_1.channel_bind(turn_2_port, 0x4000).await?;
+            turn_1.channel_bind(turn_3_port, 0x4001).await?;
+            turn_1.channel_bind(turn_4_port, 0x4002).await?;
+            turn_1.refresh(600).await?;
+
+            turn_2.create_permission(turn_1_port).await?;
+            turn_2.create_permission(turn_3_port).await?;
+            turn_2.channel_bind(turn_1_port, 0x4000).await?;
+            turn_2.channel_bind(turn_3_port, 0x4002).await?;
+            turn_2.refresh(600).await?;
+
+            turn_3.create_permission(turn_1_port).await?;
+            turn_3.create_permission(turn_2_port).await?;
+            turn_3.channel_bind(turn_1_port, 0x4001).await?;
+            turn_3.channel_bind(turn_2_port, 0x4002).await?;
+            turn_3.refresh(600).await?;
+
+            turn_4.create_permission(turn_1_port).await?;
+            turn_4.channel_bind(turn_1_port, 0x4002).await?;
+            turn_4.refresh(600).await?;
+
+            // Binding the same channel to the same peer again refreshes it.
+            turn_1.channel_bind(turn_2_port, 0x4000).await?;
+            turn_2.channel_bind(turn_1_port, 0x4000).await?;
+            turn_4.channel_bind(turn_1_port, 0x4002).await?;
+
+            // The channel is bound to another peer, the peer to another channel.
+            assert!(turn_1.channel_bind(turn_3_port, 0x4000).await.is_err());
+            assert!(turn_1.channel_bind(turn_4_port, 0x4003).await.is_err());
+            assert!(turn_2.channel_bind(turn_3_port, 0x4000).await.is_err());
+            assert!(turn_3.channel_bind(turn_1_port, 0x4002).await.is_err());
+        }
+
+        {
+            let data = "1 forwards to 2,3,4 channel data".as_bytes();
+            turn_1.send_channel_data(0x4000, data).await?;
+            let ret = turn_2.recv_channel_data().await?;
+            assert_eq!(ret.0, 0x4000);
+            assert_eq!(ret.1, data);
+
+            turn_1.send_channel_data(0x4001, data).await?;
+            let ret = turn_3.recv_channel_data().await?;
+            assert_eq!(ret.0, 0x4001);
+            assert_eq!(ret.1, data);
+
+            turn_1.send_channel_data(0x4002, data).await?;
+            let ret = turn_4.recv_channel_data().await?;
+            assert_eq!(ret.0, 0x4002);
+            assert_eq!(ret.1, data);
+        }
+
+        {
+            let data = "2 forwards to 1,3 channel data".as_bytes();
+            turn_2.send_channel_data(0x4000, data).await?;
+            let ret = turn_1.recv_channel_data().await?;
+            assert_eq!(ret.0, 0x4000);
+            assert_eq!(ret.1, data);
+            assert!(turn_3.recv_channel_data().await.is_err());
+            assert!(turn_4.recv_channel_data().await.is_err());
+
+            turn_2.send_channel_data(0x4002, data).await?;
+            let ret = turn_3.recv_channel_data().await?;
+            assert_eq!(ret.0, 0x4002);
+            assert_eq!(ret.1, data);
+            assert!(turn_1.recv_channel_data().await.is_err());
+            assert!(turn_4.recv_channel_data().await.is_err());
+
+            turn_2.send_channel_data(0x4001, data).await?;
+            assert!(turn_1.recv_channel_data().await.is_err());
+            assert!(turn_3.recv_channel_data().await.is_err());
+            assert!(turn_4.recv_channel_data().await.is_err());
+        }
+
+        {
+            let data = "3 forwards to 1,2 channel data".as_bytes();
+            turn_3.send_channel_data(0x4001, data).await?;
+            let ret = turn_1.recv_channel_data().await?;
+            assert_eq!(ret.0, 0x4001);
+            assert_eq!(ret.1, data);
+            assert!(turn_2.recv_channel_data().await.is_err());
+            assert!(turn_4.recv_channel_data().await.is_err());
+
+            turn_3.send_channel_data(0x4002, data).await?;
+            let ret = turn_2.recv_channel_data().await?;
+            assert_eq!(ret.0, 0x4002);
+            assert_eq!(ret.1, data);
+            assert!(turn_1.recv_channel_data().await.is_err());
+            assert!(turn_4.recv_channel_data().await.is_err());
+
+            turn_3.send_channel_data(0x4000, data).await?;
+            assert!(turn_2.recv_channel_data().await.is_err());
+            assert!(turn_1.recv_channel_data().await.is_err());
+            assert!(turn_4.recv_channel_data().await.is_err());
+        }
+
+        {
+            let data = "4 forwards to 1 channel data".as_bytes();
+            turn_4.send_channel_data(0x4002, data).await?;
+            let ret = turn_1.recv_channel_data().await?;
+            assert_eq!(ret.0, 0x4002);
+            assert_eq!(ret.1, data);
+            assert!(turn_2.recv_channel_data().await.is_err());
+            assert!(turn_3.recv_channel_data().await.is_err());
+
+            turn_4.send_channel_data(0x4000, data).await?;
+            assert!(turn_1.recv_channel_data().await.is_err());
+            assert!(turn_2.recv_channel_data().await.is_err());
+            assert!(turn_3.recv_channel_data().await.is_err());
+
+            turn_4.send_channel_data(0x4001, data).await?;
+            assert!(turn_1.recv_channel_data().await.is_err());
+            assert!(turn_2.recv_channel_data().await.is_err());
+            assert!(turn_3.recv_channel_data().await.is_err());
+        }
+
+        {
+            let data = "1 forwards to 2,3,4".as_bytes();
+            turn_1.send_indication(turn_2_port, data).await?;
+            let ret = turn_2.recv_indication().await?;
+            assert_eq!(ret.0, turn_1_port);
+            assert_eq!(ret.1, data);
+
+            turn_1.send_indication(turn_3_port, data).await?;
+            let ret = turn_3.recv_indication().await?;
+            assert_eq!(ret.0, turn_1_port);
+            assert_eq!(ret.1, data);
+
+            turn_1.send_indication(turn_4_port, data).await?;
+            let ret = turn_4.recv_indication().await?;
+            assert_eq!(ret.0, turn_1_port);
+            assert_eq!(ret.1, data);
+        }
+
+        {
+            let data = "2 forwards to 1,3".as_bytes();
+            turn_2.send_indication(turn_1_port, data).await?;
+            let ret = turn_1.recv_indication().await?;
+            assert_eq!(ret.0, turn_2_port);
+            assert_eq!(ret.1, data);
+
+            turn_2.send_indication(turn_3_port, data).await?;
+            let ret = turn_3.recv_indication().await?;
+            assert_eq!(ret.0, turn_2_port);
+            assert_eq!(ret.1, data);
+
+            turn_2.send_indication(turn_4_port, data).await?;
+            assert!(turn_4.recv_indication().await.is_err());
+        }
+
+        {
+            let data = "3 forwards to 1,2".as_bytes();
+            turn_3.send_indication(turn_1_port, data).await?;
+            let ret = turn_1.recv_indication().await?;
+            assert_eq!(ret.0, turn_3_port);
+            assert_eq!(ret.1, data);
+
+            turn_3.send_indication(turn_2_port, data).await?;
+            let ret = turn_2.recv_indication().await?;
+            assert_eq!(ret.0, turn_3_port);
+            assert_eq!(ret.1, data);
+
+            turn_3.send_indication(turn_4_port, data).await?;
+            assert!(turn_4.recv_indication().await.is_err());
+        }
+
+        {
+            let data = "4 forwards to 1".as_bytes();
+            turn_4.send_indication(turn_1_port, data).await?;
+            let ret = turn_1.recv_indication().await?;
+            assert_eq!(ret.0, turn_4_port);
+            assert_eq!(ret.1, data);
+
+            turn_4.send_indication(turn_3_port, data).await?;
+            assert!(turn_3.recv_indication().await.is_err());
+        }
+
+        {
+            let info = controller.get_info().await.unwrap().payload;
+            assert_eq!(info.port_allocated, 4);
+            assert_eq!(info.port_capacity, 16383);
+
+            let interface = info.interfaces.first().unwrap();
+            assert_eq!(interface.bind, "127.0.0.1:3478".parse()?);
+            assert_eq!(interface.external, "127.0.0.1:3478".parse()?);
+            assert_eq!(interface.transport, DriverTransport::UDP);
+        }
+
+        {
+            turn_1.refresh(0).await?;
+            turn_2.refresh(0).await?;
+            turn_3.refresh(0).await?;
+        }
+
+        assert!(controller
+            .get_session(&SessionAddr {
+                address: turn_1.local_addr()?,
+                interface: "127.0.0.1:3478".parse()?,
+            })
+            .await
+            .is_none());
+
+        assert!(controller
+            .get_session(&SessionAddr {
+                address: turn_2.local_addr()?,
+                interface: "127.0.0.1:3478".parse()?,
+            })
+            .await
+            .is_none());
+
+        assert!(controller
+            .get_session(&SessionAddr {
+                address: turn_3.local_addr()?,
+                interface: "127.0.0.1:3478".parse()?,
+            })
+            .await
+            .is_none());
+
+        assert!(controller
+            .get_session(&SessionAddr {
+                address: turn_4.local_addr()?,
+                interface: "127.0.0.1:3478".parse()?,
+            })
+            .await
+            .is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn turn_statistics_take_testing() -> Result<()> {
+        let statistics = Statistics::default();
+        let addr = turn::SessionAddr {
+            address: "127.0.0.1:10000".parse()?,
+            interface: "127.0.0.1:3478".parse()?,
+        };
+
+        statistics.register(addr, None);
+
+        // The counts reported while the statistics are taken land either in a
+        // snapshot or in the remaining counts.
+        let workers = (0..4)
+            .map(|_| {
+                let reporter = statistics.get_reporter(Transport::UDP);
+                std::thread::spawn(move || {
+                    for _ in 0..100_000 {
+                        reporter.send(&addr, &[Stats::SendBytes(2), Stats::SendPkts(1)]);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let (mut bytes, mut pkts) = (0, 0);
+        while workers.iter().any(|it| !it.is_finished()) {
+            let counts = statistics.take(&addr).unwrap();
+            bytes += counts.send_bytes;
+            pkts += counts.send_pkts;
+        }
+
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        let counts = statistics.take(&addr).unwrap();
+        assert_eq!(bytes + counts.send_bytes, 800_000);
+        assert_eq!(pkts + counts.send_pkts, 400_000);
+        assert_eq!(statistics.get(&addr).unwrap().send_pkts, 0);
+
+        Ok(())
+    }
+
+    #[derive(Clone)]
+    struct MemoryObserver;
+
+    impl turn::Observer for MemoryObserver {
+        async fn get_password(&self, _: &turn::SessionAddr, _: &str) -> Option<String> {
+            Some("test".to_string())
+        }
+    }
+
+    async fn memory_request(
+        transport: &mut MemoryTransport<MemoryObserver>,
+        client: SocketAddr,
+        method: Method,
+        peer: Option<SocketAddr>,
+    ) -> Result<Option<SocketAddr>> {
+        let digest = long_term_credential_digest("test", "test", "localhost");
+        let mut bytes = BytesMut::with_capacity(1500);
+        let mut message = MessageWriter::new(method, &TOKEN, &mut bytes);
+        message.append::<ReqeestedTransport>(Transport::UDP);
+        if let Some(peer) = peer {
+            message.append::<XorPeerAddress>(peer);
+        }
+
+        message.append::<UserName>("test");
+        message.append::<Realm>("localhost");
+        message.flush(Some(&digest))?;
+
+        transport.send(client, &bytes).await?;
+        let res = transport.recv(&client).unwrap();
+
+        let mut decoder = Decoder::default();
+        if let Payload::Message(message) = decoder.decode(&res)? {
+            ensure!(message.get::<ErrorCode>().is_none(), "request failed");
+            Ok(message.get::<XorRelayedAddress>())
+        } else {
+            unreachable!()
+        }
+    }
+
+    #[tokio::test]
+    async fn turn_memory_transport_testing() -> Result<()> {
+        let interface: SocketAddr = "127.0.0.1:3478".parse()?;
+        let service = turn::Service::new(
+            "localhost".to_string(),
+            vec![interface],
+            turn::ServiceOptions::default(),
+            MemoryObserver,
+        );
+
+        let mut transport = MemoryTransport::new(&service, interface);
+        let client_1: SocketAddr = "127.0.0.1:10000".parse()?;
+        let client_2: SocketAddr = "127.0.0.1:10001".parse()?;
+        let allocate = Method::Allocate(Kind::Request);
+        let relay_1 = memory_request(&mut transport, client_1, allocate, None)
+            .await?
+            .unwrap();
+        let relay_2 = memory_request(&mut transport, client_2, allocate, None)
+            .await?
+            .unwrap();
+
+        let create_permission = Method::CreatePermission(Kind::Request);
+        memory_request(&mut transport, client_1, create_permission, Some(relay_2)).await?;
+        memory_request(&mut transport, client_2, create_permission, Some(relay_1)).await?;
+
+        let mut bytes = BytesMut::with_capacity(1500);
+        let mut message = MessageWriter::new(Method::SendIndication, &TOKEN, &mut bytes);
+        message.append::<XorPeerAddress>(relay_2);
+        message.append::<Data>(b"hello");
+        message.flush(None)?;
+
+        transport.send(client_1, &bytes).await?;
+        assert!(transport.recv(&client_1).is_none());
+
+        let res = transport.recv(&client_2).unwrap();
+        let mut decoder = Decoder::default();
+        if let Payload::Message(message) = decoder.decode(&res)? {
+            assert_eq!(message.method, Method::DataIndication);
+            assert_eq!(message.get::<XorPeerAddress>(), Some(relay_1));
+            assert_eq!(message.get::<Data>(), Some(&b"hello"[..]));
+        } else {
+            unreachable!()
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_memory_channel_data_testing() -> Result<()> {
+        let interface: SocketAddr = "127.0.0.1:3478".parse()?;
+        let service = turn::Service::new(
+            "localhost".to_string(),
+            vec![interface],
+            turn::ServiceOptions::default(),
+            MemoryObserver,
+        );
+
+        let mut transport = MemoryTransport::new(&service, interface);
+        let client: SocketAddr = "127.0.0.1:10000".parse()?;
+        let peer: SocketAddr = "127.0.0.1:10001".parse()?;
+
+        let peer_relay = transport.allocate(peer, ("test", "test")).await?;
+        let binding = transport
+            .bind_channel(client, ("test", "test"), peer_relay, 0x4000)
+            .await?;
+
+        assert_eq!(binding.channel, 0x4000);
+
+        // The data of the peer on the channel of the client is relayed as is,
+        // whatever the padding of its length.
+        for data in ["1", "12", "123", "1234"] {
+            let mut bytes = BytesMut::with_capacity(1500);
+            ChannelData {
+                number: binding.channel,
+                bytes: data.as_bytes(),
+            }
+            .encode(&mut bytes);
+
+            transport.send(peer, &bytes).await?;
+            assert!(transport.recv(&peer).is_none());
+
+            let res = transport.recv(&client).unwrap();
+            let mut decoder = Decoder::default();
+            if let Payload::ChannelData(channel_data) = decoder.decode(&res)? {
+                assert_eq!(channel_data.number, binding.channel);
+                assert_eq!(channel_data.bytes, data.as_bytes());
+            } else {
+                unreachable!()
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_data_indication_transaction_testing() -> Result<()> {
+        // The ipv6 addresses are XORed with the transaction id too.
+        let interface: SocketAddr = "[::1]:3478".parse()?;
+        let service = turn::Service::new(
+            "localhost".to_string(),
+            vec![interface],
+            turn::ServiceOptions::default(),
+            MemoryObserver,
+        );
+
+        let mut transport = MemoryTransport::new(&service, interface);
+        let client_1: SocketAddr = "[::1]:10000".parse()?;
+        let client_2: SocketAddr = "[::1]:10001".parse()?;
+        let allocate = Method::Allocate(Kind::Request);
+        let relay_1 = memory_request(&mut transport, client_1, allocate, None)
+            .await?
+            .unwrap();
+        let relay_2 = memory_request(&mut transport, client_2, allocate, None)
+            .await?
+            .unwrap();
+
+        let create_permission = Method::CreatePermission(Kind::Request);
+        memory_request(&mut transport, client_2, create_permission, Some(relay_1)).await?;
+
+        let mut tokens = Vec::with_capacity(2);
+        for _ in 0..2 {
+            let mut bytes = BytesMut::with_capacity(1500);
+            let mut message = MessageWriter::new(Method::SendIndication, &TOKEN, &mut bytes);
+            message.append::<XorPeerAddress>(relay_2);
+            message.append::<Data>(b"hello");
+            message.flush(None)?;
+
+            transport.send(client_1, &bytes).await?;
+
+            let res = transport.recv(&client_2).unwrap();
+            let mut decoder = Decoder::default();
+            if let Payload::Message(message) = decoder.decode(&res)? {
+                assert_eq!(message.method, Method::DataIndication);
+                assert_eq!(message.get::<XorPeerAddress>(), Some(relay_1));
+                assert_eq!(message.get::<Data>(), Some(&b"hello"[..]));
+                tokens.push(message.token.to_vec());
+            } else {
+                unreachable!()
+            }
+        }
+
+        // Each Data indication has a fresh transaction id, not the one of the
+        // Send indication.
+        assert!(tokens.iter().all(|it| it.as_slice() != TOKEN.as_slice()));
+        assert_ne!(tokens[0], tokens[1]);
+
+        Ok(())
+    }
+
+    async fn memory_allocate(
+        transport: &mut MemoryTransport<MemoryObserver>,
+        client: SocketAddr,
+        password: Option<&str>,
+        nonce: Option<&str>,
+    ) -> Result<(Option<u16>, Option<String>, Option<String>)> {
+        let mut bytes = BytesMut::with_capacity(1500);
+        let mut message = MessageWriter::new(Method::Allocate(Kind::Request), &TOKEN, &mut bytes);
+        message.append::<ReqeestedTransport>(Transport::UDP);
+        message.append::<UserName>("test");
+        message.append::<Realm>("localhost");
+        if let Some(nonce) = nonce {
+            message.append::<Nonce>(nonce);
+        }
+
+        let digest = password.map(|it| long_term_credential_digest("test", it, "localhost"));
+        message.flush(digest.as_ref())?;
+
+        transport.send(client, &bytes).await?;
+        let res = transport.recv(&client).unwrap();
+
+        let mut decoder = Decoder::default();
+        if let Payload::Message(message) = decoder.decode(&res)? {
+            Ok((
+                message.get::<ErrorCode>().map(|it| it.code),
+                message.get::<Nonce>().map(|it| it.to_string()),
+                message.get::<Realm>().map(|it| it.to_string()),
+            ))
+        } else {
+            unreachable!()
+        }
+    }
+
+    #[tokio::test]
+    async fn turn_challenge_and_bad_credential_testing() -> Result<()> {
+        let interface: SocketAddr = "127.0.0.1:3478".parse()?;
+        let client: SocketAddr = "127.0.0.1:10000".parse()?;
+        let service = turn::Service::new(
+            "localhost".to_string(),
+            vec![interface],
+            turn::ServiceOptions::default(),
+            MemoryObserver,
+        );
+
+        let mut transport = MemoryTransport::new(&service, interface);
+        let unauthorized = Some(ErrorKind::Unauthorized as u16);
+        let realm = Some("localhost".to_string());
+
+        // The request without the message integrity is challenged.
+        let (code, nonce, challenge_realm) =
+            memory_allocate(&mut transport, client, None, None).await?;
+        assert_eq!(code, unauthorized);
+        assert_eq!(challenge_realm, realm);
+        let nonce = nonce.unwrap();
+        assert_eq!(nonce.len(), 16);
+
+        // The request with a wrong password is rejected, with the challenge
+        // for the retry.
+        let (code, rejected_nonce, rejected_realm) =
+            memory_allocate(&mut transport, client, Some("wrong"), Some(&nonce)).await?;
+        assert_eq!(code, unauthorized);
+        assert_eq!(rejected_nonce.as_deref(), Some(nonce.as_str()));
+        assert_eq!(rejected_realm, realm);
+
+        let (code, _, _) =
+            memory_allocate(&mut transport, client, Some("test"), Some(&nonce)).await?;
+        assert_eq!(code, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_allocation_rate_testing() -> Result<()> {
+        let interface: SocketAddr = "127.0.0.1:3478".parse()?;
+        let service = turn::Service::new(
+            "localhost".to_string(),
+            vec![interface],
+            turn::ServiceOptions {
+                allocation_rate: Some(3),
+                ..Default::default()
+            },
+            MemoryObserver,
+        );
+
+        let mut transport = MemoryTransport::new(&service, interface);
+        async fn allocate(
+            transport: &mut MemoryTransport<MemoryObserver>,
+            port: u16,
+        ) -> Result<Option<u16>> {
+            let client = SocketAddr::new("127.0.0.1".parse()?, port);
+            let (_, nonce, _) = memory_allocate(transport, client, None, None).await?;
+            let (code, _, _) =
+                memory_allocate(transport, client, Some("test"), nonce.as_deref()).await?;
+            Ok(code)
+        }
+
+        // The storm beyond the rate is shed, the challenges take no token.
+        let mut codes = Vec::with_capacity(10);
+        for port in 10000..10010 {
+            codes.push(allocate(&mut transport, port).await?);
+        }
+
+        let shed = Some(ErrorKind::InsufficientCapacity as u16);
+        assert_eq!(codes.iter().filter(|it| it.is_none()).count(), 3);
+        assert_eq!(codes[3..], [shed; 7]);
+
+        // The bucket refills over time.
+        sleep(Duration::from_millis(400)).await;
+        assert_eq!(allocate(&mut transport, 10010).await?, None);
+        assert_eq!(allocate(&mut transport, 10011).await?, shed);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_allocate_require_secure_testing() -> Result<()> {
+        let plaintext: SocketAddr = "127.0.0.1:3478".parse()?;
+        let secure: SocketAddr = "127.0.0.1:5349".parse()?;
+        let service = turn::Service::new(
+            "localhost".to_string(),
+            vec![plaintext, secure],
+            turn::ServiceOptions {
+                allocate_require_secure: true,
+                listeners: [(
+                    secure,
+                    turn::Listener {
+                        secure: true,
+                        ..Default::default()
+                    },
+                )]
+                .into_iter()
+                .collect(),
+                ..Default::default()
+            },
+            MemoryObserver,
+        );
+
+        let client: SocketAddr = "127.0.0.1:10000".parse()?;
+        let mut transport = MemoryTransport::new(&service, plaintext);
+        assert_eq!(
+            memory_allocate(&mut transport, client, Some("test"), None)
+                .await?
+                .0,
+            Some(ErrorKind::Forbidden as u16)
+        );
+
+        // The binding requests are still answered on the plaintext listener.
+        let binding = Method::Binding(Kind::Request);
+        memory_request(&mut transport, client, binding, None).await?;
+
+        let mut transport = MemoryTransport::new(&service, secure);
+        let allocate = Method::Allocate(Kind::Request);
+        ensure!(memory_request(&mut transport, client, allocate, None)
+            .await?
+            .is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_allocate_tcp_transport_testing() -> Result<()> {
+        let interface: SocketAddr = "127.0.0.1:3478".parse()?;
+        let client: SocketAddr = "127.0.0.1:10000".parse()?;
+        let service = turn::Service::new(
+            "localhost".to_string(),
+            vec![interface],
+            turn::ServiceOptions::default(),
+            MemoryObserver,
+        );
+
+        let mut transport = MemoryTransport::new(&service, interface);
+        let (_, nonce, _) = memory_allocate(&mut transport, client, None, None).await?;
+        let digest = long_term_credential_digest("test", "test", "localhost");
+
+        // The TCP relay of RFC 6062 is refused, and no allocation is made.
+        let mut bytes = BytesMut::with_capacity(1500);
+        let mut message = MessageWriter::new(Method::Allocate(Kind::Request), &TOKEN, &mut bytes);
+        message.append::<ReqeestedTransport>(Transport::TCP);
+        message.append::<UserName>("test");
+        message.append::<Realm>("localhost");
+        message.append::<Nonce>(nonce.as_deref().unwrap());
+        message.flush(Some(&digest))?;
+
+        transport.send(client, &bytes).await?;
+        let res = transport.recv(&client).unwrap();
+
+        let mut decoder = Decoder::default();
+        if let Payload::Message(message) = decoder.decode(&res)? {
+            assert_eq!(message.method, Method::Allocate(Kind::Error));
+            assert_eq!(
+                message.get::<ErrorCode>().map(|it| it.code),
+                Some(ErrorKind::UnsupportedTransportAddress as u16)
+            );
+        } else {
+            unreachable!()
+        }
+
+        let (code, _, _) =
+            memory_allocate(&mut transport, client, Some("test"), nonce.as_deref()).await?;
+        assert_eq!(code, None);
+
+        Ok(())
+    }
+
+    async fn memory_create_permission(
+        transport: &mut MemoryTransport<MemoryObserver>,
+        client: SocketAddr,
+        peer: SocketAddr,
+    ) -> Result<Option<u16>> {
+        let digest = long_term_credential_digest("test", "test", "localhost");
+        let mut bytes = BytesMut::with_capacity(1500);
+        let mut message =
+            MessageWriter::new(Method::CreatePermission(Kind::Request), &TOKEN, &mut bytes);
+        message.append::<XorPeerAddress>(peer);
+        message.append::<UserName>("test");
+        message.append::<Realm>("localhost");
+        message.flush(Some(&digest))?;
+
+        transport.send(client, &bytes).await?;
+        let res = transport.recv(&client).unwrap();
+
+        let mut decoder = Decoder::default();
+        if let Payload::Message(message) = decoder.decode(&res)? {
+            Ok(message.get::<ErrorCode>().map(|it| it.code))
+        } else {
+            unreachable!()
+        }
+    }
+
+    #[tokio::test]
+    async fn turn_create_permission_peer_testing() -> Result<()> {
+        let interface: SocketAddr = "127.0.0.1:3478".parse()?;
+        let service = turn::Service::new(
+            "localhost".to_string(),
+            vec![interface],
+            turn::ServiceOptions::default(),
+            MemoryObserver,
+        );
+
+        let mut transport = MemoryTransport::new(&service, interface);
+        let client_1: SocketAddr = "127.0.0.1:10000".parse()?;
+        let client_2: SocketAddr = "127.0.0.1:10001".parse()?;
+        let allocate = Method::Allocate(Kind::Request);
+        let peer = memory_request(&mut transport, client_2, allocate, None)
+            .await?
+            .unwrap();
+
+        // There is no allocation on the 5-tuple of the client yet.
+        assert_eq!(
+            memory_create_permission(&mut transport, client_1, peer).await?,
+            Some(ErrorKind::AllocationMismatch as u16)
+        );
+
+        memory_request(&mut transport, client_1, allocate, None).await?;
+
+        // The peer is not the external address of the server, only the relayed
+        // address of another allocation.
+        ensure!(peer.ip() == interface.ip() && peer.port() != interface.port());
+        assert_eq!(
+            memory_create_permission(&mut transport, client_1, peer).await?,
+            None
+        );
+
+        // The family of the peer must be the family of the relayed address.
+        let ipv6: SocketAddr = format!("[::1]:{}", peer.port()).parse()?;
+        assert_eq!(
+            memory_create_permission(&mut transport, client_1, ipv6).await?,
+            Some(ErrorKind::PeerAddressFamilyMismatch as u16)
+        );
+
+        // The addresses that are not relayed by the server are forbidden, not a
+        // family mismatch.
+        assert_eq!(
+            memory_create_permission(&mut transport, client_1, "8.8.8.8:3478".parse()?).await?,
+            Some(ErrorKind::Forbidden as u16)
+        );
+
+        Ok(())
+    }
+
+    async fn memory_mobility(
+        transport: &mut MemoryTransport<MemoryObserver>,
+        client: SocketAddr,
+        method: Method,
+        ticket: Option<&[u8]>,
+    ) -> Result<(Option<u16>, Option<Vec<u8>>)> {
+        let digest = long_term_credential_digest("test", "test", "localhost");
+        let mut bytes = BytesMut::with_capacity(1500);
+        let mut message = MessageWriter::new(method, &TOKEN, &mut bytes);
+        message.append::<ReqeestedTransport>(Transport::UDP);
+        if let Some(ticket) = ticket {
+            message.append::<MobilityTicket>(ticket);
+        }
+
+        message.append::<UserName>("test");
+        message.append::<Realm>("localhost");
+        message.flush(Some(&digest))?;
+
+        transport.send(client, &bytes).await?;
+        let res = transport.recv(&client).unwrap();
+
+        let mut decoder = Decoder::default();
+        if let Payload::Message(message) = decoder.decode(&res)? {
+            Ok((
+                message.get::<ErrorCode>().map(|it| it.code),
+                message.get::<MobilityTicket>().map(|it| it.to_vec()),
+            ))
+        } else {
+            unreachable!()
+        }
+    }
+
+    #[tokio::test]
+    async fn turn_mobility_ticket_testing() -> Result<()> {
+        let interface: SocketAddr = "127.0.0.1:3478".parse()?;
+        let service = turn::Service::new(
+            "localhost".to_string(),
+            vec![interface],
+            turn::ServiceOptions {
+                mobility: true,
+                ..Default::default()
+            },
+            MemoryObserver,
+        );
+
+        let sessions = service.get_sessions();
+        let mut transport = MemoryTransport::new(&service, interface);
+        let (allocate, refresh) = (
+            Method::Allocate(Kind::Request),
+            Method::Refresh(Kind::Request),
+        );
+
+        // The ticket is only issued to the clients that ask for mobility.
+        let client = SocketAddr::new("127.0.0.1".parse()?, 10000);
+        assert_eq!(
+            memory_mobility(&mut transport, client, allocate, None).await?,
+            (None, None)
+        );
+
+        let wifi = turn::SessionAddr {
+            address: "127.0.0.1:10001".parse()?,
+            interface,
+        };
+
+        let (code, ticket) =
+            memory_mobility(&mut transport, wifi.address, allocate, Some(&[])).await?;
+        assert_eq!(code, None);
+        let ticket = ticket.unwrap();
+        let relay = sessions.get_allocation(&wifi).unwrap().relay;
+
+        // The client moves to another ip, the allocation follows the ticket.
+        let cellular = turn::SessionAddr {
+            address: "127.0.0.2:20000".parse()?,
+            interface,
+        };
+
+        let (code, renewed) =
+            memory_mobility(&mut transport, cellular.address, refresh, Some(&ticket)).await?;
+        assert_eq!(code, None);
+        assert_ne!(renewed.as_deref(), Some(ticket.as_slice()));
+        assert!(sessions.get_session(&wifi).get_ref().is_none());
+        assert_eq!(sessions.get_allocation(&cellular).unwrap().relay, relay);
+
+        // The ticket of the moved allocation and the forged tickets move nothing.
+        let bad_request = Some(ErrorKind::BadRequest as u16);
+        let other: SocketAddr = "127.0.0.3:30000".parse()?;
+        assert_eq!(
+            memory_mobility(&mut transport, other, refresh, Some(&ticket))
+                .await?
+                .0,
+            bad_request
+        );
+
+        let mut forged = renewed.unwrap();
+        forged[1] ^= 1;
+        assert_eq!(
+            memory_mobility(&mut transport, other, refresh, Some(&forged))
+                .await?
+                .0,
+            bad_request
+        );
+
+        // Without mobility the tickets are refused.
+        let service = turn::Service::new(
+            "localhost".to_string(),
+            vec![interface],
+            turn::ServiceOptions::default(),
+            MemoryObserver,
+        );
+
+        let mut transport = MemoryTransport::new(&service, interface);
+        assert_eq!(
+            memory_mobility(&mut transport, client, allocate, Some(&[])).await?,
+            (None, None)
+        );
+
+        assert_eq!(
+            memory_mobility(&mut transport, client, refresh, Some(&ticket))
+                .await?
+                .0,
+            Some(ErrorKind::MobilityForbidden as u16)
+        );
+
+        Ok(())
+    }
+
+    #[derive(Clone, Default)]
+    struct AuditObserver(Arc<std::sync::Mutex<Vec<turn::AuditEvent>>>);
+
+    impl turn::Observer for AuditObserver {
+        async fn get_password(&self, _: &turn::SessionAddr, _: &str) -> Option<String> {
+            Some("test".to_string())
+        }
+
+        fn audit(&self, event: &turn::AuditEvent) {
+            self.0.lock().unwrap().push(*event);
+        }
+    }
+
+    #[tokio::test]
+    async fn turn_relay_audit_testing() -> Result<()> {
+        let interface: SocketAddr = "127.0.0.1:3478".parse()?;
+        let client: SocketAddr = "127.0.0.1:10000".parse()?;
+        let observer = AuditObserver::default();
+        let service = turn::Service::new(
+            "localhost".to_string(),
+            vec![interface],
+            turn::ServiceOptions::default(),
+            observer.clone(),
+        );
+
+        let mut transport = MemoryTransport::new(&service, interface);
+        let peer = transport
+            .allocate("127.0.0.1:10001".parse()?, ("test", "test"))
+            .await?;
+
+        let started = std::time::SystemTime::now();
+        let binding = transport
+            .bind_channel(client, ("test", "test"), peer, 0x4000)
+            .await?;
+
+        // The permission and the channel are both audited, with the whole
+        // 5-tuple of the relay path.
+        let events = observer.0.lock().unwrap().clone();
+        let client = turn::SessionAddr {
+            address: client,
+            interface,
+        };
+
+        assert_eq!(
+            events
+                .iter()
+                .map(|it| (it.kind, it.client, it.relay, it.peer))
+                .collect::<Vec<_>>(),
+            [
+                (turn::AuditKind::Permission, client, binding.relay, peer),
+                (
+                    turn::AuditKind::Channel(0x4000),
+                    client,
+                    binding.relay,
+                    peer
+                ),
+            ]
+        );
+
+        ensure!(events.iter().all(|it| it.time >= started));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_load_shedding_testing() -> Result<()> {
+        let interface: SocketAddr = "127.0.0.1:3478".parse()?;
+        let service = turn::Service::new(
+            "localhost".to_string(),
+            vec![interface],
+            turn::ServiceOptions {
+                max_in_flight: Some(1),
+                ..Default::default()
+            },
+            MemoryObserver,
+        );
+
+        let mut transport = MemoryTransport::new(&service, interface);
+        let client: SocketAddr = "127.0.0.1:10000".parse()?;
+        let allocate = Method::Allocate(Kind::Request);
+        let peer = memory_request(&mut transport, "127.0.0.1:10001".parse()?, allocate, None)
+            .await?
+            .unwrap();
+        let other = memory_request(&mut transport, "127.0.0.1:10002".parse()?, allocate, None)
+            .await?
+            .unwrap();
+
+        memory_request(&mut transport, client, allocate, None).await?;
+        assert_eq!(
+            memory_create_permission(&mut transport, client, peer).await?,
+            None
+        );
+
+        // A stalled request holds the server over the threshold.
+        let shedder = service.get_load_shedder().unwrap();
+        let stalled = shedder.enter();
+
+        let (code, _, _) = memory_allocate(
+            &mut transport,
+            "127.0.0.1:10003".parse()?,
+            Some("test"),
+            None,
+        )
+        .await?;
+        assert_eq!(code, Some(ErrorKind::InsufficientCapacity as u16));
+        assert_eq!(
+            memory_create_permission(&mut transport, client, other).await?,
+            Some(ErrorKind::AllocationQuotaReached as u16)
+        );
+
+        // The binding requests and the existing permissions are still served.
+        let binding = Method::Binding(Kind::Request);
+        memory_request(&mut transport, client, binding, None).await?;
+        assert_eq!(
+            memory_create_permission(&mut transport, client, peer).await?,
+            None
+        );
+
+        drop(stalled);
+        assert_eq!(shedder.in_flight(), 0);
+        assert_eq!(
+            memory_create_permission(&mut transport, client, other).await?,
+            None
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_relay_pins_testing() -> Result<()> {
+        let interface: SocketAddr = "127.0.0.1:3478".parse()?;
+        let dedicated: IpAddr = "127.0.0.3".parse()?;
+        let pool: [IpAddr; 3] = [interface.ip(), "127.0.0.2".parse()?, dedicated];
+        let service = turn::Service::new(
+            "localhost".to_string(),
+            vec![interface],
+            turn::ServiceOptions {
+                relay_addresses: [(interface, pool[1..].to_vec())].into_iter().collect(),
+                relay_pins: turn::policy::RelayPins::new([
+                    ("acme:*".to_string(), dedicated),
+                    ("ghost".to_string(), "10.0.0.1".parse()?),
+                ]),
+                ..Default::default()
+            },
+            MemoryObserver,
+        );
+
+        // The allocations of the tenant are all relayed on its dedicated ip.
+        let mut transport = MemoryTransport::new(&service, interface);
+        for port in 10000..10004 {
+            let client = SocketAddr::new(interface.ip(), port);
+            let relay = transport.allocate(client, ("acme:alice", "test")).await?;
+            assert_eq!(relay.ip(), dedicated);
+        }
+
+        // The other users, and a pin to an ip that is not of the interface,
+        // fall back to the rotation.
+        let relays = [
+            transport
+                .allocate("127.0.0.1:10004".parse()?, ("test", "test"))
+                .await?,
+            transport
+                .allocate("127.0.0.1:10005".parse()?, ("test", "test"))
+                .await?,
+            transport
+                .allocate("127.0.0.1:10006".parse()?, ("ghost", "test"))
+                .await?,
+        ];
+
+        assert_eq!(relays.map(|it| it.ip()), pool);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_unknown_optional_attribute_testing() -> Result<()> {
+        let interface: SocketAddr = "127.0.0.1:3478".parse()?;
+        let client: SocketAddr = "127.0.0.1:10000".parse()?;
+        let service = turn::Service::new(
+            "localhost".to_string(),
+            vec![interface],
+            turn::ServiceOptions::default(),
+            MemoryObserver,
+        );
+
+        let mut transport = MemoryTransport::new(&service, interface);
+
+        let mut bytes = BytesMut::with_capacity(1500);
+        let mut message = MessageWriter::new(Method::Binding(Kind::Request), &TOKEN, &mut bytes);
+        message.append::<Software>("client");
+        message.flush(None)?;
+
+        // The comprehension-optional attributes that are unknown to the server,
+        // one of them before the SOFTWARE and one with a padded value after it.
+        let mut request = bytes[..20].to_vec();
+        request.extend_from_slice(&[0x8F, 0xFF, 0x00, 0x04, 1, 2, 3, 4]);
+        request.extend_from_slice(&bytes[20..]);
+        request.extend_from_slice(&[0xC0, 0x01, 0x00, 0x03, 1, 2, 3, 0]);
+
+        let size = (request.len() - 20) as u16;
+        request[2..4].copy_from_slice(&size.to_be_bytes());
+
+        transport.send(client, &request).await?;
+        let res = transport.recv(&client).unwrap();
+
+        let mut decoder = Decoder::default();
+        if let Payload::Message(message) = decoder.decode(&res)? {
+            assert_eq!(message.method, Method::Binding(Kind::Response));
+            assert!(message.get::<ErrorCode>().is_none());
+            assert_eq!(message.get::<XorMappedAddress>(), Some(client));
+        } else {
+            unreachable!()
+        }
+
+        Ok(())
+    }
+
+    #[derive(Clone, Default)]
+    struct EvictionObserver(Arc<std::sync::Mutex<Vec<(&'static str, turn::SessionAddr)>>>);
+
+    impl turn::Observer for EvictionObserver {
+        async fn get_password(&self, _: &turn::SessionAddr, _: &str) -> Option<String> {
+            Some("test".to_string())
+        }
+
+        fn evicted(&self, addr: &turn::SessionAddr, _: &str) {
+            self.0.lock().unwrap().push(("evicted", *addr));
+        }
+
+        fn closed(&self, addr: &turn::SessionAddr, _: &str, _: Option<&turn::AllocationContext>) {
+            self.0.lock().unwrap().push(("closed", *addr));
+        }
+    }
+
+    #[tokio::test]
+    async fn turn_eviction_testing() -> Result<()> {
+        let interface: SocketAddr = "127.0.0.1:3478".parse()?;
+        let client: SocketAddr = "127.0.0.1:10000".parse()?;
+        let observer = EvictionObserver::default();
+        let service = turn::Service::new(
+            "localhost".to_string(),
+            vec![interface],
+            turn::ServiceOptions::default(),
+            observer.clone(),
+        );
+
+        let mut transport = MemoryTransport::new(&service, interface);
+        transport.allocate(client, ("test", "test")).await?;
+
+        let addr = turn::SessionAddr {
+            address: client,
+            interface,
+        };
+
+        // The eviction is notified before the session is closed, and the
+        // session is gone at once.
+        let sessions = service.get_sessions();
+        assert!(sessions.evict(&addr));
+        assert!(sessions.get_session(&addr).get_ref().is_none());
+        assert_eq!(
+            *observer.0.lock().unwrap(),
+            [("evicted", addr), ("closed", addr)]
+        );
+
+        assert!(!sessions.evict(&addr));
+        assert_eq!(observer.0.lock().unwrap().len(), 2);
+
+        // The client can allocate again.
+        transport.allocate(client, ("test", "test")).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_max_relay_paths_testing() -> Result<()> {
+        let interface: SocketAddr = "127.0.0.1:3478".parse()?;
+        let client: SocketAddr = "127.0.0.1:10000".parse()?;
+        let service = turn::Service::new(
+            "localhost".to_string(),
+            vec![interface],
+            turn::ServiceOptions {
+                max_relay_paths: Some(2),
+                ..Default::default()
+            },
+            MemoryObserver,
+        );
+
+        let mut transport = MemoryTransport::new(&service, interface);
+        let peer = transport
+            .allocate("127.0.0.1:10001".parse()?, ("test", "test"))
+            .await?;
+        let other = transport
+            .allocate("127.0.0.1:10002".parse()?, ("test", "test"))
+            .await?;
+
+        // The permission and the channel of the peer fill the table.
+        transport
+            .bind_channel(client, ("test", "test"), peer, 0x4000)
+            .await?;
+        assert_eq!(service.get_sessions().relay_paths(), 2);
+
+        // The next peer is rejected, the existing permission is refreshed.
+        assert_eq!(
+            memory_create_permission(&mut transport, client, other).await?,
+            Some(ErrorKind::InsufficientCapacity as u16)
+        );
+
+        assert_eq!(
+            memory_create_permission(&mut transport, client, peer).await?,
+            None
+        );
+        assert_eq!(service.get_sessions().relay_paths(), 2);
+
+        // The paths of the closed allocation are released.
+        service.get_sessions().refresh(
+            &turn::SessionAddr {
+                address: client,
+                interface,
+            },
+            0,
+        );
+
+        transport.allocate(client, ("test", "test")).await?;
+        assert_eq!(
+            memory_create_permission(&mut transport, client, other).await?,
+            None
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_drain_listener_testing() -> Result<()> {
+        let drained: SocketAddr = "127.0.0.1:3478".parse()?;
+        let active: SocketAddr = "127.0.0.1:3479".parse()?;
+        let alternate: SocketAddr = "192.0.2.1:3478".parse()?;
+        let client: SocketAddr = "127.0.0.1:10000".parse()?;
+        let service = turn::Service::new(
+            "localhost".to_string(),
+            vec![drained, active],
+            turn::ServiceOptions::default(),
+            MemoryObserver,
+        );
+
+        let mut transport = MemoryTransport::new(&service, drained);
+        let mut other = MemoryTransport::new(&service, active);
+        let peer = other
+            .allocate("127.0.0.1:10001".parse()?, ("test", "test"))
+            .await?;
+
+        transport.allocate(client, ("test", "test")).await?;
+        assert!(!service.drain_listener("127.0.0.1:3480".parse()?, None));
+        assert!(service.drain_listener(drained, Some(alternate)));
+
+        // The new allocation on the drained listener is redirected.
+        let digest = long_term_credential_digest("test", "test", "localhost");
+        let mut bytes = BytesMut::with_capacity(1500);
+        let mut message = MessageWriter::new(Method::Allocate(Kind::Request), &TOKEN, &mut bytes);
+        message.append::<ReqeestedTransport>(Transport::UDP);
+        message.append::<UserName>("test");
+        message.append::<Realm>("localhost");
+        message.flush(Some(&digest))?;
+
+        let redirected: SocketAddr = "127.0.0.1:10002".parse()?;
+        transport.send(redirected, &bytes).await?;
+        let res = transport.recv(&redirected).unwrap();
+
+        let mut decoder = Decoder::default();
+        if let Payload::Message(message) = decoder.decode(&res)? {
+            assert_eq!(
+                message.get::<ErrorCode>().map(|it| it.code),
+                Some(ErrorKind::TryAlternate as u16)
+            );
+
+            assert_eq!(message.get::<AlternateServer>(), Some(alternate));
+            assert!(message.integrity(&digest).is_ok());
+        } else {
+            unreachable!()
+        }
+
+        // The other listener and the existing allocation keep working.
+        other
+            .allocate("127.0.0.1:10003".parse()?, ("test", "test"))
+            .await?;
+
+        assert_eq!(
+            memory_create_permission(&mut transport, client, peer).await?,
+            None
+        );
+
+        assert!(service.resume_listener(&drained));
+        assert!(!service.resume_listener(&drained));
+        transport.allocate(redirected, ("test", "test")).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_send_indication_empty_data_testing() -> Result<()> {
+        let interface: SocketAddr = "127.0.0.1:3478".parse()?;
+        let service = turn::Service::new(
+            "localhost".to_string(),
+            vec![interface],
+            turn::ServiceOptions {
+                diagnostic_indications: true,
+                ..Default::default()
+            },
+            MemoryObserver,
+        );
+
+        let mut transport = MemoryTransport::new(&service, interface);
+        let client_1: SocketAddr = "127.0.0.1:10000".parse()?;
+        let client_2: SocketAddr = "127.0.0.1:10001".parse()?;
+        let relay_1 = transport.allocate(client_1, ("test", "test")).await?;
+        let relay_2 = transport.allocate(client_2, ("test", "test")).await?;
+        assert_eq!(
+            memory_create_permission(&mut transport, client_2, relay_1).await?,
+            None
+        );
+
+        // The empty data is relayed as an empty datagram.
+        let mut bytes = BytesMut::with_capacity(1500);
+        let mut message = MessageWriter::new(Method::SendIndication, &TOKEN, &mut bytes);
+        message.append::<XorPeerAddress>(relay_2);
+        message.append::<Data>(&[]);
+        message.flush(None)?;
+
+        transport.send(client_1, &bytes).await?;
+        assert!(transport.recv(&client_1).is_none());
+
+        let res = transport.recv(&client_2).unwrap();
+        let mut decoder = Decoder::default();
+        if let Payload::Message(message) = decoder.decode(&res)? {
+            assert_eq!(message.method, Method::DataIndication);
+            assert_eq!(message.get::<XorPeerAddress>(), Some(relay_1));
+            assert_eq!(message.get::<Data>(), Some(&[][..]));
+        } else {
+            unreachable!()
+        }
+
+        // The missing data is discarded, the diagnostic indication says why.
+        let mut bytes = BytesMut::with_capacity(1500);
+        let mut message = MessageWriter::new(Method::SendIndication, &TOKEN, &mut bytes);
+        message.append::<XorPeerAddress>(relay_2);
+        message.flush(None)?;
+
+        transport.send(client_1, &bytes).await?;
+        assert!(transport.recv(&client_2).is_none());
+
+        let res = transport.recv(&client_1).unwrap();
+        let mut decoder = Decoder::default();
+        if let Payload::Message(message) = decoder.decode(&res)? {
+            assert_eq!(message.method, Method::DataIndication);
+            assert_eq!(
+                message.get::<ErrorCode>().map(|it| it.code),
+                Some(ErrorKind::BadRequest as u16)
+            );
+
+            assert_eq!(message.get::<Data>(), None);
+        } else {
+            unreachable!()
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_max_peer_addresses_testing() -> Result<()> {
+        let interface: SocketAddr = "127.0.0.1:3478".parse()?;
+        let client: SocketAddr = "127.0.0.1:10000".parse()?;
+        let service = turn::Service::new(
+            "localhost".to_string(),
+            vec![interface],
+            turn::ServiceOptions {
+                max_peer_addresses: Some(10),
+                ..Default::default()
+            },
+            MemoryObserver,
+        );
+
+        let mut transport = MemoryTransport::new(&service, interface);
+        let peer = transport
+            .allocate("127.0.0.1:10001".parse()?, ("test", "test"))
+            .await?;
+
+        transport.allocate(client, ("test", "test")).await?;
+
+        let digest = long_term_credential_digest("test", "test", "localhost");
+        for (count, code) in [(11, Some(ErrorKind::BadRequest as u16)), (10, None)] {
+            let mut bytes = BytesMut::with_capacity(1500);
+            let mut message =
+                MessageWriter::new(Method::CreatePermission(Kind::Request), &TOKEN, &mut bytes);
+            for _ in 0..count {
+                message.append::<XorPeerAddress>(peer);
+            }
+
+            message.append::<UserName>("test");
+            message.append::<Realm>("localhost");
+            message.flush(Some(&digest))?;
+
+            transport.send(client, &bytes).await?;
+            let res = transport.recv(&client).unwrap();
+
+            let mut decoder = Decoder::default();
+            if let Payload::Message(message) = decoder.decode(&res)? {
+                assert_eq!(message.get::<ErrorCode>().map(|it| it.code), code);
+            } else {
+                unreachable!()
+            }
+        }
+
+        // The repeated peer installs a single permission.
+        assert_eq!(service.get_sessions().relay_paths(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_binding_response_port_testing() -> Result<()> {
+        let interface: SocketAddr = "127.0.0.1:3478".parse()?;
+        let client: SocketAddr = "127.0.0.1:10000".parse()?;
+        let requested: SocketAddr = "127.0.0.1:10001".parse()?;
+        let service = turn::Service::new(
+            "localhost".to_string(),
+            vec![interface],
+            turn::ServiceOptions::default(),
+            MemoryObserver,
+        );
+
+        let mut transport = MemoryTransport::new(&service, interface);
+        let binding = |port: Option<u16>| {
+            let mut bytes = BytesMut::with_capacity(1500);
+            let mut message =
+                MessageWriter::new(Method::Binding(Kind::Request), &TOKEN, &mut bytes);
+            if let Some(port) = port {
+                message.append::<ResponsePort>(port);
+            }
+
+            message.flush(None).map(|_| bytes)
+        };
+
+        // The response is sent to the requested port of the client.
+        transport
+            .send(client, &binding(Some(requested.port()))?)
+            .await?;
+        assert!(transport.recv(&client).is_none());
+
+        let res = transport.recv(&requested).unwrap();
+        let mut decoder = Decoder::default();
+        if let Payload::Message(message) = decoder.decode(&res)? {
+            assert_eq!(message.method, Method::Binding(Kind::Response));
+            assert_eq!(message.get::<XorMappedAddress>(), Some(client));
+        } else {
+            unreachable!()
+        }
+
+        // The port 0 and the malformed attribute are rejected to the source.
+        // The malformed attribute has a 2 bytes port, without the padding.
+        let mut malformed = vec![0x00, 0x01, 0x00, 0x08, 0x21, 0x12, 0xa4, 0x42];
+        malformed.extend_from_slice(TOKEN.as_slice());
+        malformed.extend_from_slice(&[0x00, 0x27, 0x00, 0x02, 0x27, 0x11, 0x00, 0x00]);
+
+        for bytes in [binding(Some(0))?.to_vec(), malformed] {
+            transport.send(client, &bytes).await?;
+            assert!(transport.recv(&requested).is_none());
+
+            let res = transport.recv(&client).unwrap();
+            let mut decoder = Decoder::default();
+            if let Payload::Message(message) = decoder.decode(&res)? {
+                assert_eq!(message.method, Method::Binding(Kind::Error));
+                assert_eq!(
+                    message.get::<ErrorCode>().map(|it| it.code),
+                    Some(ErrorKind::BadRequest as u16)
+                );
+            } else {
+                unreachable!()
+            }
+        }
+
+        // Without the attribute, the response is sent to the source.
+        transport.send(client, &binding(None)?).await?;
+        assert!(transport.recv(&client).is_some());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_connection_cap_testing() -> Result<()> {
+        let bind: SocketAddr = "127.0.0.1:3518".parse()?;
+        create_turn_server_with_config(Config {
+            log: Log::default(),
+            turn: Turn {
+                realm: "localhost".to_string(),
+                interfaces: vec![Interface {
+                    transport: TurnTransport::TCP,
+                    other_address: None,
+                    relay_addresses: Vec::new(),
+                    listener: Default::default(),
+                    external: bind,
+                    bind,
+                }],
+                max_connections: Some(2),
+                ..Default::default()
+            },
+            auth: Auth {
+                static_auth_secret: None,
+                static_credentials: {
+                    let mut it = HashMap::with_capacity(1);
+                    it.insert("user".to_string(), "user".to_string());
+                    it
+                },
+                ..Default::default()
+            },
+            api: Api {
+                bind: "127.0.0.1:3035".parse()?,
+                hooks: None,
+                ..Default::default()
+            },
+        })
+        .await?;
+
+        let credentials = || Credentials {
+            username: "user".to_string(),
+            password: "user".to_string(),
+        };
+
+        // The connections under the cap allocate, none of them is idle yet, so
+        // the flood past the cap has no slot to reclaim, gets 508 and is closed.
+        let mut clients = Vec::with_capacity(6);
+        for _ in 0..6 {
+            clients.push(TurnClient::new_tcp(bind, credentials()).await?);
+        }
+
+        let mut codes = Vec::with_capacity(clients.len());
+        for client in clients.iter_mut() {
+            codes.push(client.allocate_reservation(None, None).await?.err());
+        }
+
+        assert_eq!(&codes[..2], [None, None]);
+        assert!(codes[2..]
+            .iter()
+            .all(|it| *it == Some(ErrorKind::InsufficientCapacity as u16)));
+
+        // The refused connections are closed after the 508.
+        for client in clients[2..].iter_mut() {
+            assert!(client.allocate_reservation(None, None).await.is_err());
+        }
+
+        // Closing a connection frees its slot for a new one.
+        clients.truncate(1);
+        sleep(Duration::from_millis(500)).await;
+
+        let mut client = TurnClient::new_tcp(bind, credentials()).await?;
+        client.allocate().await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_tcp_disconnect_grace_transport_testing() -> Result<()> {
+        let bind: SocketAddr = "127.0.0.1:3519".parse()?;
+        create_turn_server_with_config(Config {
+            log: Log::default(),
+            turn: Turn {
+                realm: "localhost".to_string(),
+                interfaces: [TurnTransport::UDP, TurnTransport::TCP]
+                    .into_iter()
+                    .map(|transport| Interface {
+                        other_address: None,
+                        relay_addresses: Vec::new(),
+                        listener: Default::default(),
+                        external: bind,
+                        transport,
+                        bind,
+                    })
+                    .collect(),
+                tcp_disconnect_grace: Some(10),
+                ..Default::default()
+            },
+            auth: Auth {
+                static_auth_secret: None,
+                static_credentials: {
+                    let mut it = HashMap::with_capacity(1);
+                    it.insert("grace".to_string(), "grace".to_string());
+                    it
+                },
+                ..Default::default()
+            },
+            api: Api {
+                bind: "127.0.0.1:3036".parse()?,
+                hooks: None,
+                ..Default::default()
+            },
+        })
+        .await?;
+
+        let credentials = || Credentials {
+            username: "grace".to_string(),
+            password: "grace".to_string(),
+        };
+
+        let mut tcp = TurnClient::new_tcp(bind, credentials()).await?;
+        let tcp_port = tcp.allocate().await?;
+
+        drop(tcp);
+        sleep(Duration::from_millis(500)).await;
+
+        // The detached allocation stays with the tcp transport, a udp client
+        // with the same credentials gets an allocation of its own.
+        let mut udp = TurnClient::new(bind, credentials()).await?;
+        assert_ne!(udp.allocate().await?, tcp_port);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_tenant_testing() -> Result<()> {
+        let bind: SocketAddr = "127.0.0.1:3520".parse()?;
+        create_turn_server(
+            bind,
+            Auth {
+                static_auth_secret: None,
+                static_credentials: {
+                    let mut it = HashMap::with_capacity(2);
+                    it.insert("acme:alice".to_string(), "user".to_string());
+                    it.insert("bob".to_string(), "user".to_string());
+                    it
+                },
+                tenants: {
+                    let mut it = HashMap::with_capacity(1);
+                    it.insert("acme:*".to_string(), "acme".to_string());
+                    it
+                },
+                ..Default::default()
+            },
+            Api {
+                bind: "127.0.0.1:3037".parse()?,
+                hooks: None,
+                ..Default::default()
+            },
+        )
+        .await?;
+
+        let credentials = |username: &str| Credentials {
+            username: username.to_string(),
+            password: "user".to_string(),
+        };
+
+        let mut tagged = TurnClient::new(bind, credentials("acme:alice")).await?;
+        let mut untagged = TurnClient::new(bind, credentials("bob")).await?;
+        tagged.allocate().await?;
+        untagged.allocate().await?;
+
+        let controller = Controller::new("http://127.0.0.1:3037")?;
+        let session_addr = |client: &TurnClient| -> Result<SessionAddr> {
+            Ok(SessionAddr {
+                address: client.local_addr()?,
+                interface: bind,
             })
-            .await
-            .is_some());
+        };
+
+        // The tag reaches the session, the statistics of its allocation and the
+        // metrics of the tenant.
+        let addr = session_addr(&tagged)?;
+        let session = controller.get_session(&addr).await.unwrap().payload;
+        assert_eq!(session.tenant.as_deref(), Some("acme"));
+
+        let statistics = controller.get_session_statistics(&addr).await.unwrap().payload;
+        assert_eq!(statistics.tenant.as_deref(), Some("acme"));
+
+        let addr = session_addr(&untagged)?;
+        let statistics = controller.get_session_statistics(&addr).await.unwrap().payload;
+        assert_eq!(statistics.tenant, None);
+
+        // The tenant is only used by this test, so the series is not shared with
+        // the other tests running in parallel.
+        let series = "tenant_allocated{tenant=\"acme\"}";
+        let samples = parse_openmetrics(&render_openmetrics())?;
+        assert_eq!(samples.get(series).copied(), Some(1.0));
+
+        tagged.refresh(0).await?;
+
+        let samples = parse_openmetrics(&render_openmetrics())?;
+        assert_eq!(samples.get(series).copied(), Some(0.0));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn turn_rebind_testing() -> Result<()> {
+        let bind: SocketAddr = "127.0.0.1:3522".parse()?;
+        create_turn_server_with_config(Config {
+            log: Log::default(),
+            turn: Turn {
+                realm: "localhost".to_string(),
+                interfaces: vec![Interface {
+                    transport: TurnTransport::UDP,
+                    other_address: None,
+                    relay_addresses: Vec::new(),
+                    listener: Default::default(),
+                    external: bind,
+                    bind,
+                }],
+                port_change: PortChange::Lenient,
+                port_change_idle: 0,
+                ..Default::default()
+            },
+            auth: Auth {
+                static_auth_secret: None,
+                static_credentials: {
+                    let mut it = HashMap::with_capacity(1);
+                    it.insert("user".to_string(), "user".to_string());
+                    it
+                },
+                ..Default::default()
+            },
+            api: Api {
+                bind: "127.0.0.1:3039".parse()?,
+                hooks: None,
+                ..Default::default()
+            },
+        })
+        .await?;
+
+        let credentials = || Credentials {
+            username: "user".to_string(),
+            password: "user".to_string(),
+        };
+
+        let mut turn = TurnClient::new(bind, credentials()).await?;
+        let mut peer = TurnClient::new(bind, credentials()).await?;
+
+        let port = turn.allocate().await?;
+        let peer_port = peer.allocate().await?;
+        turn.create_permission(peer_port).await?;
+        peer.create_permission(port).await?;
+
+        peer.send_indication(port, b"before").await?;
+        let ret = turn.recv_indication().await?;
+        assert_eq!(ret.0, peer_port);
+        assert_eq!(ret.1, b"before");
+
+        let controller = Controller::new("http://127.0.0.1:3039")?;
+        let addr = SessionAddr {
+            address: turn.local_addr()?,
+            interface: bind,
+        };
+
+        let before = controller.get_session_statistics(&addr).await.unwrap().payload;
+
+        // The client comes back from a new port and takes the allocation over
+        // with its first authenticated request, the session, its route and its
+        // statistics are rebound to the new address together.
+        turn.reconnect_udp().await?;
+        turn.refresh_stale_nonce(600).await?;
+        turn.refresh(600).await?;
+
+        peer.send_indication(port, b"after").await?;
+        let ret = turn.recv_indication().await?;
+        assert_eq!(ret.0, peer_port);
+        assert_eq!(ret.1, b"after");
+
+        assert!(controller.get_session_statistics(&addr).await.is_none());
+
+        let moved = SessionAddr {
+            address: turn.local_addr()?,
+            interface: bind,
+        };
+
+        let after = controller.get_session_statistics(&moved).await.unwrap().payload;
+        assert!(after.received_bytes >= before.received_bytes);
+        assert!(after.send_pkts > before.send_pkts);
 
         Ok(())
     }
@@ -1,9 +1,19 @@
-use std::{collections::HashMap, fs::read_to_string, net::SocketAddr, str::FromStr};
+use std::{
+    collections::HashMap,
+    fs::read_to_string,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    ops::RangeInclusive,
+    path::PathBuf,
+    str::FromStr,
+    time::Duration,
+};
 
 use anyhow::anyhow;
 use clap::Parser;
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
+use stun::attribute::Software;
+use turn::policy::{Cidr, FamilyMode, NetworkPolicy, PortChangeMode, RateLimit, RelayPins};
 
 #[repr(C)]
 #[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
@@ -25,10 +35,48 @@ impl FromStr for Transport {
     }
 }
 
+/// The address families of the relays.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RelayFamily {
+    #[default]
+    Dual,
+    Ipv4,
+    Ipv6,
+}
+
+impl From<RelayFamily> for FamilyMode {
+    fn from(value: RelayFamily) -> Self {
+        match value {
+            RelayFamily::Dual => Self::Dual,
+            RelayFamily::Ipv4 => Self::Ipv4,
+            RelayFamily::Ipv6 => Self::Ipv6,
+        }
+    }
+}
+
+/// The handling of a client whose source port changes.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PortChange {
+    #[default]
+    Strict,
+    Lenient,
+}
+
+impl From<PortChange> for PortChangeMode {
+    fn from(value: PortChange) -> Self {
+        match value {
+            PortChange::Strict => Self::Strict,
+            PortChange::Lenient => Self::Lenient,
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Interface {
     pub transport: Transport,
-    /// turn server listen address
+    /// turn server listen address, also the source of the relayed data
     pub bind: SocketAddr,
     /// external address
     ///
@@ -37,6 +85,143 @@ pub struct Interface {
     /// you need to manually specify the server external IP
     /// address and service listening port.
     pub external: SocketAddr,
+    /// other address
+    ///
+    /// The alternate address of the interface, which is returned in the
+    /// OTHER-ADDRESS attribute of binding responses for RFC 5780 behavior
+    /// discovery. In CGNAT or cloud setups the alternate address may be a
+    /// different IP and port, so it must be the external address of another
+    /// interface of the same transport.
+    ///
+    /// The binding requests with a CHANGE-REQUEST attribute are answered from
+    /// the changed IP, the changed port or both, taken from the alternate
+    /// address, so the four combinations must be UDP interfaces. A change that
+    /// is not bound is answered with 420.
+    #[serde(default)]
+    pub other_address: Option<SocketAddr>,
+    /// relay addresses
+    ///
+    /// The additional external IP addresses of the interface, for the cloud
+    /// servers with multiple public IPs. The relayed addresses of the
+    /// allocations on the interface are assigned round-robin across the
+    /// external IP and these addresses, which spreads the relays across the
+    /// IPs. They must be of the same family as the external address.
+    #[serde(default)]
+    pub relay_addresses: Vec<IpAddr>,
+    /// listener identity
+    ///
+    /// The realm, software and options of the interface, which allows one
+    /// process to serve distinct services on different interfaces. The unset
+    /// options fall back to the options of the turn server.
+    #[serde(default, flatten)]
+    pub listener: Listener,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct Listener {
+    /// The realm of the interface.
+    pub realm: Option<String>,
+    /// The SOFTWARE attribute of the responses sent on the interface, fewer
+    /// than 128 characters.
+    pub software: Option<String>,
+    /// Overrides the echo software option of the turn server.
+    pub echo_software: Option<bool>,
+    /// Overrides the binding require auth option of the turn server.
+    pub binding_require_auth: Option<bool>,
+    /// The messages of the interface arrive over an encrypted transport,
+    /// such as the TLS or DTLS terminated by a proxy in front of it.
+    #[serde(default)]
+    pub secure: bool,
+    /// The connections of the tcp interface start with the PROXY protocol
+    /// version 2 header of a load balancer, whose source address is the
+    /// address of the client.
+    #[serde(default)]
+    pub proxy_protocol: bool,
+}
+
+/// A unix domain socket interface.
+///
+/// The interface accepts the STUN and TURN messages from a local proxy, which
+/// frames each message with the address of the original client, because the
+/// unix domain sockets have no peer address.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct UnixInterface {
+    /// The path of the unix domain socket, an existing socket file is
+    /// replaced.
+    pub path: PathBuf,
+    /// The external address of the allocations made over the socket, which
+    /// must be the external address of a udp or tcp interface.
+    pub external: SocketAddr,
+}
+
+/// A websocket interface.
+///
+/// The interface accepts the STUN and TURN messages in the binary frames of a
+/// websocket connection, for the clients behind the networks that only pass
+/// http.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct WebSocketInterface {
+    /// The address and port to which the websocket listener is bound.
+    pub bind: SocketAddr,
+    /// The external address of the allocations made over the websocket
+    /// connections, which must be the external address of a udp or tcp
+    /// interface.
+    pub external: SocketAddr,
+}
+
+/// The well-known ports of the turn server.
+///
+/// STUN and TURN over udp and tcp use port 3478 by default, the ports can be
+/// overridden when the server is not running in the common case.
+#[derive(Debug, Clone, Copy)]
+pub struct StandardPorts {
+    pub udp: u16,
+    pub tcp: u16,
+}
+
+impl Default for StandardPorts {
+    fn default() -> Self {
+        Self { udp: 3478, tcp: 3478 }
+    }
+}
+
+impl Interface {
+    /// Create the interfaces of a standard turn server, which listens on the
+    /// udp and tcp ports of the bind address and advertises the same ports on
+    /// the external address.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use turn_server::config::*;
+    ///
+    /// let interfaces = Interface::standard(
+    ///     "0.0.0.0".parse().unwrap(),
+    ///     "192.168.1.1".parse().unwrap(),
+    ///     StandardPorts::default(),
+    /// );
+    ///
+    /// assert_eq!(interfaces.len(), 2);
+    /// assert_eq!(interfaces[0].transport, Transport::UDP);
+    /// assert_eq!(interfaces[0].bind, "0.0.0.0:3478".parse().unwrap());
+    /// assert_eq!(interfaces[0].external, "192.168.1.1:3478".parse().unwrap());
+    /// assert_eq!(interfaces[1].transport, Transport::TCP);
+    /// assert_eq!(interfaces[1].bind, "0.0.0.0:3478".parse().unwrap());
+    /// assert_eq!(interfaces[1].external, "192.168.1.1:3478".parse().unwrap());
+    /// ```
+    pub fn standard(bind: IpAddr, external: IpAddr, ports: StandardPorts) -> Vec<Self> {
+        [(Transport::UDP, ports.udp), (Transport::TCP, ports.tcp)]
+            .into_iter()
+            .map(|(transport, port)| Self {
+                bind: SocketAddr::new(bind, port),
+                external: SocketAddr::new(external, port),
+                other_address: None,
+                relay_addresses: Vec::new(),
+                listener: Listener::default(),
+                transport,
+            })
+            .collect()
+    }
 }
 
 impl FromStr for Interface {
@@ -57,6 +242,9 @@ impl FromStr for Interface {
             external: external.parse::<SocketAddr>()?,
             bind: bind.parse::<SocketAddr>()?,
             transport: transport.parse()?,
+            other_address: None,
+            relay_addresses: Vec::new(),
+            listener: Listener::default(),
         })
     }
 }
@@ -79,12 +267,807 @@ pub struct Turn {
     /// ipv4 and ipv6.
     #[serde(default = "Turn::interfaces")]
     pub interfaces: Vec<Interface>,
+
+    /// unix domain socket interfaces
+    ///
+    /// For sidecar deployments, the messages can arrive over a unix domain
+    /// socket from a local proxy. Each message is framed with the address of
+    /// the original client: a big-endian u16 length of the rest of the frame,
+    /// the address family (4 or 6), the big-endian u16 port and the 4 or 16
+    /// bytes of the ip address, followed by the message. The messages sent to
+    /// the client are framed the same way. The relayed data of the allocations
+    /// made over the socket uses the relay of the external address. Requires
+    /// the `uds` feature.
+    #[serde(default)]
+    pub unix_interfaces: Vec<UnixInterface>,
+
+    /// websocket interfaces
+    ///
+    /// The messages can also arrive over websocket connections (RFC 6455),
+    /// for the clients that can only reach the server over http. After the
+    /// opening handshake, each binary frame carries one message and the
+    /// messages sent to the client are written back as binary frames. The
+    /// relayed data of the allocations made over websocket uses the relay of
+    /// the external address. Requires the `ws` feature.
+    #[serde(default)]
+    pub websocket_interfaces: Vec<WebSocketInterface>,
+
+    /// relay pins
+    ///
+    /// The relay IPs pinned to the usernames, for the tenants that must relay
+    /// from dedicated public IPs, such as for the allowlists of their peers.
+    /// The key is a username, or a prefix of the usernames followed by `*`.
+    /// The IP must be the external IP or one of the relay addresses of an
+    /// interface, the allocations of a pinned username on that interface are
+    /// relayed on the IP. The other allocations use the IPs of the interface
+    /// as usual. A username takes precedence over the prefixes, and a longer
+    /// prefix over a shorter one.
+    #[serde(default)]
+    pub relay_pins: HashMap<String, IpAddr>,
+
+    /// echo software
+    ///
+    /// By default, the binding response always contains the SOFTWARE
+    /// attribute. If this option is enabled, the SOFTWARE attribute is only
+    /// included when the binding request also contains it, which reduces
+    /// the response size for minimal clients.
+    #[serde(default)]
+    pub echo_software: bool,
+
+    /// send retries
+    ///
+    /// When sending on the udp socket fails because the send buffer is
+    /// temporarily full, the packet is retried this number of times with a
+    /// tiny backoff before it is dropped. Permanent errors are never
+    /// retried.
+    #[serde(default = "Turn::send_retries")]
+    pub send_retries: usize,
+
+    /// send retry queue
+    ///
+    /// The number of the failed sends each udp socket keeps for the retries,
+    /// the retries are sent in order, and the sends over the limit are
+    /// dropped and counted.
+    #[serde(default = "Turn::send_retry_queue")]
+    pub send_retry_queue: usize,
+
+    /// binding require auth
+    ///
+    /// Binding requests are unauthenticated per STUN and are always answered,
+    /// even when the TURN requests require authentication, which ICE
+    /// connectivity checks depend on. For locked-down environments, binding
+    /// requests can also be required to carry the long-term credential.
+    #[serde(default)]
+    pub binding_require_auth: bool,
+
+    /// allocate require secure
+    ///
+    /// Reject the allocate requests arrived on the interfaces that are not
+    /// marked secure with a 403 (Forbidden) error, so that only the
+    /// encrypted transports can relay. The binding requests stay open on all
+    /// interfaces for ICE.
+    #[serde(default)]
+    pub allocate_require_secure: bool,
+
+    /// binding allow
+    ///
+    /// The networks in the CIDR notation whose binding requests are
+    /// answered, for internal STUN servers that only serve known networks.
+    /// The binding requests of the other networks are silently dropped, so
+    /// that the server does not acknowledge its existence to them. All
+    /// networks are allowed when the list is empty.
+    #[serde(default)]
+    pub binding_allow: Vec<String>,
+
+    /// binding deny
+    ///
+    /// The networks in the CIDR notation whose binding requests are silently
+    /// dropped, which takes precedence over the allowed networks.
+    #[serde(default)]
+    pub binding_deny: Vec<String>,
+
+    /// legacy binding
+    ///
+    /// Answer the binding requests of the classic STUN (RFC 3489) clients,
+    /// which predate the magic cookie and do not understand the
+    /// XOR-MAPPED-ADDRESS attribute, with the MAPPED-ADDRESS attribute only.
+    /// Some old hardware still sends them. These requests are dropped by
+    /// default.
+    #[serde(default)]
+    pub legacy_binding: bool,
+
+    /// peer allow
+    ///
+    /// The networks in the CIDR notation of the peers that the clients can
+    /// create permissions and bind channels for, the other peers are
+    /// rejected with a 403 (Forbidden) error. All networks are allowed when
+    /// the list is empty. The peer policy can be replaced at runtime through
+    /// the api, which only affects the new permissions.
+    #[serde(default)]
+    pub peer_allow: Vec<String>,
+
+    /// peer deny
+    ///
+    /// The networks in the CIDR notation of the peers that the clients cannot
+    /// create permissions and bind channels for, which takes precedence over
+    /// the allowed networks.
+    #[serde(default)]
+    pub peer_deny: Vec<String>,
+
+    /// rate limit per ip
+    ///
+    /// The STUN requests per second accepted from a single client address, the
+    /// requests over the limit are silently dropped. The relayed data is not
+    /// limited. Unlimited by default.
+    pub rate_limit_per_ip: Option<u32>,
+
+    /// rate limit per network
+    ///
+    /// The STUN requests per second accepted from all the addresses of a
+    /// network aggregate together, so that a client rotating the addresses of
+    /// a subnet is throttled collectively. Both limits apply, the most
+    /// restrictive wins. Unlimited by default.
+    pub rate_limit_per_network: Option<u32>,
+
+    /// rate limit ipv4 prefix
+    ///
+    /// The prefix length of the IPv4 network aggregates of the rate limit,
+    /// the /24 networks by default.
+    #[serde(default = "Turn::rate_limit_ipv4_prefix")]
+    pub rate_limit_ipv4_prefix: u8,
+
+    /// rate limit ipv6 prefix
+    ///
+    /// The prefix length of the IPv6 network aggregates of the rate limit,
+    /// the /48 networks by default.
+    #[serde(default = "Turn::rate_limit_ipv6_prefix")]
+    pub rate_limit_ipv6_prefix: u8,
+
+    /// allocation rate
+    ///
+    /// The new allocations per second accepted by the whole server, the
+    /// allocations over the rate are rejected with 508 (Insufficient
+    /// Capacity), which sheds the allocation storms. The existing allocations
+    /// are not affected. Unlimited by default.
+    pub allocation_rate: Option<u32>,
+
+    /// max in flight
+    ///
+    /// The number of the requests in flight above which the server sheds the
+    /// new work, as when the password lookups fall behind. While overloaded,
+    /// the allocations are rejected with 508 (Insufficient Capacity) and the
+    /// permissions for new peers with 486 (Allocation Quota Reached), the
+    /// binding requests and the traffic of the existing allocations are
+    /// still served. The load is reported by the info api. Disabled by
+    /// default.
+    pub max_in_flight: Option<usize>,
+
+    /// relay family
+    ///
+    /// The address families of the relays, which is one of "dual", "ipv4"
+    /// and "ipv6". On a single-stack host the interfaces of the other family
+    /// are skipped at startup instead of failing to bind, and the allocations
+    /// of the other family are rejected. Both families are enabled by default.
+    #[serde(default)]
+    pub relay_family: RelayFamily,
+
+    /// port change
+    ///
+    /// The handling of a client whose source port changes during its
+    /// allocation, as after a NAT rebinding, which is one of "strict" and
+    /// "lenient". In the strict mode the client has to allocate again. In the
+    /// lenient mode an authenticated request from the new port, other than an
+    /// allocate request, migrates the allocation of the same IP and username
+    /// to it. Only use the lenient mode when the credentials are not shared by
+    /// the clients. Strict by default.
+    #[serde(default)]
+    pub port_change: PortChange,
+
+    /// port change idle
+    ///
+    /// The number of seconds an allocation has to be idle, neither relaying
+    /// the data of its client nor refreshed, before a client from a new port
+    /// takes it over in the lenient port change mode. The live flows of the
+    /// clients behind the same NAT keep their own allocations, zero takes the
+    /// allocation over right away. 10 seconds by default.
+    #[serde(default = "Turn::port_change_idle")]
+    pub port_change_idle: u64,
+
+    /// mobility
+    ///
+    /// Offer the mobility of RFC 8016, the clients that ask for it in their
+    /// allocate request get a ticket, which moves the allocation to a new
+    /// address of the client, such as after a switch from wifi to cellular,
+    /// with a refresh request from the new address. Unlike the lenient port
+    /// change, the ip of the client can change too. Disabled by default.
+    #[serde(default)]
+    pub mobility: bool,
+
+    /// max connections
+    ///
+    /// Limit the number of tcp, websocket and unix connections, which caps the
+    /// memory used under connection floods. When the limit is reached, the least
+    /// recently used idle connection is evicted and its session is closed at
+    /// once, with an evicted event, if there is no idle connection, the first
+    /// request of the new connection is answered with 508 (Insufficient Capacity)
+    /// and the connection is closed. The unix connections are never evicted, and
+    /// the udp clients share the route of their listener, so they are not
+    /// counted. Unlimited by default.
+    pub max_connections: Option<usize>,
+
+    /// connection idle
+    ///
+    /// The number of seconds a connection has to be idle, nothing relayed
+    /// through it, before it is evicted to make room for a new connection under
+    /// the max connections limit. 60 seconds by default.
+    #[serde(default = "Turn::connection_idle")]
+    pub connection_idle: u64,
+
+    /// max relay bandwidth
+    ///
+    /// Cap the relayed data of all allocations in bytes per second, for the
+    /// servers on metered links. The budget is a token bucket that holds one
+    /// second of the rate, and it is shared equally between the allocations
+    /// receiving data, so that one busy allocation cannot starve the others,
+    /// the data over the budget is dropped. Unlimited by default.
+    pub max_relay_bandwidth: Option<u64>,
+
+    /// max relay packet rate
+    ///
+    /// Cap the relayed packets of each allocation per second, which catches
+    /// the floods of tiny packets that stay under the bandwidth limit. Both
+    /// limits apply, and the packets over the rate are dropped and counted.
+    /// Unlimited by default.
+    pub max_relay_packet_rate: Option<u64>,
+
+    /// router queue
+    ///
+    /// Limit the number of the relayed packets queued for each connection or
+    /// udp listener, so that a slow client cannot grow the memory of the
+    /// server. The relayed packets over the limit are dropped and counted in
+    /// the `router_dropped` of the api, the other messages, such as the stun
+    /// responses, are never dropped. Unlimited by default.
+    pub router_queue: Option<usize>,
+
+    /// pin relay
+    ///
+    /// Pin the tcp connection of an allocation for the lifetime of the
+    /// allocation, so that the relay is never recycled mid-session to make
+    /// room for a new connection, and the peers always see the same relayed
+    /// address. The trade-off is memory: the pinned connections keep their
+    /// slot and buffers until the allocation expires or the client
+    /// disconnects, and new connections are refused when all connections
+    /// are pinned. The udp allocations are never recycled mid-session.
+    #[serde(default)]
+    pub pin_relay: bool,
+
+    /// tcp disconnect grace
+    ///
+    /// Keep the allocation of a disconnected tcp client, with its permissions
+    /// and channels, for this number of seconds instead of closing it at
+    /// once. A client that reconnects within the grace period takes it over
+    /// with its first authenticated request using the same credentials, so a
+    /// network blip does not force a full reallocation. The allocation is
+    /// closed once the grace period has elapsed. The websocket clients, which
+    /// run over tcp, get the same grace period. Disabled by default.
+    pub tcp_disconnect_grace: Option<u32>,
+
+    /// tcp coalesce delay
+    ///
+    /// Coalesce the messages forwarded to a tcp connection into fewer writes,
+    /// which reduces the syscalls under bursts of small messages. After the
+    /// first message is queued, the messages queued within this number of
+    /// milliseconds are written together, so keep it to a few milliseconds.
+    /// Disabled by default, each message is written at once.
+    pub tcp_coalesce_delay: Option<u64>,
+
+    /// tcp backlog
+    ///
+    /// The length of the queue of the pending connections of the tcp and
+    /// websocket listeners, the kernel may cap it to its own limit. The
+    /// connections over the backlog are refused by the kernel.
+    #[serde(default = "Turn::tcp_backlog")]
+    pub tcp_backlog: u32,
+
+    /// tcp accept rate
+    ///
+    /// The maximum number of connections accepted per second on each tcp
+    /// interface. Under a connection flood, the connections over the rate
+    /// are closed right after they are accepted, and the established
+    /// connections are not affected. Disabled by default.
+    pub tcp_accept_rate: Option<u32>,
+
+    /// buffer pool
+    ///
+    /// The number of the response buffers kept for reuse. Each connection
+    /// allocates a response buffer, with the pool the buffers of the closed
+    /// connections are reused by the new ones, which reduces the allocations
+    /// under a high connection churn. Size it to the number of concurrent
+    /// connections. Disabled by default.
+    #[serde(default)]
+    pub buffer_pool: usize,
+
+    /// inactivity timeout
+    ///
+    /// Deny the refresh of allocations that have not relayed any data sent
+    /// by the client for this number of seconds, so that the client has to
+    /// reallocate if it is genuinely active. This trims allocations that are
+    /// refreshed but no longer used. The relayed addresses are ports of the
+    /// listener socket, an idle allocation holds no socket of its own, only its
+    /// port. Disabled by default.
+    pub inactivity_timeout: Option<u64>,
+
+    /// verify cache ttl
+    ///
+    /// Cache the successful message integrity checks for this number of
+    /// seconds, so that the retransmitted requests skip the HMAC. An entry
+    /// only matches a byte-identical request from the same address, so the
+    /// cache does not weaken the check. Keep it within the retransmit window
+    /// of a few seconds. Disabled by default.
+    pub verify_cache_ttl: Option<u64>,
+
+    /// nonce lifetime
+    ///
+    /// The maximum age of a nonce in seconds. The requests carrying an
+    /// expired nonce are rejected with a 438 (Stale Nonce) error and a fresh
+    /// nonce, which the client retries with. A shorter window narrows the
+    /// replay of the captured requests at the cost of more round trips, a
+    /// longer window tolerates the slow clients. 600 seconds by default.
+    #[serde(default = "Turn::nonce_lifetime")]
+    pub nonce_lifetime: u64,
+
+    /// auth failure delay
+    ///
+    /// Delay the responses to the requests that failed the authentication by
+    /// at least this number of milliseconds, so that an attacker cannot tell
+    /// an unknown username from a bad password by the response latency. The
+    /// challenges of the requests without credentials are not delayed. At
+    /// most 4096 delayed responses are pending at once, the responses over it
+    /// are dropped. Disabled by default.
+    pub auth_failure_delay: Option<u64>,
+
+    /// auth failure jitter
+    ///
+    /// A random number of milliseconds up to this value is added to the auth
+    /// failure delay, the total delay is bounded by the sum of both.
+    #[serde(default)]
+    pub auth_failure_jitter: u64,
+
+    /// diagnostic indications
+    ///
+    /// Reply a Data indication with the ERROR-CODE attribute when a send
+    /// indication is discarded, for example when there is no permission for
+    /// the peer, so that the client can tell why the data was not relayed.
+    /// The indication has no DATA attribute, so that the compliant clients
+    /// ignore it. This is not part of the standard, disabled by default.
+    #[serde(default)]
+    pub diagnostic_indications: bool,
+
+    /// log relay drops
+    ///
+    /// Log the peer of the relayed data that is dropped because no
+    /// allocation has installed a permission or bound a channel for it, for
+    /// debugging the clients whose media does not arrive. The drops are
+    /// always counted, they are only logged with this option, disabled by
+    /// default.
+    #[serde(default)]
+    pub log_relay_drops: bool,
+
+    /// max relayed payload
+    ///
+    /// The maximum payload of the ChannelData messages in bytes. The larger
+    /// ChannelData messages are dropped, and are replied with a diagnostic
+    /// indication when the diagnostic indications are enabled. Raise it when
+    /// the clients and the peers are on a path with jumbo frames, the limit
+    /// can not exceed 65535. The standard limit of 1468 bytes, which fits an
+    /// Ethernet MTU, is used by default.
+    pub max_relayed_payload: Option<usize>,
+
+    /// max channels
+    ///
+    /// The maximum number of channels that a client can bind to an
+    /// allocation. The channel bind requests over the limit are rejected
+    /// with a 508 (Insufficient Capacity) error, the channels are released
+    /// when the allocation expires. 1024 channels by default.
+    #[serde(default = "Turn::max_channels")]
+    pub max_channels: usize,
+
+    /// max relay paths
+    ///
+    /// The maximum number of the relay paths of the whole server, that is the
+    /// permissions and the channels of all allocations together, which caps
+    /// the memory of the relay tables. The requests for new paths over the
+    /// limit are rejected with a 508 (Insufficient Capacity) error, the
+    /// existing paths are still refreshed, and the paths of the expired
+    /// allocations are released first. The count is reported by the info api
+    /// and the metrics. Unlimited by default.
+    pub max_relay_paths: Option<usize>,
+
+    /// max peer addresses
+    ///
+    /// The maximum number of the peer addresses in a create permission
+    /// request, which bounds the work of a single request. The requests with
+    /// more peers are rejected with a 400 (Bad Request) error. 10 peers by
+    /// default.
+    #[serde(default = "Turn::max_peer_addresses")]
+    pub max_peer_addresses: usize,
+
+    /// recv buffer size
+    ///
+    /// The kernel receive buffer size (SO_RCVBUF) of the listener sockets in
+    /// bytes. High packet rates need a larger buffer to avoid drops during
+    /// bursts, the kernel may clamp the size to its own limit. The system
+    /// default is used by default.
+    pub recv_buffer_size: Option<usize>,
+
+    /// send buffer size
+    ///
+    /// The kernel send buffer size (SO_SNDBUF) of the listener sockets in
+    /// bytes, the kernel may clamp the size to its own limit. The system
+    /// default is used by default.
+    pub send_buffer_size: Option<usize>,
+
+    /// freebind
+    ///
+    /// Bind the listener sockets with IP_FREEBIND (Linux only), so that the
+    /// listen address does not have to be local yet. This is for the
+    /// active/standby failover, where the standby binds the virtual IP before
+    /// keepalived moves it over. The listener sockets are also the relays, so
+    /// the relayed addresses move with the virtual IP. Disabled by default.
+    #[serde(default)]
+    pub freebind: bool,
+
+    /// dscp
+    ///
+    /// The DSCP (Differentiated Services Code Point) of the packets sent by
+    /// the listener sockets, such as 46 (EF) for voice, so that the networks
+    /// can give the relayed media the appropriate treatment. The listener
+    /// sockets are also the relays, so the relayed data and the responses are
+    /// marked alike. The value is from 0 to 63, the upper 6 bits of IP_TOS or
+    /// IPV6_TCLASS. The system default is kept by default.
+    pub dscp: Option<u8>,
+
+    /// control plane threads
+    ///
+    /// By default the stun requests and the relayed data are processed on
+    /// the same runtime. If this option is set, the stun requests received on
+    /// the udp interfaces, such as the binding requests of the ICE
+    /// connectivity checks, are processed on a dedicated runtime with this
+    /// number of threads, so that a relay throughput spike does not add
+    /// latency to them. The trade-off is a copy of each request and a queue
+    /// between the runtimes, the requests are dropped if the queue is full.
+    /// Disabled by default.
+    pub control_plane_threads: Option<usize>,
+}
+
+/// Parse the allowed and denied networks in the CIDR notation.
+pub fn parse_network_policy(allow: &[String], deny: &[String]) -> anyhow::Result<NetworkPolicy> {
+    let parse = |items: &[String]| items.iter().map(|it| it.parse::<Cidr>()).collect::<Result<Vec<_>, _>>();
+
+    Ok(NetworkPolicy {
+        allow: parse(allow)?,
+        deny: parse(deny)?,
+    })
 }
 
 impl Turn {
     pub fn get_externals(&self) -> Vec<SocketAddr> {
         self.interfaces.iter().map(|item| item.external).collect()
     }
+
+    /// Get the bounds of the auth failure delay.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// use turn_server::config::*;
+    ///
+    /// let mut turn = Turn::default();
+    /// assert_eq!(turn.get_auth_failure_delay(), None);
+    ///
+    /// turn.auth_failure_delay = Some(100);
+    /// turn.auth_failure_jitter = 50;
+    /// assert_eq!(
+    ///     turn.get_auth_failure_delay(),
+    ///     Some(Duration::from_millis(100)..=Duration::from_millis(150))
+    /// );
+    /// ```
+    pub fn get_auth_failure_delay(&self) -> Option<RangeInclusive<Duration>> {
+        self.auth_failure_delay.map(|delay| {
+            Duration::from_millis(delay)..=Duration::from_millis(delay.saturating_add(self.auth_failure_jitter))
+        })
+    }
+
+    /// Get the network policy of the binding requests.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use turn_server::config::*;
+    ///
+    /// let mut turn = Turn::default();
+    /// assert!(turn.get_binding_policy().unwrap().is_allowed(&"192.0.2.1".parse().unwrap()));
+    ///
+    /// turn.binding_allow = vec!["10.0.0.0/8".to_string()];
+    /// turn.binding_deny = vec!["10.0.0.1".to_string()];
+    ///
+    /// let policy = turn.get_binding_policy().unwrap();
+    /// assert!(policy.is_allowed(&"10.0.0.2".parse().unwrap()));
+    /// assert!(!policy.is_allowed(&"10.0.0.1".parse().unwrap()));
+    /// assert!(!policy.is_allowed(&"192.0.2.1".parse().unwrap()));
+    ///
+    /// turn.binding_deny = vec!["10.0.0.1/99".to_string()];
+    /// assert!(turn.get_binding_policy().is_err());
+    /// ```
+    pub fn get_binding_policy(&self) -> anyhow::Result<NetworkPolicy> {
+        parse_network_policy(&self.binding_allow, &self.binding_deny)
+    }
+
+    /// Get the network policy of the peers.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use turn_server::config::*;
+    ///
+    /// let mut turn = Turn::default();
+    /// assert!(turn.get_peer_policy().unwrap().is_allowed(&"192.0.2.1".parse().unwrap()));
+    ///
+    /// turn.peer_deny = vec!["192.0.2.0/24".to_string()];
+    /// assert!(!turn.get_peer_policy().unwrap().is_allowed(&"192.0.2.1".parse().unwrap()));
+    ///
+    /// turn.peer_allow = vec!["192.0.2".to_string()];
+    /// assert!(turn.get_peer_policy().is_err());
+    /// ```
+    pub fn get_peer_policy(&self) -> anyhow::Result<NetworkPolicy> {
+        parse_network_policy(&self.peer_allow, &self.peer_deny)
+    }
+
+    /// Get the rate of the new allocations, which is checked to be positive.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use turn_server::config::*;
+    ///
+    /// let mut turn = Turn::default();
+    /// assert_eq!(turn.get_allocation_rate().unwrap(), None);
+    ///
+    /// turn.allocation_rate = Some(100);
+    /// assert_eq!(turn.get_allocation_rate().unwrap(), Some(100));
+    ///
+    /// turn.allocation_rate = Some(0);
+    /// assert!(turn.get_allocation_rate().is_err());
+    /// ```
+    pub fn get_allocation_rate(&self) -> anyhow::Result<Option<u32>> {
+        if self.allocation_rate == Some(0) {
+            return Err(anyhow!("the allocation rate must be positive"));
+        }
+
+        Ok(self.allocation_rate)
+    }
+
+    /// Get the request rate limits, which are checked to be positive and to
+    /// have valid prefix lengths.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use turn_server::config::*;
+    ///
+    /// let mut turn = Turn::default();
+    /// assert!(turn.get_rate_limit().unwrap().is_none());
+    ///
+    /// turn.rate_limit_per_network = Some(100);
+    /// let limit = turn.get_rate_limit().unwrap().unwrap();
+    /// assert_eq!(limit.per_ip, None);
+    /// assert_eq!(limit.per_network, Some(100));
+    /// assert_eq!((limit.ipv4_prefix, limit.ipv6_prefix), (24, 48));
+    ///
+    /// turn.rate_limit_ipv4_prefix = 33;
+    /// assert!(turn.get_rate_limit().is_err());
+    ///
+    /// turn.rate_limit_ipv4_prefix = 24;
+    /// turn.rate_limit_per_ip = Some(0);
+    /// assert!(turn.get_rate_limit().is_err());
+    /// ```
+    pub fn get_rate_limit(&self) -> anyhow::Result<Option<RateLimit>> {
+        if self.rate_limit_per_ip.is_none() && self.rate_limit_per_network.is_none() {
+            return Ok(None);
+        }
+
+        if self.rate_limit_per_ip == Some(0) || self.rate_limit_per_network == Some(0) {
+            return Err(anyhow!("the rate limits must be positive"));
+        }
+
+        if self.rate_limit_ipv4_prefix > 32 || self.rate_limit_ipv6_prefix > 128 {
+            return Err(anyhow!(
+                "invalid rate limit prefix: ipv4={}, ipv6={}",
+                self.rate_limit_ipv4_prefix,
+                self.rate_limit_ipv6_prefix
+            ));
+        }
+
+        Ok(Some(RateLimit {
+            per_ip: self.rate_limit_per_ip,
+            per_network: self.rate_limit_per_network,
+            ipv4_prefix: self.rate_limit_ipv4_prefix,
+            ipv6_prefix: self.rate_limit_ipv6_prefix,
+        }))
+    }
+
+    /// Get the other address of each interface, which is checked to be the
+    /// external address of another interface of the same transport.
+    pub fn get_other_addresses(&self) -> anyhow::Result<HashMap<SocketAddr, SocketAddr>> {
+        let mut addresses = HashMap::with_capacity(self.interfaces.len());
+        for it in &self.interfaces {
+            if let Some(other) = it.other_address {
+                if other == it.external
+                    || !self
+                        .interfaces
+                        .iter()
+                        .any(|item| item.transport == it.transport && item.external == other)
+                {
+                    return Err(anyhow!(
+                        "other address is not bound by another interface: interface={}, other={}",
+                        it.external,
+                        other
+                    ));
+                }
+
+                addresses.insert(it.external, other);
+            }
+        }
+
+        Ok(addresses)
+    }
+
+    /// Get the relay addresses of each interface, which are checked to be of
+    /// the family of the interface.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use turn_server::config::*;
+    ///
+    /// let mut turn = Turn::default();
+    /// turn.interfaces = vec!["udp@127.0.0.1:3478/127.0.0.1:3478".parse().unwrap()];
+    /// assert!(turn.get_relay_addresses().unwrap().is_empty());
+    ///
+    /// turn.interfaces[0].relay_addresses = vec!["127.0.0.2".parse().unwrap()];
+    /// let addresses = turn.get_relay_addresses().unwrap();
+    /// assert_eq!(addresses.get(&turn.interfaces[0].external), Some(&vec!["127.0.0.2".parse().unwrap()]));
+    ///
+    /// turn.interfaces[0].relay_addresses = vec!["::1".parse().unwrap()];
+    /// assert!(turn.get_relay_addresses().is_err());
+    /// ```
+    pub fn get_relay_addresses(&self) -> anyhow::Result<HashMap<SocketAddr, Vec<IpAddr>>> {
+        let mut addresses = HashMap::with_capacity(self.interfaces.len());
+        for it in self.interfaces.iter().filter(|it| !it.relay_addresses.is_empty()) {
+            if let Some(ip) = it
+                .relay_addresses
+                .iter()
+                .find(|ip| ip.is_ipv4() != it.external.is_ipv4())
+            {
+                return Err(anyhow!(
+                    "relay address is not of the family of the interface: interface={}, relay={}",
+                    it.external,
+                    ip
+                ));
+            }
+
+            addresses.insert(it.external, it.relay_addresses.clone());
+        }
+
+        Ok(addresses)
+    }
+
+    /// Get the relay pins, the IP of each pin is checked to be the external
+    /// IP or a relay address of an interface.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use turn_server::config::*;
+    ///
+    /// let mut turn = Turn::default();
+    /// turn.interfaces = vec!["udp@127.0.0.1:3478/127.0.0.1:3478".parse().unwrap()];
+    /// turn.interfaces[0].relay_addresses = vec!["127.0.0.2".parse().unwrap()];
+    /// assert!(turn.get_relay_pins().unwrap().is_empty());
+    ///
+    /// turn.relay_pins.insert("acme:*".to_string(), "127.0.0.2".parse().unwrap());
+    /// let pins = turn.get_relay_pins().unwrap();
+    /// assert_eq!(pins.get("acme:alice"), Some("127.0.0.2".parse().unwrap()));
+    ///
+    /// turn.relay_pins.insert("other".to_string(), "127.0.0.3".parse().unwrap());
+    /// assert!(turn.get_relay_pins().is_err());
+    /// ```
+    pub fn get_relay_pins(&self) -> anyhow::Result<RelayPins> {
+        for (username, ip) in &self.relay_pins {
+            if !self
+                .interfaces
+                .iter()
+                .any(|it| it.external.ip() == *ip || it.relay_addresses.contains(ip))
+            {
+                return Err(anyhow!(
+                    "relay pin is not an address of the interfaces: username={}, ip={}",
+                    username,
+                    ip
+                ));
+            }
+        }
+
+        Ok(RelayPins::new(self.relay_pins.clone()))
+    }
+
+    /// Get the identity of each interface, the interfaces without their own
+    /// identity are skipped. The software of an interface must be fewer than
+    /// 128 characters, as required for the SOFTWARE attribute.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use turn_server::config::*;
+    ///
+    /// let bind = "127.0.0.1:3478".parse().unwrap();
+    /// let mut turn = Turn::default();
+    /// turn.interfaces.push(Interface {
+    ///     transport: Transport::UDP,
+    ///     bind,
+    ///     external: bind,
+    ///     other_address: None,
+    ///     relay_addresses: Vec::new(),
+    ///     listener: Default::default(),
+    /// });
+    ///
+    /// assert!(turn.get_listeners().unwrap().is_empty());
+    ///
+    /// turn.interfaces[0].listener.software = Some("example".to_string());
+    /// let listeners = turn.get_listeners().unwrap();
+    /// assert_eq!(listeners[&bind].software.as_deref(), Some("example"));
+    ///
+    /// turn.interfaces[0].listener.software = Some("x".repeat(1000));
+    /// assert!(turn.get_listeners().is_err());
+    /// ```
+    pub fn get_listeners(&self) -> anyhow::Result<HashMap<SocketAddr, turn::Listener>> {
+        for it in &self.interfaces {
+            if let Some(software) = &it.listener.software {
+                if software.chars().count() > Software::MAX_CHARS {
+                    return Err(anyhow!(
+                        "the software of the interface is over {} characters: interface={}",
+                        Software::MAX_CHARS,
+                        it.external
+                    ));
+                }
+            }
+        }
+
+        Ok(self
+            .interfaces
+            .iter()
+            .filter(|it| {
+                it.listener.realm.is_some()
+                    || it.listener.software.is_some()
+                    || it.listener.echo_software.is_some()
+                    || it.listener.binding_require_auth.is_some()
+                    || it.listener.secure
+            })
+            .map(|it| {
+                (
+                    it.external,
+                    turn::Listener {
+                        realm: it.listener.realm.clone(),
+                        software: it.listener.software.clone(),
+                        echo_software: it.listener.echo_software,
+                        binding_require_auth: it.listener.binding_require_auth,
+                        secure: it.listener.secure,
+                    },
+                )
+            })
+            .collect())
+    }
 }
 
 impl Turn {
@@ -95,6 +1078,46 @@ impl Turn {
     fn interfaces() -> Vec<Interface> {
         vec![]
     }
+
+    fn send_retries() -> usize {
+        3
+    }
+
+    fn send_retry_queue() -> usize {
+        1024
+    }
+
+    fn tcp_backlog() -> u32 {
+        1024
+    }
+
+    fn port_change_idle() -> u64 {
+        10
+    }
+
+    fn connection_idle() -> u64 {
+        60
+    }
+
+    fn max_channels() -> usize {
+        1024
+    }
+
+    fn max_peer_addresses() -> usize {
+        10
+    }
+
+    fn nonce_lifetime() -> u64 {
+        600
+    }
+
+    fn rate_limit_ipv4_prefix() -> u8 {
+        24
+    }
+
+    fn rate_limit_ipv6_prefix() -> u8 {
+        48
+    }
 }
 
 impl Default for Turn {
@@ -102,6 +1125,56 @@ impl Default for Turn {
         Self {
             realm: Self::realm(),
             interfaces: Self::interfaces(),
+            unix_interfaces: Vec::new(),
+            websocket_interfaces: Vec::new(),
+            relay_pins: HashMap::new(),
+            send_retries: Self::send_retries(),
+            send_retry_queue: Self::send_retry_queue(),
+            echo_software: false,
+            binding_require_auth: false,
+            allocate_require_secure: false,
+            binding_allow: Vec::new(),
+            binding_deny: Vec::new(),
+            legacy_binding: false,
+            peer_allow: Vec::new(),
+            peer_deny: Vec::new(),
+            rate_limit_per_ip: None,
+            rate_limit_per_network: None,
+            rate_limit_ipv4_prefix: Self::rate_limit_ipv4_prefix(),
+            rate_limit_ipv6_prefix: Self::rate_limit_ipv6_prefix(),
+            allocation_rate: None,
+            max_in_flight: None,
+            relay_family: RelayFamily::Dual,
+            port_change: PortChange::Strict,
+            port_change_idle: Self::port_change_idle(),
+            mobility: false,
+            max_connections: None,
+            connection_idle: Self::connection_idle(),
+            max_relay_bandwidth: None,
+            max_relay_packet_rate: None,
+            router_queue: None,
+            pin_relay: false,
+            tcp_disconnect_grace: None,
+            tcp_coalesce_delay: None,
+            tcp_backlog: Self::tcp_backlog(),
+            tcp_accept_rate: None,
+            buffer_pool: 0,
+            inactivity_timeout: None,
+            verify_cache_ttl: None,
+            nonce_lifetime: Self::nonce_lifetime(),
+            auth_failure_delay: None,
+            auth_failure_jitter: 0,
+            diagnostic_indications: false,
+            log_relay_drops: false,
+            max_relayed_payload: None,
+            max_channels: Self::max_channels(),
+            max_relay_paths: None,
+            max_peer_addresses: Self::max_peer_addresses(),
+            recv_buffer_size: None,
+            send_buffer_size: None,
+            freebind: false,
+            dscp: None,
+            control_plane_threads: None,
         }
     }
 }
@@ -128,28 +1201,49 @@ pub struct Api {
     /// through this service, please do not expose it directly to an unsafe
     /// environment.
     pub hooks: Option<String>,
+    /// hooks concurrency
+    ///
+    /// The maximum number of the password requests sent to the hooks service
+    /// at the same time, which protects the backend of the hooks service from
+    /// a burst of new clients. The requests beyond this limit wait for their
+    /// turn, at most 5 seconds, unlimited by default, zero is invalid.
+    pub hooks_concurrency: Option<usize>,
+    /// hooks queue
+    ///
+    /// The maximum number of the password requests waiting for their turn
+    /// when the hooks concurrency is limited, the requests beyond this limit
+    /// fail the authentication right away, and the clients retry them.
+    #[serde(default = "Api::hooks_queue")]
+    pub hooks_queue: usize,
 }
 
 impl Api {
     fn bind() -> SocketAddr {
         "127.0.0.1:3000".parse().unwrap()
     }
+
+    fn hooks_queue() -> usize {
+        1024
+    }
 }
 
 impl Default for Api {
     fn default() -> Self {
         Self {
             hooks: None,
+            hooks_concurrency: None,
+            hooks_queue: Self::hooks_queue(),
             bind: Self::bind(),
         }
     }
 }
 
-#[derive(Deserialize, Debug, Clone, Copy)]
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum LogLevel {
     Error,
     Warn,
+    #[default]
     Info,
     Debug,
     Trace,
@@ -170,12 +1264,6 @@ impl FromStr for LogLevel {
     }
 }
 
-impl Default for LogLevel {
-    fn default() -> Self {
-        Self::Info
-    }
-}
-
 impl LogLevel {
     pub fn as_level(&self) -> log::Level {
         match *self {
@@ -195,6 +1283,16 @@ pub struct Log {
     /// An enum representing the available verbosity levels of the logger.
     #[serde(default)]
     pub level: LogLevel,
+    /// audit
+    ///
+    /// Log every relay 5-tuple installed by the clients, the client, the
+    /// relayed and the peer addresses of each permission and channel binding
+    /// with the time in milliseconds since the unix epoch. The records are
+    /// written to the `audit` log target, and sent as the `audit` event of
+    /// the hooks, so that they can be routed to a tamper-evident store. They
+    /// carry no credentials. Disabled by default.
+    #[serde(default)]
+    pub audit: bool,
 }
 
 #[derive(Deserialize, Debug, Default)]
@@ -214,6 +1312,56 @@ pub struct Auth {
     /// If set, the turn server will not request external services via the HTTP
     /// Hooks API to obtain the key.
     pub static_auth_secret: Option<String>,
+    /// verify timestamp
+    ///
+    /// The usernames of the TURN REST API are `timestamp:userid`, where the
+    /// timestamp is the expiry time of the credential in seconds since the
+    /// unix epoch. With the static auth secret, reject the usernames whose
+    /// timestamp has passed or is missing. Disabled by default, since the
+    /// format of the username is only suggested by the draft.
+    #[serde(default)]
+    pub verify_timestamp: bool,
+    /// tenants
+    ///
+    /// The tenant tags of the usernames, so that the sessions, the statistics,
+    /// the metrics and the hook events can be grouped per tenant. The key is a
+    /// username, or a prefix of the usernames followed by `*`. A username takes
+    /// precedence over the prefixes, and a longer prefix over a shorter one.
+    /// The sessions of the other usernames are not tagged.
+    #[serde(default)]
+    pub tenants: HashMap<String, String>,
+}
+
+impl Auth {
+    /// Get the tenant tag of the username, see [`Auth::tenants`].
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use turn_server::config::*;
+    ///
+    /// let mut auth = Auth::default();
+    /// auth.tenants.insert("acme:*".to_string(), "acme".to_string());
+    /// auth.tenants.insert("acme:eu:*".to_string(), "acme-eu".to_string());
+    /// auth.tenants.insert("acme:vip".to_string(), "vip".to_string());
+    ///
+    /// assert_eq!(auth.get_tenant("acme:alice"), Some("acme"));
+    /// assert_eq!(auth.get_tenant("acme:eu:bob"), Some("acme-eu"));
+    /// assert_eq!(auth.get_tenant("acme:vip"), Some("vip"));
+    /// assert_eq!(auth.get_tenant("other"), None);
+    /// ```
+    pub fn get_tenant(&self, username: &str) -> Option<&str> {
+        if let Some(it) = self.tenants.get(username) {
+            return Some(it);
+        }
+
+        self.tenants
+            .iter()
+            .filter_map(|(pattern, tenant)| Some((pattern.strip_suffix('*')?, tenant)))
+            .filter(|(prefix, _)| username.starts_with(prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, tenant)| tenant.as_str())
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -272,6 +1420,19 @@ struct Cli {
     /// Example: --turn-interfaces udp@127.0.0.1:3478/127.0.0.1:3478
     #[arg(long)]
     turn_interfaces: Option<Vec<Interface>>,
+    /// Listen on the standard udp and tcp ports of all local addresses and
+    /// advertise them on the external address
+    ///
+    /// Example: --turn-standard 192.168.1.1
+    #[arg(long)]
+    turn_standard: Option<IpAddr>,
+    /// Only include the SOFTWARE attribute in the binding response when the
+    /// binding request contains it
+    #[arg(long)]
+    turn_echo_software: bool,
+    /// Require the long-term credential for binding requests
+    #[arg(long)]
+    turn_binding_require_auth: bool,
 }
 
 impl Cli {
@@ -334,6 +1495,26 @@ impl Config {
                     config.turn.interfaces.push(interface);
                 }
             }
+
+            if let Some(external) = cli.turn_standard {
+                let bind = match external {
+                    IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                    IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+                };
+
+                config
+                    .turn
+                    .interfaces
+                    .extend(Interface::standard(bind, external, StandardPorts::default()));
+            }
+
+            if cli.turn_echo_software {
+                config.turn.echo_software = true;
+            }
+
+            if cli.turn_binding_require_auth {
+                config.turn.binding_require_auth = true;
+            }
         }
 
         // Filters out transport protocols that are not enabled.
@@ -359,4 +1540,228 @@ impl Config {
 
         Ok(config)
     }
+
+    /// Check the consistency of the whole configuration, before anything is
+    /// bound, so that a half-configured server never starts.
+    ///
+    /// The options that are converted for the service are converted once
+    /// here, and the options that are only checked at runtime are checked
+    /// too. The error names the option and the offending value.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use turn_server::config::*;
+    ///
+    /// let interface = Interface {
+    ///     transport: Transport::UDP,
+    ///     bind: "0.0.0.0:3478".parse().unwrap(),
+    ///     external: "192.0.2.1:3478".parse().unwrap(),
+    ///     other_address: None,
+    ///     relay_addresses: Vec::new(),
+    ///     listener: Default::default(),
+    /// };
+    ///
+    /// let check = |f: &dyn Fn(&mut Turn)| {
+    ///     let mut turn = Turn {
+    ///         interfaces: vec![interface.clone()],
+    ///         ..Default::default()
+    ///     };
+    ///
+    ///     f(&mut turn);
+    ///     Config {
+    ///         turn,
+    ///         api: Api::default(),
+    ///         log: Log::default(),
+    ///         auth: Auth::default(),
+    ///     }
+    ///     .validate()
+    ///     .map_err(|it| it.to_string())
+    /// };
+    ///
+    /// assert!(check(&|_| ()).is_ok());
+    ///
+    /// // The external address is of another family than the bind address.
+    /// let err = check(&|it| it.interfaces[0].external = "[2001:db8::1]:3478".parse().unwrap());
+    /// assert!(err.unwrap_err().starts_with("interface external is not of the family"));
+    ///
+    /// // The clients cannot reach an unspecified or portless external address.
+    /// let err = check(&|it| it.interfaces[0].external = "0.0.0.0:3478".parse().unwrap());
+    /// assert!(err.unwrap_err().starts_with("interface external is not a reachable"));
+    ///
+    /// let err = check(&|it| it.interfaces[0].external = "192.0.2.1:0".parse().unwrap());
+    /// assert!(err.unwrap_err().starts_with("interface external is not a reachable"));
+    ///
+    /// // The same transport and address is bound twice.
+    /// let err = check(&|it| it.interfaces.push(interface.clone()));
+    /// assert!(err.unwrap_err().starts_with("interface is bound twice"));
+    ///
+    /// // The PROXY protocol header is only read on the tcp connections.
+    /// let err = check(&|it| it.interfaces[0].listener.proxy_protocol = true);
+    /// assert!(err.unwrap_err().starts_with("the proxy protocol is only supported"));
+    ///
+    /// // The websocket interface must share the external of an interface.
+    /// let err = check(&|it| {
+    ///     it.websocket_interfaces.push(WebSocketInterface {
+    ///         bind: "0.0.0.0:8080".parse().unwrap(),
+    ///         external: "192.0.2.2:3478".parse().unwrap(),
+    ///     })
+    /// });
+    ///
+    /// assert!(err.unwrap_err().starts_with("websocket interface external"));
+    ///
+    /// // The options that are converted for the service, or checked at runtime.
+    /// let err = check(&|it| it.peer_deny.push("10.0.0.0/33".to_string()));
+    /// assert!(err.unwrap_err().contains("10.0.0.0/33"));
+    ///
+    /// let err = check(&|it| it.rate_limit_per_ip = Some(0));
+    /// assert!(err.unwrap_err().contains("rate limits"));
+    ///
+    /// let err = check(&|it| it.max_relay_bandwidth = Some(0));
+    /// assert_eq!(err.unwrap_err(), "invalid max relay bandwidth: 0");
+    ///
+    /// let err = check(&|it| it.router_queue = Some(0));
+    /// assert_eq!(err.unwrap_err(), "invalid router queue: 0");
+    ///
+    /// let err = check(&|it| it.max_in_flight = Some(0));
+    /// assert_eq!(err.unwrap_err(), "invalid max in flight: 0");
+    ///
+    /// let err = check(&|it| it.max_relay_paths = Some(0));
+    /// assert_eq!(err.unwrap_err(), "invalid max relay paths: 0");
+    ///
+    /// let err = check(&|it| it.max_peer_addresses = 0);
+    /// assert_eq!(err.unwrap_err(), "invalid max peer addresses: 0");
+    ///
+    /// let err = check(&|it| it.send_retry_queue = 0);
+    /// assert_eq!(err.unwrap_err(), "invalid send retry queue: 0");
+    ///
+    /// let err = check(&|it| it.recv_buffer_size = Some(0));
+    /// assert_eq!(err.unwrap_err(), "invalid socket buffer size: 0");
+    ///
+    /// let err = check(&|it| it.dscp = Some(64));
+    /// assert_eq!(err.unwrap_err(), "invalid dscp: 64");
+    ///
+    /// let err = check(&|it| it.max_relayed_payload = Some(65536));
+    /// assert_eq!(err.unwrap_err(), "invalid max relayed payload: 65536");
+    ///
+    /// // No password request of the hooks would ever be sent.
+    /// let config = Config {
+    ///     turn: Turn {
+    ///         interfaces: vec![interface.clone()],
+    ///         ..Default::default()
+    ///     },
+    ///     api: Api {
+    ///         hooks_concurrency: Some(0),
+    ///         ..Default::default()
+    ///     },
+    ///     log: Log::default(),
+    ///     auth: Auth::default(),
+    /// };
+    ///
+    /// let err = config.validate().map_err(|it| it.to_string());
+    /// assert_eq!(err.unwrap_err(), "invalid hooks concurrency: 0");
+    /// ```
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let turn = &self.turn;
+        let externals = turn.get_externals();
+
+        for (i, it) in turn.interfaces.iter().enumerate() {
+            if it.external.is_ipv4() != it.bind.is_ipv4() {
+                return Err(anyhow!(
+                    "interface external is not of the family of the bind: bind={}, external={}",
+                    it.bind,
+                    it.external
+                ));
+            }
+
+            if it.external.ip().is_unspecified() || it.external.port() == 0 {
+                return Err(anyhow!(
+                    "interface external is not a reachable address: {}",
+                    it.external
+                ));
+            }
+
+            if it.listener.proxy_protocol && it.transport != Transport::TCP {
+                return Err(anyhow!(
+                    "the proxy protocol is only supported on the tcp interfaces: bind={}",
+                    it.bind
+                ));
+            }
+
+            if turn.interfaces[..i]
+                .iter()
+                .any(|item| item.transport == it.transport && item.bind == it.bind)
+            {
+                return Err(anyhow!(
+                    "interface is bound twice: transport={:?}, bind={}",
+                    it.transport,
+                    it.bind
+                ));
+            }
+        }
+
+        for it in &turn.unix_interfaces {
+            if !externals.contains(&it.external) {
+                return Err(anyhow!(
+                    "unix interface external is not the external of an interface: {}",
+                    it.external
+                ));
+            }
+        }
+
+        for it in &turn.websocket_interfaces {
+            if !externals.contains(&it.external) {
+                return Err(anyhow!(
+                    "websocket interface external is not the external of an interface: {}",
+                    it.external
+                ));
+            }
+        }
+
+        turn.get_binding_policy()?;
+        turn.get_peer_policy()?;
+        turn.get_rate_limit()?;
+        turn.get_allocation_rate()?;
+        turn.get_other_addresses()?;
+        turn.get_relay_addresses()?;
+        turn.get_relay_pins()?;
+        turn.get_listeners()?;
+
+        for (name, value) in [
+            ("control plane threads", turn.control_plane_threads.map(|it| it as u64)),
+            ("tcp accept rate", turn.tcp_accept_rate.map(|it| it as u64)),
+            ("max relay bandwidth", turn.max_relay_bandwidth),
+            ("max relay packet rate", turn.max_relay_packet_rate),
+            ("router queue", turn.router_queue.map(|it| it as u64)),
+            ("max in flight", turn.max_in_flight.map(|it| it as u64)),
+            ("max relay paths", turn.max_relay_paths.map(|it| it as u64)),
+            ("max peer addresses", Some(turn.max_peer_addresses as u64)),
+            ("send retry queue", Some(turn.send_retry_queue as u64)),
+            ("hooks concurrency", self.api.hooks_concurrency.map(|it| it as u64)),
+        ] {
+            if value == Some(0) {
+                return Err(anyhow!("invalid {}: 0", name));
+            }
+        }
+
+        for size in [turn.recv_buffer_size, turn.send_buffer_size].into_iter().flatten() {
+            if size == 0 || size > i32::MAX as usize {
+                return Err(anyhow!("invalid socket buffer size: {}", size));
+            }
+        }
+
+        if let Some(dscp) = turn.dscp {
+            if dscp > 63 {
+                return Err(anyhow!("invalid dscp: {}", dscp));
+            }
+        }
+
+        if let Some(size) = turn.max_relayed_payload {
+            if size > u16::MAX as usize {
+                return Err(anyhow!("invalid max relayed payload: {}", size));
+            }
+        }
+
+        Ok(())
+    }
 }
@@ -1,13 +1,15 @@
 pub mod config;
 pub mod observer;
+pub mod proxy;
 pub mod publicly;
 pub mod router;
 pub mod server;
 pub mod statistics;
+pub mod websocket;
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
-use turn::Service;
+use turn::{Service, ServiceOptions};
 
 use self::{config::Config, observer::Observer, statistics::Statistics};
 
@@ -15,18 +17,51 @@ use self::{config::Config, observer::Observer, statistics::Statistics};
 /// start the server, a function is opened to replace the main function to
 /// directly start the server.
 pub async fn startup(config: Arc<Config>) -> anyhow::Result<()> {
+    config.validate()?;
+
     let statistics = Statistics::default();
+    let router = server::create_router(&config);
     let service = Service::new(
         config.turn.realm.clone(),
         config.turn.get_externals(),
-        Observer::new(config.clone(), statistics.clone()).await?,
+        ServiceOptions {
+            echo_software: config.turn.echo_software,
+            binding_require_auth: config.turn.binding_require_auth,
+            binding_policy: config.turn.get_binding_policy()?,
+            legacy_binding: config.turn.legacy_binding,
+            peer_policy: config.turn.get_peer_policy()?,
+            rate_limit: config.turn.get_rate_limit()?,
+            allocation_rate: config.turn.get_allocation_rate()?,
+            max_in_flight: config.turn.max_in_flight,
+            relay_family: config.turn.relay_family.into(),
+            port_change: config.turn.port_change.into(),
+            port_change_idle: config.turn.port_change_idle,
+            mobility: config.turn.mobility,
+            inactivity_timeout: config.turn.inactivity_timeout,
+            disconnect_grace: config.turn.tcp_disconnect_grace,
+            other_addresses: config.turn.get_other_addresses()?.into_iter().collect(),
+            relay_addresses: config.turn.get_relay_addresses()?.into_iter().collect(),
+            relay_pins: config.turn.get_relay_pins()?,
+            listeners: config.turn.get_listeners()?.into_iter().collect(),
+            auth_failure_delay: config.turn.get_auth_failure_delay(),
+            verify_cache_ttl: config.turn.verify_cache_ttl.map(Duration::from_secs),
+            nonce_lifetime: Some(config.turn.nonce_lifetime),
+            diagnostic_indications: config.turn.diagnostic_indications,
+            max_relayed_payload: config.turn.max_relayed_payload,
+            max_channels: Some(config.turn.max_channels),
+            max_relay_paths: config.turn.max_relay_paths,
+            max_peer_addresses: Some(config.turn.max_peer_addresses),
+            allocate_require_secure: config.turn.allocate_require_secure,
+            buffer_pool: config.turn.buffer_pool,
+        },
+        Observer::new(config.clone(), statistics.clone(), router.clone()).await?,
     );
 
-    server::start(&config, &statistics, &service).await?;
+    server::start(&config, &statistics, &service, &router).await?;
 
     #[cfg(feature = "api")]
     {
-        publicly::api::start_server(config, service, statistics).await?;
+        publicly::api::start_server(config, service, statistics, router).await?;
     }
 
     // The turn server is non-blocking after it runs and needs to be kept from
@@ -1,6 +1,10 @@
-use std::{future::Future, sync::Arc};
+use std::{
+    future::Future,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use crate::{config::Config, statistics::Statistics};
+use crate::{config::Config, router::Router, statistics::Statistics};
 
 #[cfg(feature = "hooks")]
 use crate::publicly::hooks::HooksService;
@@ -10,11 +14,12 @@ use serde_json::json;
 
 use anyhow::Result;
 use base64::{prelude::BASE64_STANDARD, Engine};
-use turn::SessionAddr;
+use turn::{AllocationContext, AuditEvent, AuditKind, SessionAddr};
 
 #[derive(Clone)]
 pub struct Observer {
     config: Arc<Config>,
+    router: Router,
     #[cfg(feature = "hooks")]
     hooks: Arc<HooksService>,
     #[cfg(feature = "api")]
@@ -23,8 +28,9 @@ pub struct Observer {
 
 impl Observer {
     #[allow(unused_variables)]
-    pub async fn new(config: Arc<Config>, statistics: Statistics) -> Result<Self> {
+    pub async fn new(config: Arc<Config>, statistics: Statistics, router: Router) -> Result<Self> {
         Ok(Self {
+            router,
             #[cfg(feature = "hooks")]
             hooks: Arc::new(HooksService::new(config.clone())?),
             #[cfg(feature = "api")]
@@ -35,6 +41,7 @@ impl Observer {
 }
 
 impl turn::Observer for Observer {
+    #[allow(clippy::manual_async_fn)]
     fn get_password(&self, addr: &SessionAddr, username: &str) -> impl Future<Output = Option<String>> + Send {
         async move {
             log::info!(
@@ -53,9 +60,20 @@ impl turn::Observer for Observer {
             if let Some(it) = &self.config.auth.static_auth_secret {
                 // Because (TURN REST api) this RFC does not mandate the format of the username,
                 // only suggested values. In principle, the RFC also indicates that the
-                // timestamp part of username can be set at will, so the timestamp is not
-                // verified here, and the external web service guarantees its security by
-                // itself.
+                // timestamp part of username can be set at will, so the timestamp is only
+                // verified when asked to, otherwise the external web service guarantees its
+                // security by itself.
+                if self.config.auth.verify_timestamp && is_expired(username) {
+                    log::warn!(
+                        "auth expired: address={:?}, interface={:?}, username={:?}",
+                        addr.address,
+                        addr.interface,
+                        username,
+                    );
+
+                    return None;
+                }
+
                 return encode_password(it, username);
             }
 
@@ -70,6 +88,14 @@ impl turn::Observer for Observer {
         }
     }
 
+    /// tenant tag
+    ///
+    /// The sessions are tagged with the tenants of the usernames in the
+    /// configuration, see [`crate::config::Auth::tenants`].
+    fn get_tenant(&self, _: &SessionAddr, username: &str) -> Option<String> {
+        self.config.auth.get_tenant(username).map(|it| it.to_string())
+    }
+
     /// allocate request
     ///
     /// [rfc8489](https://tools.ietf.org/html/rfc8489)
@@ -87,18 +113,21 @@ impl turn::Observer for Observer {
     /// Known Port range) to discourage clients from using TURN to run
     /// standard services.
     #[allow(clippy::let_underscore_future)]
-    fn allocated(&self, addr: &SessionAddr, name: &str, port: u16) {
+    fn allocated(&self, addr: &SessionAddr, name: &str, port: u16, allocation: &AllocationContext) {
         log::info!(
-            "allocate: address={:?}, interface={:?}, username={:?}, port={}",
+            "allocate: address={:?}, interface={:?}, username={:?}, port={}, relay={:?}, lifetime={}",
             addr.address,
             addr.interface,
             name,
-            port
+            port,
+            allocation.relay,
+            allocation.lifetime
         );
 
         #[cfg(feature = "api")]
         {
-            self.statistics.register(*addr);
+            let tenant = self.config.auth.get_tenant(name).map(|it| it.to_string());
+            self.statistics.register(*addr, tenant);
         }
 
         #[cfg(feature = "hooks")]
@@ -110,7 +139,12 @@ impl turn::Observer for Observer {
                     "interface": addr.interface,
                 },
                 "username": name,
+                "tenant": self.config.auth.get_tenant(name),
                 "port": port,
+                "allocation": {
+                    "relay": allocation.relay,
+                    "lifetime": allocation.lifetime,
+                },
             }));
         }
     }
@@ -146,13 +180,14 @@ impl turn::Observer for Observer {
     /// transaction would initially fail but succeed on a
     /// retransmission.
     #[allow(clippy::let_underscore_future)]
-    fn channel_bind(&self, addr: &SessionAddr, name: &str, channel: u16) {
+    fn channel_bind(&self, addr: &SessionAddr, name: &str, channel: u16, allocation: &AllocationContext) {
         log::info!(
-            "channel bind: address={:?}, interface={:?}, username={:?}, channel={}",
+            "channel bind: address={:?}, interface={:?}, username={:?}, channel={}, relay={:?}",
             addr.address,
             addr.interface,
             name,
-            channel
+            channel,
+            allocation.relay
         );
 
         #[cfg(feature = "hooks")]
@@ -164,7 +199,12 @@ impl turn::Observer for Observer {
                     "interface": addr.interface,
                 },
                 "username": name,
+                "tenant": self.config.auth.get_tenant(name),
                 "channel": channel,
+                "allocation": {
+                    "relay": allocation.relay,
+                    "lifetime": allocation.lifetime,
+                },
             }));
         }
     }
@@ -205,17 +245,18 @@ impl turn::Observer for Observer {
     /// There are no mandatory attributes in the success response.
     ///
     /// > NOTE: A server need not do anything special to implement
-    /// idempotency of CreatePermission requests over UDP using the
-    /// "stateless stack approach".  Retransmitted CreatePermission
-    /// requests will simply refresh the permissions.
+    /// > idempotency of CreatePermission requests over UDP using the
+    /// > "stateless stack approach".  Retransmitted CreatePermission
+    /// > requests will simply refresh the permissions.
     #[allow(clippy::let_underscore_future)]
-    fn create_permission(&self, addr: &SessionAddr, name: &str, ports: &[u16]) {
+    fn create_permission(&self, addr: &SessionAddr, name: &str, ports: &[u16], allocation: &AllocationContext) {
         log::info!(
-            "create permission: address={:?}, interface={:?}, username={:?}, ports={:?}",
+            "create permission: address={:?}, interface={:?}, username={:?}, ports={:?}, relay={:?}",
             addr.address,
             addr.interface,
             name,
-            ports
+            ports,
+            allocation.relay
         );
 
         #[cfg(feature = "hooks")]
@@ -227,7 +268,12 @@ impl turn::Observer for Observer {
                     "interface": addr.interface,
                 },
                 "username": name,
+                "tenant": self.config.auth.get_tenant(name),
                 "ports": ports,
+                "allocation": {
+                    "relay": allocation.relay,
+                    "lifetime": allocation.lifetime,
+                },
             }));
         }
     }
@@ -251,17 +297,17 @@ impl turn::Observer for Observer {
     /// Subsequent processing depends on the "desired lifetime" value:
     ///
     /// * If the "desired lifetime" is zero, then the request succeeds and
-    /// the allocation is deleted.
+    ///   the allocation is deleted.
     ///
     /// * If the "desired lifetime" is non-zero, then the request succeeds
-    /// and the allocation's time-to-expiry is set to the "desired
-    /// lifetime".
+    ///   and the allocation's time-to-expiry is set to the "desired
+    ///   lifetime".
     ///
     /// If the request succeeds, then the server sends a success response
     /// containing:
     ///
     /// * A LIFETIME attribute containing the current value of the time-to-
-    /// expiry timer.
+    ///   expiry timer.
     ///
     /// NOTE: A server need not do anything special to implement
     /// idempotency of Refresh requests over UDP using the "stateless
@@ -272,13 +318,14 @@ impl turn::Observer for Observer {
     /// allocation has already been deleted, but the client will treat
     /// this as equivalent to a success response (see below).
     #[allow(clippy::let_underscore_future)]
-    fn refresh(&self, addr: &SessionAddr, name: &str, lifetime: u32) {
+    fn refresh(&self, addr: &SessionAddr, name: &str, lifetime: u32, allocation: &AllocationContext) {
         log::info!(
-            "refresh: address={:?}, interface={:?}, username={:?}, lifetime={}",
+            "refresh: address={:?}, interface={:?}, username={:?}, lifetime={}, relay={:?}",
             addr.address,
             addr.interface,
             name,
-            lifetime
+            lifetime,
+            allocation.relay
         );
 
         #[cfg(feature = "hooks")]
@@ -290,7 +337,154 @@ impl turn::Observer for Observer {
                     "interface": addr.interface,
                 },
                 "username": name,
+                "tenant": self.config.auth.get_tenant(name),
                 "lifetime": lifetime,
+                "allocation": {
+                    "relay": allocation.relay,
+                    "lifetime": allocation.lifetime,
+                },
+            }));
+        }
+    }
+
+    /// relay dropped
+    ///
+    /// Triggered when the data relayed by the peer is dropped, because no
+    /// allocation has installed a permission or bound a channel for the
+    /// peer. The drops are only logged with the log relay drops option.
+    fn relay_dropped(&self, peer: &SessionAddr, len: usize) {
+        if self.config.turn.log_relay_drops {
+            log::info!(
+                "relay dropped, no permission: address={:?}, interface={:?}, len={}",
+                peer.address,
+                peer.interface,
+                len
+            );
+        }
+
+        #[cfg(feature = "prometheus")]
+        {
+            crate::statistics::prometheus::METRICS.permission_dropped.inc();
+        }
+    }
+
+    /// relay audit
+    ///
+    /// Triggered for every relay 5-tuple installed by a client. The events
+    /// are only written with the audit log option, to the `audit` target.
+    fn audit(&self, event: &AuditEvent) {
+        if !self.config.log.audit {
+            return;
+        }
+
+        let (path, channel) = match event.kind {
+            AuditKind::Permission => ("permission", None),
+            AuditKind::Channel(it) => ("channel", Some(it)),
+        };
+
+        let time = event
+            .time
+            .duration_since(UNIX_EPOCH)
+            .map(|it| it.as_millis() as u64)
+            .unwrap_or(0);
+
+        log::info!(
+            target: "audit",
+            "relay audit: address={:?}, interface={:?}, path={}, channel={:?}, relay={:?}, peer={:?}, time={}",
+            event.client.address,
+            event.client.interface,
+            path,
+            channel,
+            event.relay,
+            event.peer,
+            time
+        );
+
+        #[cfg(feature = "hooks")]
+        {
+            self.hooks.emit(json!({
+                "kind": "audit",
+                "session": {
+                    "address": event.client.address,
+                    "interface": event.client.interface,
+                },
+                "path": path,
+                "channel": channel,
+                "relay": event.relay,
+                "peer": event.peer,
+                "time": time,
+            }));
+        }
+    }
+
+    /// unknown method
+    ///
+    /// Triggered when a message of a method that is not implemented is
+    /// received, which helps to spot the clients using unsupported features.
+    fn unknown_method(&self, addr: &SessionAddr, method: u16) {
+        log::warn!(
+            "unknown method: address={:?}, interface={:?}, method={:#05x}",
+            addr.address,
+            addr.interface,
+            method
+        );
+
+        #[cfg(feature = "prometheus")]
+        {
+            crate::statistics::prometheus::METRICS.unknown_methods.inc();
+        }
+    }
+
+    /// session moved
+    ///
+    /// Triggered when the session is moved to a new client address, such as
+    /// when a reconnecting client reclaims its detached allocation.
+    ///
+    /// The route and the statistics of the session move with it, the event
+    /// is triggered under the lock of the sessions, so the three of them are
+    /// moved together by [`turn::Sessions::rebind`]. The router and the
+    /// statistics never call back into the sessions, which keeps the lock
+    /// order.
+    fn moved(&self, addr: &SessionAddr, new: &SessionAddr, name: &str) {
+        log::info!(
+            "moved: address={:?}, interface={:?}, username={:?}, new address={:?}",
+            addr.address,
+            addr.interface,
+            name,
+            new.address
+        );
+
+        self.router.rebind(&addr.address, new.address);
+
+        #[cfg(feature = "api")]
+        {
+            self.statistics.rebind(addr, *new);
+        }
+    }
+
+    /// session evicted
+    ///
+    /// Triggered when the session is closed by force to reclaim its
+    /// resources, such as when its connection is evicted by a server at
+    /// capacity. The session is closed right after.
+    fn evicted(&self, addr: &SessionAddr, name: &str) {
+        log::warn!(
+            "evicted: address={:?}, interface={:?}, username={:?}",
+            addr.address,
+            addr.interface,
+            name
+        );
+
+        #[cfg(feature = "hooks")]
+        {
+            self.hooks.emit(json!({
+                "kind": "evicted",
+                "session": {
+                    "address": addr.address,
+                    "interface": addr.interface,
+                },
+                "username": name,
+                "tenant": self.config.auth.get_tenant(name),
             }));
         }
     }
@@ -301,17 +495,22 @@ impl turn::Observer for Observer {
     /// session life cycle has expired, external active deletion, or active
     /// exit of the session.
     #[allow(clippy::let_underscore_future)]
-    fn closed(&self, addr: &SessionAddr, name: &str) {
+    fn closed(&self, addr: &SessionAddr, name: &str, allocation: Option<&AllocationContext>) {
         log::info!(
-            "closed: address={:?}, interface={:?}, username={:?}",
+            "closed: address={:?}, interface={:?}, username={:?}, relay={:?}",
             addr.address,
             addr.interface,
-            name
+            name,
+            allocation.map(|it| it.relay)
         );
 
+        // The connection no longer carries an allocation, so it is reclaimable
+        // again like any other idle connection.
+        self.router.unpin(&addr.address);
+
         #[cfg(feature = "api")]
         {
-            self.statistics.unregister(&addr);
+            self.statistics.unregister(addr);
         }
 
         #[cfg(feature = "hooks")]
@@ -323,11 +522,32 @@ impl turn::Observer for Observer {
                     "interface": addr.interface,
                 },
                 "username": name,
+                "tenant": self.config.auth.get_tenant(name),
+                "allocation": allocation.map(|it| json!({
+                    "relay": it.relay,
+                    "lifetime": it.lifetime,
+                })),
             }));
         }
     }
 }
 
+// The username is `timestamp:userid`, the timestamp is the expiry time in seconds
+// since the unix epoch.
+//
+// https://datatracker.ietf.org/doc/html/draft-uberti-behave-turn-rest-00#section-2.2
+fn is_expired(username: &str) -> bool {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|it| it.as_secs())
+        .unwrap_or(0);
+
+    match username.split(':').next().map(|it| it.parse::<u64>()) {
+        Some(Ok(timestamp)) => timestamp < now,
+        _ => true,
+    }
+}
+
 // https://datatracker.ietf.org/doc/html/draft-uberti-behave-turn-rest-00#section-2.2
 fn encode_password(key: &str, username: &str) -> Option<String> {
     Some(
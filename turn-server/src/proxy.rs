@@ -0,0 +1,127 @@
+//! The PROXY protocol version 2 header of the tcp connections.
+//!
+//! [proxy-protocol](https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt)
+//!
+//! Behind a layer 4 load balancer, the peer of a connection is the load
+//! balancer and not the client. The load balancer prepends a binary header
+//! with the address of the original client to the stream, which is read
+//! before the first stun message. Only the version 2 header is supported,
+//! the TLVs after the addresses are skipped.
+
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+/// The signature at the start of the header.
+const SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// The size of the fixed part of the header, which holds the size of the
+/// rest of the header.
+pub const HEADER_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The stream does not start with the signature of the header.
+    InvalidSignature,
+    /// The version of the header is not 2.
+    UnsupportedVersion,
+    /// The command is neither LOCAL nor PROXY.
+    InvalidCommand,
+    /// The addresses are larger than the header.
+    InvalidAddress,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Get the size of the whole header from its fixed part.
+///
+/// # Example
+///
+/// ```
+/// use turn_server::proxy::*;
+///
+/// let mut bytes = b"\r\n\r\n\0\r\nQUIT\n\x21\x11\x00\x0C".to_vec();
+/// assert_eq!(header_size(&bytes), Ok(HEADER_SIZE + 12));
+///
+/// bytes[12] = 0x11;
+/// assert_eq!(header_size(&bytes), Err(Error::UnsupportedVersion));
+/// assert_eq!(header_size(b"GET / HTTP/1.1\r\n"), Err(Error::InvalidSignature));
+/// ```
+pub fn header_size(bytes: &[u8]) -> Result<usize, Error> {
+    if bytes.len() < HEADER_SIZE || bytes[..12] != SIGNATURE {
+        return Err(Error::InvalidSignature);
+    }
+
+    if bytes[12] >> 4 != 2 {
+        return Err(Error::UnsupportedVersion);
+    }
+
+    Ok(HEADER_SIZE + u16::from_be_bytes([bytes[14], bytes[15]]) as usize)
+}
+
+/// Decode the header at the start of the buffer, returns the source address
+/// and the size of the header, or `None` if the header is incomplete.
+///
+/// The source address is `None` for the LOCAL command, which the load
+/// balancer sends for its own health checks, and for the unspecified and
+/// unix families, the address of the connection is used then.
+///
+/// # Example
+///
+/// ```
+/// use turn_server::proxy::*;
+///
+/// // PROXY TCP4 203.0.113.7:5000 -> 192.0.2.1:3478
+/// let mut bytes = b"\r\n\r\n\0\r\nQUIT\n\x21\x11\x00\x0C".to_vec();
+/// bytes.extend_from_slice(&[203, 0, 113, 7, 192, 0, 2, 1, 0x13, 0x88, 0x0D, 0x96]);
+/// assert_eq!(decode(&bytes[..20]), Ok(None));
+///
+/// let (source, size) = decode(&bytes).unwrap().unwrap();
+/// assert_eq!(source, Some("203.0.113.7:5000".parse().unwrap()));
+/// assert_eq!(size, bytes.len());
+///
+/// // LOCAL, with no addresses.
+/// let bytes = b"\r\n\r\n\0\r\nQUIT\n\x20\x00\x00\x00";
+/// assert_eq!(decode(bytes), Ok(Some((None, HEADER_SIZE))));
+///
+/// let bytes = b"\r\n\r\n\0\r\nQUIT\n\x21\x11\x00\x04\0\0\0\0";
+/// assert_eq!(decode(bytes), Err(Error::InvalidAddress));
+/// ```
+pub fn decode(bytes: &[u8]) -> Result<Option<(Option<SocketAddr>, usize)>, Error> {
+    if bytes.len() < HEADER_SIZE {
+        return Ok(None);
+    }
+
+    let size = header_size(bytes)?;
+    if bytes.len() < size {
+        return Ok(None);
+    }
+
+    let source = match bytes[12] & 0x0F {
+        0x0 => None,
+        0x1 => {
+            let addresses = &bytes[HEADER_SIZE..size];
+            match bytes[13] >> 4 {
+                // AF_INET, the source, the destination and their ports.
+                0x1 if addresses.len() >= 12 => Some(SocketAddr::new(
+                    Ipv4Addr::from(<[u8; 4]>::try_from(&addresses[..4]).unwrap()).into(),
+                    u16::from_be_bytes([addresses[8], addresses[9]]),
+                )),
+                // AF_INET6
+                0x2 if addresses.len() >= 36 => Some(SocketAddr::new(
+                    Ipv6Addr::from(<[u8; 16]>::try_from(&addresses[..16]).unwrap()).into(),
+                    u16::from_be_bytes([addresses[32], addresses[33]]),
+                )),
+                0x1 | 0x2 => return Err(Error::InvalidAddress),
+                _ => None,
+            }
+        }
+        _ => return Err(Error::InvalidCommand),
+    };
+
+    Ok(Some((source, size)))
+}
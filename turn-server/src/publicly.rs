@@ -20,7 +20,7 @@ pub mod api {
         http::HeaderValue,
         middleware,
         response::{IntoResponse, Response},
-        routing::{delete, get},
+        routing::{delete, get, put},
         Json, Router,
     };
 
@@ -28,15 +28,21 @@ pub mod api {
     use serde::Deserialize;
     use serde_json::json;
     use tokio::net::TcpListener;
-    use turn::{PortAllocatePools, Service, SessionAddr};
+    use turn::{policy::Policies, PortAllocatePools, Service, SessionAddr};
 
     use super::NONCE;
-    use crate::{config::Config, observer::Observer, statistics::Statistics};
+    use crate::{
+        config::{parse_network_policy, Config},
+        observer::Observer,
+        router::Router as Routes,
+        statistics::Statistics,
+    };
 
     struct AppState {
         config: Arc<Config>,
         service: Service<Observer>,
         statistics: Statistics,
+        router: Routes,
         uptime: Instant,
     }
 
@@ -46,15 +52,51 @@ pub mod api {
         interface: SocketAddr,
     }
 
-    impl Into<SessionAddr> for SessionQueryFilter {
-        fn into(self) -> SessionAddr {
+    impl From<SessionQueryFilter> for SessionAddr {
+        fn from(val: SessionQueryFilter) -> Self {
             SessionAddr {
-                address: self.address,
-                interface: self.interface,
+                address: val.address,
+                interface: val.interface,
             }
         }
     }
 
+    #[derive(Deserialize)]
+    struct ChannelQueryFilter {
+        address: SocketAddr,
+        interface: SocketAddr,
+        number: u16,
+    }
+
+    #[derive(Deserialize)]
+    struct DrainQueryFilter {
+        interface: SocketAddr,
+        alternate: Option<SocketAddr>,
+    }
+
+    /// The networks in the CIDR notation of the replaced policies, an empty
+    /// list allows all networks.
+    #[derive(Deserialize)]
+    struct PolicyReload {
+        #[serde(default)]
+        binding_allow: Vec<String>,
+        #[serde(default)]
+        binding_deny: Vec<String>,
+        #[serde(default)]
+        peer_allow: Vec<String>,
+        #[serde(default)]
+        peer_deny: Vec<String>,
+    }
+
+    impl PolicyReload {
+        fn parse(&self) -> anyhow::Result<Policies> {
+            Ok(Policies {
+                binding: parse_network_policy(&self.binding_allow, &self.binding_deny)?,
+                peer: parse_network_policy(&self.peer_allow, &self.peer_deny)?,
+            })
+        }
+    }
+
     /// start http server
     ///
     /// Create an http server and start it, and you can access the controller
@@ -68,12 +110,14 @@ pub mod api {
         config: Arc<Config>,
         service: Service<Observer>,
         statistics: Statistics,
+        router: Routes,
     ) -> anyhow::Result<()> {
         let state = Arc::new(AppState {
             config: config.clone(),
             uptime: Instant::now(),
             service,
             statistics,
+            router,
         });
 
         #[allow(unused_mut)]
@@ -82,12 +126,17 @@ pub mod api {
                 "/info",
                 get(|State(app_state): State<Arc<AppState>>| async move {
                     let sessions = app_state.service.get_sessions();
+                    let load = app_state.service.get_load_shedder();
                     Json(json!({
                         "software": concat!(env!("CARGO_PKG_NAME"), ":", env!("CARGO_PKG_VERSION")),
                         "uptime": app_state.uptime.elapsed().as_secs(),
                         "interfaces": app_state.config.turn.interfaces,
                         "port_capacity": PortAllocatePools::capacity(),
                         "port_allocated": sessions.allocated(),
+                        "relay_paths": sessions.relay_paths(),
+                        "in_flight": load.as_ref().map(|it| it.in_flight()).unwrap_or(0),
+                        "overloaded": load.as_ref().map(|it| it.is_overloaded()).unwrap_or(false),
+                        "router_dropped": app_state.router.dropped(),
                     }))
                 }),
             )
@@ -99,6 +148,7 @@ pub mod api {
                             Json(json!({
                                 "username": session.auth.username,
                                 "password": session.auth.password,
+                                "tenant": session.auth.tenant,
                                 "permissions": session.permissions,
                                 "channels": session.allocate.channels,
                                 "port": session.allocate.port,
@@ -118,6 +168,7 @@ pub mod api {
                         let addr: SessionAddr = query.into();
                         if let Some(counts) = state.statistics.get(&addr) {
                             Json(json!({
+                                "tenant": state.statistics.get_tenant(&addr),
                                 "received_bytes": counts.received_bytes,
                                 "send_bytes": counts.send_bytes,
                                 "received_pkts": counts.received_pkts,
@@ -142,18 +193,144 @@ pub mod api {
                         }
                     },
                 ),
+            )
+            .route(
+                "/session/channels",
+                get(
+                    |Query(query): Query<SessionQueryFilter>, State(state): State<Arc<AppState>>| async move {
+                        let channels = state
+                            .service
+                            .get_sessions()
+                            .channels(&query.into())
+                            .into_iter()
+                            .map(|it| {
+                                json!({
+                                    "number": it.number,
+                                    "peer": it.peer,
+                                    "lifetime": it.lifetime,
+                                })
+                            })
+                            .collect::<Vec<_>>();
+
+                        Json(channels)
+                    },
+                ),
+            )
+            .route(
+                "/session/channel",
+                delete(
+                    |Query(query): Query<ChannelQueryFilter>, State(state): State<Arc<AppState>>| async move {
+                        let addr = SessionAddr {
+                            address: query.address,
+                            interface: query.interface,
+                        };
+
+                        if state.service.get_sessions().unbind_channel(&addr, query.number) {
+                            StatusCode::OK
+                        } else {
+                            StatusCode::EXPECTATION_FAILED
+                        }
+                    },
+                ),
+            )
+            .route(
+                "/reservations",
+                get(|State(state): State<Arc<AppState>>| async move {
+                    let reservations = state
+                        .service
+                        .get_sessions()
+                        .reservations()
+                        .into_iter()
+                        .map(|it| {
+                            json!({
+                                "token": it.token,
+                                "port": it.port,
+                                "lifetime": it.lifetime,
+                            })
+                        })
+                        .collect::<Vec<_>>();
+
+                    Json(reservations)
+                })
+                .delete(|State(state): State<Arc<AppState>>| async move {
+                    Json(json!({
+                        "released": state.service.get_sessions().prune_reservations(),
+                    }))
+                }),
+            )
+            .route(
+                "/policy",
+                put(
+                    |State(state): State<Arc<AppState>>, Json(body): Json<PolicyReload>| async move {
+                        match body.parse() {
+                            Ok(policies) => {
+                                state.service.reload_policy(policies);
+                                StatusCode::OK
+                            }
+                            Err(_) => StatusCode::BAD_REQUEST,
+                        }
+                    },
+                ),
+            )
+            .route(
+                "/listener/drain",
+                put(
+                    |Query(query): Query<DrainQueryFilter>, State(state): State<Arc<AppState>>| async move {
+                        if state.service.drain_listener(query.interface, query.alternate) {
+                            StatusCode::OK
+                        } else {
+                            StatusCode::EXPECTATION_FAILED
+                        }
+                    },
+                )
+                .delete(
+                    |Query(query): Query<DrainQueryFilter>, State(state): State<Arc<AppState>>| async move {
+                        if state.service.resume_listener(&query.interface) {
+                            StatusCode::OK
+                        } else {
+                            StatusCode::EXPECTATION_FAILED
+                        }
+                    },
+                ),
             );
 
         #[cfg(feature = "prometheus")]
         {
-            use crate::statistics::prometheus::generate_metrics;
-            use axum::http::header::CONTENT_TYPE;
+            use crate::statistics::prometheus::{generate_metrics, render_openmetrics, METRICS};
+            use axum::http::{
+                header::{ACCEPT, CONTENT_TYPE},
+                HeaderMap,
+            };
 
             let mut metrics_bytes = Vec::with_capacity(4096);
 
             app = app.route(
                 "/metrics",
-                get(|| async move {
+                get(|headers: HeaderMap, State(state): State<Arc<AppState>>| async move {
+                    // The relay paths are counted when scraped, rather than on every
+                    // permission and channel of the allocations.
+                    METRICS
+                        .relay_paths
+                        .set(state.service.get_sessions().relay_paths() as i64);
+
+                    // The scrapers that prefer the OpenMetrics format say so in the
+                    // accept header, the others get the prometheus text format.
+                    if headers
+                        .get(ACCEPT)
+                        .and_then(|it| it.to_str().ok())
+                        .map(|it| it.contains("application/openmetrics-text"))
+                        .unwrap_or(false)
+                    {
+                        return (
+                            [(
+                                CONTENT_TYPE,
+                                "application/openmetrics-text; version=1.0.0; charset=utf-8",
+                            )],
+                            render_openmetrics(),
+                        )
+                            .into_response();
+                    }
+
                     metrics_bytes.clear();
 
                     if generate_metrics(&mut metrics_bytes).is_err() {
@@ -192,15 +369,51 @@ pub mod hooks {
     use axum::http::{HeaderMap, HeaderValue};
     use reqwest::{Client, ClientBuilder};
     use serde_json::Value;
-    use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+    use tokio::{
+        sync::{
+            mpsc::{unbounded_channel, UnboundedSender},
+            Semaphore, SemaphorePermit,
+        },
+        time::timeout,
+    };
     use turn::SessionAddr;
 
     use super::NONCE;
     use crate::config::Config;
 
+    /// The timeout of the requests to the hooks server, the password requests
+    /// waiting for their turn give up after it too.
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Bounds the concurrent password requests, the requests beyond the
+    /// concurrency wait in a bounded queue.
+    struct Limiter {
+        queue: Semaphore,
+        running: Semaphore,
+    }
+
+    impl Limiter {
+        fn new(concurrency: usize, queue: usize) -> Self {
+            Self {
+                queue: Semaphore::new(concurrency + queue),
+                running: Semaphore::new(concurrency),
+            }
+        }
+
+        /// Wait for the turn of the request, returns None if the queue is
+        /// full or the turn does not come within the timeout, so that the
+        /// wedged requests of the hooks server do not hold the callers.
+        async fn acquire(&self) -> Option<(SemaphorePermit<'_>, SemaphorePermit<'_>)> {
+            let queued = self.queue.try_acquire().ok()?;
+            let running = timeout(TIMEOUT, self.running.acquire()).await.ok()?.ok()?;
+            Some((queued, running))
+        }
+    }
+
     pub struct HooksService {
         client: Arc<Client>,
         tx: UnboundedSender<Value>,
+        limiter: Option<Limiter>,
         config: Arc<Config>,
     }
 
@@ -213,7 +426,7 @@ pub mod hooks {
             let client = Arc::new(
                 ClientBuilder::new()
                     .default_headers(headers)
-                    .timeout(Duration::from_secs(5))
+                    .timeout(TIMEOUT)
                     .build()?,
             );
 
@@ -234,13 +447,35 @@ pub mod hooks {
                 }
             });
 
-            Ok(Self { client, config, tx })
+            let limiter = config
+                .api
+                .hooks_concurrency
+                .map(|it| Limiter::new(it, config.api.hooks_queue));
+
+            Ok(Self {
+                client,
+                config,
+                limiter,
+                tx,
+            })
         }
 
         // There are no matching static entries, get the password from an external hook
         // service.
         pub async fn get_password(&self, addr: &SessionAddr, username: &str) -> Option<String> {
             if let Some(server) = &self.config.api.hooks {
+                // The permits are held until the response has been read.
+                let _permit = match &self.limiter {
+                    Some(limiter) => match limiter.acquire().await {
+                        Some(it) => Some(it),
+                        None => {
+                            log::warn!("hooks queue is full or timed out, username={:?}", username);
+                            return None;
+                        }
+                    },
+                    None => None,
+                };
+
                 if let Ok(res) = self
                     .client
                     .get(format!(
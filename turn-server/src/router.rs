@@ -1,23 +1,597 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    collections::BTreeSet,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
-use ahash::AHashMap;
-use parking_lot::RwLock;
-use tokio::sync::mpsc::*;
+use ahash::{AHashMap, AHashSet};
+use parking_lot::{Mutex, RwLock};
+use tokio::sync::mpsc::{error::TryRecvError, unbounded_channel, UnboundedReceiver, UnboundedSender};
 use turn::ResponseMethod;
 
-type Receiver = UnboundedSender<(Vec<u8>, ResponseMethod, SocketAddr)>;
+type Message = (Vec<u8>, ResponseMethod, SocketAddr);
+
+struct Entry {
+    sender: UnboundedSender<Message>,
+    /// The number of the messages in the queue of the route.
+    queued: Arc<AtomicUsize>,
+    /// Entries of the transport sockets are never evicted.
+    evictable: bool,
+    /// Pinned entries still count towards the capacity, but are never evicted.
+    pinned: AtomicBool,
+    /// The last active time, in milliseconds since the router was created.
+    active: AtomicU64,
+    /// The active time the entry is indexed with in the least recently used
+    /// index, see [`Table::evict`].
+    indexed: u64,
+}
+
+/// The routes of the router.
+///
+/// The evictable entries are counted and indexed by their active time, so
+/// that the least recently used entry is found without scanning the table.
+/// The active time of an entry is updated in place without the write lock,
+/// so the index is only brought up to date lazily when the entry is the
+/// oldest one, the indexed time is never later than the active time.
+#[derive(Default)]
+struct Table {
+    entries: AHashMap<SocketAddr, Entry>,
+    lru: BTreeSet<(u64, SocketAddr)>,
+    evictable: usize,
+}
+
+impl Table {
+    fn insert(&mut self, addr: SocketAddr, entry: Entry) {
+        self.remove(&addr);
+
+        if entry.evictable {
+            self.lru.insert((entry.indexed, addr));
+            self.evictable += 1;
+        }
+
+        self.entries.insert(addr, entry);
+    }
+
+    fn remove(&mut self, addr: &SocketAddr) -> Option<Entry> {
+        let entry = self.entries.remove(addr)?;
+        self.unindex(addr, &entry);
+        Some(entry)
+    }
+
+    fn unindex(&mut self, addr: &SocketAddr, entry: &Entry) {
+        if entry.evictable {
+            self.lru.remove(&(entry.indexed, *addr));
+            self.evictable -= 1;
+        }
+    }
+
+    /// Remove the least recently used entry that has been idle for the idle
+    /// duration, the pinned entries are never evicted.
+    ///
+    /// The entries active since they were indexed are indexed again with
+    /// their active time, which happens at most once for each entry since its
+    /// last touch, so the eviction is amortized over the touches.
+    fn evict(&mut self, now: u64, idle: u64) -> Option<SocketAddr> {
+        while let Some((indexed, addr)) = self.lru.pop_first() {
+            let entry = match self.entries.get_mut(&addr) {
+                Some(it) => it,
+                None => continue,
+            };
+
+            // The pinned entry leaves the index for good, it still counts
+            // towards the capacity.
+            if entry.pinned.load(Ordering::Relaxed) {
+                continue;
+            }
+
+            let active = entry.active.load(Ordering::Relaxed);
+            if active > indexed {
+                entry.indexed = active;
+                self.lru.insert((active, addr));
+                continue;
+            }
+
+            // The oldest entry is not idle, so none of them is.
+            if now.saturating_sub(active) < idle {
+                self.lru.insert((indexed, addr));
+                return None;
+            }
+
+            self.remove(&addr);
+            return Some(addr);
+        }
+
+        None
+    }
+}
+
+/// The socket reader of a route.
+///
+/// The reader keeps the number of the messages in the queue of the route, so
+/// that the relayed data over the queue limit of the router is dropped, see
+/// [`Router::try_send`].
+pub struct RouteReceiver {
+    receiver: UnboundedReceiver<Message>,
+    queued: Arc<AtomicUsize>,
+}
+
+impl RouteReceiver {
+    /// Receive the next message of the route, `None` is returned when the
+    /// route is removed.
+    pub async fn recv(&mut self) -> Option<Message> {
+        let message = self.receiver.recv().await?;
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+        Some(message)
+    }
+
+    /// Receive the next message of the route without waiting.
+    pub fn try_recv(&mut self) -> Result<Message, TryRecvError> {
+        let message = self.receiver.try_recv()?;
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+        Ok(message)
+    }
+}
+
+/// The state of a target of a limit.
+trait Target: Default {
+    /// Get the index of the last one second window the target was seen in.
+    fn window(&self) -> u64;
+}
+
+/// The packets of a target in a one second window of a limit.
+///
+/// The index of the window and the count are packed in one value, the index
+/// in the upper and the count in the lower 32 bits, so that the count of a
+/// new window starts over in the same update that moves the window.
+#[derive(Default)]
+struct Count(AtomicU64);
+
+impl Target for Count {
+    fn window(&self) -> u64 {
+        self.0.load(Ordering::Relaxed) >> 32
+    }
+}
+
+impl Count {
+    const MASK: u64 = u32::MAX as u64;
+
+    /// Count a packet in the window, returns false if the window already
+    /// counts the rate, the refused packet is not counted.
+    fn add(&self, window: u64, rate: u64) -> bool {
+        let previous = self.0.fetch_add(1, Ordering::Relaxed);
+        if previous >> 32 >= window {
+            if previous & Self::MASK < rate {
+                return true;
+            }
+
+            // Roll the refused packet back, unless the window has moved on.
+            let _ = self.0.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |it| {
+                (it >> 32 == previous >> 32).then(|| it - 1)
+            });
+
+            return false;
+        }
+
+        // The first packet of a new window starts the count over.
+        self.0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |it| {
+                if it >> 32 < window {
+                    Some(window << 32 | 1)
+                } else {
+                    (it & Self::MASK < rate).then(|| it + 1)
+                }
+            })
+            .is_ok()
+    }
+}
+
+/// A token bucket that is refilled at the rate of the limit.
+///
+/// The tokens and the time of the last update are packed in one value, the
+/// time at which the bucket is full again, in nanoseconds since the epoch of
+/// the limit (the theoretical arrival time of GCRA). The bucket holds the
+/// tokens of the capacity duration, a take costs the time the rate needs to
+/// refill its tokens, and the take that would push the time of the full
+/// bucket past the capacity is refused. The time is updated with a
+/// compare-and-swap loop, so the concurrent takes never overshoot.
+#[derive(Default)]
+struct Bucket {
+    full: AtomicU64,
+}
+
+impl Bucket {
+    /// Take the tokens of the cost, returns false if the bucket does not
+    /// hold them.
+    fn take(&self, now: u64, cost: u64, capacity: u64) -> bool {
+        self.full
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |full| {
+                let full = full.max(now) + cost;
+                (full - now <= capacity).then_some(full)
+            })
+            .is_ok()
+    }
+
+    /// Put back the tokens of a take that was not used.
+    fn put(&self, cost: u64) {
+        let _ = self
+            .full
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |full| Some(full.saturating_sub(cost)));
+    }
+}
+
+/// The share of the bandwidth of a target.
+#[derive(Default)]
+struct Share {
+    bucket: Bucket,
+    /// The index of the last window the target was seen in.
+    seen: AtomicU64,
+}
+
+impl Target for Share {
+    fn window(&self) -> u64 {
+        self.seen.load(Ordering::Relaxed)
+    }
+}
+
+/// The usages of the targets of a limit.
+///
+/// The usage of each target is updated in place, so that the relay path only
+/// needs a read lock, the write lock is only taken for a new target. The
+/// targets that have not been seen for a window are dropped when the table
+/// has doubled since it was last pruned.
+struct Usages<T> {
+    table: RwLock<(AHashMap<SocketAddr, T>, /* prune at */ usize)>,
+    epoch: Instant,
+}
+
+impl<T> Default for Usages<T> {
+    fn default() -> Self {
+        Self {
+            table: RwLock::new((AHashMap::with_capacity(1024), 1024)),
+            epoch: Instant::now(),
+        }
+    }
+}
+
+impl<T: Target> Usages<T> {
+    /// Get the index of the current one second window, the index of a new
+    /// usage is never current.
+    fn window(&self) -> u64 {
+        self.epoch.elapsed().as_secs() + 1
+    }
+
+    /// Get the nanoseconds since the epoch of the usages.
+    fn now(&self) -> u64 {
+        self.epoch.elapsed().as_nanos() as u64
+    }
+
+    fn with<R>(&self, addr: &SocketAddr, handle: impl FnOnce(&T) -> R) -> R {
+        if let Some(usage) = self.table.read().0.get(addr) {
+            return handle(usage);
+        }
+
+        let window = self.window();
+        let mut table = self.table.write();
+        let (usages, prune) = &mut *table;
+        if usages.len() >= *prune && !usages.contains_key(addr) {
+            usages.retain(|_, it| it.window() + 1 >= window);
+            *prune = (usages.len() * 2).max(1024);
+        }
+
+        handle(usages.entry(*addr).or_default())
+    }
+}
+
+/// A server-wide cap of the relayed bandwidth.
+///
+/// The budget is a token bucket refilled at the rate, which holds the bytes
+/// of one second. The budget is shared fairly between the targets of the
+/// relayed data, each target has its own bucket refilled at the rate that
+/// holds an equal share of the budget, where the number of the targets is
+/// taken from the previous one second window and the targets seen so far in
+/// the current window. The data over the budget or over the share of its
+/// target is dropped.
+///
+/// # Example
+///
+/// ```
+/// use std::net::SocketAddr;
+/// use turn_server::router::BandwidthLimit;
+///
+/// let a = "127.0.0.1:8080".parse::<SocketAddr>().unwrap();
+/// let b = "127.0.0.1:8081".parse::<SocketAddr>().unwrap();
+/// let limit = BandwidthLimit::new(10000);
+///
+/// // The first target sends three times as much as the second one.
+/// let (mut admitted_a, mut admitted_b) = (0, 0);
+/// for _ in 0..10 {
+///     for _ in 0..3 {
+///         if limit.admit(&a, 1000) {
+///             admitted_a += 1;
+///         }
+///     }
+///
+///     if limit.admit(&b, 1000) {
+///         admitted_b += 1;
+///     }
+/// }
+///
+/// assert_eq!((admitted_a, admitted_b), (5, 5));
+/// assert_eq!(limit.dropped(), 30);
+/// ```
+pub struct BandwidthLimit {
+    rate: u64,
+    usages: Usages<Share>,
+    budget: Bucket,
+    /// The index of the current window, and the number of the targets that
+    /// received data in the current and in the previous window.
+    window: AtomicU64,
+    targets: AtomicUsize,
+    active: AtomicUsize,
+    dropped: AtomicU64,
+}
+
+impl BandwidthLimit {
+    /// The duration of the tokens held by the budget, in nanoseconds.
+    const CAPACITY: u64 = 1_000_000_000;
+
+    /// Create a limit of the rate in bytes per second.
+    pub fn new(rate: u64) -> Self {
+        Self {
+            usages: Usages::default(),
+            budget: Bucket::default(),
+            window: AtomicU64::new(0),
+            targets: AtomicUsize::new(0),
+            active: AtomicUsize::new(0),
+            dropped: AtomicU64::new(0),
+            rate: rate.max(1),
+        }
+    }
+
+    /// Check if the data of the size can be relayed to the target, the
+    /// admitted size is taken from the budget.
+    pub fn admit(&self, addr: &SocketAddr, size: usize) -> bool {
+        let now = self.usages.now();
+        let window = self.usages.window();
+
+        // The first packet of a new window starts the count of the targets over.
+        let previous = self.window.load(Ordering::Relaxed);
+        if previous != window
+            && self
+                .window
+                .compare_exchange(previous, window, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            self.active
+                .store(self.targets.swap(0, Ordering::Relaxed), Ordering::Relaxed);
+        }
+
+        // The time the rate takes to refill the bytes.
+        let cost = (size as u128 * Self::CAPACITY as u128 / self.rate as u128) as u64;
+        let admitted = self.usages.with(addr, |share| {
+            if share.seen.swap(window, Ordering::Relaxed) != window {
+                self.targets.fetch_add(1, Ordering::Relaxed);
+            }
+
+            let targets = self
+                .active
+                .load(Ordering::Relaxed)
+                .max(self.targets.load(Ordering::Relaxed))
+                .max(1);
+
+            if !share.bucket.take(now, cost, Self::CAPACITY / targets as u64) {
+                return false;
+            }
+
+            if !self.budget.take(now, cost, Self::CAPACITY) {
+                share.bucket.put(cost);
+                return false;
+            }
+
+            true
+        });
+
+        if !admitted {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+
+            #[cfg(feature = "prometheus")]
+            crate::statistics::prometheus::METRICS.bandwidth_dropped.inc();
+        }
+
+        admitted
+    }
+
+    /// Get the number of packets dropped over the limit.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// A cap of the relayed packet rate of each target.
+///
+/// The bandwidth limit does not catch the floods of tiny packets, so the
+/// packets relayed to a target are also counted in one second windows, and
+/// the packets over the rate are dropped. The targets of the relayed data
+/// are the clients, so this is a cap of each allocation.
+///
+/// # Example
+///
+/// ```
+/// use std::net::SocketAddr;
+/// use turn_server::router::PacketLimit;
+///
+/// let a = "127.0.0.1:8080".parse::<SocketAddr>().unwrap();
+/// let b = "127.0.0.1:8081".parse::<SocketAddr>().unwrap();
+/// let limit = PacketLimit::new(100);
+///
+/// // A burst of the first target, the second target is not affected.
+/// let admitted = (0..1000).filter(|_| limit.admit(&a)).count();
+/// assert_eq!(admitted, 100);
+/// assert_eq!(limit.dropped(), 900);
+/// assert!(limit.admit(&b));
+///
+/// // The concurrent senders never exceed the rate together.
+/// let c = "127.0.0.1:8082".parse::<SocketAddr>().unwrap();
+/// let admitted = std::thread::scope(|scope| {
+///     let workers = (0..8)
+///         .map(|_| scope.spawn(|| (0..100).filter(|_| limit.admit(&c)).count()))
+///         .collect::<Vec<_>>();
+///
+///     workers.into_iter().map(|it| it.join().unwrap()).sum::<usize>()
+/// });
+///
+/// assert_eq!(admitted, 100);
+/// ```
+pub struct PacketLimit {
+    rate: u64,
+    usages: Usages<Count>,
+    dropped: AtomicU64,
+}
+
+impl PacketLimit {
+    /// Create a limit of the rate in packets per second of each target.
+    pub fn new(rate: u64) -> Self {
+        Self {
+            usages: Usages::default(),
+            dropped: AtomicU64::new(0),
+            // The count of a window is kept in 32 bits, with room for the
+            // packets that are counted and rolled back.
+            rate: rate.min(u32::MAX as u64 / 2),
+        }
+    }
+
+    /// Check if a packet can be relayed to the target under the rate, the
+    /// admitted packet is counted.
+    pub fn admit(&self, addr: &SocketAddr) -> bool {
+        let window = self.usages.window();
+        let admitted = self.usages.with(addr, |count| count.add(window, self.rate));
+
+        if !admitted {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+
+            #[cfg(feature = "prometheus")]
+            crate::statistics::prometheus::METRICS.packet_rate_dropped.inc();
+        }
+
+        admitted
+    }
+
+    /// Get the number of packets dropped over the rate.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
 
 /// Handles packet forwarding between transport protocols.
 #[derive(Clone)]
-pub struct Router(Arc<RwLock<AHashMap<SocketAddr, Receiver>>>);
+pub struct Router {
+    table: Arc<RwLock<Table>>,
+    evicted: Arc<Mutex<AHashSet<SocketAddr>>>,
+    dropped: Arc<AtomicU64>,
+    bandwidth: Option<Arc<BandwidthLimit>>,
+    packets: Option<Arc<PacketLimit>>,
+    capacity: Option<usize>,
+    queue: Option<usize>,
+    idle: Duration,
+    epoch: Instant,
+}
 
 impl Default for Router {
     fn default() -> Self {
-        Self(Arc::new(RwLock::new(AHashMap::with_capacity(1024))))
+        Self::new(None, Duration::from_secs(60), None)
     }
 }
 
 impl Router {
+    /// Create a router.
+    ///
+    /// The capacity limits the number of evictable entries, see
+    /// [`Router::try_get_receiver`]. An entry is considered idle when nothing
+    /// has passed through it for the idle duration. The queue is the number of
+    /// the relayed packets each route can buffer before they are dropped, see
+    /// [`Router::try_send`], the queues are unbounded without it.
+    pub fn new(capacity: Option<usize>, idle: Duration, queue: Option<usize>) -> Self {
+        Self {
+            table: Arc::new(RwLock::new(Table {
+                entries: AHashMap::with_capacity(1024),
+                ..Default::default()
+            })),
+            evicted: Default::default(),
+            dropped: Arc::new(AtomicU64::new(0)),
+            epoch: Instant::now(),
+            bandwidth: None,
+            packets: None,
+            capacity,
+            queue,
+            idle,
+        }
+    }
+
+    /// Cap the bandwidth of the relayed data of all routes, see
+    /// [`BandwidthLimit`].
+    pub fn with_bandwidth(mut self, limit: BandwidthLimit) -> Self {
+        self.bandwidth = Some(Arc::new(limit));
+        self
+    }
+
+    /// Cap the relayed packet rate of each target, see [`PacketLimit`].
+    pub fn with_packet_rate(mut self, limit: PacketLimit) -> Self {
+        self.packets = Some(Arc::new(limit));
+        self
+    }
+
+    /// Check if the relayed data can be sent to the target under the packet
+    /// rate and the bandwidth limits, both limits apply. The other messages
+    /// are always admitted.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::{net::SocketAddr, time::Duration};
+    /// use stun::{Kind, Method};
+    /// use turn::ResponseMethod;
+    /// use turn_server::router::*;
+    ///
+    /// let addr = "127.0.0.1:8080".parse::<SocketAddr>().unwrap();
+    /// let router = Router::new(None, Duration::from_secs(60), None)
+    ///     .with_bandwidth(BandwidthLimit::new(1000))
+    ///     .with_packet_rate(PacketLimit::new(3));
+    ///
+    /// // The tiny packets are under the bandwidth but over the packet rate.
+    /// let admitted = (0..10)
+    ///     .filter(|_| router.admit(ResponseMethod::ChannelData, &addr, 10))
+    ///     .count();
+    ///
+    /// assert_eq!(admitted, 3);
+    ///
+    /// let binding = ResponseMethod::Stun(Method::Binding(Kind::Response));
+    /// assert!(router.admit(binding, &addr, 10));
+    /// ```
+    pub fn admit(&self, method: ResponseMethod, addr: &SocketAddr, size: usize) -> bool {
+        if !method.is_relayed() {
+            return true;
+        }
+
+        if let Some(ref limit) = self.packets {
+            if !limit.admit(addr) {
+                return false;
+            }
+        }
+
+        match self.bandwidth {
+            Some(ref limit) => limit.admit(addr, size),
+            None => true,
+        }
+    }
+
+    fn now(&self) -> u64 {
+        self.epoch.elapsed().as_millis() as u64
+    }
+
     /// Get the socket reader for the route.
     ///
     /// Each transport protocol is layered according to its own socket, and
@@ -43,12 +617,251 @@ impl Router {
     ///     assert_eq!(ret.2, addr);
     /// }
     /// ```
-    pub fn get_receiver(&self, interface: SocketAddr) -> UnboundedReceiver<(Vec<u8>, ResponseMethod, SocketAddr)> {
-        let (sender, receiver) = unbounded_channel();
-        self.0.write().insert(interface, sender);
+    pub fn get_receiver(&self, interface: SocketAddr) -> RouteReceiver {
+        let (entry, receiver) = self.entry(self.now(), false);
+        self.table.write().insert(interface, entry);
         receiver
     }
 
+    fn entry(&self, now: u64, evictable: bool) -> (Entry, RouteReceiver) {
+        let (sender, receiver) = unbounded_channel();
+        let queued = Arc::new(AtomicUsize::new(0));
+        (
+            Entry {
+                active: AtomicU64::new(now),
+                pinned: AtomicBool::new(false),
+                queued: queued.clone(),
+                indexed: now,
+                evictable,
+                sender,
+            },
+            RouteReceiver { receiver, queued },
+        )
+    }
+
+    /// Try to get the socket reader for the route, with the capacity limit.
+    ///
+    /// When the router is at capacity, the least recently used idle entry is
+    /// evicted to reclaim its slot, the reader of the evicted entry is
+    /// closed and the eviction is recorded, see [`Router::take_evicted`]. If
+    /// there is no idle entry, or the route of the address is already live,
+    /// `None` is returned.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::{net::SocketAddr, time::Duration};
+    /// use turn::ResponseMethod;
+    /// use turn_server::router::*;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let addrs = (1..=4)
+    ///         .map(|port| SocketAddr::from(([127, 0, 0, 1], port)))
+    ///         .collect::<Vec<_>>();
+    ///
+    ///     let router = Router::new(Some(2), Duration::from_secs(60), None);
+    ///     let _a = router.try_get_receiver(addrs[0]).unwrap();
+    ///     let _b = router.try_get_receiver(addrs[1]).unwrap();
+    ///     assert!(router.try_get_receiver(addrs[2]).is_none());
+    ///     assert!(router.try_get_receiver(addrs[0]).is_none());
+    ///
+    ///     let router = Router::new(Some(2), Duration::ZERO, None);
+    ///     let mut a = router.try_get_receiver(addrs[0]).unwrap();
+    ///     let mut b = router.try_get_receiver(addrs[1]).unwrap();
+    ///
+    ///     std::thread::sleep(Duration::from_millis(10));
+    ///     router.send(&addrs[1], ResponseMethod::ChannelData, &addrs[1], &[1, 2, 3]);
+    ///
+    ///     let mut c = router.try_get_receiver(addrs[2]).unwrap();
+    ///     assert!(a.recv().await.is_none());
+    ///     assert!(b.recv().await.is_some());
+    ///     assert!(router.take_evicted(&addrs[0]));
+    ///     assert!(!router.take_evicted(&addrs[0]));
+    ///     assert!(!router.take_evicted(&addrs[1]));
+    ///
+    ///     router.send(&addrs[2], ResponseMethod::ChannelData, &addrs[2], &[1, 2, 3]);
+    ///     assert!(c.recv().await.is_some());
+    /// }
+    /// ```
+    pub fn try_get_receiver(&self, interface: SocketAddr) -> Option<RouteReceiver> {
+        let now = self.now();
+        let mut table = self.table.write();
+
+        // The live entry of the address is never replaced, which would orphan
+        // its reader, such as two connections with the same PROXY source.
+        if table.entries.contains_key(&interface) {
+            return None;
+        }
+
+        if let Some(capacity) = self.capacity {
+            if table.evictable >= capacity {
+                // Dropping the sender closes the reader of the evicted entry.
+                let addr = table.evict(now, self.idle.as_millis() as u64)?;
+                self.evicted.lock().insert(addr);
+            }
+        }
+
+        self.evicted.lock().remove(&interface);
+
+        let (entry, receiver) = self.entry(now, true);
+        table.insert(interface, entry);
+        Some(receiver)
+    }
+
+    /// Take the eviction of the route.
+    ///
+    /// Returns true once if the route was evicted to reclaim its slot, so
+    /// that the closed reader can tell the eviction from the close of its
+    /// socket.
+    pub fn take_evicted(&self, interface: &SocketAddr) -> bool {
+        self.evicted.lock().remove(interface)
+    }
+
+    /// Mark the route as active.
+    ///
+    /// Active routes are not considered idle, and will not be evicted first
+    /// when the router is at capacity.
+    pub fn touch(&self, interface: &SocketAddr) {
+        if let Some(entry) = self.table.read().entries.get(interface) {
+            entry.active.store(self.now(), Ordering::Relaxed);
+        }
+    }
+
+    /// Pin the route.
+    ///
+    /// The pinned route is never evicted to reclaim its slot until it is
+    /// unpinned, see [`Router::unpin`], or removed when the socket is closed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::{net::SocketAddr, time::Duration};
+    /// use turn::ResponseMethod;
+    /// use turn_server::router::*;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let addrs = (1..=3)
+    ///         .map(|port| SocketAddr::from(([127, 0, 0, 1], port)))
+    ///         .collect::<Vec<_>>();
+    ///
+    ///     let router = Router::new(Some(2), Duration::ZERO, None);
+    ///     let mut a = router.try_get_receiver(addrs[0]).unwrap();
+    ///     let _b = router.try_get_receiver(addrs[1]).unwrap();
+    ///     router.pin(&addrs[0]);
+    ///     router.pin(&addrs[1]);
+    ///
+    ///     assert!(router.try_get_receiver(addrs[2]).is_none());
+    ///
+    ///     // The unpinned entry is evicted, the pinned entry is kept.
+    ///     router.remove(&addrs[1]);
+    ///     let mut c = router.try_get_receiver(addrs[2]).unwrap();
+    ///     let _b = router.try_get_receiver(addrs[1]).unwrap();
+    ///     assert!(c.recv().await.is_none());
+    ///     assert!(a.try_recv().is_err());
+    ///
+    ///     router.send(&addrs[0], ResponseMethod::ChannelData, &addrs[0], &[1, 2, 3]);
+    ///     assert!(a.recv().await.is_some());
+    /// }
+    /// ```
+    pub fn pin(&self, interface: &SocketAddr) {
+        if let Some(entry) = self.table.read().entries.get(interface) {
+            entry.pinned.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Unpin the route.
+    ///
+    /// The route is evictable again once it is idle, such as when the
+    /// allocation the route was pinned for is closed or expired.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::{net::SocketAddr, time::Duration};
+    /// use turn_server::router::*;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let addrs = (1..=3)
+    ///         .map(|port| SocketAddr::from(([127, 0, 0, 1], port)))
+    ///         .collect::<Vec<_>>();
+    ///
+    ///     let router = Router::new(Some(2), Duration::ZERO, None);
+    ///     let mut a = router.try_get_receiver(addrs[0]).unwrap();
+    ///     let _b = router.try_get_receiver(addrs[1]).unwrap();
+    ///     router.pin(&addrs[0]);
+    ///     router.pin(&addrs[1]);
+    ///
+    ///     assert!(router.try_get_receiver(addrs[2]).is_none());
+    ///
+    ///     router.unpin(&addrs[0]);
+    ///     let _c = router.try_get_receiver(addrs[2]).unwrap();
+    ///     assert!(a.recv().await.is_none());
+    /// }
+    /// ```
+    pub fn unpin(&self, interface: &SocketAddr) {
+        let mut table = self.table.write();
+        let Table { entries, lru, .. } = &mut *table;
+        if let Some(entry) = entries.get(interface) {
+            // The pinned entry may have left the index, see [`Table::evict`].
+            if entry.pinned.swap(false, Ordering::Relaxed) && entry.evictable {
+                lru.insert((entry.indexed, *interface));
+            }
+        }
+    }
+
+    /// Move the route to a new address.
+    ///
+    /// The routes of the tcp connections are keyed by the address of the
+    /// client, when the client migrates the route is moved in place, so the
+    /// pending data and the state of the route are kept. Returns false if
+    /// there is no route for the old address or the new address is taken,
+    /// such as when the client has moved over a connection of its own.
+    ///
+    /// The route is moved with the session by the moved event of the
+    /// observer, see [`turn::Sessions::rebind`], which is the entry point of
+    /// the rebinding.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::net::SocketAddr;
+    /// use turn::ResponseMethod;
+    /// use turn_server::router::*;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let old = "127.0.0.1:8080".parse::<SocketAddr>().unwrap();
+    ///     let new = "127.0.0.1:9090".parse::<SocketAddr>().unwrap();
+    ///     let router = Router::default();
+    ///     let mut receiver = router.get_receiver(old);
+    ///
+    ///     assert!(router.rebind(&old, new));
+    ///     assert!(!router.rebind(&old, new));
+    ///     assert!(!router.try_send(&old, ResponseMethod::ChannelData, &old, &[1, 2, 3]));
+    ///
+    ///     router.send(&new, ResponseMethod::ChannelData, &new, &[4, 5, 6]);
+    ///     let ret = receiver.recv().await.unwrap();
+    ///     assert_eq!(ret.0, vec![4, 5, 6]);
+    ///     assert_eq!(ret.2, new);
+    /// }
+    /// ```
+    pub fn rebind(&self, interface: &SocketAddr, new: SocketAddr) -> bool {
+        let mut table = self.table.write();
+        if table.entries.contains_key(&new) {
+            return false;
+        }
+
+        if let Some(entry) = table.remove(interface) {
+            table.insert(new, entry);
+            true
+        } else {
+            false
+        }
+    }
+
     /// Send data to router.
     ///
     /// By specifying the socket identifier and destination address, the route
@@ -56,6 +869,9 @@ impl Router {
     /// that calling this function will not notify whether the socket exists.
     /// If it does not exist, the data will be discarded by default.
     ///
+    /// The data is always queued, the queue limit and the relay limits only
+    /// apply to the relayed data sent with [`Router::try_send`].
+    ///
     /// # Example
     ///
     /// ```
@@ -77,19 +893,92 @@ impl Router {
     /// }
     /// ```
     pub fn send(&self, interface: &SocketAddr, method: ResponseMethod, addr: &SocketAddr, data: &[u8]) {
+        self.push(interface, None, (data.to_vec(), method, *addr));
+    }
+
+    /// Try to send the relayed data to router without growing the queue.
+    ///
+    /// The relayed data is checked with the relay limits, see
+    /// [`Router::admit`]. If the queue of the socket is at the queue limit of
+    /// the router, the data is dropped immediately and counted, so that one
+    /// slow socket cannot grow the memory of the relay. Returns whether the
+    /// data was queued.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::{net::SocketAddr, time::Duration};
+    /// use stun::{Kind, Method};
+    /// use turn::ResponseMethod;
+    /// use turn_server::router::*;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let addr = "127.0.0.1:8080".parse::<SocketAddr>().unwrap();
+    ///     let router = Router::new(None, Duration::from_secs(60), Some(2));
+    ///     let mut receiver = router.get_receiver(addr);
+    ///
+    ///     assert!(router.try_send(&addr, ResponseMethod::ChannelData, &addr, &[1]));
+    ///     assert!(router.try_send(&addr, ResponseMethod::ChannelData, &addr, &[2]));
+    ///     assert!(!router.try_send(&addr, ResponseMethod::ChannelData, &addr, &[3]));
+    ///     assert!(!router.try_send(&addr, ResponseMethod::ChannelData, &addr, &[4]));
+    ///     assert_eq!(router.dropped(), 2);
+    ///
+    ///     // The other messages are never dropped.
+    ///     let binding = ResponseMethod::Stun(Method::Binding(Kind::Response));
+    ///     router.send(&addr, binding, &addr, &[5]);
+    ///     assert_eq!(router.dropped(), 2);
+    ///
+    ///     assert_eq!(receiver.recv().await.unwrap().0, vec![1]);
+    ///     assert_eq!(receiver.recv().await.unwrap().0, vec![2]);
+    ///     assert!(router.try_send(&addr, ResponseMethod::ChannelData, &addr, &[6]));
+    ///     assert_eq!(receiver.recv().await.unwrap().0, vec![5]);
+    ///     assert_eq!(receiver.recv().await.unwrap().0, vec![6]);
+    /// }
+    /// ```
+    pub fn try_send(&self, interface: &SocketAddr, method: ResponseMethod, addr: &SocketAddr, data: &[u8]) -> bool {
+        if !self.admit(method, addr, data.len()) {
+            return false;
+        }
+
+        self.push(interface, self.queue, (data.to_vec(), method, *addr))
+    }
+
+    fn push(&self, interface: &SocketAddr, limit: Option<usize>, message: Message) -> bool {
         let mut is_destroy = false;
 
         {
-            if let Some(sender) = self.0.read().get(interface) {
-                if sender.send((data.to_vec(), method, *addr)).is_err() {
-                    is_destroy = true;
+            if let Some(entry) = self.table.read().entries.get(interface) {
+                if limit.is_some_and(|it| entry.queued.load(Ordering::Relaxed) >= it) {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+
+                    #[cfg(feature = "prometheus")]
+                    crate::statistics::prometheus::METRICS.queue_dropped.inc();
+
+                    return false;
+                }
+
+                entry.queued.fetch_add(1, Ordering::Relaxed);
+                if entry.sender.send(message).is_ok() {
+                    entry.active.store(self.now(), Ordering::Relaxed);
+                    return true;
                 }
+
+                is_destroy = true;
             }
         }
 
         if is_destroy {
             self.remove(interface);
         }
+
+        false
+    }
+
+    /// Get the number of the relayed packets dropped because the queue was
+    /// full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
     }
 
     /// delete socket.
@@ -118,6 +1007,6 @@ impl Router {
     /// }
     /// ```
     pub fn remove(&self, interface: &SocketAddr) {
-        drop(self.0.write().remove(interface))
+        drop(self.table.write().remove(interface))
     }
 }
@@ -1,12 +1,32 @@
 use crate::{
     config::{Config, Interface},
-    router::Router,
+    router::{BandwidthLimit, PacketLimit, RouteReceiver, Router},
     statistics::Statistics,
 };
 
-use std::net::SocketAddr;
+use std::{
+    future::Future,
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
-use turn::{Observer, Service};
+use parking_lot::Mutex;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::{
+    net::{TcpListener, UdpSocket},
+    runtime::{Builder, Handle},
+    sync::{
+        mpsc::{channel, error::TryRecvError, Sender},
+        Semaphore,
+    },
+    time::{sleep, timeout_at, Instant},
+};
+use turn::{policy::FamilyMode, Observer, ResponseMethod, Service};
 
 #[allow(unused)]
 struct ServerStartOptions<T> {
@@ -15,6 +35,728 @@ struct ServerStartOptions<T> {
     service: Service<T>,
     router: Router,
     statistics: Statistics,
+    retry: SendRetry,
+    retry_queue: usize,
+    socket_options: SocketOptions,
+    control: Option<ControlPlane>,
+    pin_relay: bool,
+    disconnect_grace: Option<u32>,
+    delays: DelayLimit,
+    coalesce_delay: Option<Duration>,
+    accept_rate: Option<u32>,
+    backlog: u32,
+    message_size: usize,
+    proxy_protocol: bool,
+}
+
+/// Retry policy for socket sends.
+///
+/// Sending on a udp socket can fail transiently when the socket send buffer
+/// is full, in which case the buffer usually clears quickly. Instead of
+/// dropping the packet immediately, the packet is handed over to the retry
+/// queue of the socket and retried a bounded number of times with a tiny
+/// backoff, so the socket loop is not blocked, see [`RetryQueue`]. Permanent
+/// errors are never retried.
+#[derive(Debug, Clone, Copy)]
+pub struct SendRetry {
+    pub retries: usize,
+    pub backoff: Duration,
+}
+
+impl SendRetry {
+    pub fn new(retries: usize) -> Self {
+        Self {
+            backoff: Duration::from_millis(1),
+            retries,
+        }
+    }
+
+    /// Check if the send error is transient and worth retrying.
+    ///
+    /// The full send buffer is reported as ENOBUFS or as would-block by the
+    /// kernel, both clear as soon as the buffer drains, so they are retried
+    /// instead of being treated as fatal.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::io::{Error, ErrorKind};
+    /// use turn_server::server::SendRetry;
+    ///
+    /// #[cfg(unix)]
+    /// assert!(SendRetry::is_transient(&Error::from_raw_os_error(libc::ENOBUFS)));
+    /// assert!(SendRetry::is_transient(&Error::from(ErrorKind::WouldBlock)));
+    /// assert!(SendRetry::is_transient(&Error::from(ErrorKind::Interrupted)));
+    /// assert!(!SendRetry::is_transient(&Error::from(ErrorKind::InvalidInput)));
+    /// assert!(!SendRetry::is_transient(&Error::from(ErrorKind::HostUnreachable)));
+    /// ```
+    pub fn is_transient(error: &io::Error) -> bool {
+        #[cfg(unix)]
+        if error.raw_os_error() == Some(libc::ENOBUFS) {
+            return true;
+        }
+
+        matches!(error.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted)
+    }
+}
+
+/// The bounded queue of the sends of a socket that failed transiently.
+///
+/// The queue is drained by a single task in order, each send is retried
+/// with the backoff of the policy growing with each attempt. While the queue
+/// is not empty the new sends of the socket are queued behind it as well,
+/// see [`RetryQueue::is_pending`], so the retries do not reorder the packets.
+/// The sends over the capacity of the queue are dropped and counted.
+///
+/// # Example
+///
+/// ```
+/// use std::{
+///     io::{Error, ErrorKind},
+///     sync::{Arc, Mutex},
+///     time::Duration,
+/// };
+///
+/// use turn_server::server::{RetryQueue, SendRetry};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let sent = Arc::new(Mutex::new(Vec::new()));
+///     let sent_ = sent.clone();
+///     let queue = RetryQueue::new(SendRetry::new(3), 2, move |packet: u8| {
+///         let sent = sent_.clone();
+///         async move {
+///             // The first attempt of every packet is interrupted.
+///             let mut sent = sent.lock().unwrap();
+///             if sent.last() == Some(&(packet, false)) {
+///                 sent.push((packet, true));
+///                 Ok(())
+///             } else {
+///                 sent.push((packet, false));
+///                 Err(Error::from(ErrorKind::Interrupted))
+///             }
+///         }
+///     });
+///
+///     assert!(queue.push(1));
+///     assert!(queue.push(2));
+///     assert!(!queue.push(3));
+///     assert_eq!(queue.dropped(), 1);
+///     assert!(queue.is_pending());
+///
+///     while queue.is_pending() {
+///         tokio::time::sleep(Duration::from_millis(10)).await;
+///     }
+///
+///     // The packets are retried in order.
+///     assert_eq!(
+///         *sent.lock().unwrap(),
+///         [(1, false), (1, true), (2, false), (2, true)]
+///     );
+/// }
+/// ```
+pub struct RetryQueue<T> {
+    retries: usize,
+    capacity: usize,
+    sender: Sender<T>,
+    pending: Arc<AtomicUsize>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl<T> Clone for RetryQueue<T> {
+    fn clone(&self) -> Self {
+        Self {
+            retries: self.retries,
+            capacity: self.capacity,
+            sender: self.sender.clone(),
+            pending: self.pending.clone(),
+            dropped: self.dropped.clone(),
+        }
+    }
+}
+
+impl<T> RetryQueue<T>
+where
+    T: Clone + Send + 'static,
+{
+    /// Create the queue of the capacity and start the task draining it, the
+    /// task stops when all the handles of the queue are dropped.
+    pub fn new<F, Fut>(retry: SendRetry, capacity: usize, send: F) -> Self
+    where
+        F: Fn(T) -> Fut + Send + 'static,
+        Fut: Future<Output = io::Result<()>> + Send,
+    {
+        let (sender, mut receiver) = channel::<T>(capacity.max(1));
+        let pending = Arc::new(AtomicUsize::new(0));
+
+        let pending_ = pending.clone();
+        tokio::spawn(async move {
+            while let Some(item) = receiver.recv().await {
+                for attempt in 0..retry.retries {
+                    if attempt > 0 {
+                        sleep(retry.backoff * (1 << attempt.min(8)) as u32).await;
+                    }
+
+                    match send(item.clone()).await {
+                        Err(e) if SendRetry::is_transient(&e) => (),
+                        _ => break,
+                    }
+                }
+
+                pending_.fetch_sub(1, Ordering::Relaxed);
+            }
+        });
+
+        Self {
+            dropped: Arc::new(AtomicU64::new(0)),
+            retries: retry.retries,
+            capacity: capacity.max(1),
+            pending,
+            sender,
+        }
+    }
+
+    /// Queue the send, returns false if the queue is full and the send is
+    /// dropped. The queue of a policy without retries keeps nothing.
+    pub fn push(&self, item: T) -> bool {
+        // The send being retried by the task is still pending, so the capacity
+        // is bounded by the pending sends rather than by the channel.
+        let pending = self.pending.fetch_add(1, Ordering::Relaxed);
+        if self.retries > 0 && pending < self.capacity && self.sender.try_send(item).is_ok() {
+            return true;
+        }
+
+        self.pending.fetch_sub(1, Ordering::Relaxed);
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+
+        #[cfg(feature = "prometheus")]
+        crate::statistics::prometheus::METRICS.retry_dropped.inc();
+
+        false
+    }
+
+    /// Check if there are sends in the queue, the new sends of the socket are
+    /// queued behind them.
+    pub fn is_pending(&self) -> bool {
+        self.pending.load(Ordering::Relaxed) > 0
+    }
+
+    /// Get the number of the sends dropped because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// The options of the sockets, the kernel buffer sizes and the bind flags.
+///
+/// High packet rates need larger kernel buffers to avoid drops during bursts,
+/// the sizes are applied with SO_RCVBUF and SO_SNDBUF, and the system default
+/// is kept when the size is not set. The kernel may clamp the size to its own
+/// limit, in which case a warning is logged.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SocketOptions {
+    pub recv: Option<usize>,
+    pub send: Option<usize>,
+    /// Bind the sockets with IP_FREEBIND, so that the address does not have
+    /// to be local yet. Only supported on Linux, ignored with a warning on the
+    /// other platforms.
+    pub freebind: bool,
+    /// The DSCP of the sent packets, the upper 6 bits of IP_TOS or
+    /// IPV6_TCLASS. The IPv6 sockets are only marked on the platforms that
+    /// support IPV6_TCLASS, and left with a warning on the others.
+    pub dscp: Option<u8>,
+}
+
+impl SocketOptions {
+    fn apply(&self, socket: &Socket, bind: &SocketAddr) -> io::Result<()> {
+        if self.freebind {
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            {
+                if bind.is_ipv4() {
+                    socket.set_freebind(true)?;
+                } else {
+                    socket.set_freebind_ipv6(true)?;
+                }
+            }
+
+            #[cfg(not(any(target_os = "android", target_os = "linux")))]
+            log::warn!("freebind is not supported on this platform: bind={}", bind);
+        }
+
+        // The DSCP is the upper 6 bits of the byte, the lower 2 bits are the ECN.
+        if let Some(dscp) = self.dscp {
+            let tos = (dscp as u32) << 2;
+            if bind.is_ipv4() {
+                socket.set_tos(tos)?;
+            } else {
+                #[cfg(any(target_os = "android", target_os = "linux", target_os = "macos"))]
+                socket.set_tclass_v6(tos)?;
+
+                #[cfg(not(any(target_os = "android", target_os = "linux", target_os = "macos")))]
+                log::warn!("dscp of ipv6 is not supported on this platform: bind={}", bind);
+            }
+        }
+
+        if let Some(size) = self.recv {
+            socket.set_recv_buffer_size(size)?;
+
+            let actual = socket.recv_buffer_size()?;
+            if actual < size {
+                log::warn!(
+                    "socket recv buffer clamped by the kernel: requested={}, actual={}",
+                    size,
+                    actual
+                );
+            }
+        }
+
+        if let Some(size) = self.send {
+            socket.set_send_buffer_size(size)?;
+
+            let actual = socket.send_buffer_size()?;
+            if actual < size {
+                log::warn!(
+                    "socket send buffer clamped by the kernel: requested={}, actual={}",
+                    size,
+                    actual
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Bind the udp socket with the options.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use socket2::SockRef;
+    /// use turn_server::server::SocketOptions;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let socket_options = SocketOptions {
+    ///         recv: Some(65536),
+    ///         send: Some(65536),
+    ///         freebind: false,
+    ///         dscp: Some(46),
+    ///     };
+    ///
+    ///     let socket = socket_options.bind_udp("127.0.0.1:0".parse().unwrap()).unwrap();
+    ///     let socket = SockRef::from(&socket);
+    ///
+    ///     // The kernel may round up or clamp the size, only the lower bound of a
+    ///     // size below the usual limits is asserted.
+    ///     assert!(socket.recv_buffer_size().unwrap() >= 65536);
+    ///     assert!(socket.send_buffer_size().unwrap() >= 65536);
+    ///
+    ///     // The EF class of the voice.
+    ///     #[cfg(target_os = "linux")]
+    ///     assert_eq!(socket.tos().unwrap(), 46 << 2);
+    ///
+    ///     // The virtual address of the failover is not local, it can only be
+    ///     // bound with freebind.
+    ///     #[cfg(target_os = "linux")]
+    ///     {
+    ///         let vip = "192.0.2.1:0".parse().unwrap();
+    ///         assert!(SocketOptions::default().bind_udp(vip).is_err());
+    ///
+    ///         let socket_options = SocketOptions {
+    ///             freebind: true,
+    ///             ..Default::default()
+    ///         };
+    ///
+    ///         let socket = socket_options.bind_udp(vip).unwrap();
+    ///         assert!(SockRef::from(&socket).freebind().unwrap());
+    ///     }
+    /// }
+    /// ```
+    pub fn bind_udp(&self, bind: SocketAddr) -> io::Result<UdpSocket> {
+        let socket = Socket::new(Domain::for_address(bind), Type::DGRAM, Some(Protocol::UDP))?;
+        self.apply(&socket, &bind)?;
+
+        socket.set_nonblocking(true)?;
+        socket.bind(&bind.into())?;
+        UdpSocket::from_std(socket.into())
+    }
+
+    /// Bind the tcp listener with the options and the length of the queue of
+    /// the pending connections, the accepted connections inherit the buffer
+    /// sizes of the listener.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use socket2::SockRef;
+    /// use turn_server::server::SocketOptions;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let socket_options = SocketOptions {
+    ///         recv: Some(65536),
+    ///         send: None,
+    ///         freebind: false,
+    ///         dscp: None,
+    ///     };
+    ///
+    ///     let listener = socket_options.bind_tcp("127.0.0.1:0".parse().unwrap(), 128).unwrap();
+    ///     assert!(SockRef::from(&listener).recv_buffer_size().unwrap() >= 65536);
+    ///
+    ///     #[cfg(target_os = "linux")]
+    ///     {
+    ///         let socket_options = SocketOptions {
+    ///             freebind: true,
+    ///             ..Default::default()
+    ///         };
+    ///
+    ///         let listener = socket_options.bind_tcp("[2001:db8::1]:0".parse().unwrap(), 128);
+    ///         if let Ok(listener) = listener {
+    ///             // The hosts without IPv6 cannot create the socket at all.
+    ///             assert!(SockRef::from(&listener).freebind_ipv6().unwrap());
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub fn bind_tcp(&self, bind: SocketAddr, backlog: u32) -> io::Result<TcpListener> {
+        let socket = Socket::new(Domain::for_address(bind), Type::STREAM, Some(Protocol::TCP))?;
+        self.apply(&socket, &bind)?;
+
+        // Keep the behavior of the tokio listener, which allows the address to be
+        // reused while the previous connections are in the TIME_WAIT state.
+        #[cfg(unix)]
+        socket.set_reuse_address(true)?;
+
+        socket.set_nonblocking(true)?;
+        socket.bind(&bind.into())?;
+        socket.listen(backlog.min(i32::MAX as u32) as i32)?;
+        TcpListener::from_std(socket.into())
+    }
+}
+
+/// The runtime of the stun control plane.
+///
+/// By default the stun requests and the relayed data are processed on the
+/// same runtime. Binding requests are cheap but latency sensitive for ICE
+/// connectivity checks, while the relay is throughput heavy, so a throughput
+/// spike of the relay adds latency to the connectivity checks. The control
+/// plane runs the stun requests received on the udp sockets on a dedicated
+/// runtime, and the ChannelData and indications stay on the main runtime.
+/// The requests are handed over through a queue, which costs a copy of each
+/// request, and the requests are dropped if the queue is full.
+///
+/// # Test
+///
+/// ```
+/// use turn_server::server::ControlPlane;
+///
+/// // binding request
+/// assert!(ControlPlane::is_control(&[0x00, 0x01, 0x00, 0x00]));
+/// // send indication
+/// assert!(!ControlPlane::is_control(&[0x00, 0x16, 0x00, 0x00]));
+/// // channel data
+/// assert!(!ControlPlane::is_control(&[0x40, 0x00, 0x00, 0x00]));
+/// ```
+#[derive(Clone)]
+pub struct ControlPlane {
+    handle: Handle,
+    threads: usize,
+}
+
+impl ControlPlane {
+    /// Create the control plane runtime with the number of worker threads,
+    /// the runtime lives as long as the process.
+    pub fn new(threads: usize) -> io::Result<Self> {
+        let runtime = Builder::new_multi_thread()
+            .thread_name("turn-control-plane")
+            .worker_threads(threads)
+            .enable_all()
+            .build()?;
+
+        let handle = runtime.handle().clone();
+        std::thread::Builder::new()
+            .name("turn-control-plane".to_string())
+            .spawn(move || runtime.block_on(std::future::pending::<()>()))?;
+
+        Ok(Self { handle, threads })
+    }
+
+    /// Check if the message belongs to the control plane, which are the stun
+    /// messages except the indications.
+    pub fn is_control(bytes: &[u8]) -> bool {
+        if bytes.len() < 2 || bytes[0] >> 6 != 0 {
+            return false;
+        }
+
+        u16::from_be_bytes([bytes[0], bytes[1]]) & 0x0110 != 0x0010
+    }
+}
+
+/// Coalescing of the writes of a tcp connection.
+///
+/// Over tcp the many small messages forwarded to a connection cause as many
+/// small writes. With a delay, the messages queued for the connection are
+/// collected into one write, waiting at most the delay after the first
+/// message for the next ones, so the latency is bounded. Without a delay each
+/// message is written on its own.
+///
+/// # Example
+///
+/// ```
+/// use std::{net::SocketAddr, time::Duration};
+/// use turn::ResponseMethod;
+/// use turn_server::{router::Router, server::WriteBatch};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let addr = "127.0.0.1:8080".parse::<SocketAddr>().unwrap();
+///     let router = Router::default();
+///     let mut receiver = router.get_receiver(addr);
+///
+///     let mut batch = WriteBatch::new(Some(Duration::from_millis(5)));
+///     for _ in 0..3 {
+///         router.send(&addr, ResponseMethod::ChannelData, &addr, &[0x40, 0x00, 0x00, 0x01, 0xff]);
+///     }
+///
+///     // The burst is written at once, with the padding of each channel data.
+///     assert!(batch.collect(&mut receiver).await);
+///     assert_eq!(batch.frames(), 3);
+///     assert_eq!(batch.as_bytes().len(), 24);
+///
+///     router.send(&addr, ResponseMethod::ChannelData, &addr, &[0x40, 0x00, 0x00, 0x00]);
+///     assert!(batch.collect(&mut receiver).await);
+///     assert_eq!(batch.frames(), 1);
+///
+///     let mut batch = WriteBatch::new(None);
+///     for _ in 0..2 {
+///         router.send(&addr, ResponseMethod::ChannelData, &addr, &[0x40, 0x00, 0x00, 0x00]);
+///     }
+///
+///     assert!(batch.collect(&mut receiver).await);
+///     assert_eq!(batch.frames(), 1);
+///
+///     router.remove(&addr);
+///     assert!(batch.collect(&mut receiver).await);
+///     assert!(!batch.collect(&mut receiver).await);
+/// }
+/// ```
+pub struct WriteBatch {
+    buffer: Vec<u8>,
+    delay: Option<Duration>,
+    frames: usize,
+    size: usize,
+}
+
+impl WriteBatch {
+    /// The maximum size of a batch, the messages queued after it is reached
+    /// are left for the next batch.
+    pub const MAX_SIZE: usize = 65536;
+
+    pub fn new(delay: Option<Duration>) -> Self {
+        Self {
+            buffer: Vec::with_capacity(if delay.is_some() { Self::MAX_SIZE } else { 2048 }),
+            frames: 0,
+            size: 0,
+            delay,
+        }
+    }
+
+    fn push(&mut self, bytes: &[u8], method: ResponseMethod) {
+        self.buffer.extend_from_slice(bytes);
+        self.frames += 1;
+        self.size += bytes.len();
+
+        // The channel data needs to be aligned in multiples of 4 in tcp. If the
+        // channel data is forwarded to tcp, the alignment bit needs to be filled,
+        // because if the channel data comes from udp, it is not guaranteed to be
+        // aligned and needs to be checked.
+        if method == ResponseMethod::ChannelData {
+            let pad = bytes.len() % 4;
+            if pad > 0 {
+                self.buffer.extend_from_slice(&[0u8; 4][..(4 - pad)]);
+            }
+        }
+    }
+
+    /// Collect the next batch, returns false when the receiver is closed and
+    /// there is nothing left to write.
+    pub async fn collect(&mut self, receiver: &mut RouteReceiver) -> bool {
+        self.buffer.clear();
+        self.frames = 0;
+        self.size = 0;
+
+        match receiver.recv().await {
+            Some((bytes, method, _)) => self.push(&bytes, method),
+            None => return false,
+        }
+
+        let deadline = match self.delay {
+            Some(delay) => Instant::now() + delay,
+            None => return true,
+        };
+
+        while self.buffer.len() < Self::MAX_SIZE {
+            let message = match receiver.try_recv() {
+                Ok(it) => it,
+                Err(TryRecvError::Disconnected) => break,
+                Err(TryRecvError::Empty) => match timeout_at(deadline, receiver.recv()).await {
+                    Ok(Some(it)) => it,
+                    _ => break,
+                },
+            };
+
+            self.push(&message.0, message.1);
+        }
+
+        true
+    }
+
+    /// Get the collected bytes, with the padding of the channel data.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Get the number of the collected messages.
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Get the size of the collected messages, without the padding.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+/// The rate limit of the accepted tcp connections.
+///
+/// The connections are counted in one-second windows, the connections over
+/// the rate of the current window are shed, which only closes the new
+/// connections, the established connections are not affected.
+///
+/// # Example
+///
+/// ```
+/// use turn_server::server::AcceptLimit;
+///
+/// let limit = AcceptLimit::new(3);
+/// let admitted = (0..10).filter(|_| limit.admit()).count();
+///
+/// assert_eq!(admitted, 3);
+/// assert_eq!(limit.shed(), 7);
+/// ```
+pub struct AcceptLimit {
+    rate: u32,
+    window: Mutex<(Instant, u32)>,
+    shed: AtomicU64,
+}
+
+impl AcceptLimit {
+    /// Create a limit of the rate in connections per second.
+    pub fn new(rate: u32) -> Self {
+        Self {
+            window: Mutex::new((Instant::now(), 0)),
+            shed: AtomicU64::new(0),
+            rate,
+        }
+    }
+
+    /// Check if a new connection can be accepted, the admitted connection
+    /// is counted in the current window.
+    pub fn admit(&self) -> bool {
+        let mut window = self.window.lock();
+        if window.0.elapsed() >= Duration::from_secs(1) {
+            *window = (Instant::now(), 0);
+        }
+
+        if window.1 >= self.rate {
+            self.shed.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+
+        window.1 += 1;
+        true
+    }
+
+    /// Get the number of connections shed over the limit.
+    pub fn shed(&self) -> u64 {
+        self.shed.load(Ordering::Relaxed)
+    }
+}
+
+/// The bound of the pending delayed responses.
+///
+/// The responses of the failed authentications are held back by the auth
+/// failure delay, each one by a task that sleeps until it is sent. A client
+/// flooding bad credentials would otherwise queue an unbounded number of
+/// those tasks, so the responses over the limit are dropped at once, the
+/// client retransmits the request anyway.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+/// use turn_server::server::DelayLimit;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let limit = DelayLimit::new(2);
+///     let spawned = (0..5)
+///         .filter(|_| limit.spawn(Duration::from_millis(50), async {}))
+///         .count();
+///
+///     assert_eq!(spawned, 2);
+///     assert_eq!(limit.dropped(), 3);
+///
+///     tokio::time::sleep(Duration::from_millis(200)).await;
+///     assert!(limit.spawn(Duration::from_millis(50), async {}));
+/// }
+/// ```
+#[derive(Clone)]
+pub struct DelayLimit {
+    pending: Arc<Semaphore>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl DelayLimit {
+    /// The default number of the pending delayed responses of the server.
+    pub const DEFAULT: usize = 4096;
+
+    /// Create a limit of the number of the pending delayed responses.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            pending: Arc::new(Semaphore::new(limit)),
+            dropped: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Run the task after the delay, returns `false` and drops the task if
+    /// the limit of the pending tasks is reached.
+    pub fn spawn<F>(&self, delay: Duration, task: F) -> bool
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let permit = match self.pending.clone().try_acquire_owned() {
+            Ok(it) => it,
+            Err(_) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                return false;
+            }
+        };
+
+        tokio::spawn(async move {
+            sleep(delay).await;
+            task.await;
+
+            drop(permit);
+        });
+
+        true
+    }
+
+    /// Get the number of delayed responses dropped over the limit.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
 }
 
 #[allow(unused)]
@@ -24,19 +766,198 @@ trait Server {
         T: Clone + Observer + 'static;
 }
 
+/// Hand the response over to the router of the endpoint.
+///
+/// The relayed data over the queue limit of the endpoint is dropped and
+/// counted by the router, see [`Router::try_send`], the other messages are
+/// always queued.
+fn forward(router: &Router, endpoint: &SocketAddr, method: ResponseMethod, target: &SocketAddr, bytes: &[u8]) {
+    if !method.is_relayed() {
+        router.send(endpoint, method, target, bytes);
+    } else if !router.try_send(endpoint, method, target, bytes) {
+        log::trace!("relayed data dropped: endpoint={:?}, addr={:?}", endpoint, target);
+    }
+}
+
+/// The time a connection over the connection cap has to send its request,
+/// which is answered with 508 (Insufficient Capacity).
+#[cfg(any(feature = "tcp", feature = "ws", all(unix, feature = "uds")))]
+const SATURATED_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Read from the connection, the read fails with a timeout once the deadline
+/// has passed, there is no deadline if it is `None`.
+#[cfg(any(feature = "tcp", feature = "ws", all(unix, feature = "uds")))]
+async fn read_until<F>(deadline: Option<Instant>, read: F) -> io::Result<usize>
+where
+    F: Future<Output = io::Result<usize>>,
+{
+    match deadline {
+        Some(it) => timeout_at(it, read)
+            .await
+            .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into())),
+        None => read.await,
+    }
+}
+
+/// Close the session of a disconnected client, the allocation is kept for
+/// the grace period instead if there is one, so that the client can
+/// reclaim it by reconnecting.
+#[cfg(any(feature = "tcp", feature = "ws"))]
+fn close_session<T: Observer + 'static>(sessions: &turn::Sessions<T>, addr: &turn::SessionAddr, grace: Option<u32>) {
+    if !grace.is_some_and(|it| sessions.detach(addr, it, stun::Transport::TCP)) {
+        sessions.refresh(addr, 0);
+    }
+}
+
 #[cfg(feature = "udp")]
 mod udp {
-    use super::{Server as ServerExt, ServerStartOptions};
-    use crate::statistics::Stats;
+    use super::{forward, ControlPlane, DelayLimit, RetryQueue, SendRetry, Server as ServerExt, ServerStartOptions};
+    use crate::{
+        router::Router,
+        statistics::{StatisticsReporter, Stats},
+    };
 
-    use std::{io::ErrorKind::ConnectionReset, ops::Deref, sync::Arc};
+    use std::{io::ErrorKind::ConnectionReset, net::SocketAddr, ops::Deref, sync::Arc};
 
     use once_cell::sync::Lazy;
-    use stun::Transport;
-    use tokio::net::UdpSocket;
-    use turn::{Observer, ResponseMethod, SessionAddr};
+    use stun::{Decoder, Transport};
+    use tokio::{
+        net::UdpSocket,
+        sync::{mpsc::channel, Mutex},
+    };
+    use turn::{operations::Response, Observer, SessionAddr};
+
+    static NUM_CPUS: Lazy<usize> = Lazy::new(num_cpus::get);
+
+    /// A send of the retry queue of the socket, the packet, the target and the
+    /// session the packet is counted to.
+    type Retry = (Arc<[u8]>, SocketAddr, SessionAddr);
+
+    /// Create the retry queue of the socket, the retried sends are counted to
+    /// their sessions like the other sends.
+    fn retry_queue(
+        retry: SendRetry,
+        capacity: usize,
+        socket: &Arc<UdpSocket>,
+        reporter: StatisticsReporter,
+    ) -> RetryQueue<Retry> {
+        let socket = socket.clone();
+        RetryQueue::new(retry, capacity, move |(bytes, target, session_addr): Retry| {
+            let reporter = reporter.clone();
+            let socket = socket.clone();
+            async move {
+                let size = socket.send_to(&bytes, target).await?;
+                reporter.send(&session_addr, &[Stats::SendBytes(size as u32), Stats::SendPkts(1)]);
+
+                Ok(())
+            }
+        })
+    }
+
+    /// Send the packet on the socket.
+    ///
+    /// The send buffer of the socket is temporarily full, hand the packet over
+    /// to the retry queue so that the socket loop is not blocked. The packets
+    /// sent while the queue is not empty are queued behind it, so that they do
+    /// not overtake the retried packets.
+    async fn send(
+        socket: &UdpSocket,
+        queue: &RetryQueue<Retry>,
+        reporter: &StatisticsReporter,
+        session_addr: SessionAddr,
+        target: SocketAddr,
+        bytes: &[u8],
+    ) {
+        let queued = || {
+            if !queue.push((Arc::from(bytes), target, session_addr)) {
+                log::debug!("udp socket retry queue is full, packet dropped: addr={}", target);
+            }
+        };
+
+        if queue.is_pending() {
+            return queued();
+        }
 
-    static NUM_CPUS: Lazy<usize> = Lazy::new(|| num_cpus::get());
+        match socket.send_to(bytes, target).await {
+            Ok(_) => reporter.send(
+                &session_addr,
+                &[Stats::SendBytes(bytes.len() as u32), Stats::SendPkts(1)],
+            ),
+            Err(e) if SendRetry::is_transient(&e) => queued(),
+            Err(e) => {
+                if e.kind() != ConnectionReset {
+                    log::warn!("udp socket send failed: addr={}, err={}", target, e);
+                }
+            }
+        }
+    }
+
+    /// Send the response of the request, the relayed data of other interfaces
+    /// is handed over to the router.
+    ///
+    /// The response is sent on the socket that received the request, so that
+    /// its source address is the address the client sent the request to and
+    /// the NAT mapping of the client matches. Each listener has its own
+    /// socket, the workers and the control plane of a listener share it.
+    async fn reply(
+        res: Response<'_>,
+        socket: &Arc<UdpSocket>,
+        router: &Router,
+        reporter: &StatisticsReporter,
+        queue: &RetryQueue<Retry>,
+        delays: &DelayLimit,
+        session_addr: SessionAddr,
+    ) {
+        #[cfg(feature = "prometheus")]
+        crate::statistics::prometheus::METRICS.response(res.method, res.bytes.len());
+
+        let target = res.relay.as_ref().unwrap_or(&session_addr.address);
+        if let Some(ref endpoint) = res.endpoint {
+            forward(router, endpoint, res.method, target, res.bytes);
+        } else if !router.admit(res.method, target, res.bytes.len()) {
+            // The relayed data is over the bandwidth limit of the server.
+        } else if let Some(delay) = res.delay {
+            // The delayed response is sent by a separate task, so that the socket
+            // loop is not blocked.
+            let bytes = res.bytes.to_vec();
+            let reporter = reporter.clone();
+            let socket = socket.clone();
+            let target = *target;
+            let spawned = delays.spawn(delay, async move {
+                if socket.send_to(&bytes, target).await.is_ok() {
+                    reporter.send(
+                        &session_addr,
+                        &[
+                            Stats::SendBytes(bytes.len() as u32),
+                            Stats::SendPkts(1),
+                            Stats::ErrorPkts(1),
+                        ],
+                    );
+                }
+            });
+
+            if !spawned {
+                log::debug!("delayed response dropped: addr={}", session_addr.address);
+            }
+        } else {
+            send(socket, queue, reporter, session_addr, *target, res.bytes).await;
+            if res.method.is_error() {
+                reporter.send(&session_addr, &[Stats::ErrorPkts(1)]);
+            }
+        }
+    }
+
+    /// The first message of the datagram, or `None` if the length of the
+    /// message is malformed.
+    ///
+    /// The size is exact, the ChannelData over udp is not padded (RFC 8656
+    /// section 12.5), only the stream transports pad it.
+    fn coalesced(bytes: &[u8]) -> Option<&[u8]> {
+        match Decoder::message_size(bytes, false) {
+            Ok(size) if size <= bytes.len() => Some(&bytes[..size]),
+            _ => None,
+        }
+    }
 
     /// udp socket process thread.
     ///
@@ -53,18 +974,70 @@ mod udp {
                 service,
                 router,
                 statistics,
+                retry,
+                retry_queue: capacity,
+                socket_options,
+                control,
+                delays,
+                message_size,
+                ..
             }: ServerStartOptions<T>,
         ) -> Result<(), anyhow::Error>
         where
             T: Clone + Observer + 'static,
         {
-            let socket = Arc::new(UdpSocket::bind(bind).await?);
+            let socket = Arc::new(socket_options.bind_udp(bind)?);
             let local_addr = socket.local_addr()?;
 
+            // The retried sends of all the workers of the socket share one queue, so
+            // that they are sent in order.
+            let queue = retry_queue(retry, capacity, &socket, statistics.get_reporter(Transport::UDP));
+
+            // The stun requests are handed over to the workers of the control plane
+            // runtime, each worker processes the requests with its own operationer.
+            let control = control.map(|control| {
+                let (sender, receiver) = channel::<(Vec<u8>, SocketAddr)>(4096);
+                let receiver = Arc::new(Mutex::new(receiver));
+
+                for _ in 0..control.threads {
+                    let socket = socket.clone();
+                    let router = router.clone();
+                    let delays = delays.clone();
+                    let queue = queue.clone();
+                    let receiver = receiver.clone();
+                    let reporter = statistics.get_reporter(Transport::UDP);
+                    let mut operationer = service.get_operationer(external, external);
+
+                    let mut session_addr = SessionAddr {
+                        address: external,
+                        interface: external,
+                    };
+
+                    control.handle.spawn(async move {
+                        loop {
+                            let (bytes, addr) = match receiver.lock().await.recv().await {
+                                Some(it) => it,
+                                None => break,
+                            };
+
+                            session_addr.address = addr;
+                            if let Ok(Some(res)) = operationer.route(&bytes, addr).await {
+                                reply(res, &socket, &router, &reporter, &queue, &delays, session_addr).await;
+                            }
+                        }
+                    });
+                }
+
+                sender
+            });
+
             tokio::spawn(async move {
                 for _ in 0..*NUM_CPUS.deref() {
+                    let control = control.clone();
                     let socket = socket.clone();
                     let router = router.clone();
+                    let delays = delays.clone();
+                    let queue = queue.clone();
                     let reporter = statistics.get_reporter(Transport::UDP);
                     let mut operationer = service.get_operationer(external, external);
 
@@ -74,7 +1047,9 @@ mod udp {
                     };
 
                     tokio::spawn(async move {
-                        let mut buf = vec![0u8; 2048];
+                        // The read buffer is reused for all the packets of the worker, the
+                        // relayed data is only copied when it is forwarded to another socket.
+                        let mut buf = vec![0u8; message_size];
 
                         loop {
                             // Note: An error will also be reported when the remote host is
@@ -93,33 +1068,37 @@ mod udp {
                                 &[Stats::ReceivedBytes(size as u32), Stats::ReceivedPkts(1)],
                             );
 
-                            // The stun message requires at least 4 bytes. (currently the
-                            // smallest stun message is channel data,
-                            // excluding content)
-                            if size >= 4 {
-                                if let Ok(Some(res)) = operationer.route(&buf[..size], addr).await {
-                                    let target = res.relay.as_ref().unwrap_or(&addr);
-                                    if let Some(ref endpoint) = res.endpoint {
-                                        router.send(endpoint, res.method, target, res.bytes);
-                                    } else {
-                                        if let Err(e) = socket.send_to(res.bytes, target).await {
-                                            if e.kind() != ConnectionReset {
-                                                break;
-                                            }
-                                        }
+                            // Several messages can be coalesced in a single datagram, they are
+                            // processed in sequence and each response is sent on its own. The
+                            // stun message requires at least 4 bytes. (currently the smallest
+                            // stun message is channel data, excluding content)
+                            let mut offset = 0;
+                            while offset + 4 <= size {
+                                // The next message starts right after the previous one. A
+                                // malformed length ends the walk, the trailing bytes are not
+                                // parsed as a new message, only a datagram whose first
+                                // message is malformed is processed as a whole.
+                                let bytes = match coalesced(&buf[offset..size]) {
+                                    Some(it) => it,
+                                    None if offset == 0 => &buf[..size],
+                                    None => break,
+                                };
 
-                                        reporter.send(
-                                            &session_addr,
-                                            &[Stats::SendBytes(res.bytes.len() as u32), Stats::SendPkts(1)],
-                                        );
+                                offset += bytes.len();
 
-                                        if let ResponseMethod::Stun(method) = res.method {
-                                            if method.is_error() {
-                                                reporter.send(&session_addr, &[Stats::ErrorPkts(1)]);
-                                            }
+                                if let Some(ref control) = control {
+                                    if ControlPlane::is_control(bytes) {
+                                        if control.try_send((bytes.to_vec(), addr)).is_err() {
+                                            log::warn!("control plane queue is full, request dropped: addr={}", addr);
                                         }
+
+                                        continue;
                                     }
                                 }
+
+                                if let Ok(Some(res)) = operationer.route(bytes, addr).await {
+                                    reply(res, &socket, &router, &reporter, &queue, &delays, session_addr).await;
+                                }
                             }
                         }
                     });
@@ -136,16 +1115,7 @@ mod udp {
                     while let Some((bytes, _, addr)) = receiver.recv().await {
                         session_addr.address = addr;
 
-                        if let Err(e) = socket.send_to(&bytes, addr).await {
-                            if e.kind() != ConnectionReset {
-                                break;
-                            }
-                        } else {
-                            reporter.send(
-                                &session_addr,
-                                &[Stats::SendBytes(bytes.len() as u32), Stats::SendPkts(1)],
-                            );
-                        }
+                        send(&socket, &queue, &reporter, session_addr, addr, &bytes).await;
                     }
 
                     router.remove(&external);
@@ -167,19 +1137,40 @@ mod udp {
 
 #[cfg(feature = "tcp")]
 mod tcp {
-    use super::{Server as ServerExt, ServerStartOptions};
-    use crate::statistics::Stats;
+    use super::{
+        close_session, forward, read_until, AcceptLimit, Server as ServerExt, ServerStartOptions, WriteBatch,
+        SATURATED_TIMEOUT,
+    };
+    use crate::{proxy, statistics::Stats};
 
     use std::{
+        io,
+        net::SocketAddr,
         ops::{Deref, DerefMut},
         sync::Arc,
+        time::Duration,
     };
 
-    use stun::{Decoder, Transport};
-    use tokio::{io::AsyncReadExt, io::AsyncWriteExt, net::TcpListener, sync::Mutex};
+    use stun::{Decoder, Kind, Method, Transport};
+    use tokio::{
+        io::AsyncReadExt,
+        io::AsyncWriteExt,
+        net::{TcpListener, TcpStream},
+        sync::{
+            mpsc::{channel, Receiver},
+            Mutex, Semaphore,
+        },
+        time::{timeout, Instant},
+    };
     use turn::{Observer, ResponseMethod, SessionAddr};
 
-    static ZERO_BYTES: [u8; 8] = [0u8; 8];
+    /// The time the load balancer has to send the PROXY protocol header of a
+    /// new connection.
+    const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// The number of the PROXY protocol headers read at the same time, the
+    /// connections over the limit are closed at once.
+    const PROXY_HEADER_PENDING: usize = 1024;
 
     /// An emulated double buffer queue, this is used when reading data over
     /// TCP.
@@ -203,14 +1194,15 @@ mod tcp {
         index: usize,
     }
 
-    impl Default for ExchangeBuffer {
+    impl ExchangeBuffer {
+        /// Create the buffers, each of which holds a message of the size.
         #[rustfmt::skip]
-        fn default() -> Self {
+        fn new(size: usize) -> Self {
             Self {
                 index: 0,
                 buffers: [
-                    (vec![0u8; 2048], 0),
-                    (vec![0u8; 2048], 0),
+                    (vec![0u8; size], 0),
+                    (vec![0u8; size], 0),
                 ],
             }
         }
@@ -273,8 +1265,101 @@ mod tcp {
                     .copy_from_slice(&current_bytes[len..current_len]);
             }
 
-            &current_bytes[..len]
-        }
+            &current_bytes[..len]
+        }
+    }
+
+    /// Read the PROXY protocol header at the start of the connection, returns
+    /// the source address of the header.
+    ///
+    /// The header is read exactly, so that the stun messages after it are left
+    /// in the socket.
+    async fn read_proxy_header(socket: &mut TcpStream) -> io::Result<Option<SocketAddr>> {
+        let mut bytes = vec![0u8; proxy::HEADER_SIZE];
+        socket.read_exact(&mut bytes).await?;
+
+        let size = proxy::header_size(&bytes).map_err(io::Error::other)?;
+        bytes.resize(size, 0);
+        socket.read_exact(&mut bytes[proxy::HEADER_SIZE..]).await?;
+
+        match proxy::decode(&bytes) {
+            Ok(Some((source, _))) => Ok(source),
+            Ok(None) => Err(io::ErrorKind::UnexpectedEof.into()),
+            Err(e) => Err(io::Error::other(e)),
+        }
+    }
+
+    /// Accept the connections of the listener.
+    ///
+    /// Under a connection flood the connections over the accept limit are
+    /// closed at once, before any task or state is allocated for them.
+    ///
+    /// With the PROXY protocol, the address of a connection is the source
+    /// address of its header. The header is read by a separate task, so that a
+    /// slow connection does not hold up the others, and the connections whose
+    /// header is invalid or late are closed. The pending headers are bounded,
+    /// see [`PROXY_HEADER_PENDING`].
+    fn accept(
+        listener: TcpListener,
+        proxy_protocol: bool,
+        accept_limit: Option<AcceptLimit>,
+    ) -> io::Result<Receiver<(TcpStream, SocketAddr)>> {
+        let (sender, receiver) = channel(1024);
+        let pending = Arc::new(Semaphore::new(PROXY_HEADER_PENDING));
+        let interface = listener.local_addr()?;
+
+        tokio::spawn(async move {
+            while let Ok((mut socket, address)) = listener.accept().await {
+                if accept_limit.as_ref().is_some_and(|it| !it.admit()) {
+                    log::warn!(
+                        "tcp socket shed, accept rate exceeded: addr={:?}, interface={:?}",
+                        address,
+                        interface
+                    );
+
+                    continue;
+                }
+
+                if !proxy_protocol {
+                    if sender.send((socket, address)).await.is_err() {
+                        break;
+                    }
+
+                    continue;
+                }
+
+                let permit = match pending.clone().try_acquire_owned() {
+                    Ok(it) => it,
+                    Err(_) => {
+                        log::debug!("tcp socket closed, too many pending proxy headers: addr={:?}", address);
+
+                        continue;
+                    }
+                };
+
+                // The failures are only logged at the debug level, a flood of bad
+                // connections would otherwise flood the logs too.
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    let ret = timeout(PROXY_HEADER_TIMEOUT, read_proxy_header(&mut socket)).await;
+                    drop(permit);
+
+                    match ret {
+                        Ok(Ok(source)) => {
+                            let _ = sender.send((socket, source.unwrap_or(address))).await;
+                        }
+                        Ok(Err(e)) => {
+                            log::debug!("tcp socket closed, invalid proxy header: addr={:?}, err={}", address, e);
+                        }
+                        Err(_) => {
+                            log::debug!("tcp socket closed, proxy header timeout: addr={:?}", address);
+                        }
+                    }
+                });
+            }
+        });
+
+        Ok(receiver)
     }
 
     /// tcp socket process thread.
@@ -291,22 +1376,50 @@ mod tcp {
                 service,
                 router,
                 statistics,
+                socket_options,
+                pin_relay,
+                disconnect_grace,
+                delays,
+                coalesce_delay,
+                accept_rate,
+                backlog,
+                message_size,
+                proxy_protocol,
+                ..
             }: ServerStartOptions<T>,
         ) -> Result<(), anyhow::Error>
         where
             T: Clone + Observer + 'static,
         {
-            let listener = TcpListener::bind(bind).await?;
+            let listener = socket_options.bind_tcp(bind, backlog)?;
             let local_addr = listener.local_addr()?;
+            let mut accepted = accept(listener, proxy_protocol, accept_rate.map(AcceptLimit::new))?;
 
             tokio::spawn(async move {
                 // Accept all connections on the current listener, but exit the entire
                 // process when an error occurs.
-                while let Ok((socket, address)) = listener.accept().await {
+                while let Some((socket, address)) = accepted.recv().await {
                     let router = router.clone();
+                    let delays = delays.clone();
                     let reporter = statistics.get_reporter(Transport::TCP);
-                    let mut receiver = router.get_receiver(address);
                     let mut operationer = service.get_operationer(address, external);
+                    operationer.set_transport(Transport::TCP);
+
+                    // When the router is at capacity and there is no idle connection to
+                    // reclaim, or the address already has a live connection, the
+                    // connection has no route, its first request is answered with 508
+                    // (Insufficient Capacity) and the connection is closed.
+                    let receiver = router.try_get_receiver(address);
+                    let saturated = receiver.is_none();
+                    if saturated {
+                        log::warn!(
+                            "tcp socket refused, insufficient capacity: addr={:?}, interface={:?}",
+                            address,
+                            local_addr
+                        );
+
+                        operationer.set_saturated(true);
+                    }
 
                     log::info!("tcp socket accept: addr={:?}, interface={:?}", address, local_addr,);
 
@@ -328,43 +1441,58 @@ mod tcp {
                     // Use a separate task to handle messages forwarded to this socket.
                     let writer_ = writer.clone();
                     let reporter_ = reporter.clone();
-                    tokio::spawn(async move {
-                        while let Some((bytes, method, _)) = receiver.recv().await {
-                            let mut writer = writer_.lock().await;
-                            if writer.write_all(bytes.as_slice()).await.is_err() {
-                                break;
-                            } else {
-                                reporter_.send(
-                                    &session_addr,
-                                    &[Stats::SendBytes(bytes.len() as u32), Stats::SendPkts(1)],
-                                );
-                            }
-
-                            // The channel data needs to be aligned in multiples of 4 in
-                            // tcp. If the channel data is forwarded to tcp, the alignment
-                            // bit needs to be filled, because if the channel data comes
-                            // from udp, it is not guaranteed to be aligned and needs to be
-                            // checked.
-                            if method == ResponseMethod::ChannelData {
-                                let pad = bytes.len() % 4;
-                                if pad > 0 && writer.write_all(&ZERO_BYTES[..(4 - pad)]).await.is_err() {
+                    let router_ = router.clone();
+                    let sessions = service.get_sessions();
+                    if let Some(mut receiver) = receiver {
+                        tokio::spawn(async move {
+                            let mut batch = WriteBatch::new(coalesce_delay);
+                            while batch.collect(&mut receiver).await {
+                                if writer_.lock().await.write_all(batch.as_bytes()).await.is_err() {
                                     break;
+                                } else {
+                                    reporter_.send(
+                                        &session_addr,
+                                        &[
+                                            Stats::SendBytes(batch.size() as u32),
+                                            Stats::SendPkts(batch.frames() as u32),
+                                        ],
+                                    );
                                 }
                             }
-                        }
-                    });
+
+                            // The route has been removed, either the connection is closed or it was
+                            // evicted by the router, close the session and the connection. The
+                            // evicted session is not kept for a reconnect, its slot is reclaimed.
+                            if router_.take_evicted(&address) {
+                                log::warn!("tcp socket evicted: addr={:?}, interface={:?}", address, local_addr);
+                                sessions.evict(&session_addr);
+                            } else {
+                                close_session(&sessions, &session_addr, disconnect_grace);
+                            }
+
+                            let _ = writer_.lock().await.shutdown().await;
+                        });
+                    }
 
                     let sessions = service.get_sessions();
                     tokio::spawn(async move {
-                        let mut buffer = ExchangeBuffer::default();
+                        let mut buffer = ExchangeBuffer::new(message_size);
 
-                        'a: while let Ok(size) = reader.read(&mut buffer).await {
+                        // The connection without a route only has a short time to send its
+                        // request.
+                        let deadline = saturated.then(|| Instant::now() + SATURATED_TIMEOUT);
+
+                        'a: while let Ok(size) = read_until(deadline, reader.read(&mut buffer)).await {
                             // When the received message is 0, it means that the socket
                             // has been closed.
                             if size == 0 {
                                 break;
                             } else {
                                 reporter.send(&session_addr, &[Stats::ReceivedBytes(size as u32)]);
+                                if !saturated {
+                                    router.touch(&address);
+                                }
+
                                 buffer.advance(size);
                             }
 
@@ -386,9 +1514,9 @@ mod tcp {
                                 let size = match Decoder::message_size(&buffer, true) {
                                     Err(_) => break,
                                     Ok(s) => {
-                                        // Limit the maximum length of messages to 2048, this is to prevent buffer
-                                        // overflow attacks.
-                                        if s > 2048 {
+                                        // Limit the maximum length of messages to the buffer size, this is to
+                                        // prevent buffer overflow attacks.
+                                        if s > message_size {
                                             break 'a;
                                         }
 
@@ -405,13 +1533,47 @@ mod tcp {
                                 let chunk = buffer.split(size);
                                 if let Ok(ret) = operationer.route(chunk, address).await {
                                     if let Some(res) = ret {
+                                        #[cfg(feature = "prometheus")]
+                                        crate::statistics::prometheus::METRICS.response(res.method, res.bytes.len());
+
+                                        // The connection is the relay of the allocation, pinning it
+                                        // keeps the relay for the lifetime of the allocation.
+                                        if pin_relay
+                                            && res.method == ResponseMethod::Stun(Method::Allocate(Kind::Response))
+                                        {
+                                            router.pin(&address);
+                                        }
+
                                         if let Some(ref inerface) = res.endpoint {
-                                            router.send(
+                                            forward(
+                                                &router,
                                                 inerface,
                                                 res.method,
                                                 res.relay.as_ref().unwrap_or(&address),
                                                 res.bytes,
                                             );
+                                        } else if let Some(delay) = res.delay {
+                                            // The delayed response is written by a separate task, so
+                                            // that the connection keeps being read.
+                                            let bytes = res.bytes.to_vec();
+                                            let reporter = reporter.clone();
+                                            let writer = writer.clone();
+                                            let spawned = delays.spawn(delay, async move {
+                                                if writer.lock().await.write_all(&bytes).await.is_ok() {
+                                                    reporter.send(
+                                                        &session_addr,
+                                                        &[
+                                                            Stats::SendBytes(bytes.len() as u32),
+                                                            Stats::SendPkts(1),
+                                                            Stats::ErrorPkts(1),
+                                                        ],
+                                                    );
+                                                }
+                                            });
+
+                                            if !spawned {
+                                                log::debug!("delayed response dropped: addr={}", session_addr.address);
+                                            }
                                         } else {
                                             if writer.lock().await.write_all(res.bytes).await.is_err() {
                                                 break 'a;
@@ -422,10 +1584,13 @@ mod tcp {
                                                 &[Stats::SendBytes(res.bytes.len() as u32), Stats::SendPkts(1)],
                                             );
 
-                                            if let ResponseMethod::Stun(method) = res.method {
-                                                if method.is_error() {
-                                                    reporter.send(&session_addr, &[Stats::ErrorPkts(1)]);
-                                                }
+                                            if res.method.is_error() {
+                                                reporter.send(&session_addr, &[Stats::ErrorPkts(1)]);
+                                            }
+
+                                            // The connection without a route is closed after its 508.
+                                            if saturated {
+                                                break 'a;
                                             }
                                         }
                                     }
@@ -435,11 +1600,20 @@ mod tcp {
                             }
                         }
 
+                        // The connection without a route has no session and no route, the
+                        // address may be the one of a live connection.
+                        if saturated {
+                            let _ = writer.lock().await.shutdown().await;
+                            log::info!("tcp socket closed: addr={:?}, interface={:?}", address, local_addr);
+
+                            return;
+                        }
+
                         // When the tcp connection is closed, the procedure to close the session is
                         // process directly once, avoiding the connection being disconnected
                         // directly without going through the closing
                         // process.
-                        sessions.refresh(&session_addr, 0);
+                        close_session(&sessions, &session_addr, disconnect_grace);
 
                         router.remove(&address);
 
@@ -461,32 +1635,728 @@ mod tcp {
     }
 }
 
+#[cfg(all(unix, feature = "uds"))]
+mod unix {
+    use super::{forward, read_until, DelayLimit, SATURATED_TIMEOUT};
+    use crate::{config::UnixInterface, router::Router, statistics::Statistics, statistics::Stats};
+
+    use std::{
+        net::{IpAddr, Ipv6Addr, SocketAddr},
+        os::unix::fs::FileTypeExt,
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc,
+        },
+    };
+
+    use anyhow::{bail, ensure};
+    use stun::Transport;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::UnixListener,
+        sync::Mutex,
+        time::Instant,
+    };
+
+    use turn::{Observer, Service, SessionAddr};
+
+    /// The frame header is the length, the family, the port and at most 16
+    /// bytes of the ip address.
+    const MAX_HEADER_SIZE: usize = 2 + 1 + 2 + 16;
+
+    static ENDPOINTS: AtomicU32 = AtomicU32::new(0);
+
+    /// The unix domain socket connections have no address, each connection is
+    /// routed by an address of the discard-only prefix 100::/64 (RFC 6666),
+    /// which is never the address of a real interface.
+    fn endpoint() -> SocketAddr {
+        let id = ENDPOINTS.fetch_add(1, Ordering::Relaxed);
+        let ip = Ipv6Addr::new(0x100, 0, 0, 0, 0, 0, (id >> 16) as u16, id as u16);
+        SocketAddr::new(IpAddr::V6(ip), 0)
+    }
+
+    /// Decode the frame at the start of the buffer, returns the address of the
+    /// client, the message and the size of the frame, or `None` if the frame
+    /// is incomplete.
+    fn decode(bytes: &[u8]) -> anyhow::Result<Option<(SocketAddr, &[u8], usize)>> {
+        if bytes.len() < 2 {
+            return Ok(None);
+        }
+
+        // Limit the maximum length of messages to 2048, this is to prevent buffer
+        // overflow attacks.
+        let size = u16::from_be_bytes([bytes[0], bytes[1]]) as usize + 2;
+        if size > 2048 + MAX_HEADER_SIZE {
+            bail!("unix frame is too large: size={}", size);
+        }
+
+        if bytes.len() < size {
+            return Ok(None);
+        }
+
+        let frame = &bytes[2..size];
+        let (ip, offset) = match frame.first() {
+            Some(4) if frame.len() >= 7 => (IpAddr::from(<[u8; 4]>::try_from(&frame[3..7])?), 7),
+            Some(6) if frame.len() >= 19 => (IpAddr::from(<[u8; 16]>::try_from(&frame[3..19])?), 19),
+            _ => bail!("invalid unix frame header"),
+        };
+
+        let port = u16::from_be_bytes([frame[1], frame[2]]);
+        Ok(Some((SocketAddr::new(ip, port), &frame[offset..], size)))
+    }
+
+    /// Frame the message sent to the client.
+    fn encode(addr: &SocketAddr, message: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(message.len() + MAX_HEADER_SIZE);
+        bytes.extend_from_slice(&[0, 0]);
+
+        match addr.ip() {
+            IpAddr::V4(ip) => {
+                bytes.push(4);
+                bytes.extend_from_slice(&addr.port().to_be_bytes());
+                bytes.extend_from_slice(&ip.octets());
+            }
+            IpAddr::V6(ip) => {
+                bytes.push(6);
+                bytes.extend_from_slice(&addr.port().to_be_bytes());
+                bytes.extend_from_slice(&ip.octets());
+            }
+        }
+
+        bytes.extend_from_slice(message);
+
+        let size = (bytes.len() - 2) as u16;
+        bytes[..2].copy_from_slice(&size.to_be_bytes());
+        bytes
+    }
+
+    /// unix domain socket process thread.
+    ///
+    /// A local proxy forwards the messages of many clients over one
+    /// connection, each message is framed with the address of the client. The
+    /// connection is routed like a udp socket, the messages forwarded to the
+    /// clients are framed with the address of the client and written to the
+    /// connection.
+    pub async fn start<T>(
+        UnixInterface { path, external }: UnixInterface,
+        service: Service<T>,
+        router: Router,
+        statistics: Statistics,
+        delays: DelayLimit,
+    ) -> anyhow::Result<()>
+    where
+        T: Clone + Observer + 'static,
+    {
+        if let Ok(metadata) = std::fs::metadata(&path) {
+            ensure!(
+                metadata.file_type().is_socket(),
+                "unix interface path is not a socket: {:?}",
+                path
+            );
+
+            std::fs::remove_file(&path)?;
+        }
+
+        let listener = UnixListener::bind(&path)?;
+
+        log::info!(
+            "turn server listening: path={:?}, external={}, transport=UDS",
+            path,
+            external,
+        );
+
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                let endpoint = endpoint();
+
+                let mut operationer = service.get_operationer(endpoint, external);
+                let reporter = statistics.get_reporter(Transport::TCP);
+                operationer.set_transport(Transport::TCP);
+
+                // The connection counts toward the connection cap, but it carries the
+                // clients of a proxy, so it is pinned and never evicted. The connection
+                // over the cap answers its first request with 508 (Insufficient
+                // Capacity) and is closed, see the tcp listener.
+                let receiver = router.try_get_receiver(endpoint);
+                let saturated = receiver.is_none();
+                if saturated {
+                    log::warn!("unix socket refused, insufficient capacity: path={:?}", path);
+
+                    operationer.set_saturated(true);
+                } else {
+                    router.pin(&endpoint);
+                }
+
+                log::info!("unix socket accept: endpoint={:?}, path={:?}", endpoint, path);
+
+                let (mut reader, writer) = socket.into_split();
+                let writer = Arc::new(Mutex::new(writer));
+
+                // Use a separate task to handle messages forwarded to this socket.
+                let writer_ = writer.clone();
+                let reporter_ = reporter.clone();
+                if let Some(mut receiver) = receiver {
+                    tokio::spawn(async move {
+                        while let Some((bytes, _, addr)) = receiver.recv().await {
+                            if writer_.lock().await.write_all(&encode(&addr, &bytes)).await.is_err() {
+                                break;
+                            }
+
+                            reporter_.send(
+                                &SessionAddr {
+                                    interface: external,
+                                    address: addr,
+                                },
+                                &[Stats::SendBytes(bytes.len() as u32), Stats::SendPkts(1)],
+                            );
+                        }
+                    });
+                }
+
+                let router = router.clone();
+                let delays = delays.clone();
+                let path = path.clone();
+                tokio::spawn(async move {
+                    let mut buffer = Vec::with_capacity(4096);
+                    let deadline = saturated.then(|| Instant::now() + SATURATED_TIMEOUT);
+
+                    'a: while let Ok(size) = read_until(deadline, reader.read_buf(&mut buffer)).await {
+                        // When the received message is 0, it means that the socket
+                        // has been closed.
+                        if size == 0 {
+                            break;
+                        }
+
+                        loop {
+                            let size = match decode(&buffer) {
+                                Err(e) => {
+                                    log::warn!("unix socket read failed: endpoint={:?}, err={}", endpoint, e);
+
+                                    break 'a;
+                                }
+                                Ok(None) => break,
+                                Ok(Some((address, message, size))) => {
+                                    let session_addr = SessionAddr {
+                                        interface: external,
+                                        address,
+                                    };
+
+                                    reporter.send(
+                                        &session_addr,
+                                        &[Stats::ReceivedBytes(message.len() as u32), Stats::ReceivedPkts(1)],
+                                    );
+
+                                    // The stun message requires at least 4 bytes.
+                                    if message.len() >= 4 {
+                                        if let Ok(Some(res)) = operationer.route(message, address).await {
+                                            #[cfg(feature = "prometheus")]
+                                            crate::statistics::prometheus::METRICS
+                                                .response(res.method, res.bytes.len());
+
+                                            let target = res.relay.as_ref().unwrap_or(&address);
+                                            if let Some(ref endpoint) = res.endpoint {
+                                                forward(&router, endpoint, res.method, target, res.bytes);
+                                            } else if router.admit(res.method, target, res.bytes.len()) {
+                                                let bytes = encode(target, res.bytes);
+                                                let mut stats =
+                                                    vec![Stats::SendBytes(res.bytes.len() as u32), Stats::SendPkts(1)];
+
+                                                if res.method.is_error() {
+                                                    stats.push(Stats::ErrorPkts(1));
+                                                }
+
+                                                if let Some(delay) = res.delay {
+                                                    // The delayed response is written by a separate task, so
+                                                    // that the connection keeps being read.
+                                                    let reporter = reporter.clone();
+                                                    let writer = writer.clone();
+                                                    let spawned = delays.spawn(delay, async move {
+                                                        if writer.lock().await.write_all(&bytes).await.is_ok() {
+                                                            reporter.send(&session_addr, &stats);
+                                                        }
+                                                    });
+
+                                                    if !spawned {
+                                                        log::debug!(
+                                                            "delayed response dropped: addr={}",
+                                                            session_addr.address
+                                                        );
+                                                    }
+                                                } else if writer.lock().await.write_all(&bytes).await.is_ok() {
+                                                    reporter.send(&session_addr, &stats);
+                                                } else {
+                                                    break 'a;
+                                                }
+
+                                                // The connection without a route is closed after its 508.
+                                                if saturated {
+                                                    break 'a;
+                                                }
+                                            }
+                                        }
+                                    }
+
+                                    size
+                                }
+                            };
+
+                            buffer.drain(..size);
+                        }
+                    }
+
+                    // The sessions of the clients are kept until they expire like the
+                    // sessions of a udp socket, the proxy may reconnect.
+                    if !saturated {
+                        router.remove(&endpoint);
+                    }
+
+                    log::info!("unix socket disconnect: endpoint={:?}, path={:?}", endpoint, path);
+                });
+            }
+
+            log::error!("unix server close: path={:?}", path);
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "ws")]
+mod ws {
+    use super::{close_session, forward, read_until, ServerStartOptions, SATURATED_TIMEOUT};
+    use crate::{
+        statistics::Stats,
+        websocket::{decode, encode, handshake, Opcode},
+    };
+
+    use std::sync::Arc;
+
+    use stun::Transport;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        sync::Mutex,
+        time::Instant,
+    };
+
+    use turn::{Observer, SessionAddr};
+
+    /// websocket process thread.
+    ///
+    /// Each connection starts with the opening handshake, after which every
+    /// binary frame carries one stun message or ChannelData message. The
+    /// messages are processed like the messages of a tcp connection, and the
+    /// responses and the forwarded messages are written back as binary frames.
+    pub async fn start<T>(
+        ServerStartOptions {
+            bind,
+            external,
+            service,
+            router,
+            statistics,
+            socket_options,
+            disconnect_grace,
+            delays,
+            backlog,
+            message_size,
+            ..
+        }: ServerStartOptions<T>,
+    ) -> anyhow::Result<()>
+    where
+        T: Clone + Observer + 'static,
+    {
+        let listener = socket_options.bind_tcp(bind, backlog)?;
+        let local_addr = listener.local_addr()?;
+
+        log::info!(
+            "turn server listening: bind={}, external={}, transport=WS",
+            local_addr,
+            external,
+        );
+
+        tokio::spawn(async move {
+            while let Ok((socket, address)) = listener.accept().await {
+                let router = router.clone();
+                let delays = delays.clone();
+                let reporter = statistics.get_reporter(Transport::TCP);
+                let mut operationer = service.get_operationer(address, external);
+                operationer.set_transport(Transport::TCP);
+
+                // The connection over the cap answers its first request with 508
+                // (Insufficient Capacity) and is closed, see the tcp listener.
+                let receiver = router.try_get_receiver(address);
+                let saturated = receiver.is_none();
+                if saturated {
+                    log::warn!(
+                        "websocket refused, insufficient capacity: addr={:?}, interface={:?}",
+                        address,
+                        local_addr
+                    );
+
+                    operationer.set_saturated(true);
+                }
+
+                let sessions = service.get_sessions();
+                let session_addr = SessionAddr {
+                    interface: external,
+                    address,
+                };
+
+                log::info!("websocket accept: addr={:?}, interface={:?}", address, local_addr);
+
+                if let Err(e) = socket.set_nodelay(true) {
+                    log::error!("websocket set nodelay failed!: addr={}, err={}", address, e);
+                }
+
+                let (mut reader, writer) = socket.into_split();
+                let writer = Arc::new(Mutex::new(writer));
+
+                tokio::spawn(async move {
+                    let mut buffer = Vec::with_capacity(4096);
+                    let deadline = saturated.then(|| Instant::now() + SATURATED_TIMEOUT);
+
+                    // The opening handshake, a request that is not a websocket upgrade
+                    // is answered with a bad request.
+                    let upgraded = loop {
+                        match read_until(deadline, reader.read_buf(&mut buffer)).await {
+                            Ok(0) | Err(_) => break false,
+                            Ok(_) => (),
+                        }
+
+                        match handshake(&buffer) {
+                            Ok(None) => continue,
+                            Ok(Some((response, size))) => {
+                                buffer.drain(..size);
+                                break writer.lock().await.write_all(response.as_bytes()).await.is_ok();
+                            }
+                            Err(e) => {
+                                log::warn!("websocket handshake failed: addr={:?}, err={}", address, e);
+
+                                let _ = writer
+                                    .lock()
+                                    .await
+                                    .write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n")
+                                    .await;
+
+                                break false;
+                            }
+                        }
+                    };
+
+                    if upgraded {
+                        // Use a separate task to handle messages forwarded to this socket.
+                        let writer_ = writer.clone();
+                        let reporter_ = reporter.clone();
+                        let router_ = router.clone();
+                        let sessions_ = sessions.clone();
+                        if let Some(mut receiver) = receiver {
+                            tokio::spawn(async move {
+                                while let Some((bytes, _, _)) = receiver.recv().await {
+                                    let frame = encode(Opcode::Binary, &bytes);
+                                    if writer_.lock().await.write_all(&frame).await.is_err() {
+                                        break;
+                                    }
+
+                                    reporter_.send(
+                                        &session_addr,
+                                        &[Stats::SendBytes(bytes.len() as u32), Stats::SendPkts(1)],
+                                    );
+                                }
+
+                                if router_.take_evicted(&address) {
+                                    log::warn!("websocket evicted: addr={:?}, interface={:?}", address, local_addr);
+                                    sessions_.evict(&session_addr);
+                                    let _ = writer_.lock().await.write_all(&encode(Opcode::Close, &[])).await;
+                                }
+
+                                let _ = writer_.lock().await.shutdown().await;
+                            });
+                        }
+
+                        // The payload of the fragmented message.
+                        let mut message = Vec::new();
+
+                        'a: loop {
+                            // Decode all the complete frames in the buffer before reading
+                            // more, the handshake may be followed by the first frames.
+                            loop {
+                                let (frame, size) = match decode(&buffer, message_size) {
+                                    Ok(Some(it)) => it,
+                                    Ok(None) => break,
+                                    Err(e) => {
+                                        log::warn!("websocket read failed: addr={:?}, err={}", address, e);
+
+                                        break 'a;
+                                    }
+                                };
+
+                                buffer.drain(..size);
+                                match frame.opcode {
+                                    Opcode::Binary | Opcode::Continuation => message.extend(frame.payload),
+                                    Opcode::Ping => {
+                                        let pong = encode(Opcode::Pong, &frame.payload);
+                                        if writer.lock().await.write_all(&pong).await.is_err() {
+                                            break 'a;
+                                        }
+
+                                        continue;
+                                    }
+                                    Opcode::Pong => continue,
+                                    Opcode::Close | Opcode::Text => {
+                                        let _ = writer.lock().await.write_all(&encode(Opcode::Close, &[])).await;
+
+                                        break 'a;
+                                    }
+                                }
+
+                                if message.len() > message_size {
+                                    break 'a;
+                                }
+
+                                if !frame.fin {
+                                    continue;
+                                }
+
+                                if !saturated {
+                                    router.touch(&address);
+                                }
+
+                                reporter.send(
+                                    &session_addr,
+                                    &[Stats::ReceivedBytes(message.len() as u32), Stats::ReceivedPkts(1)],
+                                );
+
+                                // The stun message requires at least 4 bytes.
+                                if message.len() >= 4 {
+                                    if let Ok(Some(res)) = operationer.route(&message, address).await {
+                                        #[cfg(feature = "prometheus")]
+                                        crate::statistics::prometheus::METRICS.response(res.method, res.bytes.len());
+
+                                        let target = res.relay.as_ref().unwrap_or(&address);
+                                        if let Some(ref endpoint) = res.endpoint {
+                                            forward(&router, endpoint, res.method, target, res.bytes);
+                                        } else if router.admit(res.method, target, res.bytes.len()) {
+                                            let bytes = encode(Opcode::Binary, res.bytes);
+                                            let mut stats =
+                                                vec![Stats::SendBytes(res.bytes.len() as u32), Stats::SendPkts(1)];
+
+                                            if res.method.is_error() {
+                                                stats.push(Stats::ErrorPkts(1));
+                                            }
+
+                                            if let Some(delay) = res.delay {
+                                                // The delayed response is written by a separate task, so
+                                                // that the connection keeps being read.
+                                                let reporter = reporter.clone();
+                                                let writer = writer.clone();
+                                                let spawned = delays.spawn(delay, async move {
+                                                    if writer.lock().await.write_all(&bytes).await.is_ok() {
+                                                        reporter.send(&session_addr, &stats);
+                                                    }
+                                                });
+
+                                                if !spawned {
+                                                    log::debug!(
+                                                        "delayed response dropped: addr={}",
+                                                        session_addr.address
+                                                    );
+                                                }
+                                            } else if writer.lock().await.write_all(&bytes).await.is_ok() {
+                                                reporter.send(&session_addr, &stats);
+                                            } else {
+                                                break 'a;
+                                            }
+
+                                            // The connection without a route is closed after its 508.
+                                            if saturated {
+                                                let _ = writer.lock().await.write_all(&encode(Opcode::Close, &[])).await;
+
+                                                break 'a;
+                                            }
+                                        }
+                                    }
+                                }
+
+                                message.clear();
+                            }
+
+                            // When the received message is 0, it means that the socket
+                            // has been closed.
+                            match read_until(deadline, reader.read_buf(&mut buffer)).await {
+                                Ok(0) | Err(_) => break,
+                                Ok(_) => (),
+                            }
+                        }
+                    }
+
+                    // The connection without a route has no session and no route, the
+                    // address may be the one of a live connection.
+                    if saturated {
+                        let _ = writer.lock().await.shutdown().await;
+                        log::info!("websocket closed: addr={:?}, interface={:?}", address, local_addr);
+
+                        return;
+                    }
+
+                    close_session(&sessions, &session_addr, disconnect_grace);
+                    router.remove(&address);
+
+                    log::info!("websocket disconnect: addr={:?}, interface={:?}", address, local_addr);
+                });
+            }
+
+            log::error!("websocket server close: interface={:?}", local_addr);
+        });
+
+        Ok(())
+    }
+}
+
 /// start turn server.
 ///
 /// create a specified number of threads,
-/// each thread processes udp data separately.
-pub async fn start<T>(config: &Config, statistics: &Statistics, service: &Service<T>) -> anyhow::Result<()>
+/// each thread processes udp data separately. The router shared by the
+/// listeners is returned, so that its counters can be read.
+/// Create the router shared by the listeners.
+///
+/// The router is created ahead of the listeners, so that the observer can
+/// release the pinned routes when the allocations close, see
+/// [`Router::unpin`].
+pub fn create_router(config: &Config) -> Router {
+    let mut router = Router::new(
+        config.turn.max_connections,
+        Duration::from_secs(config.turn.connection_idle),
+        config.turn.router_queue,
+    );
+
+    if let Some(rate) = config.turn.max_relay_bandwidth {
+        router = router.with_bandwidth(BandwidthLimit::new(rate));
+    }
+
+    if let Some(rate) = config.turn.max_relay_packet_rate {
+        router = router.with_packet_rate(PacketLimit::new(rate));
+    }
+
+    router
+}
+
+/// Start the listeners of the interfaces.
+///
+/// The configuration is validated first, see [`Config::validate`], so that
+/// an invalid option is an error rather than a panic of the listeners.
+///
+/// # Example
+///
+/// ```
+/// use turn::{Observer, Service, ServiceOptions};
+/// use turn_server::{config::*, server, statistics::Statistics};
+///
+/// #[derive(Clone)]
+/// struct ObserverTest;
+///
+/// impl Observer for ObserverTest {}
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let mut config = Config {
+///         turn: Turn::default(),
+///         api: Api::default(),
+///         log: Log::default(),
+///         auth: Auth::default(),
+///     };
+///
+///     config.turn.control_plane_threads = Some(0);
+///
+///     let service = Service::new(
+///         "localhost".to_string(),
+///         Vec::new(),
+///         ServiceOptions::default(),
+///         ObserverTest,
+///     );
+///
+///     let router = server::create_router(&config);
+///     let ret = server::start(&config, &Statistics::default(), &service, &router).await;
+///     assert_eq!(ret.unwrap_err().to_string(), "invalid control plane threads: 0");
+/// }
+/// ```
+pub async fn start<T>(
+    config: &Config,
+    statistics: &Statistics,
+    service: &Service<T>,
+    router: &Router,
+) -> anyhow::Result<()>
 where
     T: Clone + Observer + 'static,
 {
     #[allow(unused)]
     use crate::config::Transport;
 
-    let router = Router::default();
+    config.validate()?;
+
+    let socket_options = SocketOptions {
+        recv: config.turn.recv_buffer_size,
+        send: config.turn.send_buffer_size,
+        freebind: config.turn.freebind,
+        dscp: config.turn.dscp,
+    };
+
+    let control = match config.turn.control_plane_threads {
+        Some(threads) => Some(ControlPlane::new(threads)?),
+        None => None,
+    };
+
+    // The receive buffers hold the largest ChannelData message, which is the
+    // relayed payload, the channel header and the tcp padding, but never less than the 2048
+    // bytes of the standard messages.
+    let message_size = match config.turn.max_relayed_payload {
+        Some(size) => (size + 8).max(2048),
+        None => 2048,
+    };
+
+    // The delayed responses of all the listeners share one bound.
+    let delays = DelayLimit::new(DelayLimit::DEFAULT);
+
+    let options = |bind, external, proxy_protocol| ServerStartOptions {
+        statistics: statistics.clone(),
+        service: service.clone(),
+        router: router.clone(),
+        retry: SendRetry::new(config.turn.send_retries),
+        retry_queue: config.turn.send_retry_queue,
+        control: control.clone(),
+        pin_relay: config.turn.pin_relay,
+        disconnect_grace: config.turn.tcp_disconnect_grace,
+        delays: delays.clone(),
+        coalesce_delay: config.turn.tcp_coalesce_delay.map(Duration::from_millis),
+        accept_rate: config.turn.tcp_accept_rate,
+        backlog: config.turn.tcp_backlog,
+        proxy_protocol,
+        message_size,
+        socket_options,
+        external,
+        bind,
+    };
+
     for Interface {
         transport,
         external,
         bind,
+        listener,
+        ..
     } in config.turn.interfaces.iter().cloned()
     {
+        // The interfaces of the disabled family are not bound, so that a
+        // single-stack host starts without the other family.
+        if !FamilyMode::from(config.turn.relay_family).is_enabled(&bind.ip()) {
+            log::warn!(
+                "skip the interface of the disabled family: transport={:?}, bind={}",
+                transport,
+                bind
+            );
+
+            continue;
+        }
+
         #[allow(unused)]
-        let options = ServerStartOptions {
-            statistics: statistics.clone(),
-            service: service.clone(),
-            router: router.clone(),
-            external,
-            bind,
-        };
+        let options = options(bind, external, listener.proxy_protocol);
 
         match transport {
             #[cfg(feature = "udp")]
@@ -498,5 +2368,15 @@ where
         };
     }
 
+    #[cfg(all(unix, feature = "uds"))]
+    for interface in config.turn.unix_interfaces.iter().cloned() {
+        unix::start(interface, service.clone(), router.clone(), statistics.clone(), delays.clone()).await?;
+    }
+
+    #[cfg(feature = "ws")]
+    for interface in config.turn.websocket_interfaces.iter() {
+        ws::start(options(interface.bind, interface.external, false)).await?;
+    }
+
     Ok(())
 }
@@ -12,13 +12,19 @@ use turn::SessionAddr;
 ///
 /// Integrated Prometheus Metrics Exporter
 pub mod prometheus {
+    use std::fmt::Write;
+
     use anyhow::Result;
     use once_cell::sync::Lazy;
-    use prometheus::{register_int_counter, register_int_gauge, Encoder, IntCounter, IntGauge, TextEncoder};
+    use prometheus::{
+        proto::MetricType, register_int_counter, register_int_counter_vec, register_int_gauge,
+        register_int_gauge_vec, Encoder, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+    };
 
     use super::{Counts, Number, Stats};
 
-    use stun::Transport;
+    use stun::{Kind, Method, Transport};
+    use turn::ResponseMethod;
 
     // The `register_int_counter` macro would be too long if written out in full,
     // with too many line breaks after formatting, and this is wrapped directly into
@@ -32,7 +38,7 @@ pub mod prometheus {
         };
     }
 
-    pub static METRICS: Lazy<Metrics> = Lazy::new(|| Metrics::default());
+    pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::default);
 
     /// # Example
     ///
@@ -73,6 +79,21 @@ pub mod prometheus {
     /// Summarized metrics data for Global/TCP/UDP.
     pub struct Metrics {
         pub allocated: IntGauge,
+        /// The allocations of each tenant, only the tagged allocations are
+        /// counted, see [`crate::config::Auth::tenants`].
+        pub tenant_allocated: IntGaugeVec,
+        pub relay_paths: IntGauge,
+        pub unknown_methods: IntCounter,
+        pub bandwidth_dropped: IntCounter,
+        pub queue_dropped: IntCounter,
+        pub retry_dropped: IntCounter,
+        pub packet_rate_dropped: IntCounter,
+        pub permission_dropped: IntCounter,
+        pub relayed_bytes: IntCounter,
+        /// The responses of the requests by the method and the result, the
+        /// labels only take the values of the known methods, so the number of
+        /// the series is fixed.
+        pub responses: IntCounterVec,
         pub total: Counts<IntCounter>,
         pub tcp: Counts<IntCounter>,
         pub udp: Counts<IntCounter>,
@@ -91,9 +112,95 @@ pub mod prometheus {
                 tcp: Counts::new("tcp")?,
                 udp: Counts::new("udp")?,
                 allocated: register_int_gauge!("allocated", "The number of allocated ports, count = 16383")?,
+                tenant_allocated: register_int_gauge_vec!(
+                    "tenant_allocated",
+                    "The number of the allocations of each tenant",
+                    &["tenant"]
+                )?,
+                relay_paths: register_int_gauge!(
+                    "relay_paths",
+                    "The number of the permissions and the channels of all allocations"
+                )?,
+                unknown_methods: register_int_counter!(
+                    "unknown_methods",
+                    "The number of the received messages of the unknown methods"
+                )?,
+                bandwidth_dropped: register_int_counter!(
+                    "bandwidth_dropped",
+                    "The number of the relayed packets dropped over the bandwidth limit"
+                )?,
+                queue_dropped: register_int_counter!(
+                    "queue_dropped",
+                    "The number of the packets dropped because the queue of the route was full"
+                )?,
+                retry_dropped: register_int_counter!(
+                    "retry_dropped",
+                    "The number of the udp sends dropped because the retry queue of the socket was full"
+                )?,
+                packet_rate_dropped: register_int_counter!(
+                    "packet_rate_dropped",
+                    "The number of the relayed packets dropped over the packet rate limit"
+                )?,
+                permission_dropped: register_int_counter!(
+                    "permission_dropped",
+                    "The number of the relayed packets dropped without a permission or channel for the peer"
+                )?,
+                relayed_bytes: register_int_counter!(
+                    "relayed_bytes",
+                    "The number of the relayed bytes of the channel data and the data indications"
+                )?,
+                responses: register_int_counter_vec!(
+                    "responses",
+                    "The number of the responses of the requests",
+                    &["method", "result"]
+                )?,
             })
         }
 
+        /// Count the response of the route, the relayed data is counted in
+        /// bytes and the responses of the requests by their method.
+        ///
+        /// # Example
+        ///
+        /// ```
+        /// use stun::{Kind, Method};
+        /// use turn::ResponseMethod;
+        /// use turn_server::statistics::prometheus::*;
+        ///
+        /// METRICS.response(ResponseMethod::Stun(Method::Allocate(Kind::Error)), 100);
+        /// METRICS.response(ResponseMethod::ChannelData, 100);
+        ///
+        /// assert_eq!(METRICS.responses.with_label_values(&["allocate", "error"]).get(), 1);
+        /// assert_eq!(METRICS.responses.with_label_values(&["allocate", "success"]).get(), 0);
+        /// assert_eq!(METRICS.relayed_bytes.get(), 100);
+        /// ```
+        pub fn response(&self, method: ResponseMethod, size: usize) {
+            if method.is_relayed() {
+                self.relayed_bytes.inc_by(size as u64);
+                return;
+            }
+
+            let name = match method {
+                ResponseMethod::Stun(Method::Binding(_)) => "binding",
+                ResponseMethod::Stun(Method::Allocate(_)) => "allocate",
+                ResponseMethod::Stun(Method::CreatePermission(_)) => "create_permission",
+                ResponseMethod::Stun(Method::ChannelBind(_)) => "channel_bind",
+                ResponseMethod::Stun(Method::Refresh(_)) => "refresh",
+                _ => "unknown",
+            };
+
+            let result = match method {
+                ResponseMethod::Stun(Method::Binding(Kind::Response))
+                | ResponseMethod::Stun(Method::Allocate(Kind::Response))
+                | ResponseMethod::Stun(Method::CreatePermission(Kind::Response))
+                | ResponseMethod::Stun(Method::ChannelBind(Kind::Response))
+                | ResponseMethod::Stun(Method::Refresh(Kind::Response)) => "success",
+                _ => "error",
+            };
+
+            self.responses.with_label_values(&[name, result]).inc();
+        }
+
         /// # Example
         ///
         /// ```
@@ -122,6 +229,77 @@ pub mod prometheus {
         TextEncoder::new().encode(&prometheus::gather(), buf)?;
         Ok(())
     }
+
+    /// Render the metrics in the OpenMetrics text format.
+    ///
+    /// [OpenMetrics](https://github.com/OpenObservability/OpenMetrics/blob/main/specification/OpenMetrics.md)
+    ///
+    /// The text is rendered directly from the registered metrics, so it can be
+    /// served from any http stack. The samples of the counters have the
+    /// `_total` suffix and the text is terminated by `# EOF`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use turn_server::statistics::prometheus::*;
+    ///
+    /// METRICS.unknown_methods.inc();
+    ///
+    /// let text = render_openmetrics();
+    /// assert!(text.contains("# TYPE unknown_methods counter\n"));
+    /// assert!(text.contains("\nunknown_methods_total 1\n"));
+    /// assert!(text.contains("# TYPE allocated gauge\n"));
+    /// assert!(text.ends_with("# EOF\n"));
+    /// ```
+    pub fn render_openmetrics() -> String {
+        let mut output = String::with_capacity(4096);
+
+        for family in prometheus::gather() {
+            let (kind, suffix) = match family.get_field_type() {
+                MetricType::COUNTER => ("counter", "_total"),
+                MetricType::GAUGE => ("gauge", ""),
+                // Only the counters and the gauges are registered.
+                _ => continue,
+            };
+
+            let name = family.get_name().trim_end_matches("_total");
+            writeln!(output, "# TYPE {} {}", name, kind).ok();
+            writeln!(output, "# HELP {} {}", name, escape(family.get_help())).ok();
+
+            for metric in family.get_metric() {
+                output.push_str(name);
+                output.push_str(suffix);
+
+                let labels = metric.get_label();
+                if !labels.is_empty() {
+                    output.push('{');
+                    for (i, label) in labels.iter().enumerate() {
+                        if i > 0 {
+                            output.push(',');
+                        }
+
+                        write!(output, "{}=\"{}\"", label.get_name(), escape(label.get_value())).ok();
+                    }
+
+                    output.push('}');
+                }
+
+                let value = match family.get_field_type() {
+                    MetricType::COUNTER => metric.get_counter().get_value(),
+                    _ => metric.get_gauge().get_value(),
+                };
+
+                writeln!(output, " {}", value).ok();
+            }
+        }
+
+        output.push_str("# EOF\n");
+        output
+    }
+
+    fn escape(value: &str) -> String {
+        value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+    }
 }
 
 /// The type of information passed in the statisticsing channel
@@ -152,6 +330,27 @@ impl Number for Count {
     }
 }
 
+impl Count {
+    /// Read and reset the count in one atomic operation.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use turn_server::statistics::*;
+    ///
+    /// let count = Count::default();
+    /// count.add(3);
+    /// assert_eq!(count.take(), 3);
+    /// assert_eq!(count.take(), 0);
+    ///
+    /// count.add(1);
+    /// assert_eq!(count.get(), 1);
+    /// ```
+    pub fn take(&self) -> u64 {
+        self.0.swap(0, Ordering::Relaxed)
+    }
+}
+
 /// Worker independent statisticsing statistics
 pub struct Counts<T> {
     pub received_bytes: T,
@@ -198,9 +397,15 @@ impl<T: Number> Counts<T> {
     }
 }
 
+/// The statistics of a session and its tenant tag.
+struct Record {
+    tenant: Option<String>,
+    counts: Counts<Count>,
+}
+
 /// worker cluster statistics
 #[derive(Clone)]
-pub struct Statistics(Arc<RwLock<AHashMap<SessionAddr, Counts<Count>>>>);
+pub struct Statistics(Arc<RwLock<AHashMap<SessionAddr, Record>>>);
 
 impl Default for Statistics {
     #[cfg(feature = "api")]
@@ -248,6 +453,8 @@ impl Statistics {
 
     /// Add an address to the watch list
     ///
+    /// The address is tagged with the tenant of its session, if any.
+    ///
     /// # Example
     ///
     /// ```
@@ -262,25 +469,31 @@ impl Statistics {
     ///     interface: "127.0.0.1:3478".parse().unwrap(),
     /// };
     ///
-    /// statistics.register(addr.clone());
+    /// statistics.register(addr.clone(), None);
     /// assert_eq!(statistics.get(&addr).is_some(), true);
     /// ```
-    pub fn register(&self, addr: SessionAddr) {
+    pub fn register(&self, addr: SessionAddr, tenant: Option<String>) {
         #[cfg(feature = "prometheus")]
         {
             self::prometheus::METRICS.allocated.inc();
+
+            if let Some(it) = &tenant {
+                self::prometheus::METRICS.tenant_allocated.with_label_values(&[it]).inc();
+            }
         }
 
-        self.0.write().insert(
-            addr,
-            Counts {
+        let record = Record {
+            counts: Counts {
                 received_bytes: Count::default(),
                 send_bytes: Count::default(),
                 received_pkts: Count::default(),
                 send_pkts: Count::default(),
                 error_pkts: Count::default(),
             },
-        );
+            tenant,
+        };
+
+        self.0.write().insert(addr, record);
     }
 
     /// Remove an address from the watch list
@@ -299,7 +512,7 @@ impl Statistics {
     ///     interface: "127.0.0.1:3478".parse().unwrap(),
     /// };
     ///
-    /// statistics.register(addr.clone());
+    /// statistics.register(addr.clone(), None);
     /// assert_eq!(statistics.get(&addr).is_some(), true);
     ///
     /// statistics.unregister(&addr);
@@ -311,7 +524,61 @@ impl Statistics {
             self::prometheus::METRICS.allocated.dec();
         }
 
-        self.0.write().remove(addr);
+        #[allow(unused_variables)]
+        if let Some(record) = self.0.write().remove(addr) {
+            #[cfg(feature = "prometheus")]
+            {
+                if let Some(it) = &record.tenant {
+                    self::prometheus::METRICS.tenant_allocated.with_label_values(&[it]).dec();
+                }
+            }
+        }
+    }
+
+    /// Move the statistics of an address to a new address
+    ///
+    /// The counters are kept, so the statistics of a migrated session add
+    /// up across the migration. The statistics are moved with the session by
+    /// the moved event of the observer, see [`turn::Sessions::rebind`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::net::SocketAddr;
+    /// use turn::*;
+    /// use turn_server::statistics::*;
+    ///
+    /// let statistics = Statistics::default();
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let new = SessionAddr {
+    ///     address: "127.0.0.1:9090".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// statistics.register(addr, None);
+    /// assert!(statistics.rebind(&addr, new));
+    /// assert!(!statistics.rebind(&addr, new));
+    ///
+    /// assert!(statistics.get(&addr).is_none());
+    /// assert!(statistics.get(&new).is_some());
+    /// ```
+    pub fn rebind(&self, addr: &SessionAddr, new: SessionAddr) -> bool {
+        let mut map = self.0.write();
+        if map.contains_key(&new) {
+            return false;
+        }
+
+        if let Some(record) = map.remove(addr) {
+            map.insert(new, record);
+            true
+        } else {
+            false
+        }
     }
 
     /// Obtain a list of statistics from statisticsing
@@ -332,11 +599,11 @@ impl Statistics {
     ///     interface: "127.0.0.1:3478".parse().unwrap(),
     /// };
     ///
-    /// statistics.register(addr.clone());
+    /// statistics.register(addr.clone(), None);
     /// assert_eq!(statistics.get(&addr).is_some(), true);
     /// ```
     pub fn get(&self, addr: &SessionAddr) -> Option<Counts<u64>> {
-        self.0.read().get(addr).map(|counts| Counts {
+        self.0.read().get(addr).map(|Record { counts, .. }| Counts {
             received_bytes: counts.received_bytes.get(),
             received_pkts: counts.received_pkts.get(),
             send_bytes: counts.send_bytes.get(),
@@ -344,6 +611,66 @@ impl Statistics {
             error_pkts: counts.error_pkts.get(),
         })
     }
+
+    /// Get the tenant tag of an address, `None` if the address is not
+    /// registered or its session has no tenant.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use turn::*;
+    /// use turn_server::statistics::*;
+    ///
+    /// let statistics = Statistics::default();
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// statistics.register(addr, Some("acme".to_string()));
+    /// assert_eq!(statistics.get_tenant(&addr).as_deref(), Some("acme"));
+    ///
+    /// statistics.unregister(&addr);
+    /// assert_eq!(statistics.get_tenant(&addr), None);
+    /// ```
+    pub fn get_tenant(&self, addr: &SessionAddr) -> Option<String> {
+        self.0.read().get(addr).and_then(|it| it.tenant.clone())
+    }
+
+    /// Read and reset the statistics of an address.
+    ///
+    /// Each counter is read and zeroed in one atomic operation, so for the
+    /// delta-based metering the counts reported concurrently are either in
+    /// this snapshot or in the next one, and never lost or counted twice.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use turn::*;
+    /// use turn_server::statistics::*;
+    ///
+    /// let statistics = Statistics::default();
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// assert!(statistics.take(&addr).is_none());
+    ///
+    /// statistics.register(addr, None);
+    /// assert_eq!(statistics.take(&addr).unwrap().send_bytes, 0);
+    /// ```
+    pub fn take(&self, addr: &SessionAddr) -> Option<Counts<u64>> {
+        self.0.read().get(addr).map(|Record { counts, .. }| Counts {
+            received_bytes: counts.received_bytes.take(),
+            received_pkts: counts.received_pkts.take(),
+            send_bytes: counts.send_bytes.take(),
+            send_pkts: counts.send_pkts.take(),
+            error_pkts: counts.error_pkts.take(),
+        })
+    }
 }
 
 /// statistics reporter
@@ -354,7 +681,7 @@ impl Statistics {
 #[derive(Clone)]
 #[allow(unused)]
 pub struct StatisticsReporter {
-    map: Arc<RwLock<AHashMap<SessionAddr, Counts<Count>>>>,
+    map: Arc<RwLock<AHashMap<SessionAddr, Record>>>,
     transport: Transport,
 }
 
@@ -370,9 +697,9 @@ impl StatisticsReporter {
                 }
             }
 
-            if let Some(counts) = self.map.read().get(addr) {
+            if let Some(record) = self.map.read().get(addr) {
                 for item in reports {
-                    counts.add(item);
+                    record.counts.add(item);
                 }
             }
         }
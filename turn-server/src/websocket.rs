@@ -0,0 +1,287 @@
+//! The WebSocket framing of the stun messages.
+//!
+//! [rfc6455](https://tools.ietf.org/html/rfc6455)
+//!
+//! For the restrictive networks that only pass http, the stun messages can be
+//! tunneled in the binary frames of a WebSocket connection, one message per
+//! frame. Only the server side of the protocol is implemented: the opening
+//! handshake, the masked frames of the client and the unmasked frames of the
+//! server. There are no extensions.
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use sha1::{Digest, Sha1};
+
+/// The GUID appended to the key of the client in the opening handshake.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The maximum size of the opening handshake request.
+pub const MAX_HANDSHAKE_SIZE: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl TryFrom<u8> for Opcode {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0x0 => Self::Continuation,
+            0x1 => Self::Text,
+            0x2 => Self::Binary,
+            0x8 => Self::Close,
+            0x9 => Self::Ping,
+            0xA => Self::Pong,
+            _ => return Err(Error::InvalidOpcode),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The request is not a WebSocket opening handshake.
+    InvalidHandshake,
+    /// The opening handshake request is larger than [`MAX_HANDSHAKE_SIZE`].
+    HandshakeTooLarge,
+    InvalidOpcode,
+    /// The frames of the client must be masked.
+    Unmasked,
+    /// The payload of the frame is larger than the limit.
+    FrameTooLarge,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Derive the Sec-WebSocket-Accept header from the Sec-WebSocket-Key header
+/// of the client.
+///
+/// # Example
+///
+/// ```
+/// use turn_server::websocket::accept_key;
+///
+/// assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+/// ```
+pub fn accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key.as_bytes());
+    hasher.update(GUID.as_bytes());
+    BASE64_STANDARD.encode(hasher.finalize())
+}
+
+/// Parse the opening handshake request at the start of the buffer, returns
+/// the response of the handshake and the size of the request, or `None` if
+/// the request is incomplete.
+///
+/// The request must carry the `Upgrade: websocket`, `Connection: Upgrade`,
+/// `Sec-WebSocket-Key` and `Sec-WebSocket-Version: 13` headers, see
+/// [rfc6455 section 4.2.1](https://tools.ietf.org/html/rfc6455#section-4.2.1).
+///
+/// # Example
+///
+/// ```
+/// use turn_server::websocket::*;
+///
+/// let request = b"GET /turn HTTP/1.1\r\n\
+///     Host: localhost\r\n\
+///     Upgrade: websocket\r\n\
+///     Connection: Upgrade\r\n\
+///     Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+///     Sec-WebSocket-Version: 13\r\n\r\n";
+///
+/// assert_eq!(handshake(&request[..20]), Ok(None));
+///
+/// let (response, size) = handshake(request).unwrap().unwrap();
+/// assert_eq!(size, request.len());
+/// assert!(response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+/// assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+///
+/// assert_eq!(
+///     handshake(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n"),
+///     Err(Error::InvalidHandshake)
+/// );
+///
+/// let request = b"GET /turn HTTP/1.1\r\n\
+///     Upgrade: websocket\r\n\
+///     Connection: keep-alive, upgrade\r\n\
+///     Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+///     Sec-WebSocket-Version: 13\r\n\r\n";
+///
+/// assert!(handshake(request).unwrap().is_some());
+///
+/// let request = b"GET /turn HTTP/1.1\r\n\
+///     Upgrade: websocket\r\n\
+///     Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+///     Sec-WebSocket-Version: 13\r\n\r\n";
+///
+/// assert_eq!(handshake(request), Err(Error::InvalidHandshake));
+///
+/// let request = b"GET /turn HTTP/1.1\r\n\
+///     Upgrade: websocket\r\n\
+///     Connection: Upgrade\r\n\
+///     Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+///     Sec-WebSocket-Version: 8\r\n\r\n";
+///
+/// assert_eq!(handshake(request), Err(Error::InvalidHandshake));
+/// ```
+pub fn handshake(bytes: &[u8]) -> Result<Option<(String, usize)>, Error> {
+    let size = match bytes.windows(4).position(|it| it == b"\r\n\r\n") {
+        Some(it) => it + 4,
+        None if bytes.len() >= MAX_HANDSHAKE_SIZE => return Err(Error::HandshakeTooLarge),
+        None => return Ok(None),
+    };
+
+    let request = std::str::from_utf8(&bytes[..size]).map_err(|_| Error::InvalidHandshake)?;
+    let mut lines = request.split("\r\n");
+    if !lines.next().map(|it| it.starts_with("GET ")).unwrap_or(false) {
+        return Err(Error::InvalidHandshake);
+    }
+
+    // The Connection header is a list of tokens, such as "keep-alive, Upgrade".
+    let (mut upgrade, mut connection, mut version, mut key) = (false, false, false, None);
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("upgrade") {
+                upgrade = value.eq_ignore_ascii_case("websocket");
+            } else if name.eq_ignore_ascii_case("connection") {
+                connection = value.split(',').any(|it| it.trim().eq_ignore_ascii_case("upgrade"));
+            } else if name.eq_ignore_ascii_case("sec-websocket-version") {
+                version = value == "13";
+            } else if name.eq_ignore_ascii_case("sec-websocket-key") {
+                key = Some(value);
+            }
+        }
+    }
+
+    match key {
+        Some(key) if upgrade && connection && version => Ok(Some((
+            format!(
+                "HTTP/1.1 101 Switching Protocols\r\n\
+                Upgrade: websocket\r\n\
+                Connection: Upgrade\r\n\
+                Sec-WebSocket-Accept: {}\r\n\r\n",
+                accept_key(key)
+            ),
+            size,
+        ))),
+        _ => Err(Error::InvalidHandshake),
+    }
+}
+
+/// A frame of the client.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub fin: bool,
+    pub opcode: Opcode,
+    /// The payload, which is already unmasked.
+    pub payload: Vec<u8>,
+}
+
+/// Decode the frame of the client at the start of the buffer, returns the
+/// frame and the size of the frame, or `None` if the frame is incomplete.
+///
+/// # Example
+///
+/// ```
+/// use turn_server::websocket::*;
+///
+/// // A masked binary frame of the client.
+/// let bytes = [0x82, 0x83, 0x01, 0x02, 0x03, 0x04, 0x01 ^ 1, 0x02 ^ 2, 0x03 ^ 3];
+/// assert_eq!(decode(&bytes[..4], 2048), Ok(None));
+///
+/// let (frame, size) = decode(&bytes, 2048).unwrap().unwrap();
+/// assert_eq!(size, bytes.len());
+/// assert_eq!(frame.opcode, Opcode::Binary);
+/// assert!(frame.fin);
+/// assert_eq!(frame.payload, vec![1, 2, 3]);
+///
+/// assert_eq!(decode(&bytes, 2), Err(Error::FrameTooLarge));
+/// assert_eq!(decode(&encode(Opcode::Binary, &[1, 2, 3]), 2048), Err(Error::Unmasked));
+/// ```
+pub fn decode(bytes: &[u8], limit: usize) -> Result<Option<(Frame, usize)>, Error> {
+    if bytes.len() < 2 {
+        return Ok(None);
+    }
+
+    let fin = bytes[0] & 0x80 != 0;
+    let opcode = Opcode::try_from(bytes[0] & 0x0F)?;
+    if bytes[1] & 0x80 == 0 {
+        return Err(Error::Unmasked);
+    }
+
+    let (len, offset) = match bytes[1] & 0x7F {
+        126 if bytes.len() >= 4 => (u16::from_be_bytes([bytes[2], bytes[3]]) as u64, 4),
+        127 if bytes.len() >= 10 => (u64::from_be_bytes(bytes[2..10].try_into().unwrap()), 10),
+        126 | 127 => return Ok(None),
+        len => (len as u64, 2),
+    };
+
+    if len > limit as u64 {
+        return Err(Error::FrameTooLarge);
+    }
+
+    let size = offset + 4 + len as usize;
+    if bytes.len() < size {
+        return Ok(None);
+    }
+
+    let mask = &bytes[offset..offset + 4];
+    let payload = bytes[offset + 4..size]
+        .iter()
+        .enumerate()
+        .map(|(i, it)| it ^ mask[i % 4])
+        .collect();
+
+    Ok(Some((Frame { fin, opcode, payload }, size)))
+}
+
+/// Encode a frame of the server, which is not masked and not fragmented.
+///
+/// # Example
+///
+/// ```
+/// use turn_server::websocket::*;
+///
+/// assert_eq!(encode(Opcode::Binary, &[1, 2, 3]), vec![0x82, 0x03, 1, 2, 3]);
+/// assert_eq!(&encode(Opcode::Binary, &[0; 200])[..4], &[0x82, 126, 0, 200]);
+/// assert_eq!(encode(Opcode::Pong, &[]), vec![0x8A, 0x00]);
+/// ```
+pub fn encode(opcode: Opcode, payload: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(payload.len() + 10);
+    bytes.push(
+        0x80 | match opcode {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xA,
+        },
+    );
+
+    if payload.len() < 126 {
+        bytes.push(payload.len() as u8);
+    } else if payload.len() <= u16::MAX as usize {
+        bytes.push(126);
+        bytes.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    } else {
+        bytes.push(127);
+        bytes.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+    }
+
+    bytes.extend_from_slice(payload);
+    bytes
+}
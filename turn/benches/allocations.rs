@@ -0,0 +1,122 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    future::Future,
+    net::SocketAddr,
+    pin::pin,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+use bytes::BytesMut;
+use mycrl_turn::{Observer, Service, ServiceOptions, SessionAddr};
+use stun::{
+    attribute::{Data, XorPeerAddress},
+    MessageWriter, Method,
+};
+
+/// Counts the heap allocations of the process.
+struct Counter;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counter {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counter = Counter;
+
+#[derive(Clone)]
+struct ObserverTest;
+
+impl Observer for ObserverTest {
+    async fn get_password(&self, _: &SessionAddr, _: &str) -> Option<String> {
+        Some("test".to_string())
+    }
+}
+
+/// Poll the future once, the relaying never waits, and unlike a blocking
+/// executor polling does not allocate.
+fn poll_once<F: Future>(future: F) -> F::Output {
+    match pin!(future).poll(&mut Context::from_waker(Waker::noop())) {
+        Poll::Ready(it) => it,
+        Poll::Pending => unreachable!(),
+    }
+}
+
+fn main() {
+    let interface = "127.0.0.1:3478".parse::<SocketAddr>().unwrap();
+    let client = "127.0.0.1:10000".parse::<SocketAddr>().unwrap();
+    let peer = "127.0.0.1:10001".parse::<SocketAddr>().unwrap();
+    let service = Service::new(
+        "localhost".to_string(),
+        vec![interface],
+        ServiceOptions::default(),
+        ObserverTest,
+    );
+
+    let addr = SessionAddr {
+        address: client,
+        interface,
+    };
+    let peer_addr = SessionAddr {
+        address: peer,
+        interface,
+    };
+    let sessions = service.get_sessions();
+    pollster::block_on(sessions.get_digest(&addr, "test", "test"));
+    pollster::block_on(sessions.get_digest(&peer_addr, "test", "test"));
+    let port = sessions.allocate(&addr).unwrap();
+    let peer_port = sessions.allocate(&peer_addr).unwrap();
+    assert!(sessions.bind_channel(&addr, &interface, peer_port, 0x4000));
+    assert!(sessions.bind_channel(&peer_addr, &interface, port, 0x4000));
+    assert!(sessions.create_permission(&addr, &interface, &[peer_port]));
+    assert!(sessions.create_permission(&peer_addr, &interface, &[port]));
+
+    let mut channel_data = vec![0x40, 0x00, 0x04, 0x00];
+    channel_data.resize(4 + 1024, 0);
+
+    let mut indication = BytesMut::with_capacity(1500);
+    {
+        let mut message = MessageWriter::new(Method::SendIndication, &[1u8; 12], &mut indication);
+        message.append::<XorPeerAddress>(SocketAddr::new(interface.ip(), peer_port));
+        message.append::<Data>(&[0u8; 1024]);
+        message.flush(None).unwrap();
+    }
+
+    // The operationer and its buffers are created once, like the one of a socket
+    // loop, only the relaying of the packets is counted.
+    let mut operationer = service.get_operationer(interface, interface);
+    for (name, bytes) in [
+        ("channel_data", &channel_data[..]),
+        ("send_indication", &indication[..]),
+    ] {
+        const PACKETS: usize = 100_000;
+
+        // Warm up the lazily allocated state.
+        poll_once(operationer.route(bytes, client))
+            .unwrap()
+            .unwrap();
+
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        for _ in 0..PACKETS {
+            poll_once(operationer.route(bytes, client))
+                .unwrap()
+                .unwrap();
+        }
+
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+        println!(
+            "relay/{}: {:.3} allocations per packet",
+            name,
+            allocations as f64 / PACKETS as f64
+        );
+    }
+}
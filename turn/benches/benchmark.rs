@@ -0,0 +1,157 @@
+use std::{
+    net::SocketAddr,
+    thread,
+    time::{Duration, Instant},
+};
+
+use bytes::BytesMut;
+use criterion::*;
+use mycrl_turn::{auth::VerifyCache, Observer, Service, ServiceOptions, SessionAddr};
+use stun::{
+    attribute::{Realm, UserName},
+    util::long_term_credential_digest,
+    Decoder, Kind, MessageWriter, Method, Payload,
+};
+
+#[derive(Clone)]
+struct ObserverTest;
+
+impl Observer for ObserverTest {
+    async fn get_password(&self, _: &SessionAddr, _: &str) -> Option<String> {
+        Some("test".to_string())
+    }
+}
+
+fn criterion_benchmark(c: &mut Criterion) {
+    let interface = "127.0.0.1:3478".parse::<SocketAddr>().unwrap();
+    let client = "127.0.0.1:10000".parse::<SocketAddr>().unwrap();
+
+    // An authenticated binding request, which is sent over and over again as
+    // a retransmit.
+    let mut bytes = BytesMut::with_capacity(1500);
+    {
+        let mut message =
+            MessageWriter::new(Method::Binding(Kind::Request), &[1u8; 12], &mut bytes);
+        message.append::<UserName>("test");
+        message.append::<Realm>("localhost");
+        message
+            .flush(Some(&long_term_credential_digest(
+                "test",
+                "test",
+                "localhost",
+            )))
+            .unwrap();
+    }
+
+    let mut retransmit = c.benchmark_group("retransmit");
+    for (name, ttl) in [
+        ("binding_auth", None),
+        ("binding_auth_verify_cache", Some(Duration::from_secs(5))),
+    ] {
+        let service = Service::new(
+            "localhost".to_string(),
+            vec![interface],
+            ServiceOptions {
+                binding_require_auth: true,
+                verify_cache_ttl: ttl,
+                ..Default::default()
+            },
+            ObserverTest,
+        );
+
+        let mut operationer = service.get_operationer(interface, interface);
+        retransmit.throughput(Throughput::Elements(1));
+        retransmit.bench_function(name, |b| {
+            b.iter(|| {
+                pollster::block_on(operationer.route(&bytes, client))
+                    .unwrap()
+                    .unwrap();
+            })
+        });
+    }
+
+    retransmit.finish();
+
+    // A new operationer for each connection, whose response buffer is either
+    // taken from the pool or allocated.
+    let mut connection = c.benchmark_group("connection");
+    for (name, buffer_pool) in [("operationer_allocated", 0), ("operationer_pooled", 1024)] {
+        let service = Service::new(
+            "localhost".to_string(),
+            vec![interface],
+            ServiceOptions {
+                buffer_pool,
+                ..Default::default()
+            },
+            ObserverTest,
+        );
+
+        connection.throughput(Throughput::Elements(1));
+        connection.bench_function(name, |b| {
+            b.iter(|| {
+                let mut operationer = service.get_operationer(interface, interface);
+                pollster::block_on(operationer.route(&bytes, client))
+                    .unwrap()
+                    .unwrap();
+            })
+        });
+    }
+
+    connection.finish();
+
+    // The threads validating the retransmits of their own clients at once,
+    // with more transactions than the cache holds, so every insert evicts.
+    let key = long_term_credential_digest("test", "test", "localhost");
+    let messages = (0..256u16)
+        .map(|it| {
+            let mut token = [0u8; 12];
+            token[..2].copy_from_slice(&it.to_be_bytes());
+
+            let mut bytes = BytesMut::with_capacity(1500);
+            let mut message =
+                MessageWriter::new(Method::Binding(Kind::Request), &token, &mut bytes);
+            message.append::<UserName>("test");
+            message.append::<Realm>("localhost");
+            message.flush(Some(&key)).unwrap();
+            bytes
+        })
+        .collect::<Vec<_>>();
+
+    let mut contended = c.benchmark_group("verify_cache");
+    for threads in [1, 4, 8] {
+        let cache = VerifyCache::new(Duration::from_secs(5), 512);
+
+        contended.throughput(Throughput::Elements(threads));
+        contended.bench_function(format!("contended_{}", threads), |b| {
+            b.iter_custom(|iters| {
+                let start = Instant::now();
+                thread::scope(|scope| {
+                    for port in 0..threads {
+                        let (cache, messages) = (&cache, &messages);
+                        scope.spawn(move || {
+                            let addr = SessionAddr {
+                                address: SocketAddr::new(client.ip(), 20000 + port as u16),
+                                interface,
+                            };
+
+                            let mut decoder = Decoder::default();
+                            for index in 0..iters as usize {
+                                let bytes = &messages[index % messages.len()];
+                                if let Payload::Message(reader) = decoder.decode(bytes).unwrap() {
+                                    black_box(cache.validate(&addr, &reader, &key));
+                                }
+                            }
+                        });
+                    }
+                });
+
+                start.elapsed()
+            })
+        });
+    }
+
+    contended.finish();
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,40 @@
+#![no_main]
+
+use std::{net::SocketAddr, sync::OnceLock};
+
+use libfuzzer_sys::fuzz_target;
+use turn::{Observer, Service, ServiceOptions, SessionAddr};
+
+#[derive(Clone)]
+struct ObserverTest;
+
+impl Observer for ObserverTest {
+    async fn get_password(&self, _: &SessionAddr, _: &str) -> Option<String> {
+        Some("test".to_string())
+    }
+}
+
+static SERVICE: OnceLock<Service<ObserverTest>> = OnceLock::new();
+
+fuzz_target!(|data: &[u8]| {
+    let interface = "127.0.0.1:3478".parse::<SocketAddr>().unwrap();
+    let service = SERVICE.get_or_init(|| {
+        Service::new(
+            "localhost".to_string(),
+            vec![interface],
+            ServiceOptions::default(),
+            ObserverTest,
+        )
+    });
+
+    // The first bytes select the client, so that the sessions created by the
+    // previous inputs are also exercised.
+    let (port, data) = match data {
+        [a, b, rest @ ..] => (u16::from_be_bytes([*a, *b]), rest),
+        _ => (0, data),
+    };
+
+    let mut operationer = service.get_operationer(interface, interface);
+    let client = SocketAddr::new(interface.ip(), port);
+    let _ = pollster::block_on(operationer.try_process(data, client));
+});
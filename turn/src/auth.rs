@@ -0,0 +1,505 @@
+//! Long-term credential mechanism building blocks.
+//!
+//! [rfc8489](https://tools.ietf.org/html/rfc8489)
+//!
+//! The processors share these functions to authenticate the requests, and
+//! custom processors can use them instead of duplicating the logic.
+
+use std::{
+    collections::VecDeque,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use ahash::AHashMap;
+use bytes::BytesMut;
+use parking_lot::{Mutex, RwLock};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use stun::{
+    attribute::{Error, ErrorCode, ErrorKind, Nonce, Realm},
+    util::{constant_time_eq, hmac_sha1, long_term_credential_digest},
+    Kind, MessageReader, MessageWriter, Method, StunError,
+};
+
+use crate::SessionAddr;
+
+/// Create a new nonce.
+///
+/// The nonce is a random string of length 16.
+///
+/// # Test
+///
+/// ```
+/// use mycrl_turn::auth::make_nonce;
+///
+/// let nonce = make_nonce();
+/// assert_eq!(nonce.len(), 16);
+/// assert!(nonce.chars().all(|it| it.is_ascii_alphanumeric()));
+/// assert_ne!(nonce, make_nonce());
+/// ```
+pub fn make_nonce() -> String {
+    let mut rng = thread_rng();
+    std::iter::repeat(())
+        .map(|_| rng.sample(Alphanumeric) as char)
+        .take(16)
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// Derive the long-term credential key.
+///
+/// key = MD5(username ":" realm ":" SASLprep(password))
+///
+/// # Test
+///
+/// ```
+/// use mycrl_turn::auth::derive_key;
+///
+/// let key = derive_key("user", "realm", "pass");
+/// assert_eq!(
+///     key,
+///     [
+///         0x84, 0x93, 0xfb, 0xc5, 0x3b, 0xa5, 0x82, 0xfb, 0x4c, 0x04, 0x4c, 0x45,
+///         0x6b, 0xdc, 0x40, 0xeb,
+///     ]
+/// );
+/// ```
+pub fn derive_key(username: &str, realm: &str, password: &str) -> [u8; 16] {
+    long_term_credential_digest(username, password, realm)
+}
+
+/// Check the message integrity of the message with the key.
+///
+/// # Test
+///
+/// ```
+/// use bytes::BytesMut;
+/// use mycrl_turn::auth::*;
+/// use stun::{attribute::UserName, Decoder, Kind, MessageWriter, Method, Payload};
+///
+/// let key = derive_key("user", "realm", "pass");
+/// let mut bytes = BytesMut::with_capacity(1500);
+/// let mut message = MessageWriter::new(Method::Allocate(Kind::Request), &[0u8; 12], &mut bytes);
+/// message.append::<UserName>("user");
+/// message.flush(Some(&key)).unwrap();
+///
+/// let mut decoder = Decoder::default();
+/// if let Payload::Message(reader) = decoder.decode(&bytes).unwrap() {
+///     assert!(validate_integrity(&reader, &key));
+///     assert!(!validate_integrity(&reader, &derive_key("user", "realm", "other")));
+/// } else {
+///     unreachable!()
+/// }
+/// ```
+pub fn validate_integrity(reader: &MessageReader<'_>, key: &[u8; 16]) -> bool {
+    reader.integrity(key).is_ok()
+}
+
+/// Write the 401 (Unauthorized) challenge response of the request.
+///
+/// The response contains the REALM and NONCE attributes, which the client
+/// uses to derive the key and retry the request. Returns the method of the
+/// response, indications cannot be challenged.
+///
+/// # Test
+///
+/// ```
+/// use bytes::BytesMut;
+/// use mycrl_turn::auth::*;
+/// use stun::{
+///     attribute::{ErrorCode, ErrorKind, Nonce, Realm},
+///     Decoder, Kind, MessageWriter, Method, Payload,
+/// };
+///
+/// let mut bytes = BytesMut::with_capacity(1500);
+/// let mut message = MessageWriter::new(Method::Allocate(Kind::Request), &[0u8; 12], &mut bytes);
+/// message.flush(None).unwrap();
+///
+/// let mut decoder = Decoder::default();
+/// let mut response = BytesMut::with_capacity(1500);
+/// if let Payload::Message(reader) = decoder.decode(&bytes).unwrap() {
+///     let nonce = make_nonce();
+///     let method = challenge_response(&reader, "realm", &nonce, &mut response).unwrap();
+///     assert_eq!(method, Method::Allocate(Kind::Error));
+///
+///     let mut decoder = Decoder::default();
+///     if let Payload::Message(reader) = decoder.decode(&response).unwrap() {
+///         assert_eq!(reader.method, Method::Allocate(Kind::Error));
+///         assert_eq!(reader.get::<ErrorCode>().unwrap().code, ErrorKind::Unauthorized as u16);
+///         assert_eq!(reader.get::<Realm>(), Some("realm"));
+///         assert_eq!(reader.get::<Nonce>(), Some(nonce.as_str()));
+///     } else {
+///         unreachable!()
+///     }
+/// } else {
+///     unreachable!()
+/// }
+/// ```
+pub fn challenge_response(
+    reader: &MessageReader<'_>,
+    realm: &str,
+    nonce: &str,
+    bytes: &mut BytesMut,
+) -> Result<Method, StunError> {
+    let method = match reader.method {
+        Method::Binding(_) => Method::Binding(Kind::Error),
+        Method::Allocate(_) => Method::Allocate(Kind::Error),
+        Method::CreatePermission(_) => Method::CreatePermission(Kind::Error),
+        Method::ChannelBind(_) => Method::ChannelBind(Kind::Error),
+        Method::Refresh(_) => Method::Refresh(Kind::Error),
+        _ => return Err(StunError::UnknownMethod),
+    };
+
+    let mut message = MessageWriter::extend(method, reader, bytes);
+    message.append::<ErrorCode>(Error::from(ErrorKind::Unauthorized));
+    message.append::<Nonce>(nonce);
+    message.append::<Realm>(realm);
+    message.flush(None)?;
+
+    Ok(method)
+}
+
+/// The realms accepted by the service.
+///
+/// For zero-downtime credential rotation, the realm can be rotated while the
+/// previous realm is still accepted during a grace window. Challenges are
+/// always issued with the current realm, and the previous realms are dropped
+/// once their grace window has elapsed.
+///
+/// # Test
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use mycrl_turn::auth::Realms;
+///
+/// let realms = Realms::new("old".to_string());
+/// assert_eq!(realms.current().as_str(), "old");
+///
+/// realms.rotate("new".to_string(), Duration::from_secs(60));
+/// assert_eq!(realms.current().as_str(), "new");
+/// assert!(realms.is_active("new"));
+/// assert!(realms.is_active("old"));
+///
+/// realms.rotate("newer".to_string(), Duration::ZERO);
+/// assert!(realms.is_active("newer"));
+/// assert!(!realms.is_active("new"));
+/// assert!(realms.is_active("old"));
+/// assert!(!realms.is_active("unknown"));
+/// ```
+pub struct Realms {
+    current: RwLock<Arc<String>>,
+    previous: RwLock<Vec<(Arc<String>, Instant)>>,
+}
+
+impl Realms {
+    pub fn new(realm: String) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(realm)),
+            previous: RwLock::new(Vec::with_capacity(2)),
+        }
+    }
+
+    /// Get the current realm, which is used to issue the challenges.
+    pub fn current(&self) -> Arc<String> {
+        self.current.read().clone()
+    }
+
+    /// Replace the current realm, the replaced realm is still accepted for
+    /// the grace window.
+    pub fn rotate(&self, realm: String, grace: Duration) {
+        let previous = std::mem::replace(&mut *self.current.write(), Arc::new(realm));
+
+        let mut realms = self.previous.write();
+        realms.retain(|(it, _)| it != &previous);
+        realms.push((previous, Instant::now() + grace));
+    }
+
+    /// Check if the realm is the current realm or a previous realm whose
+    /// grace window has not elapsed, the elapsed realms are dropped.
+    pub fn is_active(&self, realm: &str) -> bool {
+        if self.current.read().as_str() == realm {
+            return true;
+        }
+
+        let now = Instant::now();
+        {
+            let realms = self.previous.read();
+            if !realms.iter().any(|(_, expires)| *expires <= now) {
+                return realms.iter().any(|(it, _)| it.as_str() == realm);
+            }
+        }
+
+        let mut realms = self.previous.write();
+        realms.retain(|(_, expires)| *expires > now);
+        realms.iter().any(|(it, _)| it.as_str() == realm)
+    }
+}
+
+/// A short-lived cache of the successful message integrity checks.
+///
+/// The retransmits of a request are byte-identical, so within the
+/// retransmit window the same HMAC would be computed over and over. The
+/// cache is keyed by the source address and the transaction id, and an
+/// entry only matches when the message bytes, which include the
+/// MESSAGE-INTEGRITY attribute, and the key are identical too, so the result
+/// is always the one the HMAC would have given. The failed checks are never
+/// cached.
+///
+/// The entries are evicted in the order they were inserted, which is also
+/// the order they expire in, so the expired entries are dropped from the
+/// front and a full cache evicts its oldest entry without scanning.
+///
+/// # Test
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use bytes::BytesMut;
+/// use mycrl_turn::{auth::*, SessionAddr};
+/// use stun::{attribute::UserName, Decoder, Kind, MessageWriter, Method, Payload};
+///
+/// let addr = SessionAddr {
+///     address: "127.0.0.1:8080".parse().unwrap(),
+///     interface: "127.0.0.1:3478".parse().unwrap(),
+/// };
+///
+/// let key = derive_key("user", "realm", "pass");
+/// let mut bytes = BytesMut::with_capacity(1500);
+/// let mut message = MessageWriter::new(Method::Allocate(Kind::Request), &[0u8; 12], &mut bytes);
+/// message.append::<UserName>("user");
+/// message.flush(Some(&key)).unwrap();
+///
+/// let cache = VerifyCache::new(Duration::from_secs(5), 16);
+/// let mut decoder = Decoder::default();
+/// if let Payload::Message(reader) = decoder.decode(&bytes).unwrap() {
+///     assert!(!cache.validate(&addr, &reader, &derive_key("user", "realm", "other")));
+///     assert_eq!(cache.len(), 0);
+///
+///     assert!(cache.validate(&addr, &reader, &key));
+///     assert!(cache.validate(&addr, &reader, &key));
+///     assert_eq!(cache.len(), 1);
+///
+///     // The key is part of the entry.
+///     assert!(!cache.validate(&addr, &reader, &derive_key("user", "realm", "other")));
+/// } else {
+///     unreachable!()
+/// }
+///
+/// // A full cache evicts its oldest entry to keep the new one.
+/// let cache = VerifyCache::new(Duration::from_secs(5), 2);
+/// for token in 0..4u8 {
+///     let token = [token; 12];
+///     let mut bytes = BytesMut::with_capacity(1500);
+///     let mut message = MessageWriter::new(Method::Allocate(Kind::Request), &token, &mut bytes);
+///     message.append::<UserName>("user");
+///     message.flush(Some(&key)).unwrap();
+///
+///     let mut decoder = Decoder::default();
+///     if let Payload::Message(reader) = decoder.decode(&bytes).unwrap() {
+///         assert!(cache.validate(&addr, &reader, &key));
+///     }
+/// }
+///
+/// assert_eq!(cache.len(), 2);
+/// ```
+pub struct VerifyCache {
+    ttl: Duration,
+    capacity: usize,
+    entries: Mutex<VerifyEntries>,
+}
+
+type VerifyKey = (SessionAddr, [u8; 12]);
+
+/// The entries of the verify cache and their insertion order.
+#[derive(Default)]
+struct VerifyEntries {
+    table: AHashMap<VerifyKey, (Vec<u8>, [u8; 16], Instant)>,
+    order: VecDeque<(VerifyKey, Instant)>,
+}
+
+impl VerifyCache {
+    /// Create a cache, the entries expire after the ttl and at most capacity
+    /// entries are kept.
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            entries: Mutex::new(VerifyEntries {
+                table: AHashMap::with_capacity(capacity.min(1024)),
+                order: VecDeque::with_capacity(capacity.min(1024)),
+            }),
+            capacity,
+            ttl,
+        }
+    }
+
+    /// Get the number of entries, including the expired entries that have
+    /// not been dropped yet.
+    pub fn len(&self) -> usize {
+        self.entries.lock().table.len()
+    }
+
+    /// Check if the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Check the message integrity of the message with the key, the result is
+    /// taken from the cache when the message is a retransmit.
+    pub fn validate(&self, addr: &SessionAddr, reader: &MessageReader<'_>, key: &[u8; 16]) -> bool {
+        let token: [u8; 12] = match reader.token.try_into() {
+            Ok(it) => it,
+            Err(_) => return validate_integrity(reader, key),
+        };
+
+        let now = Instant::now();
+        if let Some((bytes, digest, expires)) = self.entries.lock().table.get(&(*addr, token)) {
+            if *expires > now && digest == key && bytes.as_slice() == reader.as_bytes() {
+                return true;
+            }
+        }
+
+        if !validate_integrity(reader, key) {
+            return false;
+        }
+
+        if self.capacity == 0 {
+            return true;
+        }
+
+        let mut entries = self.entries.lock();
+        let VerifyEntries { table, order } = &mut *entries;
+
+        // The front is the oldest entry, it is dropped once it has expired or
+        // to make room for the new entry. An entry that has been replaced
+        // since is only dropped from the order.
+        while let Some((key, expires)) = order.front() {
+            if *expires > now && order.len() < self.capacity {
+                break;
+            }
+
+            if table.get(key).map(|it| it.2 == *expires).unwrap_or(false) {
+                table.remove(key);
+            }
+
+            order.pop_front();
+        }
+
+        let expires = now + self.ttl;
+        table.insert((*addr, token), (reader.as_bytes().to_vec(), *key, expires));
+
+        order.push_back(((*addr, token), expires));
+        true
+    }
+}
+
+/// The mobility tickets of RFC 8016.
+///
+/// [rfc8016](https://tools.ietf.org/html/rfc8016)
+///
+/// A ticket is the address of the session that holds the allocation, sealed
+/// with an HMAC-SHA1 under a random key of the service, so the tickets issued
+/// by the service are told apart without being stored. The key only lives in
+/// memory, a restart invalidates the tickets along with the allocations.
+///
+/// # Test
+///
+/// ```
+/// use mycrl_turn::{auth::MobilityTickets, SessionAddr};
+///
+/// let addr = SessionAddr {
+///     address: "[::1]:8080".parse().unwrap(),
+///     interface: "127.0.0.1:3478".parse().unwrap(),
+/// };
+///
+/// let tickets = MobilityTickets::default();
+/// let mut ticket = tickets.issue(&addr);
+/// assert_eq!(tickets.redeem(&ticket), Some(addr));
+///
+/// // The tickets of another service and the tampered tickets are refused.
+/// assert_eq!(MobilityTickets::default().redeem(&ticket), None);
+///
+/// ticket[2] ^= 1;
+/// assert_eq!(tickets.redeem(&ticket), None);
+/// assert_eq!(tickets.redeem(&[]), None);
+/// ```
+pub struct MobilityTickets {
+    key: [u8; 20],
+}
+
+impl Default for MobilityTickets {
+    fn default() -> Self {
+        Self {
+            key: thread_rng().gen(),
+        }
+    }
+}
+
+impl MobilityTickets {
+    /// The size of the HMAC-SHA1 at the end of the tickets.
+    const MAC_SIZE: usize = 20;
+
+    /// Issue the ticket of the session of addr.
+    pub fn issue(&self, addr: &SessionAddr) -> Vec<u8> {
+        let mut ticket = Vec::with_capacity(64);
+        encode_address(&addr.address, &mut ticket);
+        encode_address(&addr.interface, &mut ticket);
+
+        // The key is always a valid HMAC key.
+        if let Ok(mac) = hmac_sha1(&self.key, &[&ticket]) {
+            ticket.extend_from_slice(&mac.into_bytes());
+        }
+
+        ticket
+    }
+
+    /// Validate the ticket, returns the address of the session the ticket was
+    /// issued to, or `None` if the ticket was not issued by this service.
+    pub fn redeem(&self, ticket: &[u8]) -> Option<SessionAddr> {
+        let size = ticket.len().checked_sub(Self::MAC_SIZE)?;
+        let (body, mac) = ticket.split_at(size);
+        if !constant_time_eq(&hmac_sha1(&self.key, &[body]).ok()?.into_bytes(), mac) {
+            return None;
+        }
+
+        let (address, body) = decode_address(body)?;
+        let (interface, body) = decode_address(body)?;
+        body.is_empty()
+            .then_some(SessionAddr { address, interface })
+    }
+}
+
+/// Append the family, the ip and the port of the address.
+fn encode_address(addr: &SocketAddr, bytes: &mut Vec<u8>) {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            bytes.push(4);
+            bytes.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            bytes.push(6);
+            bytes.extend_from_slice(&ip.octets());
+        }
+    }
+
+    bytes.extend_from_slice(&addr.port().to_be_bytes());
+}
+
+/// Read an address written by [`encode_address`], returns the address and the
+/// remaining bytes.
+fn decode_address(bytes: &[u8]) -> Option<(SocketAddr, &[u8])> {
+    let (ip, bytes): (IpAddr, _) = match bytes.first()? {
+        4 if bytes.len() >= 7 => (
+            Ipv4Addr::from(<[u8; 4]>::try_from(&bytes[1..5]).ok()?).into(),
+            &bytes[5..],
+        ),
+        6 if bytes.len() >= 19 => (
+            Ipv6Addr::from(<[u8; 16]>::try_from(&bytes[1..17]).ok()?).into(),
+            &bytes[17..],
+        ),
+        _ => return None,
+    };
+
+    let port = u16::from_be_bytes([bytes[0], bytes[1]]);
+    Some((SocketAddr::new(ip, port), &bytes[2..]))
+}
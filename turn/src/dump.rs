@@ -0,0 +1,92 @@
+//! Attribute dump of the stun messages.
+//!
+//! For the interop debugging, the dispatcher logs every attribute of the
+//! requests and of their responses at the debug level. The dump is only
+//! compiled with the `dump` feature, and only formatted when the debug level
+//! is enabled, so it costs nothing in production. The message integrity and
+//! the credentials are redacted.
+
+use std::fmt::Write;
+
+use stun::attribute::AttrKind;
+
+/// The attributes whose value is never written, only their size.
+///
+/// USERNAME, MESSAGE-INTEGRITY, MESSAGE-INTEGRITY-SHA256 and USERHASH.
+const REDACTED: [u16; 4] = [0x0006, 0x0008, 0x001C, 0x001E];
+
+/// Format the attributes of the stun message, in the order of the message.
+///
+/// The known attributes are given by name, the others by their type. The
+/// printable values are quoted, the others are written in hex.
+///
+/// # Test
+///
+/// ```
+/// use bytes::BytesMut;
+/// use mycrl_turn::dump::attributes;
+/// use stun::{
+///     attribute::{Realm, Software, UserName},
+///     util::long_term_credential_digest,
+///     Kind, MessageWriter, Method,
+/// };
+///
+/// let mut bytes = BytesMut::with_capacity(1500);
+/// let mut message = MessageWriter::new(Method::Binding(Kind::Request), &[0u8; 12], &mut bytes);
+/// message.append::<UserName>("test");
+/// message.append::<Realm>("localhost");
+/// message.append::<Software>("client");
+/// message
+///     .flush(Some(&long_term_credential_digest("test", "test", "localhost")))
+///     .unwrap();
+///
+/// let dump = attributes(&bytes);
+/// assert!(dump.starts_with(
+///     "UserName=<redacted 4 bytes>, Realm=\"localhost\", Software=\"client\", \
+///     MessageIntegrity=<redacted 20 bytes>, Fingerprint="
+/// ));
+///
+/// assert!(!dump.contains("test"));
+/// assert_eq!(attributes(&[0u8; 8]), "");
+/// ```
+pub fn attributes(bytes: &[u8]) -> String {
+    let mut output = String::new();
+    let mut offset = 20;
+
+    while bytes.len() >= offset + 4 {
+        let key = u16::from_be_bytes([bytes[offset], bytes[offset + 1]]);
+        let size = u16::from_be_bytes([bytes[offset + 2], bytes[offset + 3]]) as usize;
+
+        offset += 4;
+        if bytes.len() < offset + size {
+            break;
+        }
+
+        if !output.is_empty() {
+            output.push_str(", ");
+        }
+
+        match AttrKind::try_from(key) {
+            Ok(kind) if kind != AttrKind::Unknown => write!(output, "{:?}=", kind),
+            _ => write!(output, "0x{:04x}=", key),
+        }
+        .ok();
+
+        let value = &bytes[offset..offset + size];
+        if REDACTED.contains(&key) {
+            write!(output, "<redacted {} bytes>", size).ok();
+        } else if !value.is_empty() && value.iter().all(|it| it.is_ascii_graphic() || *it == b' ') {
+            write!(output, "{:?}", String::from_utf8_lossy(value)).ok();
+        } else {
+            output.push_str("0x");
+            for it in value {
+                write!(output, "{:02x}", it).ok();
+            }
+        }
+
+        // The values are padded to a 4 byte boundary.
+        offset += size.next_multiple_of(4);
+    }
+
+    output
+}
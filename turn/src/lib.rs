@@ -1,14 +1,44 @@
+pub mod auth;
+#[cfg(feature = "dump")]
+pub mod dump;
+#[cfg(feature = "test-util")]
+pub mod memory;
+pub mod middleware;
 pub mod operations;
+pub mod policy;
+pub mod pool;
 pub mod sessions;
 
-use self::operations::ServiceContext;
+use self::{
+    auth::{MobilityTickets, Realms, VerifyCache},
+    middleware::Middleware,
+    operations::ServiceContext,
+    policy::{
+        AllocationLimiter, Drains, FamilyMode, LoadShedder, NetworkPolicy, Policies, PolicyStore,
+        PortChangeMode, RateLimit, RateLimiter, RelayPins,
+    },
+    pool::BufferPool,
+    sessions::NONCE_LIFETIME,
+};
 
 pub use self::{
-    operations::{Operationer, ResponseMethod},
-    sessions::{PortAllocatePools, Session, SessionAddr, Sessions},
+    operations::{Operationer, ProcessError, ResponseMethod},
+    sessions::{
+        AllocationContext, AuditEvent, AuditKind, ChannelInfo, PortAllocatePools, ReservationInfo,
+        Session, SessionAddr, Sessions,
+    },
+};
+
+use std::{
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    ops::RangeInclusive,
+    sync::{atomic::AtomicUsize, Arc},
+    time::Duration,
 };
 
-use std::{future::Future, net::SocketAddr, sync::Arc};
+use ahash::HashMap;
+use stun::attribute::Transport;
 
 #[rustfmt::skip]
 static SOFTWARE: &str = concat!(
@@ -26,6 +56,396 @@ pub trait Observer: Send + Sync {
         async { None }
     }
 
+    /// realm selection
+    ///
+    /// Select the realm used as the credential domain of the request, the
+    /// username and the REALM attribute of the request are given. This
+    /// allows a single listener to serve multiple tenants, for example by
+    /// embedding the tenant in the username (`tenant:user`). Returning
+    /// `None` uses the realm of the service.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use std::net::SocketAddr;
+    ///
+    /// use bytes::BytesMut;
+    /// use mycrl_turn::*;
+    /// use stun::{
+    ///     attribute::{ReqeestedTransport, Transport, UserName, Realm, Nonce},
+    ///     util::long_term_credential_digest,
+    ///     Decoder, Kind, MessageWriter, Method, Payload,
+    /// };
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(&self, _: &SessionAddr, _: &str) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    ///
+    ///     fn get_realm(
+    ///         &self,
+    ///         _: &SessionAddr,
+    ///         username: &str,
+    ///         _: Option<&str>,
+    ///     ) -> Option<String> {
+    ///         username.split_once(':').map(|(tenant, _)| tenant.to_string())
+    ///     }
+    /// }
+    ///
+    /// let interface = "127.0.0.1:3478".parse::<SocketAddr>().unwrap();
+    /// let service = Service::new(
+    ///     "localhost".to_string(),
+    ///     vec![interface],
+    ///     ServiceOptions::default(),
+    ///     ObserverTest,
+    /// );
+    ///
+    /// // The client knows nothing but its credential, the realm and the nonce
+    /// // are taken from the challenge of each response.
+    /// let allocate = |client: &str, username: &str| {
+    ///     let address = client.parse().unwrap();
+    ///     let mut challenge: Option<(String, String)> = None;
+    ///     for _ in 0..3 {
+    ///         let mut bytes = BytesMut::with_capacity(1500);
+    ///         let mut message =
+    ///             MessageWriter::new(Method::Allocate(Kind::Request), &[0u8; 12], &mut bytes);
+    ///         message.append::<ReqeestedTransport>(Transport::UDP);
+    ///         let digest = challenge.as_ref().map(|(realm, nonce)| {
+    ///             message.append::<UserName>(username);
+    ///             message.append::<Realm>(realm);
+    ///             message.append::<Nonce>(nonce);
+    ///             long_term_credential_digest(username, "test", realm)
+    ///         });
+    ///
+    ///         message.flush(digest.as_ref()).unwrap();
+    ///
+    ///         let mut operationer = service.get_operationer(interface, interface);
+    ///         let res = pollster::block_on(operationer.route(&bytes, address)).unwrap().unwrap();
+    ///         if res.method == ResponseMethod::Stun(Method::Allocate(Kind::Response)) {
+    ///             return challenge.map(|(realm, _)| realm);
+    ///         }
+    ///
+    ///         let mut decoder = Decoder::default();
+    ///         if let Payload::Message(reader) = decoder.decode(res.bytes).unwrap() {
+    ///             let realm = reader.get::<Realm>().unwrap().to_string();
+    ///             let nonce = reader.get::<Nonce>().unwrap().to_string();
+    ///             challenge = Some((realm, nonce));
+    ///         }
+    ///     }
+    ///
+    ///     None
+    /// };
+    ///
+    /// assert_eq!(allocate("127.0.0.1:10001", "a:user"), Some("a".to_string()));
+    /// assert_eq!(allocate("127.0.0.1:10002", "b:user"), Some("b".to_string()));
+    /// assert_eq!(allocate("127.0.0.1:10003", "user"), Some("localhost".to_string()));
+    /// ```
+    fn get_realm(&self, addr: &SessionAddr, username: &str, realm: Option<&str>) -> Option<String> {
+        None
+    }
+
+    /// tenant tag
+    ///
+    /// Tag the session with an opaque tenant, called once the password of a
+    /// new session has been given by [`Observer::get_password`]. The tag is
+    /// kept in [`sessions::Auth::tenant`] for the lifetime of the session, so
+    /// that the metrics and the logs can be grouped per tenant. Returning
+    /// `None` leaves the session untagged.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use std::net::SocketAddr;
+    ///
+    /// use bytes::BytesMut;
+    /// use mycrl_turn::*;
+    /// use stun::{
+    ///     attribute::{ReqeestedTransport, Transport, UserName, Realm},
+    ///     util::long_term_credential_digest,
+    ///     Kind, MessageWriter, Method,
+    /// };
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(&self, _: &SessionAddr, _: &str) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    ///
+    ///     fn get_tenant(&self, _: &SessionAddr, username: &str) -> Option<String> {
+    ///         username.split_once(':').map(|(tenant, _)| tenant.to_string())
+    ///     }
+    /// }
+    ///
+    /// let interface = "127.0.0.1:3478".parse::<SocketAddr>().unwrap();
+    /// let service = Service::new(
+    ///     "localhost".to_string(),
+    ///     vec![interface],
+    ///     ServiceOptions::default(),
+    ///     ObserverTest,
+    /// );
+    ///
+    /// let mut allocate = |client: &str, username: &str| {
+    ///     let mut bytes = BytesMut::with_capacity(1500);
+    ///     let mut message =
+    ///         MessageWriter::new(Method::Allocate(Kind::Request), &[0u8; 12], &mut bytes);
+    ///     message.append::<ReqeestedTransport>(Transport::UDP);
+    ///     message.append::<UserName>(username);
+    ///     message.append::<Realm>("localhost");
+    ///     message
+    ///         .flush(Some(&long_term_credential_digest(username, "test", "localhost")))
+    ///         .unwrap();
+    ///
+    ///     let address = client.parse().unwrap();
+    ///     let mut operationer = service.get_operationer(interface, interface);
+    ///     let res = pollster::block_on(operationer.route(&bytes, address));
+    ///     assert_eq!(res.unwrap().unwrap().method, ResponseMethod::Stun(Method::Allocate(Kind::Response)));
+    ///
+    ///     let addr = SessionAddr { address, interface };
+    ///     let sessions = service.get_sessions();
+    ///     let session = sessions.get_session(&addr);
+    ///     assert!(sessions.get_allocation(&addr).is_some());
+    ///     session.get_ref().unwrap().auth.tenant.clone()
+    /// };
+    ///
+    /// assert_eq!(allocate("127.0.0.1:10001", "acme:user"), Some("acme".to_string()));
+    /// assert_eq!(allocate("127.0.0.1:10002", "user"), None);
+    /// ```
+    fn get_tenant(&self, addr: &SessionAddr, username: &str) -> Option<String> {
+        None
+    }
+
+    /// peer address translation
+    ///
+    /// Translate the peer address given by the client in the XOR-PEER-ADDRESS
+    /// attribute into the actual relay destination, for example when the
+    /// relay runs as a NAT64 gateway and the IPv4 peer is embedded in an IPv6
+    /// prefix. The translation applies to CreatePermission, ChannelBind and
+    /// Send indications, the addresses given to the client are unchanged.
+    /// Returning `None` keeps the peer address as is.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    ///
+    /// use bytes::BytesMut;
+    /// use mycrl_turn::*;
+    /// use stun::{
+    ///     attribute::{ReqeestedTransport, Transport, UserName, Realm, XorPeerAddress},
+    ///     util::long_term_credential_digest,
+    ///     Kind, MessageWriter, Method,
+    /// };
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(&self, _: &SessionAddr, _: &str) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    ///
+    ///     fn translate_peer(&self, _: &SessionAddr, peer: &SocketAddr) -> Option<SocketAddr> {
+    ///         // The well-known NAT64 prefix 64:ff9b::/96.
+    ///         if let IpAddr::V6(ip) = peer.ip() {
+    ///             let octets = ip.octets();
+    ///             if octets[..12] == [0, 0x64, 0xff, 0x9b, 0, 0, 0, 0, 0, 0, 0, 0] {
+    ///                 let ip = Ipv4Addr::new(octets[12], octets[13], octets[14], octets[15]);
+    ///                 return Some(SocketAddr::new(IpAddr::V4(ip), peer.port()));
+    ///             }
+    ///         }
+    ///
+    ///         None
+    ///     }
+    /// }
+    ///
+    /// let interface = "127.0.0.1:3478".parse::<SocketAddr>().unwrap();
+    /// let service = Service::new(
+    ///     "localhost".to_string(),
+    ///     vec![interface],
+    ///     ServiceOptions::default(),
+    ///     ObserverTest,
+    /// );
+    ///
+    /// let digest = long_term_credential_digest("test", "test", "localhost");
+    /// let mut request = |client: &str, method: Method, peer: Option<SocketAddr>| {
+    ///     let mut bytes = BytesMut::with_capacity(1500);
+    ///     let mut message = MessageWriter::new(method, &[0u8; 12], &mut bytes);
+    ///     message.append::<ReqeestedTransport>(Transport::UDP);
+    ///     if let Some(peer) = peer {
+    ///         message.append::<XorPeerAddress>(peer);
+    ///     }
+    ///
+    ///     message.append::<UserName>("test");
+    ///     message.append::<Realm>("localhost");
+    ///     message.flush(Some(&digest)).unwrap();
+    ///
+    ///     let mut operationer = service.get_operationer(interface, interface);
+    ///     let res = pollster::block_on(operationer.route(&bytes, client.parse().unwrap()));
+    ///     res.unwrap().unwrap().method
+    /// };
+    ///
+    /// let allocate = Method::Allocate(Kind::Request);
+    /// let create_permission = Method::CreatePermission(Kind::Request);
+    ///
+    /// request("127.0.0.1:10001", allocate, None);
+    /// request("127.0.0.1:10002", allocate, None);
+    ///
+    /// let port = service
+    ///     .get_sessions()
+    ///     .get_session(&SessionAddr {
+    ///         address: "127.0.0.1:10002".parse().unwrap(),
+    ///         interface,
+    ///     })
+    ///     .get_ref()
+    ///     .unwrap()
+    ///     .allocate
+    ///     .port
+    ///     .unwrap();
+    ///
+    /// let nat64 = SocketAddr::new("64:ff9b::7f00:1".parse().unwrap(), port);
+    /// let unknown = SocketAddr::new("2001:db8::7f00:1".parse().unwrap(), port);
+    ///
+    /// assert_eq!(
+    ///     request("127.0.0.1:10001", create_permission, Some(nat64)),
+    ///     ResponseMethod::Stun(Method::CreatePermission(Kind::Response))
+    /// );
+    ///
+    /// assert_eq!(
+    ///     request("127.0.0.1:10001", create_permission, Some(unknown)),
+    ///     ResponseMethod::Stun(Method::CreatePermission(Kind::Error))
+    /// );
+    /// ```
+    fn translate_peer(&self, addr: &SessionAddr, peer: &SocketAddr) -> Option<SocketAddr> {
+        None
+    }
+
+    /// peer authorization
+    ///
+    /// Authorize the relay from the session to the peer, beyond the static
+    /// permissions, for example by asking a firewall service. This is
+    /// consulted on ChannelBind and on the first Send indication to a new
+    /// peer. The decision is cached in the session, so the observer is asked
+    /// only once per peer for the lifetime of the session and the relayed
+    /// data is not slowed down. The denied ChannelBind requests are rejected
+    /// with a 403 (Forbidden) error, the denied Send indications are
+    /// discarded.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use std::{
+    ///     net::SocketAddr,
+    ///     sync::{Arc, Mutex},
+    /// };
+    ///
+    /// use bytes::BytesMut;
+    /// use mycrl_turn::*;
+    /// use stun::{
+    ///     attribute::*,
+    ///     util::long_term_credential_digest,
+    ///     Decoder, Kind, MessageWriter, Method, Payload,
+    /// };
+    ///
+    /// #[derive(Clone, Default)]
+    /// struct ObserverTest(Arc<Mutex<Vec<SocketAddr>>>);
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(&self, _: &SessionAddr, _: &str) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    ///
+    ///     async fn authorize_peer(&self, _: &SessionAddr, _: &str, peer: &SocketAddr) -> bool {
+    ///         let mut peers = self.0.lock().unwrap();
+    ///         peers.push(*peer);
+    ///
+    ///         // The first peer asked about is allowed, the others are denied.
+    ///         peers[0] == *peer
+    ///     }
+    /// }
+    ///
+    /// let interface = "127.0.0.1:3478".parse::<SocketAddr>().unwrap();
+    /// let observer = ObserverTest::default();
+    /// let service = Service::new(
+    ///     "localhost".to_string(),
+    ///     vec![interface],
+    ///     ServiceOptions::default(),
+    ///     observer.clone(),
+    /// );
+    ///
+    /// let digest = long_term_credential_digest("test", "test", "localhost");
+    /// let mut operationer = service.get_operationer(interface, interface);
+    /// let mut request = |client: &str, method: Method, peer: Option<SocketAddr>| {
+    ///     let mut bytes = BytesMut::with_capacity(1500);
+    ///     let mut message = MessageWriter::new(method, &[0u8; 12], &mut bytes);
+    ///     if method == Method::SendIndication {
+    ///         message.append::<XorPeerAddress>(peer.unwrap());
+    ///         message.append::<Data>(b"hello");
+    ///         message.flush(None).unwrap();
+    ///     } else {
+    ///         message.append::<ReqeestedTransport>(Transport::UDP);
+    ///         if let Some(peer) = peer {
+    ///             message.append::<XorPeerAddress>(peer);
+    ///             message.append::<ChannelNumber>(0x4000);
+    ///         }
+    ///
+    ///         message.append::<UserName>("test");
+    ///         message.append::<Realm>("localhost");
+    ///         message.flush(Some(&digest)).unwrap();
+    ///     }
+    ///
+    ///     let res = pollster::block_on(operationer.route(&bytes, client.parse().unwrap()));
+    ///     res.unwrap().map(|res| {
+    ///         let mut decoder = Decoder::default();
+    ///         match decoder.decode(res.bytes).unwrap() {
+    ///             Payload::Message(message) => (message.method, message.get::<XorRelayedAddress>(), res.relay),
+    ///             _ => unreachable!(),
+    ///         }
+    ///     })
+    /// };
+    ///
+    /// let allocate = Method::Allocate(Kind::Request);
+    /// let create_permission = Method::CreatePermission(Kind::Request);
+    ///
+    /// let relay = request("127.0.0.1:10000", allocate, None).unwrap().1;
+    /// let clients = ["127.0.0.1:10001", "127.0.0.1:10002"];
+    /// let peers = clients.map(|client| request(client, allocate, None).unwrap().1.unwrap());
+    ///
+    /// for (client, peer) in clients.into_iter().zip(peers) {
+    ///     request("127.0.0.1:10000", create_permission, Some(peer));
+    ///     request(client, create_permission, relay);
+    /// }
+    ///
+    /// // The data of the allowed peer is relayed, of the denied peer it is not.
+    /// let send = Method::SendIndication;
+    /// for _ in 0..2 {
+    ///     let res = request("127.0.0.1:10000", send, Some(peers[0])).unwrap();
+    ///     assert_eq!(res.0, Method::DataIndication);
+    ///     assert_eq!(res.2, Some("127.0.0.1:10001".parse().unwrap()));
+    ///
+    ///     assert!(request("127.0.0.1:10000", send, Some(peers[1])).is_none());
+    /// }
+    ///
+    /// let channel_bind = Method::ChannelBind(Kind::Request);
+    /// let res = request("127.0.0.1:10000", channel_bind, Some(peers[1])).unwrap();
+    /// assert_eq!(res.0, Method::ChannelBind(Kind::Error));
+    ///
+    /// // The decisions are cached, the observer is only asked once per peer.
+    /// assert_eq!(observer.0.lock().unwrap().as_slice(), &peers);
+    /// ```
+    fn authorize_peer(
+        &self,
+        addr: &SessionAddr,
+        username: &str,
+        peer: &SocketAddr,
+    ) -> impl Future<Output = bool> + Send {
+        async { true }
+    }
+
     /// allocate request
     ///
     /// [rfc8489](https://tools.ietf.org/html/rfc8489)
@@ -42,7 +462,14 @@ pub trait Observer: Send + Sync {
     /// server SHOULD NOT allocate ports in the range 0 - 1023 (the Well-
     /// Known Port range) to discourage clients from using TURN to run
     /// standard services.
-    fn allocated(&self, addr: &SessionAddr, username: &str, port: u16) {}
+    fn allocated(
+        &self,
+        addr: &SessionAddr,
+        username: &str,
+        port: u16,
+        allocation: &AllocationContext,
+    ) {
+    }
 
     /// channel binding request
     ///
@@ -74,7 +501,14 @@ pub trait Observer: Send + Sync {
     /// different channel, eliminating the possibility that the
     /// transaction would initially fail but succeed on a
     /// retransmission.
-    fn channel_bind(&self, addr: &SessionAddr, username: &str, channel: u16) {}
+    fn channel_bind(
+        &self,
+        addr: &SessionAddr,
+        username: &str,
+        channel: u16,
+        allocation: &AllocationContext,
+    ) {
+    }
 
     /// create permission request
     ///
@@ -112,10 +546,17 @@ pub trait Observer: Send + Sync {
     /// There are no mandatory attributes in the success response.
     ///
     /// > NOTE: A server need not do anything special to implement
-    /// idempotency of CreatePermission requests over UDP using the
-    /// "stateless stack approach".  Retransmitted CreatePermission
-    /// requests will simply refresh the permissions.
-    fn create_permission(&self, addr: &SessionAddr, username: &str, ports: &[u16]) {}
+    /// > idempotency of CreatePermission requests over UDP using the
+    /// > "stateless stack approach".  Retransmitted CreatePermission
+    /// > requests will simply refresh the permissions.
+    fn create_permission(
+        &self,
+        addr: &SessionAddr,
+        username: &str,
+        ports: &[u16],
+        allocation: &AllocationContext,
+    ) {
+    }
 
     /// refresh request
     ///
@@ -136,17 +577,17 @@ pub trait Observer: Send + Sync {
     /// Subsequent processing depends on the "desired lifetime" value:
     ///
     /// * If the "desired lifetime" is zero, then the request succeeds and
-    /// the allocation is deleted.
+    ///   the allocation is deleted.
     ///
     /// * If the "desired lifetime" is non-zero, then the request succeeds
-    /// and the allocation's time-to-expiry is set to the "desired
-    /// lifetime".
+    ///   and the allocation's time-to-expiry is set to the "desired
+    ///   lifetime".
     ///
     /// If the request succeeds, then the server sends a success response
     /// containing:
     ///
     /// * A LIFETIME attribute containing the current value of the time-to-
-    /// expiry timer.
+    ///   expiry timer.
     ///
     /// NOTE: A server need not do anything special to implement
     /// idempotency of Refresh requests over UDP using the "stateless
@@ -156,14 +597,594 @@ pub trait Observer: Send + Sync {
     /// will cause a 437 (Allocation Mismatch) response if the
     /// allocation has already been deleted, but the client will treat
     /// this as equivalent to a success response (see below).
-    fn refresh(&self, addr: &SessionAddr, username: &str, lifetime: u32) {}
+    ///
+    /// The hook is called with the new lifetime after each successful
+    /// refresh, a lifetime of 0 is the deletion of the allocation, so the
+    /// session duration can be tracked without the closed hook.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use std::{
+    ///     net::SocketAddr,
+    ///     sync::{Arc, Mutex},
+    /// };
+    ///
+    /// use bytes::BytesMut;
+    /// use mycrl_turn::{sessions::AllocationContext, *};
+    /// use stun::{
+    ///     attribute::{Lifetime, ReqeestedTransport, Realm, Transport, UserName},
+    ///     util::long_term_credential_digest,
+    ///     Kind, MessageWriter, Method,
+    /// };
+    ///
+    /// #[derive(Clone, Default)]
+    /// struct ObserverTest(Arc<Mutex<Vec<(u32, u32)>>>);
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(&self, _: &SessionAddr, _: &str) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    ///
+    ///     fn refresh(&self, _: &SessionAddr, _: &str, lifetime: u32, allocation: &AllocationContext) {
+    ///         self.0.lock().unwrap().push((lifetime, allocation.lifetime));
+    ///     }
+    /// }
+    ///
+    /// let interface = "127.0.0.1:3478".parse::<SocketAddr>().unwrap();
+    /// let client = "127.0.0.1:10000".parse::<SocketAddr>().unwrap();
+    ///
+    /// let observer = ObserverTest::default();
+    /// let service = Service::new(
+    ///     "localhost".to_string(),
+    ///     vec![interface],
+    ///     ServiceOptions::default(),
+    ///     observer.clone(),
+    /// );
+    ///
+    /// let mut operationer = service.get_operationer(interface, interface);
+    /// let mut request = |method: Method, lifetime: Option<u32>| {
+    ///     let mut bytes = BytesMut::with_capacity(1500);
+    ///     let mut message = MessageWriter::new(method, &[0u8; 12], &mut bytes);
+    ///     message.append::<ReqeestedTransport>(Transport::UDP);
+    ///     if let Some(lifetime) = lifetime {
+    ///         message.append::<Lifetime>(lifetime);
+    ///     }
+    ///
+    ///     message.append::<UserName>("test");
+    ///     message.append::<Realm>("localhost");
+    ///     message
+    ///         .flush(Some(&long_term_credential_digest("test", "test", "localhost")))
+    ///         .unwrap();
+    ///
+    ///     pollster::block_on(operationer.route(&bytes, client)).unwrap().unwrap().method
+    /// };
+    ///
+    /// request(Method::Allocate(Kind::Request), None);
+    ///
+    /// let refresh = Method::Refresh(Kind::Request);
+    /// assert_eq!(request(refresh, Some(1200)), ResponseMethod::Stun(Method::Refresh(Kind::Response)));
+    /// assert_eq!(request(refresh, Some(0)), ResponseMethod::Stun(Method::Refresh(Kind::Response)));
+    ///
+    /// // The allocation is gone, the retransmitted teardown is not reported.
+    /// assert_eq!(request(refresh, Some(0)), ResponseMethod::Stun(Method::Refresh(Kind::Error)));
+    /// assert_eq!(observer.0.lock().unwrap().as_slice(), &[(1200, 1200), (0, 0)]);
+    /// ```
+    fn refresh(
+        &self,
+        addr: &SessionAddr,
+        username: &str,
+        lifetime: u32,
+        allocation: &AllocationContext,
+    ) {
+    }
+
+    /// relay inbound
+    ///
+    /// Triggered when the data of a peer is relayed toward the client, by a
+    /// ChannelData message or a Send indication of the peer. Like the other
+    /// relay hooks, the client comes first: the client is the transport
+    /// address the data is sent to, the peer is the session the data was
+    /// received from, as in [`Observer::relay_dropped`], and the length is
+    /// the length of the relayed payload. This is called on the relay path of
+    /// every packet, so it must be cheap.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use std::{
+    ///     net::SocketAddr,
+    ///     sync::{Arc, Mutex},
+    /// };
+    ///
+    /// use bytes::BytesMut;
+    /// use mycrl_turn::*;
+    /// use stun::{
+    ///     attribute::{Data, XorPeerAddress},
+    ///     MessageWriter, Method,
+    /// };
+    ///
+    /// #[derive(Clone, Default)]
+    /// struct ObserverTest(Arc<Mutex<Vec<(SocketAddr, SessionAddr, usize)>>>);
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(&self, _: &SessionAddr, _: &str) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    ///
+    ///     fn relay_inbound(&self, client: &SocketAddr, peer: &SessionAddr, len: usize) {
+    ///         self.0.lock().unwrap().push((*client, *peer, len));
+    ///     }
+    /// }
+    ///
+    /// let interface = "127.0.0.1:3478".parse::<SocketAddr>().unwrap();
+    /// let client = "127.0.0.1:10000".parse::<SocketAddr>().unwrap();
+    /// let peer = "127.0.0.1:10001".parse::<SocketAddr>().unwrap();
+    ///
+    /// let observer = ObserverTest::default();
+    /// let service = Service::new(
+    ///     "localhost".to_string(),
+    ///     vec![interface],
+    ///     ServiceOptions::default(),
+    ///     observer.clone(),
+    /// );
+    ///
+    /// let addr = SessionAddr { address: client, interface };
+    /// let peer_addr = SessionAddr { address: peer, interface };
+    /// let sessions = service.get_sessions();
+    /// pollster::block_on(sessions.get_digest(&addr, "test", "test"));
+    /// pollster::block_on(sessions.get_digest(&peer_addr, "test", "test"));
+    /// let port = sessions.allocate(&addr).unwrap();
+    /// let peer_port = sessions.allocate(&peer_addr).unwrap();
+    /// assert!(sessions.bind_channel(&addr, &interface, peer_port, 0x4000));
+    /// assert!(sessions.bind_channel(&peer_addr, &interface, port, 0x4000));
+    ///
+    /// let mut operationer = service.get_operationer(interface, interface);
+    ///
+    /// // The peer relays a ChannelData message of 100 bytes.
+    /// let mut bytes = vec![0x40, 0x00, 0x00, 100];
+    /// bytes.resize(104, 0);
+    /// assert!(pollster::block_on(operationer.route(&bytes, peer)).unwrap().is_some());
+    ///
+    /// // The peer relays a Send indication of 5 bytes.
+    /// let mut bytes = BytesMut::with_capacity(1500);
+    /// let mut message = MessageWriter::new(Method::SendIndication, &[0u8; 12], &mut bytes);
+    /// message.append::<XorPeerAddress>(SocketAddr::new(interface.ip(), port));
+    /// message.append::<Data>(b"hello");
+    /// message.flush(None).unwrap();
+    /// assert!(pollster::block_on(operationer.route(&bytes, peer)).unwrap().is_some());
+    ///
+    /// assert_eq!(
+    ///     observer.0.lock().unwrap().as_slice(),
+    ///     &[(client, peer_addr, 100), (client, peer_addr, 5)]
+    /// );
+    /// ```
+    fn relay_inbound(&self, client: &SocketAddr, peer: &SessionAddr, len: usize) {}
+
+    /// relay dropped
+    ///
+    /// Triggered when the data relayed by the peer is dropped, because no
+    /// allocation has installed a permission or bound a channel for the
+    /// peer, with the size of the data. The data is dropped silently as
+    /// required by [rfc8656](https://tools.ietf.org/html/rfc8656#section-11.5),
+    /// this lets the integrators count the drops.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use std::{
+    ///     net::SocketAddr,
+    ///     sync::{Arc, Mutex},
+    /// };
+    ///
+    /// use bytes::BytesMut;
+    /// use mycrl_turn::*;
+    /// use stun::{
+    ///     attribute::{Data, XorPeerAddress},
+    ///     MessageWriter, Method,
+    /// };
+    ///
+    /// #[derive(Clone, Default)]
+    /// struct ObserverTest(Arc<Mutex<Vec<(SessionAddr, usize)>>>);
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(&self, _: &SessionAddr, _: &str) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    ///
+    ///     fn relay_dropped(&self, peer: &SessionAddr, len: usize) {
+    ///         self.0.lock().unwrap().push((*peer, len));
+    ///     }
+    /// }
+    ///
+    /// let interface = "127.0.0.1:3478".parse::<SocketAddr>().unwrap();
+    /// let client = "127.0.0.1:10000".parse::<SocketAddr>().unwrap();
+    /// let peer = "127.0.0.1:10001".parse::<SocketAddr>().unwrap();
+    ///
+    /// let observer = ObserverTest::default();
+    /// let service = Service::new(
+    ///     "localhost".to_string(),
+    ///     vec![interface],
+    ///     ServiceOptions::default(),
+    ///     observer.clone(),
+    /// );
+    ///
+    /// let addr = SessionAddr { address: client, interface };
+    /// let peer_addr = SessionAddr { address: peer, interface };
+    /// let sessions = service.get_sessions();
+    /// pollster::block_on(sessions.get_digest(&addr, "test", "test"));
+    /// pollster::block_on(sessions.get_digest(&peer_addr, "test", "test"));
+    /// let port = sessions.allocate(&addr).unwrap();
+    /// sessions.allocate(&peer_addr).unwrap();
+    ///
+    /// let mut operationer = service.get_operationer(interface, interface);
+    ///
+    /// // The client has no permission for the peer.
+    /// let mut bytes = BytesMut::with_capacity(1500);
+    /// let mut message = MessageWriter::new(Method::SendIndication, &[0u8; 12], &mut bytes);
+    /// message.append::<XorPeerAddress>(SocketAddr::new(interface.ip(), port));
+    /// message.append::<Data>(b"hello");
+    /// message.flush(None).unwrap();
+    /// assert!(pollster::block_on(operationer.route(&bytes, peer)).unwrap().is_none());
+    ///
+    /// // The client has no channel for the peer.
+    /// let mut bytes = vec![0x40, 0x00, 0x00, 100];
+    /// bytes.resize(104, 0);
+    /// assert!(pollster::block_on(operationer.route(&bytes, peer)).unwrap().is_none());
+    ///
+    /// assert_eq!(
+    ///     observer.0.lock().unwrap().as_slice(),
+    ///     &[(peer_addr, 5), (peer_addr, 100)]
+    /// );
+    /// ```
+    fn relay_dropped(&self, peer: &SessionAddr, len: usize) {}
+
+    /// relay audit
+    ///
+    /// Triggered for every relay 5-tuple a client installs, once per peer of
+    /// a create permission request and once for a channel bind request, the
+    /// refreshes included. The event has the client, the relayed and the
+    /// peer addresses and the time, but no credentials, so that it can be
+    /// written to an audit log.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use std::{
+    ///     net::SocketAddr,
+    ///     sync::{Arc, Mutex},
+    /// };
+    ///
+    /// use bytes::BytesMut;
+    /// use mycrl_turn::*;
+    /// use stun::{
+    ///     attribute::{Realm, UserName, XorPeerAddress},
+    ///     util::long_term_credential_digest,
+    ///     MessageWriter, Method, Kind,
+    /// };
+    ///
+    /// #[derive(Clone, Default)]
+    /// struct ObserverTest(Arc<Mutex<Vec<AuditEvent>>>);
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(&self, _: &SessionAddr, _: &str) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    ///
+    ///     fn audit(&self, event: &AuditEvent) {
+    ///         self.0.lock().unwrap().push(*event);
+    ///     }
+    /// }
+    ///
+    /// let interface = "127.0.0.1:3478".parse::<SocketAddr>().unwrap();
+    /// let client = "127.0.0.1:10000".parse::<SocketAddr>().unwrap();
+    ///
+    /// let observer = ObserverTest::default();
+    /// let service = Service::new(
+    ///     "localhost".to_string(),
+    ///     vec![interface],
+    ///     ServiceOptions::default(),
+    ///     observer.clone(),
+    /// );
+    ///
+    /// let addr = SessionAddr { address: client, interface };
+    /// let peer_addr = SessionAddr { address: "127.0.0.1:10001".parse().unwrap(), interface };
+    /// let sessions = service.get_sessions();
+    /// pollster::block_on(sessions.get_digest(&addr, "test", "localhost"));
+    /// pollster::block_on(sessions.get_digest(&peer_addr, "test", "localhost"));
+    /// let port = sessions.allocate(&addr).unwrap();
+    /// let peer = SocketAddr::new(interface.ip(), sessions.allocate(&peer_addr).unwrap());
+    ///
+    /// let mut bytes = BytesMut::with_capacity(1500);
+    /// let method = Method::CreatePermission(Kind::Request);
+    /// let mut message = MessageWriter::new(method, &[0u8; 12], &mut bytes);
+    /// message.append::<XorPeerAddress>(peer);
+    /// message.append::<UserName>("test");
+    /// message.append::<Realm>("localhost");
+    /// message
+    ///     .flush(Some(&long_term_credential_digest("test", "test", "localhost")))
+    ///     .unwrap();
+    ///
+    /// let mut operationer = service.get_operationer(interface, interface);
+    /// pollster::block_on(operationer.route(&bytes, client)).unwrap();
+    ///
+    /// let events = observer.0.lock().unwrap();
+    /// assert_eq!(events.len(), 1);
+    /// assert_eq!(events[0].kind, AuditKind::Permission);
+    /// assert_eq!(events[0].client, addr);
+    /// assert_eq!(events[0].relay.port(), port);
+    /// assert_eq!(events[0].peer, peer);
+    /// ```
+    fn audit(&self, event: &AuditEvent) {}
+
+    /// unknown method
+    ///
+    /// Triggered when a message of a method that is not implemented is
+    /// received, with the method number of the message. The requests are
+    /// answered with a 400 (Bad Request) error, the other messages are
+    /// discarded.
+    fn unknown_method(&self, addr: &SessionAddr, method: u16) {}
+
+    /// session moved
+    ///
+    /// Triggered when the session is moved to a new client address, such as
+    /// when a reconnecting client reclaims its detached allocation, see
+    /// [`Sessions::reclaim`]. The session is only known by the new address
+    /// afterwards.
+    fn moved(&self, addr: &SessionAddr, new: &SessionAddr, username: &str) {}
+
+    /// session evicted
+    ///
+    /// Triggered when the session is closed by force to reclaim its
+    /// resources, such as when the connection of the client is evicted by a
+    /// server at capacity, see [`Sessions::evict`]. The session is closed
+    /// right after, which also triggers the closed event.
+    fn evicted(&self, addr: &SessionAddr, username: &str) {}
 
     /// session closed
     ///
     /// Triggered when the session leaves from the turn. Possible reasons: the
     /// session life cycle has expired, external active deletion, or active
     /// exit of the session.
-    fn closed(&self, addr: &SessionAddr, username: &str) {}
+    fn closed(&self, addr: &SessionAddr, username: &str, allocation: Option<&AllocationContext>) {}
+}
+
+/// Turn service options.
+///
+/// Behavioral knobs of the processors, the default value keeps the standard
+/// behavior of the turn server.
+#[derive(Debug, Clone, Default)]
+pub struct ServiceOptions {
+    /// Only include the SOFTWARE attribute in the binding response when the
+    /// binding request also contains a SOFTWARE attribute, by default the
+    /// SOFTWARE attribute is always appended.
+    pub echo_software: bool,
+    /// Require the long-term credential for binding requests, by default
+    /// binding requests are answered without authentication.
+    pub binding_require_auth: bool,
+    /// The networks of the clients whose binding requests are answered, the
+    /// binding requests of the other clients are silently dropped, so that
+    /// the server does not acknowledge its existence to them. All networks
+    /// are allowed by default.
+    pub binding_policy: NetworkPolicy,
+    /// The networks of the peers that the clients can create permissions and
+    /// bind channels for, the other peers are rejected with a 403
+    /// (Forbidden) error. All networks are allowed by default.
+    pub peer_policy: NetworkPolicy,
+    /// Answer the binding requests of the classic stun (RFC 3489) clients,
+    /// which have no magic cookie, with the MAPPED-ADDRESS attribute only.
+    /// These requests are dropped by default.
+    pub legacy_binding: bool,
+    /// The request rate limits of the client addresses and their networks,
+    /// the requests over the limits are silently dropped. The relayed data
+    /// is not limited. Disabled by default.
+    pub rate_limit: Option<RateLimit>,
+    /// The number of the new allocations per second of the whole server, the
+    /// allocations over the rate are rejected with a 508 (Insufficient
+    /// Capacity) error. Unlimited by default.
+    pub allocation_rate: Option<u32>,
+    /// The number of the requests in flight above which the server is
+    /// overloaded, see [`LoadShedder`]. When overloaded, the allocate
+    /// requests are rejected with a 508 (Insufficient Capacity) error and the
+    /// create permission requests for new peers with a 486 (Allocation Quota
+    /// Reached) error, the binding requests and the traffic of the existing
+    /// allocations are still served. Disabled by default.
+    pub max_in_flight: Option<usize>,
+    /// The address families of the relays, the allocations on the interfaces
+    /// of the disabled family are rejected. Both families are enabled by
+    /// default.
+    pub relay_family: FamilyMode,
+    /// The handling of a client whose source port changes during its
+    /// allocation, which has to allocate again by default.
+    pub port_change: PortChangeMode,
+    /// The seconds an allocation has to be idle, neither relaying the data of
+    /// its client nor refreshed, before a client whose source port has
+    /// changed takes it over in the lenient port change mode, see
+    /// [`Sessions::migrate`]. Zero by default, the allocation is taken over
+    /// right away.
+    pub port_change_idle: u64,
+    /// Deny the refresh of allocations that have not relayed any data sent by
+    /// the client for this number of seconds, disabled by default.
+    pub inactivity_timeout: Option<u64>,
+    /// The grace period in seconds the allocations of the disconnected
+    /// clients are detached for, see [`Sessions::detach`]. The authenticated
+    /// requests only look for a detached allocation to reclaim when it is
+    /// set, disabled by default.
+    pub disconnect_grace: Option<u32>,
+    /// The alternate address of each interface, which is returned in the
+    /// OTHER-ADDRESS attribute of the binding response for RFC 5780 behavior
+    /// discovery. The binding requests with a CHANGE-REQUEST attribute are
+    /// answered from the interface at the changed ip and port.
+    pub other_addresses: HashMap<SocketAddr, SocketAddr>,
+    /// The additional external ip addresses of each interface. The relayed
+    /// addresses of the allocations on the interface are assigned round-robin
+    /// across the ip of the interface and these addresses, which must be of
+    /// the same family as the interface.
+    pub relay_addresses: HashMap<SocketAddr, Vec<IpAddr>>,
+    /// The relay ips pinned to the usernames, for the tenants that must relay
+    /// from dedicated ips. An allocation of a pinned username is relayed on
+    /// the pinned ip when it is the ip of the interface of the request or one
+    /// of its [`relay_addresses`](Self::relay_addresses), the other
+    /// allocations rotate across the ips of the interface. No pins by
+    /// default.
+    pub relay_pins: RelayPins,
+    /// Delay the responses to the requests that failed the authentication by
+    /// a random duration within the bounds, so that the failures cannot be
+    /// told apart by timing, disabled by default.
+    pub auth_failure_delay: Option<RangeInclusive<Duration>>,
+    /// Cache the successful message integrity checks for this duration, so
+    /// that the retransmitted requests skip the HMAC, disabled by default.
+    /// This should be a few seconds at most, the retransmit window.
+    pub verify_cache_ttl: Option<Duration>,
+    /// The maximum age of a nonce in seconds, the requests carrying an
+    /// expired nonce are rejected with a 438 (Stale Nonce) error and a fresh
+    /// nonce. A shorter window narrows the replay of the captured requests,
+    /// at the cost of more round trips. 600 seconds by default.
+    pub nonce_lifetime: Option<u64>,
+    /// Reply a Data indication with the ERROR-CODE attribute when a send
+    /// indication is discarded, which describes why the data was not
+    /// relayed. This is not part of the standard and is meant for debugging
+    /// deployments, disabled by default.
+    pub diagnostic_indications: bool,
+    /// The maximum payload of the ChannelData messages in bytes, the larger
+    /// messages are discarded. For the controlled deployments on jumbo frame
+    /// networks the limit can be raised, by default it is the standard limit
+    /// [`MAX_RELAYED_PAYLOAD`](crate::operations::channel_data::MAX_RELAYED_PAYLOAD).
+    pub max_relayed_payload: Option<usize>,
+    /// The maximum number of channels bound to an allocation, the channel
+    /// bind requests over the limit are rejected with a 508 (Insufficient
+    /// Capacity) error. The channels are released with the allocation. By
+    /// default the limit is only the range of the channel numbers.
+    pub max_channels: Option<usize>,
+    /// The maximum number of the relay paths of the whole server, that is
+    /// the permissions and the channels of all allocations together, see
+    /// [`Sessions::relay_paths`]. The create permission and the channel bind
+    /// requests for new paths over the limit are rejected with a 508
+    /// (Insufficient Capacity) error, the existing paths are refreshed.
+    /// Unlimited by default.
+    pub max_relay_paths: Option<usize>,
+    /// The maximum number of the XOR-PEER-ADDRESS attributes of a create
+    /// permission request, which bounds the work of a single request. The
+    /// requests with more peers are rejected with a 400 (Bad Request) error.
+    /// By default the limit is only the size of the message.
+    pub max_peer_addresses: Option<usize>,
+    /// Reject the allocate requests arrived on the listeners that are not
+    /// [`Listener::secure`] with a 403 (Forbidden) error, so that only the
+    /// encrypted transports can relay. The binding requests are answered on
+    /// all listeners.
+    pub allocate_require_secure: bool,
+    /// Issue the mobility tickets of RFC 8016 to the allocate requests that
+    /// carry the MOBILITY-TICKET attribute, so that the client can move its
+    /// allocation to a new address with a refresh request carrying the
+    /// ticket. The refresh requests with a ticket are rejected with a 405
+    /// (Mobility Forbidden) error by default.
+    pub mobility: bool,
+    /// The number of the response buffers kept for reuse, see
+    /// [`BufferPool`](crate::pool::BufferPool). A new operationer, such as the
+    /// one of a new tcp connection, takes its buffer from the pool, and the
+    /// buffer is returned when the operationer is dropped, so it should be
+    /// sized to the number of concurrent connections. Disabled by default.
+    pub buffer_pool: usize,
+    /// The identity of each interface, which allows a single service to serve
+    /// distinct services on different listeners.
+    pub listeners: HashMap<SocketAddr, Listener>,
+}
+
+/// The identity of a listener.
+///
+/// The dispatcher selects the listener by the interface the request arrived
+/// on, the unset fields fall back to the realm and options of the service.
+///
+/// # Test
+///
+/// ```
+/// use std::net::SocketAddr;
+///
+/// use bytes::BytesMut;
+/// use mycrl_turn::*;
+/// use stun::{
+///     attribute::{ReqeestedTransport, Transport, Realm, Software},
+///     Decoder, Kind, MessageWriter, Method, Payload,
+/// };
+///
+/// #[derive(Clone)]
+/// struct ObserverTest;
+///
+/// impl Observer for ObserverTest {}
+///
+/// let first = "127.0.0.1:3478".parse::<SocketAddr>().unwrap();
+/// let second = "127.0.0.1:3479".parse::<SocketAddr>().unwrap();
+/// let service = Service::new(
+///     "localhost".to_string(),
+///     vec![first, second],
+///     ServiceOptions {
+///         listeners: [(
+///             second,
+///             Listener {
+///                 realm: Some("example.com".to_string()),
+///                 software: Some("example".to_string()),
+///                 ..Default::default()
+///             },
+///         )]
+///         .into_iter()
+///         .collect(),
+///         ..Default::default()
+///     },
+///     ObserverTest,
+/// );
+///
+/// let request = |interface: SocketAddr, method: Method| {
+///     let mut bytes = BytesMut::with_capacity(1500);
+///     let mut message = MessageWriter::new(method, &[0u8; 12], &mut bytes);
+///     message.append::<ReqeestedTransport>(Transport::UDP);
+///     message.flush(None).unwrap();
+///
+///     let mut operationer = service.get_operationer(interface, interface);
+///     let res = pollster::block_on(operationer.route(&bytes, "127.0.0.1:10000".parse().unwrap()));
+///
+///     let mut decoder = Decoder::default();
+///     if let Payload::Message(message) = decoder.decode(res.unwrap().unwrap().bytes).unwrap() {
+///         (
+///             message.get::<Realm>().map(|it| it.to_string()),
+///             message.get::<Software>().map(|it| it.to_string()),
+///         )
+///     } else {
+///         unreachable!()
+///     }
+/// };
+///
+/// let allocate = Method::Allocate(Kind::Request);
+/// let binding = Method::Binding(Kind::Request);
+///
+/// assert_eq!(request(first, allocate).0.as_deref(), Some("localhost"));
+/// assert_eq!(request(second, allocate).0.as_deref(), Some("example.com"));
+/// assert!(request(first, binding).1.unwrap().starts_with("turn-rs."));
+/// assert_eq!(request(second, binding).1.as_deref(), Some("example"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Listener {
+    /// The realm of the listener, which is used to issue the challenges and
+    /// authenticate the requests arrived on the listener.
+    pub realm: Option<String>,
+    /// The SOFTWARE attribute of the responses sent by the listener.
+    pub software: Option<String>,
+    /// Overrides [`ServiceOptions::echo_software`] for the listener.
+    pub echo_software: Option<bool>,
+    /// Overrides [`ServiceOptions::binding_require_auth`] for the listener.
+    pub binding_require_auth: Option<bool>,
+    /// The messages of the listener arrive over an encrypted transport, such
+    /// as the TLS or DTLS terminated in front of the listener.
+    pub secure: bool,
+}
+
+/// The realm, software and options of a listener, resolved once when the
+/// service is created.
+#[derive(Clone)]
+struct Identity {
+    realm: Arc<Realms>,
+    software: Arc<str>,
+    options: Arc<ServiceOptions>,
+    secure: bool,
 }
 
 /// Turn service.
@@ -171,7 +1192,18 @@ pub trait Observer: Send + Sync {
 pub struct Service<T> {
     interfaces: Arc<Vec<SocketAddr>>,
     sessions: Arc<Sessions<T>>,
-    realm: Arc<String>,
+    verify_cache: Option<Arc<VerifyCache>>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    allocation_limiter: Option<Arc<AllocationLimiter>>,
+    load_shedder: Option<Arc<LoadShedder>>,
+    buffer_pool: Option<Arc<BufferPool>>,
+    mobility: Option<Arc<MobilityTickets>>,
+    relay_cursor: Arc<AtomicUsize>,
+    policies: Arc<PolicyStore>,
+    drains: Arc<Drains>,
+    listeners: Arc<HashMap<SocketAddr, Identity>>,
+    middleware: Option<Arc<dyn Middleware>>,
+    identity: Identity,
     observer: T,
 }
 
@@ -195,17 +1227,399 @@ where
     ///
     /// impl Observer for ObserverTest {}
     ///
-    /// Service::new("test".to_string(), vec![], ObserverTest);
+    /// Service::new("test".to_string(), vec![], ServiceOptions::default(), ObserverTest);
     /// ```
-    pub fn new(realm: String, interfaces: Vec<SocketAddr>, observer: T) -> Self {
+    pub fn new(
+        realm: String,
+        interfaces: Vec<SocketAddr>,
+        options: ServiceOptions,
+        observer: T,
+    ) -> Self {
+        // The number of retransmits cached at most, which bounds the memory used
+        // by the cache.
+        let verify_cache = options
+            .verify_cache_ttl
+            .map(|ttl| Arc::new(VerifyCache::new(ttl, 16384)));
+
+        // The number of buckets kept for each kind of the limits, the refilled
+        // buckets are dropped beyond it.
+        let rate_limiter = options
+            .rate_limit
+            .map(|limit| Arc::new(RateLimiter::new(limit, 65536)));
+
+        let allocation_limiter = options
+            .allocation_rate
+            .map(|rate| Arc::new(AllocationLimiter::new(rate)));
+
+        let load_shedder = options
+            .max_in_flight
+            .map(|threshold| Arc::new(LoadShedder::new(threshold)));
+
+        let buffer_pool = match options.buffer_pool {
+            0 => None,
+            capacity => Some(Arc::new(BufferPool::new(capacity))),
+        };
+
+        let mobility = options
+            .mobility
+            .then(|| Arc::new(MobilityTickets::default()));
+
+        // The policies are shared by all the listeners, so that a reload
+        // applies to all of them.
+        let policies = Arc::new(PolicyStore::new(Policies {
+            binding: options.binding_policy.clone(),
+            peer: options.peer_policy.clone(),
+        }));
+
+        let identity = Identity {
+            realm: Arc::new(Realms::new(realm)),
+            software: Arc::from(SOFTWARE),
+            options: Arc::new(options),
+            secure: false,
+        };
+
+        let listeners = identity
+            .options
+            .listeners
+            .iter()
+            .map(|(interface, listener)| {
+                let options = ServiceOptions {
+                    echo_software: listener
+                        .echo_software
+                        .unwrap_or(identity.options.echo_software),
+                    binding_require_auth: listener
+                        .binding_require_auth
+                        .unwrap_or(identity.options.binding_require_auth),
+                    ..identity.options.as_ref().clone()
+                };
+
+                let identity = Identity {
+                    realm: match &listener.realm {
+                        Some(realm) => Arc::new(Realms::new(realm.clone())),
+                        None => identity.realm.clone(),
+                    },
+                    software: match &listener.software {
+                        Some(software) => Arc::from(software.as_str()),
+                        None => identity.software.clone(),
+                    },
+                    options: Arc::new(options),
+                    secure: listener.secure,
+                };
+
+                (*interface, identity)
+            })
+            .collect();
+
         Self {
-            sessions: Sessions::new(observer.clone()),
+            sessions: Sessions::with_nonce_lifetime(
+                observer.clone(),
+                identity.options.nonce_lifetime.unwrap_or(NONCE_LIFETIME),
+            ),
             interfaces: Arc::new(interfaces),
-            realm: Arc::new(realm),
+            listeners: Arc::new(listeners),
+            middleware: None,
+            relay_cursor: Default::default(),
+            drains: Default::default(),
+            verify_cache,
+            policies,
+            rate_limiter,
+            allocation_limiter,
+            load_shedder,
+            buffer_pool,
+            mobility,
+            identity,
             observer,
         }
     }
 
+    /// Get the load shedder of the service, only set with
+    /// [`ServiceOptions::max_in_flight`].
+    pub fn get_load_shedder(&self) -> Option<Arc<LoadShedder>> {
+        self.load_shedder.clone()
+    }
+
+    /// Get the current network policies of the service.
+    pub fn get_policies(&self) -> Arc<Policies> {
+        self.policies.get()
+    }
+
+    /// Replace the network policies of the service without a restart.
+    ///
+    /// The new policies apply to the requests processed after the swap, the
+    /// existing sessions, permissions and channels are unaffected.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use std::{net::SocketAddr, sync::Arc};
+    ///
+    /// use bytes::BytesMut;
+    /// use mycrl_turn::{
+    ///     policy::{NetworkPolicy, Policies},
+    ///     sessions::AllocationContext,
+    ///     *,
+    /// };
+    /// use parking_lot::Mutex;
+    /// use stun::{
+    ///     attribute::{ErrorCode, ErrorKind, Realm, ReqeestedTransport, Transport, UserName, XorPeerAddress},
+    ///     util::long_term_credential_digest,
+    ///     Decoder, Kind, MessageWriter, Method, Payload,
+    /// };
+    ///
+    /// #[derive(Clone, Default)]
+    /// struct ObserverTest(Arc<Mutex<Vec<SocketAddr>>>);
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(&self, _: &SessionAddr, _: &str) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    ///
+    ///     fn allocated(&self, _: &SessionAddr, _: &str, _: u16, allocation: &AllocationContext) {
+    ///         self.0.lock().push(allocation.relay);
+    ///     }
+    /// }
+    ///
+    /// let interface = "127.0.0.1:3478".parse::<SocketAddr>().unwrap();
+    /// let observer = ObserverTest::default();
+    /// let service = Service::new(
+    ///     "localhost".to_string(),
+    ///     vec![interface],
+    ///     ServiceOptions::default(),
+    ///     observer.clone(),
+    /// );
+    ///
+    /// let digest = long_term_credential_digest("test", "test", "localhost");
+    /// let mut operationer = service.get_operationer(interface, interface);
+    /// let mut request = |client: &str, method: Method, peer: Option<SocketAddr>| {
+    ///     let mut bytes = BytesMut::with_capacity(1500);
+    ///     let mut message = MessageWriter::new(method, &[0u8; 12], &mut bytes);
+    ///     message.append::<ReqeestedTransport>(Transport::UDP);
+    ///     if let Some(peer) = peer {
+    ///         message.append::<XorPeerAddress>(peer);
+    ///     }
+    ///
+    ///     message.append::<UserName>("test");
+    ///     message.append::<Realm>("localhost");
+    ///     message.flush(Some(&digest)).unwrap();
+    ///
+    ///     let res = pollster::block_on(operationer.route(&bytes, client.parse().unwrap()));
+    ///     let mut decoder = Decoder::default();
+    ///     if let Payload::Message(message) = decoder.decode(res.unwrap().unwrap().bytes).unwrap() {
+    ///         message.get::<ErrorCode>().map(|it| it.code)
+    ///     } else {
+    ///         unreachable!()
+    ///     }
+    /// };
+    ///
+    /// request("127.0.0.1:10000", Method::Allocate(Kind::Request), None);
+    /// request("127.0.0.1:10001", Method::Allocate(Kind::Request), None);
+    /// request("127.0.0.1:10002", Method::Allocate(Kind::Request), None);
+    /// let (peer, other) = {
+    ///     let relays = observer.0.lock();
+    ///     (relays[1], relays[2])
+    /// };
+    ///
+    /// let create_permission = Method::CreatePermission(Kind::Request);
+    /// assert_eq!(request("127.0.0.1:10000", create_permission, Some(peer)), None);
+    ///
+    /// // Deny the peers of the network in the middle of the session.
+    /// service.reload_policy(Policies {
+    ///     peer: NetworkPolicy {
+    ///         allow: vec![],
+    ///         deny: vec!["127.0.0.0/8".parse().unwrap()],
+    ///     },
+    ///     ..Default::default()
+    /// });
+    ///
+    /// assert_eq!(
+    ///     request("127.0.0.1:10000", create_permission, Some(other)),
+    ///     Some(ErrorKind::Forbidden as u16)
+    /// );
+    ///
+    /// // The existing permission is kept.
+    /// let addr = SessionAddr { address: "127.0.0.1:10000".parse().unwrap(), interface };
+    /// assert!(service.get_sessions().has_permission(&addr, &peer));
+    ///
+    /// service.reload_policy(Policies::default());
+    /// assert_eq!(request("127.0.0.1:10000", create_permission, Some(other)), None);
+    /// ```
+    pub fn reload_policy(&self, policies: Policies) {
+        self.policies.reload(policies);
+    }
+
+    /// Drain the listener for maintenance.
+    ///
+    /// The listener takes no new allocations, while the other listeners and
+    /// the existing allocations on the listener keep working. The allocate
+    /// requests are answered with a 300 (Try Alternate) error and the
+    /// ALTERNATE-SERVER attribute if the alternate server is given, or a 508
+    /// (Insufficient Capacity) error otherwise. Returns false if the listener
+    /// is not an interface of the service.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use std::net::SocketAddr;
+    ///
+    /// use bytes::BytesMut;
+    /// use mycrl_turn::*;
+    /// use stun::{
+    ///     attribute::{AlternateServer, ErrorCode, ErrorKind, Realm, ReqeestedTransport, Transport, UserName},
+    ///     util::long_term_credential_digest,
+    ///     Decoder, Kind, MessageWriter, Method, Payload,
+    /// };
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(&self, _: &SessionAddr, _: &str) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let interface = "127.0.0.1:3478".parse::<SocketAddr>().unwrap();
+    /// let alternate = "192.0.2.1:3478".parse::<SocketAddr>().unwrap();
+    /// let service = Service::new(
+    ///     "localhost".to_string(),
+    ///     vec![interface],
+    ///     ServiceOptions::default(),
+    ///     ObserverTest,
+    /// );
+    ///
+    /// let digest = long_term_credential_digest("test", "test", "localhost");
+    /// let mut operationer = service.get_operationer(interface, interface);
+    /// let mut allocate = |client: &str| {
+    ///     let mut bytes = BytesMut::with_capacity(1500);
+    ///     let mut message =
+    ///         MessageWriter::new(Method::Allocate(Kind::Request), &[0u8; 12], &mut bytes);
+    ///     message.append::<ReqeestedTransport>(Transport::UDP);
+    ///     message.append::<UserName>("test");
+    ///     message.append::<Realm>("localhost");
+    ///     message.flush(Some(&digest)).unwrap();
+    ///
+    ///     let res = pollster::block_on(operationer.route(&bytes, client.parse().unwrap()));
+    ///     let mut decoder = Decoder::default();
+    ///     if let Payload::Message(message) = decoder.decode(res.unwrap().unwrap().bytes).unwrap() {
+    ///         (
+    ///             message.get::<ErrorCode>().map(|it| it.code),
+    ///             message.get::<AlternateServer>(),
+    ///         )
+    ///     } else {
+    ///         unreachable!()
+    ///     }
+    /// };
+    ///
+    /// assert!(!service.drain_listener("127.0.0.1:3479".parse().unwrap(), None));
+    ///
+    /// assert!(service.drain_listener(interface, Some(alternate)));
+    /// assert_eq!(
+    ///     allocate("127.0.0.1:10000"),
+    ///     (Some(ErrorKind::TryAlternate as u16), Some(alternate))
+    /// );
+    ///
+    /// assert!(service.drain_listener(interface, None));
+    /// assert_eq!(
+    ///     allocate("127.0.0.1:10000"),
+    ///     (Some(ErrorKind::InsufficientCapacity as u16), None)
+    /// );
+    ///
+    /// assert!(service.resume_listener(&interface));
+    /// assert_eq!(allocate("127.0.0.1:10000"), (None, None));
+    /// ```
+    pub fn drain_listener(&self, interface: SocketAddr, alternate: Option<SocketAddr>) -> bool {
+        if !self.interfaces.contains(&interface) {
+            return false;
+        }
+
+        self.drains.drain(interface, alternate);
+        true
+    }
+
+    /// Take new allocations on the drained listener again, see
+    /// [`Service::drain_listener`]. Returns false if the listener is not
+    /// drained.
+    pub fn resume_listener(&self, interface: &SocketAddr) -> bool {
+        self.drains.resume(interface)
+    }
+
+    /// Register the middleware of the service, see [`Middleware`].
+    pub fn with_middleware(mut self, middleware: Arc<dyn Middleware>) -> Self {
+        self.middleware = Some(middleware);
+        self
+    }
+
+    /// Rotate the realm of the service.
+    ///
+    /// The challenges are issued with the new realm right away, and the
+    /// requests using the previous realm are still accepted for the grace
+    /// window, so that the credentials can be rotated without downtime. The
+    /// listeners with their own realm are not rotated. A request in an
+    /// elapsed or unknown realm gets the same 401 challenge as an unknown
+    /// username.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use std::{net::SocketAddr, time::Duration};
+    ///
+    /// use bytes::BytesMut;
+    /// use mycrl_turn::*;
+    /// use stun::{
+    ///     attribute::{ReqeestedTransport, Transport, UserName, Realm},
+    ///     util::long_term_credential_digest,
+    ///     Kind, MessageWriter, Method,
+    /// };
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(&self, _: &SessionAddr, _: &str) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let interface = "127.0.0.1:3478".parse::<SocketAddr>().unwrap();
+    /// let service = Service::new(
+    ///     "localhost".to_string(),
+    ///     vec![interface],
+    ///     ServiceOptions::default(),
+    ///     ObserverTest,
+    /// );
+    ///
+    /// let allocate = |client: &str, realm: &str| {
+    ///     let mut bytes = BytesMut::with_capacity(1500);
+    ///     let mut message =
+    ///         MessageWriter::new(Method::Allocate(Kind::Request), &[0u8; 12], &mut bytes);
+    ///     message.append::<ReqeestedTransport>(Transport::UDP);
+    ///     message.append::<UserName>("test");
+    ///     message.append::<Realm>(realm);
+    ///     message
+    ///         .flush(Some(&long_term_credential_digest("test", "test", realm)))
+    ///         .unwrap();
+    ///
+    ///     let mut operationer = service.get_operationer(interface, interface);
+    ///     let res = pollster::block_on(operationer.route(&bytes, client.parse().unwrap()));
+    ///     res.unwrap().unwrap().method
+    /// };
+    ///
+    /// let success = ResponseMethod::Stun(Method::Allocate(Kind::Response));
+    /// let error = ResponseMethod::Stun(Method::Allocate(Kind::Error));
+    ///
+    /// service.rotate_realm("next".to_string(), Duration::from_secs(60));
+    /// assert_eq!(allocate("127.0.0.1:10001", "localhost"), success);
+    /// assert_eq!(allocate("127.0.0.1:10002", "next"), success);
+    ///
+    /// service.rotate_realm("final".to_string(), Duration::ZERO);
+    /// assert_eq!(allocate("127.0.0.1:10003", "next"), error);
+    /// assert_eq!(allocate("127.0.0.1:10004", "final"), success);
+    /// assert_eq!(allocate("127.0.0.1:10005", "unknown"), error);
+    /// ```
+    pub fn rotate_realm(&self, realm: String, grace: Duration) {
+        self.identity.realm.rotate(realm, grace);
+    }
+
     /// Get operationer.
     ///
     /// # Test
@@ -221,16 +1635,32 @@ where
     /// impl Observer for ObserverTest {}
     ///
     /// let addr = "127.0.0.1:8080".parse::<SocketAddr>().unwrap();
-    /// let service = Service::new("test".to_string(), vec![], ObserverTest);
+    /// let service = Service::new("test".to_string(), vec![], ServiceOptions::default(), ObserverTest);
     ///
     /// service.get_operationer(addr, addr);
     /// ```
     pub fn get_operationer(&self, endpoint: SocketAddr, interface: SocketAddr) -> Operationer<T> {
+        let identity = self.listeners.get(&interface).unwrap_or(&self.identity);
         Operationer::new(ServiceContext {
             interfaces: self.interfaces.clone(),
             observer: self.observer.clone(),
             sessions: self.sessions.clone(),
-            realm: self.realm.clone(),
+            options: identity.options.clone(),
+            software: identity.software.clone(),
+            realm: identity.realm.clone(),
+            verify_cache: self.verify_cache.clone(),
+            rate_limiter: self.rate_limiter.clone(),
+            allocation_limiter: self.allocation_limiter.clone(),
+            load_shedder: self.load_shedder.clone(),
+            buffer_pool: self.buffer_pool.clone(),
+            mobility: self.mobility.clone(),
+            relay_cursor: self.relay_cursor.clone(),
+            policies: self.policies.clone(),
+            drains: self.drains.clone(),
+            middleware: self.middleware.clone(),
+            secure: identity.secure,
+            saturated: false,
+            transport: Transport::UDP,
             interface,
             endpoint,
         })
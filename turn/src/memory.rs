@@ -0,0 +1,330 @@
+//! In-memory transport for the tests.
+//!
+//! The transport drives the processors without any socket: the messages of
+//! the clients are routed by an operationer of the interface, and the
+//! responses and the relayed data are delivered to the inboxes of their
+//! targets, the same way the udp server sends them. A test can run a complete
+//! turn session in memory and assert the relayed bytes. Enabled by the
+//! `test-util` feature.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    net::SocketAddr,
+};
+
+use bytes::BytesMut;
+use rand::{thread_rng, Rng};
+use stun::{
+    attribute::{
+        ChannelNumber, ErrorCode, Nonce, Realm, ReqeestedTransport, Transport, UserName,
+        XorPeerAddress, XorRelayedAddress,
+    },
+    util::long_term_credential_digest,
+    Decoder, Kind, MessageWriter, Method, Payload, StunError,
+};
+
+use crate::{Observer, Operationer, Service};
+
+/// The failure of a request made by a helper of the transport.
+#[derive(Debug)]
+pub enum MemoryError {
+    /// The request or the response could not be encoded or decoded.
+    Stun(StunError),
+    /// The request is not answered.
+    Unanswered(Method),
+    /// The request is answered with the error code, see [`ErrorKind`].
+    ///
+    /// [`ErrorKind`]: stun::attribute::ErrorKind
+    Rejected(Method, u16),
+}
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stun(err) => write!(f, "stun: {}", err),
+            Self::Unanswered(method) => write!(f, "unanswered: {:?}", method),
+            // The code is encoded as the class and the number, such as 0x0401,
+            // and printed as the decimal code of the standard, such as 401.
+            Self::Rejected(method, code) => {
+                write!(f, "rejected: {:?} {}", method, (code >> 8) * 100 + (code & 0xFF))
+            }
+        }
+    }
+}
+
+impl std::error::Error for MemoryError {}
+
+impl From<StunError> for MemoryError {
+    fn from(value: StunError) -> Self {
+        Self::Stun(value)
+    }
+}
+
+/// The channel bound by [`MemoryTransport::bind_channel`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelBinding {
+    /// The relayed transport address of the allocation of the client.
+    pub relay: SocketAddr,
+    /// The channel number bound to the peer.
+    pub channel: u16,
+}
+
+/// An in-memory network of one interface of the service.
+///
+/// # Test
+///
+/// ```
+/// use std::net::SocketAddr;
+///
+/// use bytes::BytesMut;
+/// use mycrl_turn::{memory::MemoryTransport, *};
+/// use stun::{Decoder, Kind, MessageWriter, Method, Payload};
+///
+/// #[derive(Clone)]
+/// struct ObserverTest;
+///
+/// impl Observer for ObserverTest {}
+///
+/// let interface = "127.0.0.1:3478".parse::<SocketAddr>().unwrap();
+/// let client = "127.0.0.1:10000".parse::<SocketAddr>().unwrap();
+/// let service = Service::new(
+///     "localhost".to_string(),
+///     vec![interface],
+///     ServiceOptions::default(),
+///     ObserverTest,
+/// );
+///
+/// let mut transport = MemoryTransport::new(&service, interface);
+///
+/// let mut bytes = BytesMut::with_capacity(1500);
+/// MessageWriter::new(Method::Binding(Kind::Request), &[0u8; 12], &mut bytes)
+///     .flush(None)
+///     .unwrap();
+///
+/// pollster::block_on(transport.send(client, &bytes)).unwrap();
+///
+/// let res = transport.recv(&client).unwrap();
+/// let mut decoder = Decoder::default();
+/// if let Payload::Message(message) = decoder.decode(&res).unwrap() {
+///     assert_eq!(message.method, Method::Binding(Kind::Response));
+/// } else {
+///     unreachable!()
+/// }
+///
+/// assert!(transport.recv(&client).is_none());
+/// ```
+pub struct MemoryTransport<T>
+where
+    T: Observer + 'static,
+{
+    operationer: Operationer<T>,
+    inboxes: HashMap<SocketAddr, VecDeque<Vec<u8>>>,
+}
+
+impl<T> MemoryTransport<T>
+where
+    T: Observer + Clone + 'static,
+{
+    pub fn new(service: &Service<T>, interface: SocketAddr) -> Self {
+        Self {
+            operationer: service.get_operationer(interface, interface),
+            inboxes: HashMap::with_capacity(16),
+        }
+    }
+
+    /// Send the bytes from the address to the interface.
+    ///
+    /// The response, if any, is delivered to the inbox of the client, or to
+    /// the inbox of the relayed peer for the relayed data. The delay of the
+    /// response is not waited for.
+    pub async fn send(&mut self, from: SocketAddr, bytes: &[u8]) -> Result<(), StunError> {
+        if let Some(res) = self.operationer.route(bytes, from).await? {
+            let target = res.relay.unwrap_or(from);
+            self.inboxes
+                .entry(target)
+                .or_default()
+                .push_back(res.bytes.to_vec());
+        }
+
+        Ok(())
+    }
+
+    /// Take the oldest message delivered to the address.
+    pub fn recv(&mut self, addr: &SocketAddr) -> Option<Vec<u8>> {
+        self.inboxes.get_mut(addr)?.pop_front()
+    }
+
+    /// Send an authenticated request of the client and take its response,
+    /// the realm and the nonce are taken from the challenge of the first
+    /// request.
+    async fn request(
+        &mut self,
+        client: SocketAddr,
+        method: Method,
+        credentials: (&str, &str),
+        attributes: impl Fn(&mut MessageWriter<'_>),
+    ) -> Result<Option<SocketAddr>, MemoryError> {
+        let mut challenge: Option<(String, String)> = None;
+
+        loop {
+            let token: [u8; 12] = thread_rng().gen();
+            let mut bytes = BytesMut::with_capacity(1500);
+            let mut message = MessageWriter::new(method, &token, &mut bytes);
+            attributes(&mut message);
+
+            let digest = if let Some((realm, nonce)) = &challenge {
+                message.append::<UserName>(credentials.0);
+                message.append::<Realm>(realm);
+                message.append::<Nonce>(nonce);
+                Some(long_term_credential_digest(
+                    credentials.0,
+                    credentials.1,
+                    realm,
+                ))
+            } else {
+                None
+            };
+
+            message.flush(digest.as_ref())?;
+            self.send(client, &bytes).await?;
+
+            let res = self.recv(&client).ok_or(MemoryError::Unanswered(method))?;
+            let mut decoder = Decoder::default();
+            let message = match decoder.decode(&res)? {
+                Payload::Message(it) => it,
+                Payload::ChannelData(_) => return Err(MemoryError::Unanswered(method)),
+            };
+
+            match message.get::<ErrorCode>() {
+                None => return Ok(message.get::<XorRelayedAddress>()),
+                Some(_) if challenge.is_none() => {
+                    let realm = message
+                        .get::<Realm>()
+                        .ok_or(MemoryError::Unanswered(method))?;
+                    let nonce = message
+                        .get::<Nonce>()
+                        .ok_or(MemoryError::Unanswered(method))?;
+                    challenge = Some((realm.to_string(), nonce.to_string()));
+                }
+                Some(it) => return Err(MemoryError::Rejected(method, it.code)),
+            }
+        }
+    }
+
+    /// Allocate a udp relay for the client with the long-term credential of
+    /// the username and the password, returns the relayed transport address.
+    pub async fn allocate(
+        &mut self,
+        client: SocketAddr,
+        credentials: (&str, &str),
+    ) -> Result<SocketAddr, MemoryError> {
+        let method = Method::Allocate(Kind::Request);
+        self.request(client, method, credentials, |message| {
+            message.append::<ReqeestedTransport>(Transport::UDP);
+        })
+        .await?
+        .ok_or(MemoryError::Unanswered(method))
+    }
+
+    /// Allocate a relay for the client, install a permission for the peer
+    /// and bind the channel to it, for the tests of the data path.
+    ///
+    /// The requests go through the processors like the requests of a real
+    /// client, the peer is the relayed transport address of another
+    /// allocation of the interface.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use std::net::SocketAddr;
+    ///
+    /// use mycrl_turn::{memory::*, *};
+    /// use stun::{attribute::ErrorKind, ChannelData, Decoder, Payload};
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(&self, _: &SessionAddr, _: &str) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let interface = "127.0.0.1:3478".parse::<SocketAddr>().unwrap();
+    /// let client = "127.0.0.1:10000".parse::<SocketAddr>().unwrap();
+    /// let peer = "127.0.0.1:10001".parse::<SocketAddr>().unwrap();
+    /// let service = Service::new(
+    ///     "localhost".to_string(),
+    ///     vec![interface],
+    ///     ServiceOptions::default(),
+    ///     ObserverTest,
+    /// );
+    ///
+    /// let mut transport = MemoryTransport::new(&service, interface);
+    /// let peer_relay = pollster::block_on(transport.allocate(peer, ("test", "test"))).unwrap();
+    /// let binding =
+    ///     pollster::block_on(transport.bind_channel(client, ("test", "test"), peer_relay, 0x4000))
+    ///         .unwrap();
+    ///
+    /// assert_eq!(binding.channel, 0x4000);
+    /// assert_eq!(binding.relay.ip(), interface.ip());
+    ///
+    /// // The channel of the client carries the data of the peer.
+    /// let mut bytes = vec![0x40, 0x00, 0x00, 0x05];
+    /// bytes.extend_from_slice(b"hello");
+    /// pollster::block_on(transport.send(peer, &bytes)).unwrap();
+    ///
+    /// let res = transport.recv(&client).unwrap();
+    /// let mut decoder = Decoder::default();
+    /// if let Payload::ChannelData(ChannelData { number, bytes }) = decoder.decode(&res).unwrap() {
+    ///     assert_eq!(number, 0x4000);
+    ///     assert_eq!(bytes, b"hello");
+    /// } else {
+    ///     unreachable!()
+    /// }
+    ///
+    /// // The channel number is out of the range.
+    /// let client = "127.0.0.1:10002".parse::<SocketAddr>().unwrap();
+    /// let res = pollster::block_on(transport.bind_channel(client, ("test", "test"), peer_relay, 1));
+    /// assert!(matches!(
+    ///     res,
+    ///     Err(MemoryError::Rejected(_, code)) if code == ErrorKind::BadRequest as u16
+    /// ));
+    ///
+    /// // The error code is displayed in decimal.
+    /// assert!(res.unwrap_err().to_string().ends_with(" 400"));
+    /// ```
+    pub async fn bind_channel(
+        &mut self,
+        client: SocketAddr,
+        credentials: (&str, &str),
+        peer: SocketAddr,
+        channel: u16,
+    ) -> Result<ChannelBinding, MemoryError> {
+        let relay = self.allocate(client, credentials).await?;
+
+        self.request(
+            client,
+            Method::CreatePermission(Kind::Request),
+            credentials,
+            |message| {
+                message.append::<XorPeerAddress>(peer);
+            },
+        )
+        .await?;
+
+        self.request(
+            client,
+            Method::ChannelBind(Kind::Request),
+            credentials,
+            |message| {
+                message.append::<ChannelNumber>(channel);
+                message.append::<XorPeerAddress>(peer);
+            },
+        )
+        .await?;
+
+        Ok(ChannelBinding { relay, channel })
+    }
+}
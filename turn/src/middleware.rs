@@ -0,0 +1,157 @@
+//! Request processing middlewares.
+//!
+//! A middleware runs around the processors of the stun messages, so that the
+//! vendor specific attributes can be handled without forking the processors.
+//! No middleware is registered by default, in which case the dispatcher skips
+//! the hooks entirely.
+
+use bytes::BytesMut;
+use stun::{MessageReader, Method};
+
+use crate::{operations::Response, SessionAddr};
+
+/// The action of the dispatcher after [`Middleware::before`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Process the message with the standard processor.
+    Continue,
+    /// Skip the processor, the middleware has written the response of this
+    /// method into the bytes.
+    Respond(Method),
+    /// Skip the processor and drop the message without a response.
+    Drop,
+}
+
+/// The hooks invoked by the dispatcher around each processor.
+///
+/// # Test
+///
+/// ```
+/// use std::{
+///     net::SocketAddr,
+///     sync::{Arc, Mutex},
+/// };
+///
+/// use bytes::BytesMut;
+/// use mycrl_turn::{middleware::*, operations::Response, *};
+/// use stun::{
+///     attribute::{ErrorCode, ErrorKind, Error},
+///     Decoder, Kind, MessageReader, MessageWriter, Method, Payload,
+/// };
+///
+/// /// A vendor specific attribute, which is carried by the requests of the
+/// /// clients of the integrator.
+/// const VENDOR_TAG: u16 = 0xc001;
+///
+/// #[derive(Clone)]
+/// struct ObserverTest;
+///
+/// impl Observer for ObserverTest {}
+///
+/// /// Logs the vendor tag and rejects the requests tagged with "blocked".
+/// #[derive(Default)]
+/// struct VendorTagLogger(Mutex<Vec<String>>);
+///
+/// impl Middleware for VendorTagLogger {
+///     fn before(&self, _: &SessionAddr, reader: &MessageReader<'_>, bytes: &mut BytesMut) -> Action {
+///         let tag = match reader.get_raw(VENDOR_TAG) {
+///             Some(it) => String::from_utf8_lossy(it).to_string(),
+///             None => return Action::Continue,
+///         };
+///
+///         self.0.lock().unwrap().push(format!("before: {}", tag));
+///         if tag != "blocked" {
+///             return Action::Continue;
+///         }
+///
+///         let method = Method::Binding(Kind::Error);
+///         let mut message = MessageWriter::extend(method, reader, bytes);
+///         message.append::<ErrorCode>(Error::from(ErrorKind::Forbidden));
+///         message.flush(None).unwrap();
+///         Action::Respond(method)
+///     }
+///
+///     fn after(&self, _: &SessionAddr, reader: &MessageReader<'_>, res: Option<&Response<'_>>) {
+///         if reader.get_raw(VENDOR_TAG).is_some() {
+///             self.0.lock().unwrap().push(format!("after: {:?}", res.map(|it| it.method)));
+///         }
+///     }
+/// }
+///
+/// let logger = Arc::new(VendorTagLogger::default());
+/// let addr = "127.0.0.1:3478".parse::<SocketAddr>().unwrap();
+/// let service = Service::new("test".to_string(), vec![], ServiceOptions::default(), ObserverTest)
+///     .with_middleware(logger.clone());
+///
+/// let binding = |tag: Option<&[u8]>| {
+///     let mut bytes = BytesMut::with_capacity(1500);
+///     let mut message = MessageWriter::new(Method::Binding(Kind::Request), &[0u8; 12], &mut bytes);
+///     message.flush(None).unwrap();
+///
+///     // Append the vendor attribute, which the writer does not know.
+///     if let Some(tag) = tag {
+///         bytes.extend_from_slice(&VENDOR_TAG.to_be_bytes());
+///         bytes.extend_from_slice(&(tag.len() as u16).to_be_bytes());
+///         bytes.extend_from_slice(tag);
+///         bytes.extend_from_slice(&[0u8; 3][..(4 - tag.len() % 4) % 4]);
+///
+///         let size = (bytes.len() - 20) as u16;
+///         bytes[2..4].copy_from_slice(&size.to_be_bytes());
+///     }
+///
+///     let mut operationer = service.get_operationer(addr, addr);
+///     let res = pollster::block_on(operationer.route(&bytes, "127.0.0.1:10000".parse().unwrap()));
+///
+///     let mut decoder = Decoder::default();
+///     if let Payload::Message(message) = decoder.decode(res.unwrap().unwrap().bytes).unwrap() {
+///         (message.method, message.get::<ErrorCode>().map(|it| it.code))
+///     } else {
+///         unreachable!()
+///     }
+/// };
+///
+/// assert_eq!(binding(None), (Method::Binding(Kind::Response), None));
+/// assert_eq!(binding(Some(b"client")), (Method::Binding(Kind::Response), None));
+/// assert_eq!(
+///     binding(Some(b"blocked")),
+///     (Method::Binding(Kind::Error), Some(ErrorKind::Forbidden as u16))
+/// );
+///
+/// assert_eq!(
+///     logger.0.lock().unwrap().as_slice(),
+///     &[
+///         "before: client".to_string(),
+///         "after: Some(Stun(Binding(Response)))".to_string(),
+///         "before: blocked".to_string(),
+///     ]
+/// );
+/// ```
+#[allow(unused)]
+pub trait Middleware: Send + Sync {
+    /// Called before the processor of the message.
+    ///
+    /// The middleware can short-circuit the processor by writing a response
+    /// into the bytes and returning [`Action::Respond`] with the method of
+    /// the response, or drop the message with [`Action::Drop`].
+    fn before(
+        &self,
+        addr: &SessionAddr,
+        reader: &MessageReader<'_>,
+        bytes: &mut BytesMut,
+    ) -> Action {
+        Action::Continue
+    }
+
+    /// Called after the processor with the response of the message, if any.
+    ///
+    /// The response already carries its message integrity, so it is only
+    /// given for inspection. This is not called for the short-circuited
+    /// messages.
+    fn after(
+        &self,
+        addr: &SessionAddr,
+        reader: &MessageReader<'_>,
+        response: Option<&Response<'_>>,
+    ) {
+    }
+}
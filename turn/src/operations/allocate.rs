@@ -1,30 +1,52 @@
-use super::{Requet, Response, ResponseMethod};
-use crate::{Observer, SOFTWARE};
+use super::{ProcessError, Requet, Response, ResponseMethod};
+use crate::{AllocationContext, Observer};
 
 use std::net::SocketAddr;
 
 use stun::{
     attribute::{
-        Error, ErrorCode, ErrorKind, Lifetime, Nonce, Realm, ReqeestedTransport, Software,
+        AlternateServer, Error, ErrorCode, ErrorKind, EvenPort, IpFamily, Lifetime, MobilityTicket,
+        ReqeestedTransport, RequestedAddressFamily, ReservationToken, Software, Transport,
         XorMappedAddress, XorRelayedAddress,
     },
     Kind, MessageReader, MessageWriter, Method,
 };
 
+/// The lifetime of the reserved ports in seconds, which is the recommended
+/// value of RFC 8656.
+const RESERVATION_LIFETIME: u64 = 30;
+
 /// return allocate error response
 #[inline(always)]
 fn reject<'a, T: Observer>(
     req: Requet<'_, 'a, T, MessageReader<'_>>,
-    err: ErrorKind,
+    err: ProcessError,
+) -> Option<Response<'a>> {
+    super::reject(req, Method::Allocate(Kind::Error), err)
+}
+
+/// return allocate try alternate response
+///
+/// The redirect of a drained listener carries the message integrity, so
+/// that the client can trust the alternate server.
+#[inline(always)]
+fn redirect<'a, T: Observer>(
+    req: Requet<'_, 'a, T, MessageReader<'_>>,
+    digest: &[u8; 16],
+    alternate: SocketAddr,
 ) -> Option<Response<'a>> {
+    log::info!(
+        "request redirected: addr={:?}, alternate={:?}",
+        req.address,
+        alternate
+    );
+
     {
         let mut message =
             MessageWriter::extend(Method::Allocate(Kind::Error), req.message, req.bytes);
-
-        message.append::<ErrorCode>(Error::from(err));
-        message.append::<Nonce>(&req.service.sessions.get_nonce(&req.address).get_ref()?.0);
-        message.append::<Realm>(&req.service.realm);
-        message.flush(None).ok()?;
+        message.append::<ErrorCode>(Error::from(ErrorKind::TryAlternate));
+        message.append::<AlternateServer>(alternate);
+        message.flush(Some(digest)).ok()?;
     }
 
     Some(Response {
@@ -32,6 +54,7 @@ fn reject<'a, T: Observer>(
         bytes: req.bytes,
         endpoint: None,
         relay: None,
+        delay: None,
     })
 }
 
@@ -49,16 +72,33 @@ fn reject<'a, T: Observer>(
 fn resolve<'a, T: Observer>(
     req: Requet<'_, 'a, T, MessageReader<'_>>,
     digest: &[u8; 16],
-    port: u16,
+    relay: SocketAddr,
+    lifetime: u32,
+    reservation: Option<u64>,
 ) -> Option<Response<'a>> {
     {
         let mut message =
             MessageWriter::extend(Method::Allocate(Kind::Response), req.message, req.bytes);
 
-        message.append::<XorRelayedAddress>(SocketAddr::new(req.service.interface.ip(), port));
+        message.append::<XorRelayedAddress>(relay);
         message.append::<XorMappedAddress>(req.address.address);
-        message.append::<Lifetime>(600);
-        message.append::<Software>(SOFTWARE);
+        message.append::<Lifetime>(lifetime);
+        if let Some(token) = reservation {
+            message.append::<ReservationToken>(token);
+        }
+
+        // The client asks for mobility with an empty ticket, the ticket is
+        // not returned if the service does not offer mobility.
+        let ticket = match (&req.service.mobility, req.message.get::<MobilityTicket>()) {
+            (Some(tickets), Some(_)) => Some(tickets.issue(req.address)),
+            _ => None,
+        };
+
+        if let Some(ticket) = &ticket {
+            message.append::<MobilityTicket>(ticket);
+        }
+
+        message.append::<Software>(&req.service.software);
         message.flush(Some(digest)).ok()?;
     }
 
@@ -67,6 +107,7 @@ fn resolve<'a, T: Observer>(
         bytes: req.bytes,
         endpoint: None,
         relay: None,
+        delay: None,
     })
 }
 
@@ -86,23 +127,267 @@ fn resolve<'a, T: Observer>(
 /// server SHOULD NOT allocate ports in the range 0 - 1023 (the Well-
 /// Known Port range) to discourage clients from using TURN to run
 /// standard services.
+///
+/// If the 5-tuple is already in use by an existing allocation, the server
+/// rejects the request with a 437 (Allocation Mismatch) error, if the family
+/// of the relay is not enabled or not the requested family, with a 440
+/// (Address Family not Supported) error, and if no port is left to allocate,
+/// with a 486 (Allocation Quota Reached) error.
+///
+/// With the EVEN-PORT attribute an even port is allocated, and if the R bit
+/// is set the next port is held in reserve for 30 seconds, the token of the
+/// reservation is returned in the RESERVATION-TOKEN attribute. A subsequent
+/// allocate request with the token is given the reserved port. The request
+/// is rejected with a 508 (Insufficient Capacity) error if no even port is
+/// left, or if the token is unknown or has expired.
+///
+/// The lifetime of the allocation is the lifetime requested in the LIFETIME
+/// attribute, which is raised to the default lifetime of 600 seconds and
+/// capped at the maximum lifetime of 3600 seconds.
+///
+/// # Test
+///
+/// ```
+/// use std::net::SocketAddr;
+///
+/// use bytes::BytesMut;
+/// use mycrl_turn::*;
+/// use stun::{
+///     attribute::{ErrorCode, ErrorKind, Lifetime, ReqeestedTransport, Transport, UserName, Realm},
+///     util::long_term_credential_digest,
+///     Decoder, Kind, MessageWriter, Method, Payload,
+/// };
+///
+/// #[derive(Clone)]
+/// struct ObserverTest;
+///
+/// impl Observer for ObserverTest {
+///     async fn get_password(&self, _: &SessionAddr, _: &str) -> Option<String> {
+///         Some("test".to_string())
+///     }
+/// }
+///
+/// let interface = "127.0.0.1:3478".parse::<SocketAddr>().unwrap();
+/// let service = Service::new(
+///     "localhost".to_string(),
+///     vec![interface],
+///     ServiceOptions::default(),
+///     ObserverTest,
+/// );
+///
+/// let digest = long_term_credential_digest("test", "test", "localhost");
+/// let mut operationer = service.get_operationer(interface, interface);
+/// let mut allocate = |client: SocketAddr, lifetime: Option<u32>| {
+///     let mut bytes = BytesMut::with_capacity(1500);
+///     let mut message =
+///         MessageWriter::new(Method::Allocate(Kind::Request), &[0u8; 12], &mut bytes);
+///     message.append::<ReqeestedTransport>(Transport::UDP);
+///     if let Some(lifetime) = lifetime {
+///         message.append::<Lifetime>(lifetime);
+///     }
+///
+///     message.append::<UserName>("test");
+///     message.append::<Realm>("localhost");
+///     message.flush(Some(&digest)).unwrap();
+///
+///     let res = pollster::block_on(operationer.route(&bytes, client));
+///     let mut decoder = Decoder::default();
+///     if let Payload::Message(message) = decoder.decode(res.unwrap().unwrap().bytes).unwrap() {
+///         (
+///             message.method,
+///             message.get::<ErrorCode>().map(|it| it.code),
+///             message.get::<Lifetime>(),
+///         )
+///     } else {
+///         unreachable!()
+///     }
+/// };
+///
+/// let client = "127.0.0.1:10000".parse().unwrap();
+/// assert_eq!(
+///     allocate(client, Some(1200)),
+///     (Method::Allocate(Kind::Response), None, Some(1200))
+/// );
+///
+/// // The 5-tuple already has an allocation.
+/// assert_eq!(
+///     allocate(client, None),
+///     (Method::Allocate(Kind::Error), Some(ErrorKind::AllocationMismatch as u16), None)
+/// );
+///
+/// // Allocate until the pool is exhausted, the lifetime is kept in range.
+/// assert_eq!(
+///     allocate("127.0.0.1:10001".parse().unwrap(), Some(7200)),
+///     (Method::Allocate(Kind::Response), None, Some(3600))
+/// );
+///
+/// let mut port = 10002;
+/// let code = loop {
+///     let client = SocketAddr::new(client.ip(), port);
+///     match allocate(client, None) {
+///         (Method::Allocate(Kind::Response), None, lifetime) => assert_eq!(lifetime, Some(600)),
+///         (_, code, _) => break code,
+///     }
+///
+///     port += 1;
+/// };
+///
+/// assert_eq!(code, Some(ErrorKind::AllocationQuotaReached as u16));
+///
+/// // The refresh is clamped to the same bounds, the zero lifetime deletes.
+/// let mut refresh = |lifetime: u32| {
+///     let mut bytes = BytesMut::with_capacity(1500);
+///     let mut message =
+///         MessageWriter::new(Method::Refresh(Kind::Request), &[0u8; 12], &mut bytes);
+///     message.append::<Lifetime>(lifetime);
+///     message.append::<UserName>("test");
+///     message.append::<Realm>("localhost");
+///     message.flush(Some(&digest)).unwrap();
+///
+///     let res = pollster::block_on(operationer.route(&bytes, client));
+///     let mut decoder = Decoder::default();
+///     if let Payload::Message(message) = decoder.decode(res.unwrap().unwrap().bytes).unwrap() {
+///         (message.method, message.get::<Lifetime>())
+///     } else {
+///         unreachable!()
+///     }
+/// };
+///
+/// assert_eq!(refresh(1), (Method::Refresh(Kind::Response), Some(600)));
+/// assert_eq!(refresh(u32::MAX), (Method::Refresh(Kind::Response), Some(3600)));
+/// assert_eq!(refresh(0), (Method::Refresh(Kind::Response), Some(0)));
+/// ```
 pub async fn process<'a, T: Observer>(
     req: Requet<'_, 'a, T, MessageReader<'_>>,
 ) -> Option<Response<'a>> {
     if req.message.get::<ReqeestedTransport>().is_none() {
-        return reject(req, ErrorKind::ServerError);
+        return reject(req, ProcessError::Parse(ErrorKind::ServerError));
+    }
+
+    // The relaying is only offered over the encrypted transports, the client
+    // is told so before the challenge, there is no point in authenticating.
+    if req.service.options.allocate_require_secure && !req.service.secure {
+        return reject(req, ProcessError::Policy(ErrorKind::Forbidden));
+    }
+
+    // The new allocations are shed before the password lookup, which is the
+    // work that falls behind under load.
+    if req.service.is_overloaded() {
+        return reject(req, ProcessError::Capacity(ErrorKind::InsufficientCapacity));
     }
 
     let (username, digest) = match req.auth().await {
-        Some(it) => it,
-        None => return reject(req, ErrorKind::Unauthorized),
+        Ok(it) => it,
+        Err(err) => {
+            let delay = req.auth_failure_delay(&err);
+            return reject(req, err).map(|it| it.with_delay(delay));
+        }
+    };
+
+    let allocated = req
+        .service
+        .sessions
+        .get_session(req.address)
+        .get_ref()
+        .map(|it| it.allocate.port.is_some())
+        .unwrap_or(false);
+
+    if allocated {
+        return reject(req, ProcessError::Policy(ErrorKind::AllocationMismatch));
+    }
+
+    // The drained listener takes no new allocations, the client is sent to the
+    // alternate server after the authentication, so the redirect is trusted.
+    match req.service.get_drain() {
+        Some(Some(alternate)) => return redirect(req, &digest, alternate),
+        Some(None) => return reject(req, ProcessError::Capacity(ErrorKind::InsufficientCapacity)),
+        None => (),
+    }
+
+    // The TCP allocations of RFC 6062 are not supported, the relays are always
+    // UDP, so the client asking for a TCP relay is told so instead of getting
+    // a relay that cannot reach its TCP peers.
+    if req.message.get::<ReqeestedTransport>() != Some(Transport::UDP) {
+        return reject(
+            req,
+            ProcessError::Policy(ErrorKind::UnsupportedTransportAddress),
+        );
+    }
+
+    // The relay is allocated on the interface of the request, so the family
+    // of the interface is the only family that can be allocated.
+    let interface = req.service.interface.ip();
+    let family = if interface.is_ipv4() {
+        IpFamily::V4
+    } else {
+        IpFamily::V6
     };
 
-    let port = match req.service.sessions.allocate(req.address) {
-        Some(it) => it,
-        None => return reject(req, ErrorKind::AllocationQuotaReached),
+    if !req.service.options.relay_family.is_enabled(&interface)
+        || req
+            .message
+            .get::<RequestedAddressFamily>()
+            .map(|it| it != family)
+            .unwrap_or(false)
+    {
+        return reject(
+            req,
+            ProcessError::Policy(ErrorKind::AddressFamilyNotSupported),
+        );
+    }
+
+    // The allocation storms are shed before any port is taken, the token is
+    // only consumed by the allocations that would otherwise succeed so far.
+    if !req.service.is_allocation_allowed() {
+        return reject(req, ProcessError::Capacity(ErrorKind::InsufficientCapacity));
+    }
+
+    // The reserved port of the token, or an even port with the next port
+    // optionally held in reserve, cannot be requested together.
+    let token = req.message.get::<ReservationToken>();
+    let even_port = req.message.get::<EvenPort>();
+    let sessions = &req.service.sessions;
+    let (port, reservation) = match (token, even_port) {
+        (Some(_), Some(_)) => return reject(req, ProcessError::Parse(ErrorKind::BadRequest)),
+        (Some(token), None) => match sessions.allocate_reserved(req.address, token) {
+            Some(it) => (it, None),
+            None => return reject(req, ProcessError::Capacity(ErrorKind::InsufficientCapacity)),
+        },
+        (None, Some(reserve)) => {
+            match sessions.allocate_even(req.address, reserve.then_some(RESERVATION_LIFETIME)) {
+                Some(it) => it,
+                None => {
+                    return reject(req, ProcessError::Capacity(ErrorKind::InsufficientCapacity))
+                }
+            }
+        }
+        (None, None) => match sessions.allocate(req.address) {
+            Some(it) => (it, None),
+            None => {
+                return reject(
+                    req,
+                    ProcessError::Capacity(ErrorKind::AllocationQuotaReached),
+                )
+            }
+        },
     };
 
-    req.service.observer.allocated(&req.address, username, port);
-    resolve(req, &digest, port)
+    let lifetime = req
+        .message
+        .get::<Lifetime>()
+        .unwrap_or(600)
+        .clamp(600, 3600);
+
+    if lifetime != 600 {
+        req.service.sessions.refresh(req.address, lifetime);
+    }
+
+    let relay = SocketAddr::new(req.service.next_relay_ip(username), port);
+    req.service.sessions.set_relay(req.address, relay);
+
+    let allocation = AllocationContext { relay, lifetime };
+    req.service
+        .observer
+        .allocated(req.address, username, port, &allocation);
+    resolve(req, &digest, relay, lifetime, reservation)
 }
@@ -1,11 +1,57 @@
-use super::{Requet, Response, ResponseMethod};
-use crate::{Observer, SOFTWARE};
+use std::net::SocketAddr;
+
+use super::{ProcessError, Requet, Response, ResponseMethod};
+use crate::Observer;
 
 use stun::{
-    attribute::{MappedAddress, ResponseOrigin, Software, XorMappedAddress},
+    attribute::{
+        AttrKind, Change, ChangeRequest, ErrorKind, MappedAddress, OtherAddress, ResponseOrigin,
+        ResponsePort, Software, XorMappedAddress,
+    },
     Kind, MessageReader, MessageWriter, Method,
 };
 
+/// return binding error response
+#[inline(always)]
+fn reject<'a, T: Observer>(
+    req: Requet<'_, 'a, T, MessageReader<'_>>,
+    err: ProcessError,
+) -> Option<Response<'a>> {
+    super::reject(req, Method::Binding(Kind::Error), err)
+}
+
+/// Get the interface that the response of the change request is sent from.
+///
+/// The changed ip is taken from the alternate address of the interface, and
+/// the changed port too, so the four combinations map to the four addresses
+/// of the RFC 5780 setup. The change is only possible on the datagram
+/// listeners, a connection cannot be answered from another socket.
+fn changed_origin<T: Observer>(
+    req: &Requet<'_, '_, T, MessageReader<'_>>,
+    change: Change,
+) -> Option<SocketAddr> {
+    let interface = req.service.interface;
+    if req.service.endpoint != interface {
+        return None;
+    }
+
+    let other = req.service.options.other_addresses.get(&interface)?;
+    let origin = SocketAddr::new(
+        if change.ip {
+            other.ip()
+        } else {
+            interface.ip()
+        },
+        if change.port {
+            other.port()
+        } else {
+            interface.port()
+        },
+    );
+
+    req.service.interfaces.contains(&origin).then_some(origin)
+}
+
 /// process binding request
 ///
 /// [rfc8489](https://tools.ietf.org/html/rfc8489)
@@ -28,15 +74,255 @@ use stun::{
 /// attribute within the body of the STUN response will remain untouched.
 /// In this way, the client can learn its reflexive transport address
 /// allocated by the outermost NAT with respect to the STUN server.
-pub fn process<'a, T: Observer>(req: Requet<'_, 'a, T, MessageReader<'_>>) -> Option<Response<'a>> {
+///
+/// Binding requests do not require credentials, unless the service is
+/// configured to require authentication for binding requests, in which case
+/// the response is also signed with the message integrity.
+///
+/// The response of a request with the RESPONSE-PORT attribute of RFC 5780 is
+/// sent to the ip address of the client at the requested port, the port 0 or
+/// a malformed attribute is rejected with a 400 (Bad Request) error. The
+/// connections ignore the attribute, the response is written to the
+/// connection.
+///
+/// # Test
+///
+/// The address is XORed with the magic cookie and, for the IPv6 addresses,
+/// with the transaction id of the request, a response that used another
+/// transaction id would decode to a garbage address at the client.
+///
+/// ```
+/// use std::net::SocketAddr;
+///
+/// use bytes::BytesMut;
+/// use mycrl_turn::*;
+/// use stun::{
+///     attribute::XorMappedAddress,
+///     Decoder, Kind, MessageWriter, Method, Payload,
+/// };
+///
+/// #[derive(Clone)]
+/// struct ObserverTest;
+///
+/// impl Observer for ObserverTest {}
+///
+/// let token = [
+///     0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6, 0x86, 0xfa, 0x87, 0xdf, 0xae,
+/// ];
+///
+/// let addrs = [
+///     ("127.0.0.1:3478", "192.0.2.1:32853"),
+///     ("[::1]:3478", "[2001:db8::1]:32853"),
+/// ];
+///
+/// for (interface, client) in addrs {
+///     let interface = interface.parse::<SocketAddr>().unwrap();
+///     let client = client.parse::<SocketAddr>().unwrap();
+///     let service = Service::new("test".to_string(), vec![], ServiceOptions::default(), ObserverTest);
+///     let mut operationer = service.get_operationer(interface, interface);
+///
+///     let mut bytes = BytesMut::with_capacity(1500);
+///     MessageWriter::new(Method::Binding(Kind::Request), &token, &mut bytes)
+///         .flush(None)
+///         .unwrap();
+///
+///     let res = pollster::block_on(operationer.route(&bytes, client)).unwrap().unwrap();
+///
+///     let mut decoder = Decoder::default();
+///     let message = match decoder.decode(res.bytes).unwrap() {
+///         Payload::Message(it) => it,
+///         _ => unreachable!(),
+///     };
+///
+///     assert_eq!(message.token, &token);
+///     assert_eq!(message.get::<XorMappedAddress>(), Some(client));
+///
+///     // Undo the XOR by hand, independently of the attribute decoder.
+///     let raw = message.get_raw(0x0020).unwrap();
+///     let mut key = vec![0x21, 0x12, 0xa4, 0x42];
+///     key.extend_from_slice(&token);
+///
+///     let port = u16::from_be_bytes([raw[2], raw[3]]) ^ 0x2112;
+///     let ip = raw[4..].iter().zip(&key).map(|(a, b)| a ^ b).collect::<Vec<_>>();
+///     let ip = match client {
+///         SocketAddr::V4(_) => <[u8; 4]>::try_from(ip).unwrap().into(),
+///         SocketAddr::V6(_) => <[u8; 16]>::try_from(ip).unwrap().into(),
+///     };
+///
+///     assert_eq!(SocketAddr::new(ip, port), client);
+/// }
+/// ```
+pub async fn process<'a, T: Observer>(
+    req: Requet<'_, 'a, T, MessageReader<'_>>,
+) -> Option<Response<'a>> {
+    let digest = if req.service.options.binding_require_auth {
+        match req.auth().await {
+            Ok((_, digest)) => Some(digest),
+            Err(err) => {
+                let delay = req.auth_failure_delay(&err);
+                return reject(req, err).map(|it| it.with_delay(delay));
+            }
+        }
+    } else {
+        None
+    };
+
+    // The change request of the RFC 5780 behavior discovery, the response is
+    // sent from the changed address, through the socket of that interface.
+    let change = req.message.get::<ChangeRequest>().unwrap_or_default();
+    let origin = if change.ip || change.port {
+        match changed_origin(&req, change) {
+            Some(it) => it,
+            None => return reject(req, ProcessError::Parse(ErrorKind::UnknownAttribute)),
+        }
+    } else {
+        req.service.interface
+    };
+
+    let target = match req.message.get::<ResponsePort>() {
+        Some(0) => return reject(req, ProcessError::Parse(ErrorKind::BadRequest)),
+        Some(port) => SocketAddr::new(req.address.address.ip(), port),
+        None if req.message.get_raw(AttrKind::ResponsePort as u16).is_some() => {
+            return reject(req, ProcessError::Parse(ErrorKind::BadRequest))
+        }
+        None => req.address.address,
+    };
+
     {
         let mut message =
-            MessageWriter::extend(Method::Binding(Kind::Response), &req.message, req.bytes);
+            MessageWriter::extend(Method::Binding(Kind::Response), req.message, req.bytes);
 
         message.append::<XorMappedAddress>(req.address.address);
         message.append::<MappedAddress>(req.address.address);
-        message.append::<ResponseOrigin>(req.service.interface);
-        message.append::<Software>(SOFTWARE);
+        message.append::<ResponseOrigin>(origin);
+
+        if let Some(other) = req
+            .service
+            .options
+            .other_addresses
+            .get(&req.service.interface)
+        {
+            message.append::<OtherAddress>(*other);
+        }
+
+        // Some clients don't want the software version, so the operator can
+        // choose to only reply it when the client sends it.
+        if !req.service.options.echo_software || req.message.get::<Software>().is_some() {
+            message.append::<Software>(&req.service.software);
+        }
+
+        message.flush(digest.as_ref()).ok()?;
+    }
+
+    let (endpoint, relay) = if origin != req.service.interface {
+        (Some(origin), Some(target))
+    } else if target != req.address.address {
+        (None, Some(target))
+    } else {
+        (None, None)
+    };
+
+    Some(Response {
+        method: ResponseMethod::Stun(Method::Binding(Kind::Response)),
+        bytes: req.bytes,
+        endpoint,
+        relay,
+        delay: None,
+    })
+}
+
+/// process classic binding request
+///
+/// [rfc3489](https://tools.ietf.org/html/rfc3489)
+///
+/// The classic stun clients do not understand the XOR-MAPPED-ADDRESS
+/// attribute, so the response echoes the 128 bits transaction id of the
+/// request and only carries the MAPPED-ADDRESS attribute. The classic
+/// clients cannot authenticate with the long-term credential, so the
+/// requests are dropped when binding requests require authentication.
+///
+/// # Test
+///
+/// ```
+/// use std::net::SocketAddr;
+///
+/// use mycrl_turn::*;
+/// use stun::{
+///     attribute::{MappedAddress, XorMappedAddress},
+///     Decoder, Kind, Method, Payload,
+/// };
+///
+/// #[derive(Clone)]
+/// struct ObserverTest;
+///
+/// impl Observer for ObserverTest {}
+///
+/// let addr = "127.0.0.1:3478".parse::<SocketAddr>().unwrap();
+/// let client = "127.0.0.1:8080".parse::<SocketAddr>().unwrap();
+///
+/// let legacy = [
+///     0x00, 0x01, 0x00, 0x00, 0x01, 0x02, 0x03, 0x04, 0x71, 0x66, 0x46, 0x31,
+///     0x2b, 0x59, 0x79, 0x65, 0x56, 0x69, 0x32, 0x72,
+/// ];
+///
+/// let service = Service::new("test".to_string(), vec![], ServiceOptions::default(), ObserverTest);
+/// let mut operationer = service.get_operationer(addr, addr);
+/// assert!(pollster::block_on(operationer.route(&legacy, client)).unwrap().is_none());
+///
+/// let service = Service::new(
+///     "test".to_string(),
+///     vec![],
+///     ServiceOptions {
+///         legacy_binding: true,
+///         ..Default::default()
+///     },
+///     ObserverTest,
+/// );
+///
+/// let mut operationer = service.get_operationer(addr, addr);
+/// let res = pollster::block_on(operationer.route(&legacy, client)).unwrap().unwrap();
+///
+/// // The response echoes the whole transaction id, in place of the magic cookie.
+/// assert_eq!(&res.bytes[..2], &[0x01, 0x01]);
+/// assert_eq!(&res.bytes[4..20], &legacy[4..20]);
+///
+/// // The attributes are checked on a copy with the magic cookie.
+/// let mut bytes = res.bytes.to_vec();
+/// bytes[4..8].copy_from_slice(&[0x21, 0x12, 0xa4, 0x42]);
+///
+/// let mut decoder = Decoder::default();
+/// if let Payload::Message(message) = decoder.decode(&bytes).unwrap() {
+///     assert_eq!(message.method, Method::Binding(Kind::Response));
+///     assert_eq!(message.get::<MappedAddress>(), Some(client));
+///     assert!(message.get::<XorMappedAddress>().is_none());
+/// } else {
+///     unreachable!()
+/// }
+///
+/// // The modern clients are answered as before.
+/// let binding = [
+///     0x00, 0x01, 0x00, 0x00, 0x21, 0x12, 0xa4, 0x42, 0x71, 0x66, 0x46, 0x31,
+///     0x2b, 0x59, 0x79, 0x65, 0x56, 0x69, 0x32, 0x72,
+/// ];
+///
+/// let res = pollster::block_on(operationer.route(&binding, client)).unwrap().unwrap();
+/// let mut decoder = Decoder::default();
+/// if let Payload::Message(message) = decoder.decode(res.bytes).unwrap() {
+///     assert_eq!(message.get::<XorMappedAddress>(), Some(client));
+/// } else {
+///     unreachable!()
+/// }
+/// ```
+pub fn legacy<'a, T: Observer>(req: Requet<'_, 'a, T, [u8; 16]>) -> Option<Response<'a>> {
+    if req.service.options.binding_require_auth {
+        return None;
+    }
+
+    {
+        let mut message =
+            MessageWriter::legacy(Method::Binding(Kind::Response), req.message, req.bytes);
+
+        message.append::<MappedAddress>(req.address.address);
         message.flush(None).ok()?;
     }
 
@@ -45,5 +331,6 @@ pub fn process<'a, T: Observer>(req: Requet<'_, 'a, T, MessageReader<'_>>) -> Op
         bytes: req.bytes,
         endpoint: None,
         relay: None,
+        delay: None,
     })
 }
@@ -1,8 +1,8 @@
-use super::{Requet, Response, ResponseMethod};
-use crate::Observer;
+use super::{ProcessError, Requet, Response, ResponseMethod};
+use crate::{AuditKind, Observer};
 
 use stun::{
-    attribute::{ChannelNumber, Error, ErrorCode, ErrorKind, Realm, XorPeerAddress},
+    attribute::{ChannelNumber, ErrorKind, XorPeerAddress},
     Kind, MessageReader, MessageWriter, Method,
 };
 
@@ -10,23 +10,9 @@ use stun::{
 #[inline(always)]
 fn reject<'a, T: Observer>(
     req: Requet<'_, 'a, T, MessageReader<'_>>,
-    err: ErrorKind,
+    err: ProcessError,
 ) -> Option<Response<'a>> {
-    {
-        let mut message =
-            MessageWriter::extend(Method::ChannelBind(Kind::Error), req.message, req.bytes);
-
-        message.append::<ErrorCode>(Error::from(err));
-        message.append::<Realm>(&req.service.realm);
-        message.flush(None).ok()?;
-    }
-
-    Some(Response {
-        method: ResponseMethod::Stun(Method::ChannelBind(Kind::Error)),
-        bytes: req.bytes,
-        endpoint: None,
-        relay: None,
-    })
+    super::reject(req, Method::ChannelBind(Kind::Error), err)
 }
 
 /// return channel binding ok response
@@ -46,6 +32,7 @@ fn resolve<'a, T: Observer>(
         bytes: req.bytes,
         endpoint: None,
         relay: None,
+        delay: None,
     })
 }
 
@@ -79,42 +66,196 @@ fn resolve<'a, T: Observer>(
 /// different channel, eliminating the possibility that the
 /// transaction would initially fail but succeed on a
 /// retransmission.
+///
+/// The channel bind requests over
+/// [`ServiceOptions::max_channels`](crate::ServiceOptions::max_channels)
+/// are rejected with a 508 (Insufficient Capacity) error, the refreshes of
+/// the existing bindings are not. A channel number bound to a different
+/// peer, or a peer bound to a different channel number, is rejected with a
+/// 400 (Bad Request) error.
+///
+/// # Test
+///
+/// ```
+/// use std::net::SocketAddr;
+///
+/// use bytes::BytesMut;
+/// use mycrl_turn::*;
+/// use stun::{
+///     attribute::*,
+///     util::long_term_credential_digest,
+///     Decoder, Kind, MessageWriter, Method, Payload,
+/// };
+///
+/// #[derive(Clone)]
+/// struct ObserverTest;
+///
+/// impl Observer for ObserverTest {
+///     async fn get_password(&self, _: &SessionAddr, _: &str) -> Option<String> {
+///         Some("test".to_string())
+///     }
+/// }
+///
+/// let interface = "127.0.0.1:3478".parse::<SocketAddr>().unwrap();
+/// let service = Service::new(
+///     "localhost".to_string(),
+///     vec![interface],
+///     ServiceOptions {
+///         max_channels: Some(2),
+///         ..Default::default()
+///     },
+///     ObserverTest,
+/// );
+///
+/// let digest = long_term_credential_digest("test", "test", "localhost");
+/// let mut operationer = service.get_operationer(interface, interface);
+/// let mut request = |client: &str, method: Method, channel: Option<(u16, SocketAddr)>| {
+///     let mut bytes = BytesMut::with_capacity(1500);
+///     let mut message = MessageWriter::new(method, &[0u8; 12], &mut bytes);
+///     message.append::<ReqeestedTransport>(Transport::UDP);
+///     if let Some((number, peer)) = channel {
+///         message.append::<ChannelNumber>(number);
+///         message.append::<XorPeerAddress>(peer);
+///     }
+///
+///     message.append::<UserName>("test");
+///     message.append::<Realm>("localhost");
+///     message.flush(Some(&digest)).unwrap();
+///
+///     let res = pollster::block_on(operationer.route(&bytes, client.parse().unwrap()));
+///     let mut decoder = Decoder::default();
+///     if let Payload::Message(message) = decoder.decode(res.unwrap().unwrap().bytes).unwrap() {
+///         (
+///             message.get::<XorRelayedAddress>(),
+///             message.get::<ErrorCode>().map(|it| it.code),
+///         )
+///     } else {
+///         unreachable!()
+///     }
+/// };
+///
+/// request("127.0.0.1:10000", Method::Allocate(Kind::Request), None);
+/// let peers = ["127.0.0.1:10001", "127.0.0.1:10002", "127.0.0.1:10003"]
+///     .map(|client| request(client, Method::Allocate(Kind::Request), None).0.unwrap());
+///
+/// let channel_bind = Method::ChannelBind(Kind::Request);
+/// for (number, peer) in [(0x4000, peers[0]), (0x4001, peers[1])] {
+///     let (_, err) = request("127.0.0.1:10000", channel_bind, Some((number, peer)));
+///     assert_eq!(err, None);
+/// }
+///
+/// // The refresh of a binding is not limited by the capacity.
+/// let (_, err) = request("127.0.0.1:10000", channel_bind, Some((0x4000, peers[0])));
+/// assert_eq!(err, None);
+///
+/// let (_, err) = request("127.0.0.1:10000", channel_bind, Some((0x4002, peers[2])));
+/// assert_eq!(err, Some(ErrorKind::InsufficientCapacity as u16));
+///
+/// // The channel is bound to another peer, the peer to another channel.
+/// for (number, peer) in [(0x4000, peers[1]), (0x4002, peers[0])] {
+///     let (_, err) = request("127.0.0.1:10000", channel_bind, Some((number, peer)));
+///     assert_eq!(err, Some(ErrorKind::BadRequest as u16));
+/// }
+/// ```
 pub async fn process<'a, T: Observer>(
     req: Requet<'_, 'a, T, MessageReader<'_>>,
 ) -> Option<Response<'a>> {
     let peer = match req.message.get::<XorPeerAddress>() {
-        None => return reject(req, ErrorKind::BadRequest),
-        Some(it) => it,
+        None => return reject(req, ProcessError::Parse(ErrorKind::BadRequest)),
+        Some(it) => req.get_peer_address(it),
     };
 
+    if !req.verify_peer_family(&peer) {
+        return reject(
+            req,
+            ProcessError::Policy(ErrorKind::PeerAddressFamilyMismatch),
+        );
+    }
+
     if !req.verify_ip(&peer) {
-        return reject(req, ErrorKind::PeerAddressFamilyMismatch);
+        return reject(req, ProcessError::Policy(ErrorKind::Forbidden));
     }
 
     let number = match req.message.get::<ChannelNumber>() {
-        None => return reject(req, ErrorKind::BadRequest),
+        None => return reject(req, ProcessError::Parse(ErrorKind::BadRequest)),
         Some(it) => it,
     };
 
     if !(0x4000..=0x7FFF).contains(&number) {
-        return reject(req, ErrorKind::BadRequest);
+        return reject(req, ProcessError::Parse(ErrorKind::BadRequest));
     }
 
     let (username, digest) = match req.auth().await {
-        None => return reject(req, ErrorKind::Unauthorized),
-        Some(it) => it,
+        Err(err) => {
+            let delay = req.auth_failure_delay(&err);
+            return reject(req, err).map(|it| it.with_delay(delay));
+        }
+        Ok(it) => it,
     };
 
+    if !req.verify_peer_policy(&peer) || !req.authorize_peer(&peer).await {
+        return reject(req, ProcessError::Policy(ErrorKind::Forbidden));
+    }
+
     if !req
         .service
         .sessions
-        .bind_channel(&req.address, &req.service.endpoint, peer.port(), number)
+        .verify_channel(req.address, peer.port(), number)
     {
-        return reject(req, ErrorKind::Forbidden);
+        return reject(req, ProcessError::Parse(ErrorKind::BadRequest));
+    }
+
+    if let Some(max) = req.service.options.max_channels {
+        let (bound, refresh) = req
+            .service
+            .sessions
+            .get_session(req.address)
+            .get_ref()
+            .map(|it| {
+                (
+                    it.allocate.channels.len(),
+                    it.allocate.channels.contains(&number),
+                )
+            })
+            .unwrap_or((0, false));
+
+        if bound >= max && !refresh {
+            return reject(req, ProcessError::Capacity(ErrorKind::InsufficientCapacity));
+        }
+    }
+
+    // A new channel number and a new peer are each a relay path of the whole
+    // server, the bound channel is refreshed at any count.
+    let bound = req
+        .service
+        .sessions
+        .get_session(req.address)
+        .get_ref()
+        .map(|it| it.allocate.channels.contains(&number))
+        .unwrap_or(false);
+
+    let new =
+        (!bound) as usize + (!req.service.sessions.has_permission(req.address, &peer)) as usize;
+    if !req.service.reserve_relay_paths(new) {
+        return reject(req, ProcessError::Capacity(ErrorKind::InsufficientCapacity));
+    }
+
+    let bound = req
+        .service
+        .sessions
+        .bind_channel(req.address, &req.service.endpoint, peer.port(), number);
+
+    req.service.release_relay_paths(new);
+    if !bound {
+        return reject(req, ProcessError::Policy(ErrorKind::Forbidden));
+    }
+
+    if let Some(allocation) = req.service.sessions.get_allocation(req.address) {
+        req.service
+            .observer
+            .channel_bind(req.address, username, number, &allocation);
+        req.audit(AuditKind::Channel(number), allocation.relay, peer);
     }
 
-    req.service
-        .observer
-        .channel_bind(&req.address, username, number);
     resolve(req, &digest)
 }
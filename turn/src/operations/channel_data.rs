@@ -1,7 +1,49 @@
 use super::{Requet, Response, ResponseMethod};
 use crate::Observer;
 
-use stun::ChannelData;
+use rand::{thread_rng, Rng};
+use stun::{
+    attribute::{Error, ErrorCode, ErrorKind},
+    ChannelData, MessageWriter, Method,
+};
+
+/// The standard maximum payload of the ChannelData messages.
+///
+/// This is the payload of a ChannelData message that fits the 1500 bytes
+/// Ethernet MTU over IPv4: 1500 - 20 (IP header) - 8 (UDP header) - 4
+/// (ChannelData header).
+pub const MAX_RELAYED_PAYLOAD: usize = 1468;
+
+/// return the diagnostic indication of an oversized channel data
+///
+/// ChannelData messages have no responses, but when the diagnostic
+/// indications are enabled the client is told why the message was not
+/// relayed, see [`ServiceOptions::diagnostic_indications`](crate::ServiceOptions::diagnostic_indications).
+#[inline(always)]
+fn reject<'a, T: Observer>(req: Requet<'_, 'a, T, ChannelData<'a>>) -> Option<Response<'a>> {
+    if !req.service.options.diagnostic_indications {
+        return None;
+    }
+
+    {
+        let token: [u8; 12] = thread_rng().gen();
+        let mut message = MessageWriter::new(Method::DataIndication, &token, req.bytes);
+        message.append::<ErrorCode>(Error {
+            code: ErrorKind::BadRequest as u16,
+            message: "Payload Too Large",
+        });
+
+        message.flush(None).ok()?;
+    }
+
+    Some(Response {
+        method: ResponseMethod::Stun(Method::DataIndication),
+        bytes: req.bytes,
+        endpoint: None,
+        relay: None,
+        delay: None,
+    })
+}
 
 /// process channel data
 ///
@@ -31,14 +73,121 @@ use stun::ChannelData;
 /// the Length field in the ChannelData message is 0, then there will be
 /// no data in the UDP datagram, but the UDP datagram is still formed and
 /// sent [(Section 4.1 of [RFC6263])](https://tools.ietf.org/html/rfc6263#section-4.1).
+///
+/// Over TCP, the ChannelData message MUST be padded to a multiple of four
+/// bytes, over UDP the padding is not required, so the padding is only
+/// carried on the TCP hops.
+///
+/// The messages whose payload is larger than
+/// [`ServiceOptions::max_relayed_payload`](crate::ServiceOptions::max_relayed_payload)
+/// are discarded.
+///
+/// # Test
+///
+/// ```
+/// use std::net::SocketAddr;
+///
+/// use mycrl_turn::*;
+/// use stun::{
+///     attribute::{ErrorCode, ErrorKind},
+///     Decoder, Method, Payload,
+/// };
+///
+/// #[derive(Clone)]
+/// struct ObserverTest;
+///
+/// impl Observer for ObserverTest {
+///     async fn get_password(&self, _: &SessionAddr, _: &str) -> Option<String> {
+///         Some("test".to_string())
+///     }
+/// }
+///
+/// let interface = "127.0.0.1:3478".parse::<SocketAddr>().unwrap();
+/// let client = "127.0.0.1:10000".parse::<SocketAddr>().unwrap();
+/// let peer = "127.0.0.1:10001".parse::<SocketAddr>().unwrap();
+///
+/// let relay = |max_relayed_payload: Option<usize>, size: usize| {
+///     let service = Service::new(
+///         "localhost".to_string(),
+///         vec![interface],
+///         ServiceOptions {
+///             diagnostic_indications: true,
+///             max_relayed_payload,
+///             ..Default::default()
+///         },
+///         ObserverTest,
+///     );
+///
+///     let addr = SessionAddr { address: client, interface };
+///     let peer_addr = SessionAddr { address: peer, interface };
+///     let sessions = service.get_sessions();
+///     pollster::block_on(sessions.get_digest(&addr, "test", "test"));
+///     pollster::block_on(sessions.get_digest(&peer_addr, "test", "test"));
+///     let port = sessions.allocate(&addr).unwrap();
+///     let peer_port = sessions.allocate(&peer_addr).unwrap();
+///     assert!(sessions.bind_channel(&addr, &interface, peer_port, 0x4000));
+///     assert!(sessions.bind_channel(&peer_addr, &interface, port, 0x4000));
+///
+///     let mut bytes = vec![0x40, 0x00];
+///     bytes.extend_from_slice(&(size as u16).to_be_bytes());
+///     bytes.resize(4 + size, 0);
+///
+///     let mut operationer = service.get_operationer(interface, interface);
+///     let res = pollster::block_on(operationer.route(&bytes, client)).unwrap().unwrap();
+///     match res.method {
+///         ResponseMethod::ChannelData => Ok(res.bytes.len()),
+///         ResponseMethod::Stun(Method::DataIndication) => {
+///             let mut decoder = Decoder::default();
+///             if let Payload::Message(message) = decoder.decode(res.bytes).unwrap() {
+///                 Err(message.get::<ErrorCode>().unwrap().code)
+///             } else {
+///                 unreachable!()
+///             }
+///         }
+///         _ => unreachable!(),
+///     }
+/// };
+///
+/// let oversized = Err(ErrorKind::BadRequest as u16);
+/// assert_eq!(relay(None, 1468), Ok(1472));
+/// assert_eq!(relay(None, 1469), oversized);
+/// assert_eq!(relay(Some(8996), 8996), Ok(9000));
+/// assert_eq!(relay(Some(8996), 8997), oversized);
+/// ```
 pub fn process<'a, T: Observer>(
     bytes: &'a [u8],
     req: Requet<'_, 'a, T, ChannelData<'a>>,
 ) -> Option<Response<'a>> {
-    let relay = req
+    let max_relayed_payload = req
+        .service
+        .options
+        .max_relayed_payload
+        .unwrap_or(MAX_RELAYED_PAYLOAD);
+
+    if req.message.bytes.len() > max_relayed_payload {
+        return reject(req);
+    }
+
+    let relay = match req
         .service
         .sessions
-        .get_channel_relay_address(&req.address, req.message.number)?;
+        .get_channel_relay_address(req.address, req.message.number)
+    {
+        Some(it) => it,
+        None => {
+            req.service
+                .observer
+                .relay_dropped(req.address, req.message.bytes.len());
+            return None;
+        }
+    };
+
+    // The data is relayed toward the client of the channel, from the peer that
+    // sent the ChannelData message.
+    let client = &relay.address;
+    req.service
+        .observer
+        .relay_inbound(client, req.address, req.message.bytes.len());
 
     Some(Response {
         method: ResponseMethod::ChannelData,
@@ -48,6 +197,9 @@ pub fn process<'a, T: Observer>(
             None
         },
         relay: Some(relay.address),
-        bytes,
+        // The padding of the message received over tcp is stripped, the
+        // padding is added again if the message is forwarded to tcp.
+        bytes: &bytes[..4 + req.message.bytes.len()],
+        delay: None,
     })
 }
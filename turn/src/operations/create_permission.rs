@@ -1,8 +1,8 @@
-use super::{Requet, Response, ResponseMethod};
-use crate::{Observer, SOFTWARE};
+use super::{ProcessError, Requet, Response, ResponseMethod};
+use crate::{AuditKind, Observer};
 
 use stun::{
-    attribute::{Error, ErrorCode, ErrorKind, Realm, Software, XorPeerAddress},
+    attribute::{ErrorKind, Software, XorPeerAddress},
     Kind, MessageReader, MessageWriter, Method,
 };
 
@@ -10,26 +10,9 @@ use stun::{
 #[inline(always)]
 fn reject<'a, T: Observer>(
     req: Requet<'_, 'a, T, MessageReader<'_>>,
-    err: ErrorKind,
+    err: ProcessError,
 ) -> Option<Response<'a>> {
-    {
-        let mut message = MessageWriter::extend(
-            Method::CreatePermission(Kind::Error),
-            req.message,
-            req.bytes,
-        );
-
-        message.append::<ErrorCode>(Error::from(err));
-        message.append::<Realm>(&req.service.realm);
-        message.flush(None).ok()?;
-    }
-
-    Some(Response {
-        method: ResponseMethod::Stun(Method::CreatePermission(Kind::Error)),
-        bytes: req.bytes,
-        endpoint: None,
-        relay: None,
-    })
+    super::reject(req, Method::CreatePermission(Kind::Error), err)
 }
 
 /// return create permission ok response
@@ -45,7 +28,7 @@ fn resolve<'a, T: Observer>(
             req.bytes,
         );
 
-        message.append::<Software>(SOFTWARE);
+        message.append::<Software>(&req.service.software);
         message.flush(Some(digest)).ok()?;
     }
 
@@ -54,6 +37,7 @@ fn resolve<'a, T: Observer>(
         bytes: req.bytes,
         endpoint: None,
         relay: None,
+        delay: None,
     })
 }
 
@@ -82,6 +66,12 @@ fn resolve<'a, T: Observer>(
 /// XOR-PEER-ADDRESS attribute; if a value is not allowed, the server
 /// rejects the request with a 403 (Forbidden) error.
 ///
+/// A request on a 5-tuple without an allocation is rejected with the 437
+/// (Allocation Mismatch) error. A request with more XOR-PEER-ADDRESS
+/// attributes than
+/// [`ServiceOptions::max_peer_addresses`](crate::ServiceOptions::max_peer_addresses)
+/// is rejected with the 400 (Bad Request) error.
+///
 /// If the message is valid and the server is capable of carrying out the
 /// request, then the server installs or refreshes a permission for the
 /// IP address contained in each XOR-PEER-ADDRESS attribute as described
@@ -100,29 +90,91 @@ pub async fn process<'a, T: Observer>(
     req: Requet<'_, 'a, T, MessageReader<'_>>,
 ) -> Option<Response<'a>> {
     let (username, digest) = match req.auth().await {
-        None => return reject(req, ErrorKind::Unauthorized),
+        Err(err) => {
+            let delay = req.auth_failure_delay(&err);
+            return reject(req, err).map(|it| it.with_delay(delay));
+        }
+        Ok(it) => it,
+    };
+
+    // The permissions belong to the allocation of the 5-tuple, there is
+    // nothing to install them on without one.
+    let allocation = match req.service.sessions.get_allocation(req.address) {
         Some(it) => it,
+        None => return reject(req, ProcessError::Policy(ErrorKind::AllocationMismatch)),
     };
 
+    // The peers are counted before any of them is checked, so that a request
+    // with a huge number of peers costs nothing more than its parsing.
+    if let Some(max) = req.service.options.max_peer_addresses {
+        if req.message.get_all::<XorPeerAddress>().count() > max {
+            return reject(req, ProcessError::Parse(ErrorKind::BadRequest));
+        }
+    }
+
     let mut ports = Vec::with_capacity(15);
+    let mut peers = Vec::with_capacity(15);
     for it in req.message.get_all::<XorPeerAddress>() {
-        if !req.verify_ip(&it) {
-            return reject(req, ErrorKind::PeerAddressFamilyMismatch);
+        let it = req.get_peer_address(it);
+        if !req.verify_peer_family(&it) {
+            return reject(
+                req,
+                ProcessError::Policy(ErrorKind::PeerAddressFamilyMismatch),
+            );
+        }
+
+        // The data is only relayed between the allocations of this server, the
+        // addresses of the other hosts are a restriction, not a mismatch.
+        if !req.verify_ip(&it) || !req.verify_peer_policy(&it) {
+            return reject(req, ProcessError::Policy(ErrorKind::Forbidden));
         }
 
         ports.push(it.port());
+        peers.push(it);
+    }
+
+    // The permissions of the existing peers are refreshed under load, only the
+    // new peers are shed.
+    if req.service.is_overloaded()
+        && peers
+            .iter()
+            .any(|it| !req.service.sessions.has_permission(req.address, it))
+    {
+        return reject(
+            req,
+            ProcessError::Capacity(ErrorKind::AllocationQuotaReached),
+        );
+    }
+
+    // Only the new peers count towards the relay paths of the whole server.
+    let mut new = peers
+        .iter()
+        .filter(|it| !req.service.sessions.has_permission(req.address, it))
+        .map(|it| it.port())
+        .collect::<Vec<_>>();
+
+    new.sort_unstable();
+    new.dedup();
+    if !req.service.reserve_relay_paths(new.len()) {
+        return reject(req, ProcessError::Capacity(ErrorKind::InsufficientCapacity));
     }
 
-    if !req
+    let created = req
         .service
         .sessions
-        .create_permission(&req.address, &req.service.endpoint, &ports)
-    {
-        return reject(req, ErrorKind::Forbidden);
+        .create_permission(req.address, &req.service.endpoint, &ports);
+
+    req.service.release_relay_paths(new.len());
+    if !created {
+        return reject(req, ProcessError::Policy(ErrorKind::Forbidden));
     }
 
     req.service
         .observer
-        .create_permission(&req.address, username, &ports);
+        .create_permission(req.address, username, &ports, &allocation);
+    for peer in peers {
+        req.audit(AuditKind::Permission, allocation.relay, peer);
+    }
+
     resolve(req, &digest)
 }
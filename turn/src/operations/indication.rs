@@ -3,11 +3,48 @@ use std::net::SocketAddr;
 use super::{Requet, Response, ResponseMethod};
 use crate::Observer;
 
+use rand::{thread_rng, Rng};
 use stun::{
-    attribute::{Data, XorPeerAddress},
+    attribute::{Data, Error, ErrorCode, ErrorKind, XorPeerAddress},
     MessageReader, MessageWriter, Method,
 };
 
+/// return the diagnostic indication of a discarded send indication
+///
+/// There are no error responses to indications, the send indications are
+/// silently discarded by default. For debugging deployments, the server can
+/// reply a Data indication with the ERROR-CODE attribute describing why the
+/// send indication was discarded. The diagnostic indication has no DATA
+/// attribute, so that the compliant clients discard it.
+#[inline(always)]
+fn reject<'a, T: Observer>(
+    req: Requet<'_, 'a, T, MessageReader<'_>>,
+    peer: Option<SocketAddr>,
+    err: ErrorKind,
+) -> Option<Response<'a>> {
+    if !req.service.options.diagnostic_indications {
+        return None;
+    }
+
+    {
+        let mut message = MessageWriter::extend(Method::DataIndication, req.message, req.bytes);
+        if let Some(peer) = peer {
+            message.append::<XorPeerAddress>(peer);
+        }
+
+        message.append::<ErrorCode>(Error::from(err));
+        message.flush(None).ok()?;
+    }
+
+    Some(Response {
+        method: ResponseMethod::Stun(Method::DataIndication),
+        bytes: req.bytes,
+        endpoint: None,
+        relay: None,
+        delay: None,
+    })
+}
+
 /// process send indication request
 ///
 /// When the server receives a Send indication, it processes as per
@@ -52,26 +89,164 @@ use stun::{
 /// and [15](https://tools.ietf.org/html/rfc8656#section-15).
 ///
 /// The resulting UDP datagram is then sent to the peer.
-pub fn process<'a, T: Observer>(req: Requet<'_, 'a, T, MessageReader<'_>>) -> Option<Response<'a>> {
-    let peer = req.message.get::<XorPeerAddress>()?;
-    let data = req.message.get::<Data>()?;
+///
+/// # Test
+///
+/// ```
+/// use std::net::SocketAddr;
+///
+/// use bytes::BytesMut;
+/// use mycrl_turn::*;
+/// use stun::{
+///     attribute::{
+///         Data, ErrorCode, ErrorKind, ReqeestedTransport, Transport, UserName, Realm,
+///         XorPeerAddress,
+///     },
+///     util::long_term_credential_digest,
+///     Decoder, Kind, MessageWriter, Method, Payload,
+/// };
+///
+/// #[derive(Clone)]
+/// struct ObserverTest;
+///
+/// impl Observer for ObserverTest {
+///     async fn get_password(&self, _: &SessionAddr, _: &str) -> Option<String> {
+///         Some("test".to_string())
+///     }
+/// }
+///
+/// let interface = "127.0.0.1:3478".parse::<SocketAddr>().unwrap();
+/// let client = "127.0.0.1:10000".parse::<SocketAddr>().unwrap();
+/// let peer = "127.0.0.1:50000".parse::<SocketAddr>().unwrap();
+///
+/// for diagnostic_indications in [false, true] {
+///     let service = Service::new(
+///         "localhost".to_string(),
+///         vec![interface],
+///         ServiceOptions {
+///             diagnostic_indications,
+///             ..Default::default()
+///         },
+///         ObserverTest,
+///     );
+///
+///     let mut operationer = service.get_operationer(interface, interface);
+///     let mut send = |method: Method| {
+///         let mut bytes = BytesMut::with_capacity(1500);
+///         let mut message = MessageWriter::new(method, &[0u8; 12], &mut bytes);
+///         if method == Method::SendIndication {
+///             message.append::<XorPeerAddress>(peer);
+///             message.append::<Data>(&[1, 2, 3]);
+///             message.flush(None).unwrap();
+///         } else {
+///             message.append::<ReqeestedTransport>(Transport::UDP);
+///             message.append::<UserName>("test");
+///             message.append::<Realm>("localhost");
+///             message
+///                 .flush(Some(&long_term_credential_digest("test", "test", "localhost")))
+///                 .unwrap();
+///         }
+///
+///         let res = pollster::block_on(operationer.route(&bytes, client)).unwrap()?;
+///         let mut decoder = Decoder::default();
+///         if let Payload::Message(message) = decoder.decode(res.bytes).unwrap() {
+///             Some((
+///                 message.method,
+///                 message.get::<ErrorCode>().map(|it| it.code),
+///                 message.get::<XorPeerAddress>(),
+///                 message.get::<Data>().is_some(),
+///             ))
+///         } else {
+///             unreachable!()
+///         }
+///     };
+///
+///     send(Method::Allocate(Kind::Request)).unwrap();
+///
+///     // There is no permission for the peer.
+///     let res = send(Method::SendIndication);
+///     if diagnostic_indications {
+///         assert_eq!(
+///             res,
+///             Some((
+///                 Method::DataIndication,
+///                 Some(ErrorKind::Forbidden as u16),
+///                 Some(peer),
+///                 false
+///             ))
+///         );
+///     } else {
+///         assert_eq!(res, None);
+///     }
+/// }
+/// ```
+pub async fn process<'a, T: Observer>(
+    req: Requet<'_, 'a, T, MessageReader<'_>>,
+) -> Option<Response<'a>> {
+    let (peer, data) = match (
+        req.message.get::<XorPeerAddress>(),
+        req.message.get::<Data>(),
+    ) {
+        // The empty DATA attribute is a keepalive of the client, which is
+        // relayed as an empty datagram, only the missing one is discarded.
+        (Some(peer), Some(data)) => (peer, data),
+        (peer, _) => return reject(req, peer, ErrorKind::BadRequest),
+    };
+
+    let target = req.get_peer_address(peer);
+
+    // The relay is selected by the family of the peer address, there are no
+    // error responses to indications, so the mismatched ones are discarded.
+    if !req.verify_peer_family(&target) {
+        return reject(req, Some(peer), ErrorKind::PeerAddressFamilyMismatch);
+    }
 
-    let relay = req
+    let relayed = match req
         .service
         .sessions
-        .get_relay_address(&req.address, peer.port())?;
+        .get_session(req.address)
+        .get_ref()
+        .and_then(|it| {
+            let port = it.allocate.port?;
+            Some(
+                it.allocate
+                    .relay
+                    .unwrap_or_else(|| SocketAddr::new(req.service.interface.ip(), port)),
+            )
+        }) {
+        Some(it) => it,
+        None => return reject(req, Some(peer), ErrorKind::AllocationMismatch),
+    };
 
-    let local_port = req
+    let relay = match req
         .service
         .sessions
-        .get_session(&req.address)
-        .get_ref()?
-        .allocate
-        .port?;
+        .get_relay_address(req.address, target.port())
+    {
+        Some(it) => it,
+        None => {
+            req.service.observer.relay_dropped(req.address, data.len());
+            return reject(req, Some(peer), ErrorKind::Forbidden);
+        }
+    };
+
+    if !req.authorize_peer(&target).await {
+        return reject(req, Some(peer), ErrorKind::Forbidden);
+    }
+
+    // The data is relayed toward the client of the relay, from the peer that
+    // sent the indication.
+    let client = &relay.address;
+    req.service
+        .observer
+        .relay_inbound(client, req.address, data.len());
 
+    // The Data indication is a new transaction, the peer address is XORed with
+    // its own transaction id.
     {
-        let mut message = MessageWriter::extend(Method::DataIndication, &req.message, req.bytes);
-        message.append::<XorPeerAddress>(SocketAddr::new(req.service.interface.ip(), local_port));
+        let token: [u8; 12] = thread_rng().gen();
+        let mut message = MessageWriter::new(Method::DataIndication, &token, req.bytes);
+        message.append::<XorPeerAddress>(relayed);
         message.append::<Data>(data);
         message.flush(None).ok()?;
     }
@@ -85,5 +260,6 @@ pub fn process<'a, T: Observer>(req: Requet<'_, 'a, T, MessageReader<'_>>) -> Op
         },
         relay: Some(relay.address),
         bytes: req.bytes,
+        delay: None,
     })
 }
@@ -5,24 +5,81 @@ pub mod channel_data;
 pub mod create_permission;
 pub mod indication;
 pub mod refresh;
+pub mod unknown;
 
 use crate::{
-    sessions::{SessionAddr, Sessions},
-    Observer,
+    auth::{validate_integrity, MobilityTickets, Realms, VerifyCache},
+    middleware::{Action, Middleware},
+    policy::{AllocationLimiter, Drains, LoadShedder, PolicyStore, PortChangeMode, RateLimiter},
+    pool::{BufferPool, BUFFER_SIZE},
+    sessions::{AuditEvent, AuditKind, SessionAddr, Sessions},
+    Observer, ServiceOptions,
 };
 
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
 
 use bytes::BytesMut;
+use rand::{thread_rng, Rng};
 use stun::{
-    attribute::{Nonce, UserName},
-    Decoder, Kind, MessageReader, Method, Payload, StunError,
+    attribute::{Error, ErrorCode, ErrorKind, MessageIntegrity, Nonce, Realm, Transport, UserName},
+    Decoder, Kind, MessageReader, MessageWriter, Method, Payload, StunError,
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ResponseMethod {
     Stun(Method),
     ChannelData,
+    /// The error response of a request whose method is not implemented, with
+    /// the message type of the response.
+    UnknownMethod(u16),
+}
+
+impl ResponseMethod {
+    /// Check if the response is an error response.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::ResponseMethod;
+    /// use stun::{Kind, Method};
+    ///
+    /// assert!(ResponseMethod::Stun(Method::Allocate(Kind::Error)).is_error());
+    /// assert!(ResponseMethod::UnknownMethod(0x011a).is_error());
+    /// assert!(!ResponseMethod::Stun(Method::Allocate(Kind::Response)).is_error());
+    /// assert!(!ResponseMethod::ChannelData.is_error());
+    /// ```
+    pub fn is_error(&self) -> bool {
+        match self {
+            Self::Stun(method) => method.is_error(),
+            Self::ChannelData => false,
+            Self::UnknownMethod(_) => true,
+        }
+    }
+
+    /// Check if the response is the relayed data of a peer.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::ResponseMethod;
+    /// use stun::{Kind, Method};
+    ///
+    /// assert!(ResponseMethod::ChannelData.is_relayed());
+    /// assert!(ResponseMethod::Stun(Method::DataIndication).is_relayed());
+    /// assert!(!ResponseMethod::Stun(Method::Binding(Kind::Response)).is_relayed());
+    /// assert!(!ResponseMethod::UnknownMethod(0x011a).is_relayed());
+    /// ```
+    pub fn is_relayed(&self) -> bool {
+        matches!(self, Self::ChannelData | Self::Stun(Method::DataIndication))
+    }
 }
 
 /// The context of the service.
@@ -30,14 +87,293 @@ pub enum ResponseMethod {
 /// A service corresponds to a Net Endpoint, different sockets have different
 /// addresses and so on, but other things are basically the same.
 pub struct ServiceContext<T: Observer> {
-    pub realm: Arc<String>,
+    pub realm: Arc<Realms>,
+    pub software: Arc<str>,
     pub sessions: Arc<Sessions<T>>,
     pub endpoint: SocketAddr,
     pub interface: SocketAddr,
     pub interfaces: Arc<Vec<SocketAddr>>,
+    pub options: Arc<ServiceOptions>,
+    pub verify_cache: Option<Arc<VerifyCache>>,
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    pub allocation_limiter: Option<Arc<AllocationLimiter>>,
+    pub load_shedder: Option<Arc<LoadShedder>>,
+    pub buffer_pool: Option<Arc<BufferPool>>,
+    /// The mobility tickets, only set with [`ServiceOptions::mobility`](crate::ServiceOptions::mobility).
+    pub mobility: Option<Arc<MobilityTickets>>,
+    pub relay_cursor: Arc<AtomicUsize>,
+    pub policies: Arc<PolicyStore>,
+    pub drains: Arc<Drains>,
+    pub middleware: Option<Arc<dyn Middleware>>,
+    /// The listener is an encrypted transport, see [`Listener::secure`](crate::Listener::secure).
+    pub secure: bool,
+    /// The transport has no room for the client, see [`Operationer::set_saturated`].
+    pub saturated: bool,
+    /// The transport of the clients, see [`Operationer::set_transport`].
+    pub transport: Transport,
     pub observer: T,
 }
 
+impl<T> ServiceContext<T>
+where
+    T: Observer + 'static,
+{
+    /// Append the challenge to the error response.
+    ///
+    /// The 401 (Unauthorized) responses must carry both the REALM and the
+    /// NONCE, the client needs the two of them to construct the authenticated
+    /// retry, so every processor appends the challenge through this function.
+    /// The realm selected for the request by the observer is given, otherwise
+    /// the challenge carries the realm of the service.
+    #[inline(always)]
+    pub(crate) fn append_challenge(
+        &self,
+        address: &SessionAddr,
+        realm: Option<&str>,
+        message: &mut MessageWriter<'_>,
+    ) -> Option<()> {
+        message.append::<Nonce>(&self.sessions.get_nonce(address).get_ref()?.0);
+        match realm {
+            Some(it) => message.append::<Realm>(it),
+            None => message.append::<Realm>(&self.realm.current()),
+        }
+
+        Some(())
+    }
+
+    /// Get the relayed ip of a new allocation of the username on the
+    /// interface.
+    ///
+    /// The ip pinned to the username is used when it is an ip of the
+    /// interface, see [`ServiceOptions::relay_pins`]. Otherwise the
+    /// allocations rotate across the ip of the interface and its additional
+    /// relay addresses, see [`ServiceOptions::relay_addresses`].
+    pub(crate) fn next_relay_ip(&self, username: &str) -> IpAddr {
+        let addresses = self.options.relay_addresses.get(&self.interface);
+        if let Some(ip) = self.options.relay_pins.get(username) {
+            if ip == self.interface.ip() || addresses.map(|it| it.contains(&ip)).unwrap_or(false) {
+                return ip;
+            }
+        }
+
+        match addresses {
+            Some(addresses) if !addresses.is_empty() => {
+                let index =
+                    self.relay_cursor.fetch_add(1, Ordering::Relaxed) % (addresses.len() + 1);
+                if index == 0 {
+                    self.interface.ip()
+                } else {
+                    addresses[index - 1]
+                }
+            }
+            _ => self.interface.ip(),
+        }
+    }
+
+    /// Check if a request of the address is within the rate limits, see
+    /// [`ServiceOptions::rate_limit`].
+    #[inline(always)]
+    pub(crate) fn is_rate_allowed(&self, address: &SocketAddr) -> bool {
+        match &self.rate_limiter {
+            Some(limiter) => limiter.allow(&address.ip()),
+            None => true,
+        }
+    }
+
+    /// Check if a new allocation is within the allocation rate of the server,
+    /// see [`ServiceOptions::allocation_rate`].
+    #[inline(always)]
+    pub(crate) fn is_allocation_allowed(&self) -> bool {
+        match &self.allocation_limiter {
+            Some(limiter) => limiter.allow(),
+            None => true,
+        }
+    }
+
+    /// Reserve the new relay paths under the limit of the whole server, see
+    /// [`ServiceOptions::max_relay_paths`]. The reservation is released with
+    /// [`ServiceContext::release_relay_paths`] after the paths are installed.
+    #[inline(always)]
+    pub(crate) fn reserve_relay_paths(&self, new: usize) -> bool {
+        match self.options.max_relay_paths {
+            Some(max) if new > 0 => self.sessions.reserve_relay_paths(new, max),
+            _ => true,
+        }
+    }
+
+    #[inline(always)]
+    pub(crate) fn release_relay_paths(&self, new: usize) {
+        if self.options.max_relay_paths.is_some() && new > 0 {
+            self.sessions.release_relay_paths(new);
+        }
+    }
+
+    /// Get the alternate server of the listener if it is drained, see
+    /// [`Service::drain_listener`](crate::Service::drain_listener).
+    #[inline(always)]
+    pub(crate) fn get_drain(&self) -> Option<Option<SocketAddr>> {
+        self.drains.get(&self.interface)
+    }
+
+    /// Check if the requests in flight are above the threshold, see
+    /// [`ServiceOptions::max_in_flight`].
+    #[inline(always)]
+    pub(crate) fn is_overloaded(&self) -> bool {
+        self.load_shedder
+            .as_ref()
+            .map(|it| it.is_overloaded())
+            .unwrap_or(false)
+    }
+}
+
+/// The failure of a processor.
+///
+/// The processors classify their failures, and the dispatcher maps each
+/// class to the error code of the response and the level the failure is
+/// logged at. The error implements [`std::error::Error`], so it converts
+/// into `anyhow::Error` at the edges.
+///
+/// # Test
+///
+/// ```
+/// use mycrl_turn::ProcessError;
+/// use stun::{attribute::ErrorKind, StunError};
+///
+/// let err = ProcessError::Capacity(ErrorKind::AllocationQuotaReached);
+/// assert_eq!(err.error_kind(), Some(ErrorKind::AllocationQuotaReached));
+/// assert_eq!(err.level(), log::Level::Warn);
+/// assert_eq!(err.to_string(), "capacity: Allocation Quota Reached");
+///
+/// let err = ProcessError::Auth(ErrorKind::StaleNonce);
+/// assert_eq!(err.error_kind(), Some(ErrorKind::StaleNonce));
+/// assert_eq!(err.level(), log::Level::Debug);
+///
+/// let err = ProcessError::Realm(ErrorKind::Unauthorized, "tenant".to_string());
+/// assert_eq!(err.error_kind(), Some(ErrorKind::Unauthorized));
+/// assert_eq!(err.to_string(), "auth: Unauthorized");
+///
+/// let err = ProcessError::Challenge;
+/// assert_eq!(err.error_kind(), Some(ErrorKind::Unauthorized));
+/// assert_eq!(err.to_string(), "challenge: Unauthorized");
+///
+/// let err = ProcessError::from(StunError::SummaryFailed);
+/// assert_eq!(err.error_kind(), None);
+/// assert_eq!(err.level(), log::Level::Error);
+/// assert_eq!(err.to_string(), "io: SummaryFailed");
+/// ```
+#[derive(Debug)]
+pub enum ProcessError {
+    /// The request is malformed or misses a required attribute.
+    Parse(ErrorKind),
+    /// The request carries no credential, which is the first request of the
+    /// long-term credential mechanism and is answered with the challenge.
+    Challenge,
+    /// The request failed the authentication.
+    Auth(ErrorKind),
+    /// The request failed the authentication in the realm selected for it by
+    /// [`Observer::get_realm`], the challenge carries that realm instead of
+    /// the realm of the service.
+    Realm(ErrorKind, String),
+    /// The server is out of a resource, such as the ports.
+    Capacity(ErrorKind),
+    /// The request is denied by a policy or by the state of the allocation.
+    Policy(ErrorKind),
+    /// The response could not be written, the request is not answered.
+    Io(StunError),
+}
+
+impl ProcessError {
+    /// Get the error code of the response, the failures without an error
+    /// code are not answered.
+    pub fn error_kind(&self) -> Option<ErrorKind> {
+        match self {
+            Self::Parse(it)
+            | Self::Auth(it)
+            | Self::Realm(it, _)
+            | Self::Capacity(it)
+            | Self::Policy(it) => Some(*it),
+            Self::Challenge => Some(ErrorKind::Unauthorized),
+            Self::Io(_) => None,
+        }
+    }
+
+    /// Get the level of the failure, the failures caused by the clients are
+    /// the normal flow of the protocol and are only logged for debugging,
+    /// while the server side failures need the attention of the operators.
+    pub fn level(&self) -> log::Level {
+        match self {
+            Self::Parse(_) | Self::Challenge | Self::Auth(_) | Self::Realm(..) => log::Level::Debug,
+            Self::Policy(_) => log::Level::Info,
+            Self::Capacity(_) => log::Level::Warn,
+            Self::Io(_) => log::Level::Error,
+        }
+    }
+}
+
+impl fmt::Display for ProcessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (class, kind) = match self {
+            Self::Parse(it) => ("parse", *it),
+            Self::Challenge => ("challenge", ErrorKind::Unauthorized),
+            Self::Auth(it) | Self::Realm(it, _) => ("auth", *it),
+            Self::Capacity(it) => ("capacity", *it),
+            Self::Policy(it) => ("policy", *it),
+            Self::Io(err) => return write!(f, "io: {}", err),
+        };
+
+        write!(f, "{}: {}", class, <&'static str>::from(kind))
+    }
+}
+
+impl std::error::Error for ProcessError {}
+
+impl From<StunError> for ProcessError {
+    fn from(value: StunError) -> Self {
+        Self::Io(value)
+    }
+}
+
+/// The error response of the failure.
+///
+/// The failure is logged at its level, and the challenge is appended to
+/// every error response, see [`ServiceContext::append_challenge`].
+#[inline(always)]
+pub(crate) fn reject<'a, T: Observer>(
+    req: Requet<'_, 'a, T, MessageReader<'_>>,
+    method: Method,
+    err: ProcessError,
+) -> Option<Response<'a>> {
+    log::log!(
+        err.level(),
+        "request failed: addr={:?}, method={:?}, err={}",
+        req.address,
+        req.message.method,
+        err
+    );
+
+    let kind = err.error_kind()?;
+    let realm = match &err {
+        ProcessError::Realm(_, it) => Some(it.as_str()),
+        _ => None,
+    };
+
+    {
+        let mut message = MessageWriter::extend(method, req.message, req.bytes);
+        message.append::<ErrorCode>(Error::from(kind));
+        req.service
+            .append_challenge(req.address, realm, &mut message)?;
+        message.flush(None).ok()?;
+    }
+
+    Some(Response {
+        method: ResponseMethod::Stun(method),
+        bytes: req.bytes,
+        endpoint: None,
+        relay: None,
+        delay: None,
+    })
+}
+
 /// The request of the service.
 pub struct Requet<'a, 'b, T, M>
 where
@@ -59,7 +395,116 @@ where
         self.service
             .interfaces
             .iter()
-            .any(|item| item.ip() == address.ip())
+            .map(|item| item.ip())
+            .chain(
+                self.service
+                    .options
+                    .relay_addresses
+                    .values()
+                    .flatten()
+                    .copied(),
+            )
+            .any(|ip| ip == address.ip())
+    }
+
+    /// Check if the peer address is allowed by the peer policy.
+    #[inline(always)]
+    pub(crate) fn verify_peer_policy(&self, peer: &SocketAddr) -> bool {
+        self.service.policies.get().peer.is_allowed(&peer.ip())
+    }
+
+    /// Check if the peer is authorized by the observer, the decision is cached
+    /// in the session so that the observer is only asked once per peer.
+    pub(crate) async fn authorize_peer(&self, peer: &SocketAddr) -> bool {
+        let sessions = &self.service.sessions;
+        if let Some(it) = sessions.get_authorization(self.address, peer) {
+            return it;
+        }
+
+        let username = match sessions.get_session(self.address).get_ref() {
+            Some(it) => it.auth.username.clone(),
+            None => return false,
+        };
+
+        let allowed = self
+            .service
+            .observer
+            .authorize_peer(self.address, &username, peer)
+            .await;
+
+        sessions.set_authorization(self.address, peer, allowed);
+        allowed
+    }
+
+    /// Check if the family of the peer address matches the relayed transport
+    /// address of the allocation.
+    ///
+    /// The data is relayed to the peer from the relayed transport address,
+    /// on a dual-stack server each allocation is relayed on the interface it
+    /// was allocated on, so the peer address must use the family of that
+    /// relay, which is also the family of the relay of the peer when the peer
+    /// is an allocation of this server. The requests without an allocation
+    /// and the unknown peers are left to the permission check.
+    #[inline(always)]
+    pub(crate) fn verify_peer_family(&self, peer: &SocketAddr) -> bool {
+        let sessions = &self.service.sessions;
+        [
+            sessions.get_allocation(self.address).map(|it| it.relay),
+            sessions.get_relayed_address(peer.port()),
+        ]
+        .into_iter()
+        .flatten()
+        .all(|it| it.is_ipv4() == peer.is_ipv4())
+    }
+
+    /// Report the relay 5-tuple installed by the request to the observer.
+    #[inline(always)]
+    pub(crate) fn audit(&self, kind: AuditKind, relay: SocketAddr, peer: SocketAddr) {
+        self.service.observer.audit(&AuditEvent {
+            client: *self.address,
+            time: SystemTime::now(),
+            kind,
+            relay,
+            peer,
+        });
+    }
+
+    /// Get the peer address of the request, translated by the observer into
+    /// the actual relay destination.
+    #[inline(always)]
+    pub(crate) fn get_peer_address(&self, peer: SocketAddr) -> SocketAddr {
+        self.service
+            .observer
+            .translate_peer(self.address, &peer)
+            .unwrap_or(peer)
+    }
+
+    /// Get the delay of the response to a failed authentication.
+    ///
+    /// Only the requests carrying the credential are delayed, the challenge
+    /// of a request without the USERNAME attribute does not depend on any
+    /// secret and is answered right away. The delay is randomized within the
+    /// configured bounds, so that "no such user" and "bad password" cannot
+    /// be distinguished by the response latency. Neither is a stale nonce,
+    /// which is checked before the credential.
+    #[inline(always)]
+    pub(crate) fn auth_failure_delay(&self, err: &ProcessError) -> Option<Duration> {
+        if !matches!(
+            err,
+            ProcessError::Auth(ErrorKind::Unauthorized)
+                | ProcessError::Realm(ErrorKind::Unauthorized, _)
+        ) {
+            return None;
+        }
+
+        let range = self.service.options.auth_failure_delay.as_ref()?;
+        self.message.get::<UserName>()?;
+
+        Some(if range.is_empty() {
+            *range.start()
+        } else {
+            thread_rng().gen_range(range.clone())
+        })
     }
 
     /// The key for the HMAC depends on whether long-term or short-term
@@ -107,32 +552,95 @@ where
     /// the end of the MESSAGE-INTEGRITY attribute prior to calculating the
     /// HMAC.  Such adjustment is necessary when attributes, such as
     /// FINGERPRINT, appear after MESSAGE-INTEGRITY.
+    ///
+    /// A request carrying a nonce that is no longer valid is rejected with a
+    /// 438 (Stale Nonce) error before the credential is looked up, so the
+    /// response does not tell whether the username exists.
+    ///
+    /// A request without the USERNAME or the MESSAGE-INTEGRITY attribute is
+    /// the first request of the mechanism and fails with
+    /// [`ProcessError::Challenge`], while a request whose credential is
+    /// wrong fails with [`ProcessError::Auth`], or with [`ProcessError::Realm`]
+    /// if the observer has selected the realm of the request. Both are
+    /// answered with a 401 (Unauthorized) error carrying the realm and a fresh
+    /// nonce, so the client can retry in the realm it has to use.
     #[inline(always)]
-    pub(crate) async fn auth(&self) -> Option<(&'a str, [u8; 16])> {
-        let username = self.message.get::<UserName>()?;
-        let digest = self
-            .service
-            .sessions
-            .get_digest(&self.address, username, self.service.realm.as_str())
-            .await?;
+    pub(crate) async fn auth(&self) -> Result<(&'a str, [u8; 16]), ProcessError> {
+        // The request without the credential is challenged, the request with a
+        // credential that does not check out is a failure of the client.
+        let username = match self.message.get::<UserName>() {
+            Some(it) if self.message.get::<MessageIntegrity>().is_some() => it,
+            _ => return Err(ProcessError::Challenge),
+        };
+
+        // The observer can select the credential domain of the request, and
+        // fall back to the realm of the service if it does not.
+        let selected =
+            self.service
+                .observer
+                .get_realm(self.address, username, self.message.get::<Realm>());
+
+        // The failures in a selected realm challenge the client with it, the
+        // client cannot know the realm the observer has picked otherwise.
+        let fail = |kind| match &selected {
+            Some(it) => ProcessError::Realm(kind, it.clone()),
+            None => ProcessError::Auth(kind),
+        };
+
+        // During a realm rotation, the previous realm is still accepted until
+        // its grace window has elapsed, after which it has no credentials, so
+        // that it fails like an unknown username and does not tell which
+        // realms exist.
+        let current = self.service.realm.current();
+        let realm = match (selected.as_deref(), self.message.get::<Realm>()) {
+            (Some(it), _) => Some(it),
+            (None, Some(it)) => Some(it).filter(|it| self.service.realm.is_active(it)),
+            (None, None) => Some(current.as_str()),
+        };
 
         // if nonce is not empty, check nonce
         if let Some(nonce) = self.message.get::<Nonce>() {
-            if self
-                .service
-                .sessions
-                .get_nonce(&self.address)
-                .get_ref()?
-                .0
-                .as_str()
-                != nonce
-            {
-                return None;
+            if !self.service.sessions.verify_nonce(self.address, nonce) {
+                return Err(fail(ErrorKind::StaleNonce));
             }
         }
 
-        self.message.integrity(&digest).ok()?;
-        Some((username, digest))
+        let digest = match realm {
+            Some(realm) => self.service.sessions.get_digest(self.address, username, realm).await,
+            None => None,
+        }
+        .ok_or_else(|| fail(ErrorKind::Unauthorized))?;
+
+        let valid = match &self.service.verify_cache {
+            Some(cache) => cache.validate(self.address, self.message, &digest),
+            None => validate_integrity(self.message, &digest),
+        };
+
+        if !valid {
+            return Err(fail(ErrorKind::Unauthorized));
+        }
+
+        // A reconnecting client takes over its detached allocation with its
+        // first authenticated request.
+        if self.service.options.disconnect_grace.is_some() {
+            self.service
+                .sessions
+                .reclaim(self.address, &digest, self.service.transport);
+        }
+
+        // A client whose source port has changed takes over its allocation
+        // with an authenticated request for it.
+        if self.service.options.port_change == PortChangeMode::Lenient
+            && self.message.method != Method::Allocate(Kind::Request)
+        {
+            self.service.sessions.migrate(
+                self.address,
+                &digest,
+                self.service.options.port_change_idle,
+            );
+        }
+
+        Ok((username, digest))
     }
 }
 
@@ -142,6 +650,18 @@ pub struct Response<'a> {
     pub bytes: &'a [u8],
     pub relay: Option<SocketAddr>,
     pub endpoint: Option<SocketAddr>,
+    /// The response should only be sent after this delay, which hides the
+    /// timing of the authentication failures.
+    pub delay: Option<Duration>,
+}
+
+impl Response<'_> {
+    /// Delay the response, see [`Response::delay`].
+    #[inline(always)]
+    pub(crate) fn with_delay(mut self, delay: Option<Duration>) -> Self {
+        self.delay = delay;
+        self
+    }
 }
 
 /// process udp message and return message + address
@@ -155,6 +675,17 @@ where
     bytes: BytesMut,
 }
 
+impl<T> Drop for Operationer<T>
+where
+    T: Observer + 'static,
+{
+    fn drop(&mut self) {
+        if let Some(pool) = &self.service.buffer_pool {
+            pool.put(std::mem::take(&mut self.bytes));
+        }
+    }
+}
+
 impl<T> Operationer<T>
 where
     T: Observer + 'static,
@@ -165,7 +696,10 @@ where
                 address: "0.0.0.0:0".parse().unwrap(),
                 interface: service.interface,
             },
-            bytes: BytesMut::with_capacity(4096),
+            bytes: match &service.buffer_pool {
+                Some(pool) => pool.get(),
+                None => BytesMut::with_capacity(BUFFER_SIZE),
+            },
             decoder: Decoder::default(),
             service,
         }
@@ -281,6 +815,39 @@ where
     ///
     /// The client may have multiple allocations on a server at the same
     /// time.
+    ///
+    /// Packets that are neither a stun message with the magic cookie nor
+    /// channel data are silently dropped, the port may be shared with other
+    /// traffic such as RTP.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use std::net::SocketAddr;
+    /// use mycrl_turn::*;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {}
+    ///
+    /// let addr = "127.0.0.1:8080".parse::<SocketAddr>().unwrap();
+    /// let service = Service::new("test".to_string(), vec![], ServiceOptions::default(), ObserverTest);
+    /// let mut operationer = service.get_operationer(addr, addr);
+    ///
+    /// let rtp = [
+    ///     0x80, 0x6f, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x12, 0x34, 0x56, 0x78,
+    ///     0x01, 0x02, 0x03, 0x04,
+    /// ];
+    ///
+    /// let no_cookie = [
+    ///     0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x71, 0x66, 0x46, 0x31,
+    ///     0x2b, 0x59, 0x79, 0x65, 0x56, 0x69, 0x32, 0x72,
+    /// ];
+    ///
+    /// assert!(pollster::block_on(operationer.route(&rtp, addr)).unwrap().is_none());
+    /// assert!(pollster::block_on(operationer.route(&no_cookie, addr)).unwrap().is_none());
+    /// ```
     #[rustfmt::skip]
     pub async fn route<'a, 'b: 'a>(
         &'b mut self,
@@ -289,6 +856,43 @@ where
     ) -> Result<Option<Response<'a>>, StunError> {
         self.address.address = address;
 
+        // The request is in flight until the response, the shedder is shared
+        // by all the operationers of the service.
+        let _in_flight = self.service.load_shedder.as_deref().map(LoadShedder::enter);
+
+        if self.service.options.legacy_binding && Decoder::is_legacy_binding(bytes) {
+            if !self.service.policies.get().binding.is_allowed(&address.ip())
+                || !self.service.is_rate_allowed(&address)
+            {
+                return Ok(None);
+            }
+
+            let token: [u8; 16] = bytes[4..20].try_into()?;
+            return Ok(binding::legacy(Requet {
+                bytes: &mut self.bytes,
+                service: &self.service,
+                address: &self.address,
+                message: &token,
+            }));
+        }
+
+        if !Decoder::is_stun(bytes) {
+            return Ok(None);
+        }
+
+        if let Some(method) = Decoder::unknown_method(bytes) {
+            if !self.service.is_rate_allowed(&address) {
+                return Ok(None);
+            }
+
+            return Ok(unknown::process(bytes, Requet {
+                bytes: &mut self.bytes,
+                service: &self.service,
+                address: &self.address,
+                message: &method,
+            }));
+        }
+
         Ok(match self.decoder.decode(bytes)? {
             Payload::ChannelData(channel) => channel_data::process(bytes, Requet {
                 bytes: &mut self.bytes,
@@ -297,6 +901,45 @@ where
                 message: &channel,
             }),
             Payload::Message(message) => {
+                #[cfg(feature = "dump")]
+                if log::log_enabled!(log::Level::Debug) {
+                    log::debug!(
+                        "stun request: addr={}, method={:?}, attributes=[{}]",
+                        address,
+                        message.method,
+                        crate::dump::attributes(bytes)
+                    );
+                }
+
+                // The send indications carry the relayed data, which is not
+                // limited.
+                if message.method != Method::SendIndication && !self.service.is_rate_allowed(&address) {
+                    return Ok(None);
+                }
+
+                if message.method == Method::Binding(Kind::Request)
+                    && !self.service.policies.get().binding.is_allowed(&address.ip())
+                {
+                    return Ok(None);
+                }
+
+                let middleware = self.service.middleware.as_deref();
+                if let Some(middleware) = middleware {
+                    self.bytes.clear();
+
+                    match middleware.before(&self.address, &message, &mut self.bytes) {
+                        Action::Continue => (),
+                        Action::Drop => return Ok(None),
+                        Action::Respond(method) => return Ok(Some(Response {
+                            method: ResponseMethod::Stun(method),
+                            bytes: &self.bytes,
+                            endpoint: None,
+                            relay: None,
+                            delay: None,
+                        })),
+                    }
+                }
+
                 let req = Requet {
                     bytes: &mut self.bytes,
                     service: &self.service,
@@ -304,16 +947,184 @@ where
                     message: &message,
                 };
 
-                match req.message.method {
-                    Method::Binding(Kind::Request) => binding::process(req),
+                let res = match req.message.method {
+                    _ if self.service.saturated => saturated(req),
+                    Method::Binding(Kind::Request) => binding::process(req).await,
                     Method::Allocate(Kind::Request) => allocate::process(req).await,
                     Method::CreatePermission(Kind::Request) => create_permission::process(req).await,
                     Method::ChannelBind(Kind::Request) => channel_bind::process(req).await,
                     Method::Refresh(Kind::Request) => refresh::process(req).await,
-                    Method::SendIndication => indication::process(req),
+                    Method::SendIndication => indication::process(req).await,
                     _ => None,
+                };
+
+                if let Some(middleware) = middleware {
+                    middleware.after(&self.address, &message, res.as_ref());
                 }
+
+                #[cfg(feature = "dump")]
+                if let Some(res) = res.as_ref().filter(|_| log::log_enabled!(log::Level::Debug)) {
+                    log::debug!(
+                        "stun response: addr={}, method={:?}, attributes=[{}]",
+                        address,
+                        res.method,
+                        crate::dump::attributes(res.bytes)
+                    );
+                }
+
+                res
             }
         })
     }
+
+    /// process the raw bytes and return a copy of the response.
+    ///
+    /// This is the catch-all entry of the dispatcher, which is suitable for
+    /// fuzzing: any parse or processing failure is swallowed and reported as
+    /// `None`, the same as a packet that needs no response.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use std::net::SocketAddr;
+    /// use mycrl_turn::*;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {}
+    ///
+    /// let addr = "127.0.0.1:8080".parse::<SocketAddr>().unwrap();
+    /// let service = Service::new("test".to_string(), vec![], ServiceOptions::default(), ObserverTest);
+    /// let mut operationer = service.get_operationer(addr, addr);
+    ///
+    /// let binding = [
+    ///     0x00, 0x01, 0x00, 0x00, 0x21, 0x12, 0xa4, 0x42, 0x71, 0x66, 0x46, 0x31,
+    ///     0x2b, 0x59, 0x79, 0x65, 0x56, 0x69, 0x32, 0x72,
+    /// ];
+    ///
+    /// let truncated = [0x00, 0x01, 0x00, 0x08, 0x21, 0x12, 0xa4, 0x42];
+    /// let channel_data = [0x40, 0x00, 0xff, 0xff, 0x01];
+    ///
+    /// assert!(pollster::block_on(operationer.try_process(&binding, addr)).is_some());
+    /// assert!(pollster::block_on(operationer.try_process(&truncated, addr)).is_none());
+    /// assert!(pollster::block_on(operationer.try_process(&channel_data, addr)).is_none());
+    /// assert!(pollster::block_on(operationer.try_process(&[], addr)).is_none());
+    /// ```
+    pub async fn try_process(&mut self, raw: &[u8], address: SocketAddr) -> Option<Vec<u8>> {
+        match self.route(raw, address).await {
+            Ok(Some(res)) => Some(res.bytes.to_vec()),
+            _ => None,
+        }
+    }
+
+    /// Set the transport of the clients of the operationer, which is UDP by
+    /// default. A detached allocation is only reclaimed by a client over the
+    /// transport it was detached from, see [`Sessions::reclaim`].
+    pub fn set_transport(&mut self, transport: Transport) {
+        self.service.transport = transport;
+    }
+
+    /// Mark the transport of the operationer as saturated.
+    ///
+    /// The transport that has no room for one more client, such as a
+    /// connection over the connection cap of the server, answers every
+    /// request with 508 (Insufficient Capacity) without processing it, and
+    /// the connection is closed after the response. The response carries no
+    /// challenge, so that no state is kept for the client.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use std::net::SocketAddr;
+    ///
+    /// use bytes::BytesMut;
+    /// use mycrl_turn::*;
+    /// use stun::{
+    ///     attribute::{ErrorCode, ErrorKind, Realm, ReqeestedTransport, Transport, UserName},
+    ///     util::long_term_credential_digest,
+    ///     Decoder, Kind, MessageWriter, Method, Payload,
+    /// };
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(&self, _: &SessionAddr, _: &str) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let interface = "127.0.0.1:3478".parse::<SocketAddr>().unwrap();
+    /// let service = Service::new(
+    ///     "localhost".to_string(),
+    ///     vec![interface],
+    ///     ServiceOptions::default(),
+    ///     ObserverTest,
+    /// );
+    ///
+    /// let digest = long_term_credential_digest("test", "test", "localhost");
+    /// let mut operationer = service.get_operationer(interface, interface);
+    /// let allocate = |operationer: &mut Operationer<ObserverTest>| {
+    ///     let mut bytes = BytesMut::with_capacity(1500);
+    ///     let mut message =
+    ///         MessageWriter::new(Method::Allocate(Kind::Request), &[0u8; 12], &mut bytes);
+    ///     message.append::<ReqeestedTransport>(Transport::UDP);
+    ///     message.append::<UserName>("test");
+    ///     message.append::<Realm>("localhost");
+    ///     message.flush(Some(&digest)).unwrap();
+    ///
+    ///     let client = "127.0.0.1:10000".parse().unwrap();
+    ///     let res = pollster::block_on(operationer.route(&bytes, client));
+    ///     let mut decoder = Decoder::default();
+    ///     if let Payload::Message(message) = decoder.decode(res.unwrap().unwrap().bytes).unwrap() {
+    ///         message.get::<ErrorCode>().map(|it| it.code)
+    ///     } else {
+    ///         unreachable!()
+    ///     }
+    /// };
+    ///
+    /// operationer.set_saturated(true);
+    /// assert_eq!(
+    ///     allocate(&mut operationer),
+    ///     Some(ErrorKind::InsufficientCapacity as u16)
+    /// );
+    /// assert!(service.get_sessions().get_session(&SessionAddr {
+    ///     address: "127.0.0.1:10000".parse().unwrap(),
+    ///     interface,
+    /// }).get_ref().is_none());
+    ///
+    /// operationer.set_saturated(false);
+    /// assert_eq!(allocate(&mut operationer), None);
+    /// ```
+    pub fn set_saturated(&mut self, saturated: bool) {
+        self.service.saturated = saturated;
+    }
+}
+
+/// Answer the request with 508 (Insufficient Capacity) without processing
+/// it, see [`Operationer::set_saturated`]. The indications are dropped.
+fn saturated<'a, T: Observer>(req: Requet<'_, 'a, T, MessageReader<'_>>) -> Option<Response<'a>> {
+    let method = match req.message.method {
+        Method::Binding(Kind::Request) => Method::Binding(Kind::Error),
+        Method::Allocate(Kind::Request) => Method::Allocate(Kind::Error),
+        Method::CreatePermission(Kind::Request) => Method::CreatePermission(Kind::Error),
+        Method::ChannelBind(Kind::Request) => Method::ChannelBind(Kind::Error),
+        Method::Refresh(Kind::Request) => Method::Refresh(Kind::Error),
+        _ => return None,
+    };
+
+    {
+        let mut message = MessageWriter::extend(method, req.message, req.bytes);
+        message.append::<ErrorCode>(Error::from(ErrorKind::InsufficientCapacity));
+        message.flush(None).ok()?;
+    }
+
+    Some(Response {
+        method: ResponseMethod::Stun(method),
+        bytes: req.bytes,
+        endpoint: None,
+        relay: None,
+        delay: None,
+    })
 }
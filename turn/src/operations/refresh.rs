@@ -1,31 +1,18 @@
 use stun::{
-    attribute::{Error, ErrorCode, ErrorKind, Lifetime},
+    attribute::{ErrorKind, Lifetime, MobilityTicket},
     Kind, MessageReader, MessageWriter, Method,
 };
 
-use super::{Requet, Response, ResponseMethod};
+use super::{ProcessError, Requet, Response, ResponseMethod};
 use crate::Observer;
 
 /// return refresh error response
 #[inline(always)]
 fn reject<'a, T: Observer>(
     req: Requet<'_, 'a, T, MessageReader<'_>>,
-    err: ErrorKind,
+    err: ProcessError,
 ) -> Option<Response<'a>> {
-    {
-        let mut message =
-            MessageWriter::extend(Method::Refresh(Kind::Error), &req.message, req.bytes);
-
-        message.append::<ErrorCode>(Error::from(err));
-        message.flush(None).ok()?;
-    }
-
-    Some(Response {
-        method: ResponseMethod::Stun(Method::Refresh(Kind::Error)),
-        bytes: req.bytes,
-        endpoint: None,
-        relay: None,
-    })
+    super::reject(req, Method::Refresh(Kind::Error), err)
 }
 
 /// return refresh ok response
@@ -37,9 +24,20 @@ pub fn resolve<'a, T: Observer>(
 ) -> Option<Response<'a>> {
     {
         let mut message =
-            MessageWriter::extend(Method::Refresh(Kind::Response), &req.message, req.bytes);
+            MessageWriter::extend(Method::Refresh(Kind::Response), req.message, req.bytes);
 
         message.append::<Lifetime>(lifetime);
+
+        // Every ticket is replaced, the client keeps the last one.
+        let ticket = match (&req.service.mobility, req.message.get::<MobilityTicket>()) {
+            (Some(tickets), Some(_)) => Some(tickets.issue(req.address)),
+            _ => None,
+        };
+
+        if let Some(ticket) = &ticket {
+            message.append::<MobilityTicket>(ticket);
+        }
+
         message.flush(Some(digest)).ok()?;
     }
 
@@ -48,6 +46,7 @@ pub fn resolve<'a, T: Observer>(
         bytes: req.bytes,
         endpoint: None,
         relay: None,
+        delay: None,
     })
 }
 
@@ -81,6 +80,15 @@ pub fn resolve<'a, T: Observer>(
 /// * A LIFETIME attribute containing the current value of the time-to-expiry
 ///   timer.
 ///
+/// [rfc8016](https://tools.ietf.org/html/rfc8016)
+///
+/// A Refresh request with a MOBILITY-TICKET attribute from an address that
+/// has no allocation moves the allocation the ticket was issued to, when it
+/// was authenticated with the same credentials, to the new address. The
+/// invalid tickets are rejected with a 400 (Bad Request) error, and the
+/// tickets are rejected with a 405 (Mobility Forbidden) error when the
+/// service does not offer mobility.
+///
 /// NOTE: A server need not do anything special to implement
 /// idempotency of Refresh requests over UDP using the "stateless
 /// stack approach".  Retransmitted Refresh requests with a non-
@@ -93,17 +101,75 @@ pub async fn process<'a, T: Observer>(
     req: Requet<'_, 'a, T, MessageReader<'_>>,
 ) -> Option<Response<'a>> {
     let (username, digest) = match req.auth().await {
-        None => return reject(req, ErrorKind::Unauthorized),
+        Err(err) => {
+            let delay = req.auth_failure_delay(&err);
+            return reject(req, err).map(|it| it.with_delay(delay));
+        }
+        Ok(it) => it,
+    };
+
+    // The client has moved to this address, the allocation of the address the
+    // ticket was issued to is taken over before it is refreshed.
+    if let Some(ticket) = req.message.get::<MobilityTicket>() {
+        let tickets = match &req.service.mobility {
+            Some(it) => it,
+            None => return reject(req, ProcessError::Policy(ErrorKind::MobilityForbidden)),
+        };
+
+        if req.service.sessions.get_allocation(req.address).is_none() {
+            let moved = tickets
+                .redeem(ticket)
+                .map(|previous| {
+                    req.service
+                        .sessions
+                        .relocate(&previous, req.address, &digest)
+                })
+                .unwrap_or(false);
+
+            if !moved {
+                return reject(req, ProcessError::Parse(ErrorKind::BadRequest));
+            }
+        }
+    }
+
+    // The lifetime is clamped to the bounds of the allocate request, the zero
+    // lifetime still deletes the allocation.
+    let lifetime = match req.message.get::<Lifetime>().unwrap_or(600) {
+        0 => 0,
+        it => it.clamp(600, 3600),
+    };
+
+    // Allocations that have not relayed any data for the configured window are
+    // denied the refresh, so the client has to reallocate if it is still active.
+    if let Some(timeout) = req.service.options.inactivity_timeout {
+        if lifetime > 0
+            && req
+                .service
+                .sessions
+                .get_inactive_time(req.address)
+                .map(|it| it >= timeout)
+                .unwrap_or(false)
+        {
+            return reject(req, ProcessError::Policy(ErrorKind::AllocationMismatch));
+        }
+    }
+
+    // The allocation is deleted by a zero lifetime, so the context is taken
+    // before the refresh. A session without an allocation, as after a change
+    // of the source port of the client, has nothing to refresh.
+    let mut allocation = match req.service.sessions.get_allocation(req.address) {
         Some(it) => it,
+        None => return reject(req, ProcessError::Policy(ErrorKind::AllocationMismatch)),
     };
 
-    let lifetime = req.message.get::<Lifetime>().unwrap_or(600);
-    if !req.service.sessions.refresh(&req.address, lifetime) {
-        return reject(req, ErrorKind::AllocationMismatch);
+    if !req.service.sessions.refresh(req.address, lifetime) {
+        return reject(req, ProcessError::Policy(ErrorKind::AllocationMismatch));
     }
 
+    allocation.lifetime = lifetime;
     req.service
         .observer
-        .refresh(&req.address, username, lifetime);
+        .refresh(req.address, username, lifetime, &allocation);
+
     resolve(req, lifetime, &digest)
 }
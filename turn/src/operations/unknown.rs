@@ -0,0 +1,97 @@
+use super::{Requet, Response, ResponseMethod};
+use crate::Observer;
+
+use stun::{
+    attribute::{Error, ErrorCode, ErrorKind},
+    MessageWriter, UnknownMethod,
+};
+
+/// process the message of an unknown method
+///
+/// [rfc8489](https://tools.ietf.org/html/rfc8489)
+///
+/// If the method of a request is not supported by the server, the server
+/// replies an error response with the error code 400 (Bad Request). The
+/// indications and the responses of the unknown methods are silently
+/// discarded. In all cases the observer is notified of the method number, so
+/// that the operators can spot the clients using unsupported features.
+///
+/// # Test
+///
+/// ```
+/// use std::{
+///     net::SocketAddr,
+///     sync::{
+///         atomic::{AtomicUsize, Ordering},
+///         Arc,
+///     },
+/// };
+///
+/// use mycrl_turn::*;
+///
+/// #[derive(Clone, Default)]
+/// struct ObserverTest(Arc<AtomicUsize>);
+///
+/// impl Observer for ObserverTest {
+///     fn unknown_method(&self, _: &SessionAddr, method: u16) {
+///         assert_eq!(method, 0x00a);
+///         self.0.fetch_add(1, Ordering::Relaxed);
+///     }
+/// }
+///
+/// let addr = "127.0.0.1:3478".parse::<SocketAddr>().unwrap();
+/// let client = "127.0.0.1:8080".parse::<SocketAddr>().unwrap();
+///
+/// // The Connect request of the TCP allocations (RFC 6062).
+/// let mut connect = [
+///     0x00, 0x0a, 0x00, 0x00, 0x21, 0x12, 0xa4, 0x42, 0x71, 0x66, 0x46, 0x31,
+///     0x2b, 0x59, 0x79, 0x65, 0x56, 0x69, 0x32, 0x72,
+/// ];
+///
+/// let observer = ObserverTest::default();
+/// let service = Service::new("test".to_string(), vec![], ServiceOptions::default(), observer.clone());
+/// let mut operationer = service.get_operationer(addr, addr);
+///
+/// let res = pollster::block_on(operationer.route(&connect, client)).unwrap().unwrap();
+/// assert_eq!(res.method, ResponseMethod::UnknownMethod(0x011a));
+/// assert_eq!(&res.bytes[..2], &[0x01, 0x1a]);
+/// assert_eq!(&res.bytes[8..20], &connect[8..20]);
+///
+/// // The error code attribute, 400 (Bad Request).
+/// assert_eq!(&res.bytes[20..22], &[0x00, 0x09]);
+/// assert_eq!(&res.bytes[26..28], &[0x04, 0x00]);
+///
+/// // The indication is discarded.
+/// connect[1] = 0x1a;
+/// assert!(pollster::block_on(operationer.route(&connect, client)).unwrap().is_none());
+///
+/// assert_eq!(observer.0.load(Ordering::Relaxed), 2);
+/// ```
+pub fn process<'a, T: Observer>(
+    bytes: &[u8],
+    req: Requet<'_, 'a, T, UnknownMethod>,
+) -> Option<Response<'a>> {
+    req.service
+        .observer
+        .unknown_method(req.address, req.message.method());
+
+    if !req.message.is_request() {
+        return None;
+    }
+
+    let token: &[u8; 12] = bytes.get(8..20)?.try_into().ok()?;
+
+    {
+        let mut message = MessageWriter::unknown_error(*req.message, token, req.bytes);
+        message.append::<ErrorCode>(Error::from(ErrorKind::BadRequest));
+        message.flush(None).ok()?;
+    }
+
+    Some(Response {
+        method: ResponseMethod::UnknownMethod(req.message.error()),
+        bytes: req.bytes,
+        endpoint: None,
+        relay: None,
+        delay: None,
+    })
+}
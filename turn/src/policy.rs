@@ -0,0 +1,864 @@
+//! Network policies of the service.
+//!
+//! The policies select the networks that the service answers, they are
+//! consulted before the requests are processed.
+
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use ahash::AHashMap;
+use parking_lot::{Mutex, RwLock};
+
+/// The error of parsing a network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidCidr(pub String);
+
+impl fmt::Display for InvalidCidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid cidr: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidCidr {}
+
+/// A network in the CIDR notation.
+///
+/// The address without a prefix length is a network of a single address.
+/// The IPv4-mapped IPv6 addresses match the IPv4 networks.
+///
+/// # Test
+///
+/// ```
+/// use mycrl_turn::policy::Cidr;
+///
+/// let cidr = "10.0.0.0/8".parse::<Cidr>().unwrap();
+/// assert!(cidr.contains(&"10.1.2.3".parse().unwrap()));
+/// assert!(cidr.contains(&"::ffff:10.1.2.3".parse().unwrap()));
+/// assert!(!cidr.contains(&"11.0.0.1".parse().unwrap()));
+/// assert!(!cidr.contains(&"::1".parse().unwrap()));
+///
+/// let cidr = "2001:db8::/32".parse::<Cidr>().unwrap();
+/// assert!(cidr.contains(&"2001:db8:1::1".parse().unwrap()));
+/// assert!(!cidr.contains(&"2001:db9::1".parse().unwrap()));
+///
+/// let cidr = "127.0.0.1".parse::<Cidr>().unwrap();
+/// assert!(cidr.contains(&"127.0.0.1".parse().unwrap()));
+/// assert!(!cidr.contains(&"127.0.0.2".parse().unwrap()));
+///
+/// assert!("0.0.0.0/0".parse::<Cidr>().unwrap().contains(&"1.1.1.1".parse().unwrap()));
+/// assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+/// assert!("10.0.0/8".parse::<Cidr>().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    ip: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// Check if the address is in the network.
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.ip, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = InvalidCidr;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let error = || InvalidCidr(value.to_string());
+        let (ip, prefix) = match value.split_once('/') {
+            Some((ip, prefix)) => (ip, Some(prefix)),
+            None => (value, None),
+        };
+
+        let ip = ip.parse::<IpAddr>().map_err(|_| error())?.to_canonical();
+        let max = if ip.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(it) => it.parse::<u8>().map_err(|_| error())?,
+            None => max,
+        };
+
+        if prefix > max {
+            return Err(error());
+        }
+
+        Ok(Self { ip, prefix })
+    }
+}
+
+/// An allow and deny list of networks.
+///
+/// The denied networks take precedence over the allowed networks, and an
+/// empty allow list allows all networks, so the default policy allows
+/// everything.
+///
+/// # Test
+///
+/// ```
+/// use mycrl_turn::policy::NetworkPolicy;
+///
+/// let policy = NetworkPolicy::default();
+/// assert!(policy.is_allowed(&"192.0.2.1".parse().unwrap()));
+///
+/// let policy = NetworkPolicy {
+///     allow: vec!["10.0.0.0/8".parse().unwrap()],
+///     deny: vec!["10.0.0.0/24".parse().unwrap()],
+/// };
+///
+/// assert!(policy.is_allowed(&"10.1.0.1".parse().unwrap()));
+/// assert!(!policy.is_allowed(&"10.0.0.1".parse().unwrap()));
+/// assert!(!policy.is_allowed(&"192.0.2.1".parse().unwrap()));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NetworkPolicy {
+    pub allow: Vec<Cidr>,
+    pub deny: Vec<Cidr>,
+}
+
+impl NetworkPolicy {
+    /// Check if the address is allowed by the policy.
+    pub fn is_allowed(&self, ip: &IpAddr) -> bool {
+        if self.deny.iter().any(|it| it.contains(ip)) {
+            return false;
+        }
+
+        self.allow.is_empty() || self.allow.iter().any(|it| it.contains(ip))
+    }
+}
+
+/// The network policies that can be replaced while the service is running.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Policies {
+    /// The networks of the clients whose binding requests are answered.
+    pub binding: NetworkPolicy,
+    /// The networks of the peers that the clients can create permissions and
+    /// bind channels for, the other peers are rejected with a 403
+    /// (Forbidden) error.
+    pub peer: NetworkPolicy,
+}
+
+/// The current policies of a service.
+///
+/// The policies are swapped as a whole, the requests in flight keep the
+/// policies they started with. Only the decisions made after the swap use
+/// the new policies, the existing permissions and channels are kept.
+pub struct PolicyStore(RwLock<Arc<Policies>>);
+
+impl PolicyStore {
+    pub fn new(policies: Policies) -> Self {
+        Self(RwLock::new(Arc::new(policies)))
+    }
+
+    /// Get the current policies.
+    pub fn get(&self) -> Arc<Policies> {
+        self.0.read().clone()
+    }
+
+    /// Replace the current policies.
+    pub fn reload(&self, policies: Policies) {
+        *self.0.write() = Arc::new(policies);
+    }
+}
+
+/// The address families of the relays.
+///
+/// The relay of an allocation is on the interface the allocate request
+/// arrived on, so the single-stack modes reject the allocations on the
+/// interfaces of the other family with a 440 (Address Family not Supported)
+/// error, as well as the requests whose REQUESTED-ADDRESS-FAMILY is not the
+/// family of the interface.
+///
+/// # Test
+///
+/// ```
+/// use std::net::SocketAddr;
+///
+/// use bytes::BytesMut;
+/// use mycrl_turn::{policy::FamilyMode, *};
+/// use stun::{
+///     attribute::{
+///         ErrorCode, ErrorKind, IpFamily, Realm, ReqeestedTransport, RequestedAddressFamily,
+///         Transport, UserName,
+///     },
+///     util::long_term_credential_digest,
+///     Decoder, Kind, MessageWriter, Method, Payload,
+/// };
+///
+/// #[derive(Clone)]
+/// struct ObserverTest;
+///
+/// impl Observer for ObserverTest {
+///     async fn get_password(&self, _: &SessionAddr, _: &str) -> Option<String> {
+///         Some("test".to_string())
+///     }
+/// }
+///
+/// let v4 = "127.0.0.1:3478".parse::<SocketAddr>().unwrap();
+/// let v6 = "[::1]:3478".parse::<SocketAddr>().unwrap();
+/// let digest = long_term_credential_digest("test", "test", "localhost");
+///
+/// let allocate = |mode: FamilyMode, interface: SocketAddr, family: Option<IpFamily>| {
+///     let service = Service::new(
+///         "localhost".to_string(),
+///         vec![v4, v6],
+///         ServiceOptions {
+///             relay_family: mode,
+///             ..Default::default()
+///         },
+///         ObserverTest,
+///     );
+///
+///     let mut bytes = BytesMut::with_capacity(1500);
+///     let mut message =
+///         MessageWriter::new(Method::Allocate(Kind::Request), &[0u8; 12], &mut bytes);
+///     message.append::<ReqeestedTransport>(Transport::UDP);
+///     if let Some(family) = family {
+///         message.append::<RequestedAddressFamily>(family);
+///     }
+///
+///     message.append::<UserName>("test");
+///     message.append::<Realm>("localhost");
+///     message.flush(Some(&digest)).unwrap();
+///
+///     let client = SocketAddr::new(interface.ip(), 10000);
+///     let mut operationer = service.get_operationer(interface, interface);
+///     let res = pollster::block_on(operationer.route(&bytes, client));
+///     let mut decoder = Decoder::default();
+///     if let Payload::Message(message) = decoder.decode(res.unwrap().unwrap().bytes).unwrap() {
+///         message.get::<ErrorCode>().map(|it| it.code)
+///     } else {
+///         unreachable!()
+///     }
+/// };
+///
+/// let unsupported = Some(ErrorKind::AddressFamilyNotSupported as u16);
+///
+/// assert_eq!(allocate(FamilyMode::Dual, v4, None), None);
+/// assert_eq!(allocate(FamilyMode::Dual, v6, None), None);
+/// assert_eq!(allocate(FamilyMode::Dual, v6, Some(IpFamily::V6)), None);
+/// assert_eq!(allocate(FamilyMode::Dual, v4, Some(IpFamily::V6)), unsupported);
+///
+/// assert_eq!(allocate(FamilyMode::Ipv4, v4, None), None);
+/// assert_eq!(allocate(FamilyMode::Ipv4, v4, Some(IpFamily::V4)), None);
+/// assert_eq!(allocate(FamilyMode::Ipv4, v6, None), unsupported);
+///
+/// assert_eq!(allocate(FamilyMode::Ipv6, v6, None), None);
+/// assert_eq!(allocate(FamilyMode::Ipv6, v4, None), unsupported);
+/// assert_eq!(allocate(FamilyMode::Ipv6, v6, Some(IpFamily::V4)), unsupported);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FamilyMode {
+    /// Both the IPv4 and the IPv6 relays.
+    #[default]
+    Dual,
+    /// Only the IPv4 relays.
+    Ipv4,
+    /// Only the IPv6 relays.
+    Ipv6,
+}
+
+impl FamilyMode {
+    /// Check if the family of the address is enabled.
+    pub fn is_enabled(&self, ip: &IpAddr) -> bool {
+        match self {
+            Self::Dual => true,
+            Self::Ipv4 => ip.to_canonical().is_ipv4(),
+            Self::Ipv6 => ip.to_canonical().is_ipv6(),
+        }
+    }
+}
+
+/// The handling of a client whose source port changes during its allocation,
+/// as after a NAT rebinding.
+///
+/// In the strict mode the new 5-tuple is a new session, it has to allocate
+/// again and the requests for the old allocation are rejected with a 437
+/// (Allocation Mismatch) error. In the lenient mode the first authenticated
+/// request from the new port, other than an allocate request, migrates the
+/// allocation of the same IP address, interface and credential to the new
+/// port, once the allocation has been idle for
+/// [`ServiceOptions::port_change_idle`](crate::ServiceOptions::port_change_idle).
+/// The message integrity is checked before the migration, so only the owner
+/// of the credential can migrate the allocation. As the clients behind the
+/// same NAT look alike, the lenient mode is only safe when the credentials
+/// are not shared by the clients.
+///
+/// # Test
+///
+/// ```
+/// use std::net::SocketAddr;
+///
+/// use bytes::BytesMut;
+/// use mycrl_turn::{policy::PortChangeMode, *};
+/// use stun::{
+///     attribute::{
+///         ErrorCode, ErrorKind, ReqeestedTransport, Realm, Transport, UserName, XorRelayedAddress,
+///     },
+///     util::long_term_credential_digest,
+///     Decoder, Kind, MessageWriter, Method, Payload,
+/// };
+///
+/// #[derive(Clone)]
+/// struct ObserverTest;
+///
+/// impl Observer for ObserverTest {
+///     async fn get_password(&self, _: &SessionAddr, _: &str) -> Option<String> {
+///         Some("test".to_string())
+///     }
+/// }
+///
+/// let interface = "127.0.0.1:3478".parse::<SocketAddr>().unwrap();
+/// let digest = long_term_credential_digest("test", "test", "localhost");
+/// let request = |service: &Service<ObserverTest>, client: &str, method: Method| {
+///     let mut bytes = BytesMut::with_capacity(1500);
+///     let mut message = MessageWriter::new(method, &[0u8; 12], &mut bytes);
+///     message.append::<ReqeestedTransport>(Transport::UDP);
+///     message.append::<UserName>("test");
+///     message.append::<Realm>("localhost");
+///     message.flush(Some(&digest)).unwrap();
+///
+///     let mut operationer = service.get_operationer(interface, interface);
+///     let res = pollster::block_on(operationer.route(&bytes, client.parse().unwrap()));
+///     let mut decoder = Decoder::default();
+///     match decoder.decode(res.unwrap().unwrap().bytes).unwrap() {
+///         Payload::Message(message) => (
+///             message.get::<ErrorCode>().map(|it| it.code),
+///             message.get::<XorRelayedAddress>(),
+///         ),
+///         _ => unreachable!(),
+///     }
+/// };
+///
+/// let port = |service: &Service<ObserverTest>, client: &str| {
+///     let addr = SessionAddr { address: client.parse().unwrap(), interface };
+///     service.get_sessions().get_session(&addr).get_ref().and_then(|it| it.allocate.port)
+/// };
+///
+/// let allocate = Method::Allocate(Kind::Request);
+/// let refresh = Method::Refresh(Kind::Request);
+/// for mode in [PortChangeMode::Strict, PortChangeMode::Lenient] {
+///     let service = Service::new(
+///         "localhost".to_string(),
+///         vec![interface],
+///         ServiceOptions {
+///             port_change: mode,
+///             ..Default::default()
+///         },
+///         ObserverTest,
+///     );
+///
+///     let relay = request(&service, "127.0.0.1:10000", allocate).1.unwrap();
+///
+///     // The source port of the client changes.
+///     let (err, _) = request(&service, "127.0.0.1:10001", refresh);
+///     if mode == PortChangeMode::Strict {
+///         assert_eq!(err, Some(ErrorKind::AllocationMismatch as u16));
+///         assert_eq!(port(&service, "127.0.0.1:10000"), Some(relay.port()));
+///         assert_eq!(port(&service, "127.0.0.1:10001"), None);
+///     } else {
+///         assert_eq!(err, None);
+///         assert_eq!(port(&service, "127.0.0.1:10000"), None);
+///         assert_eq!(port(&service, "127.0.0.1:10001"), Some(relay.port()));
+///     }
+///
+///     // A new allocation from another port never takes over an allocation.
+///     let (err, other) = request(&service, "127.0.0.1:10002", allocate);
+///     assert_eq!(err, None);
+///     assert_ne!(other, Some(relay));
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PortChangeMode {
+    /// The new source port has to allocate again.
+    #[default]
+    Strict,
+    /// The new source port takes over the allocation with a valid message
+    /// integrity.
+    Lenient,
+}
+
+/// The limits of the request rate of the clients.
+///
+/// The limits are in requests per second, and a single address as well as
+/// the network aggregate it belongs to are limited, so that a client that
+/// rotates the addresses of its network is throttled collectively. The
+/// default aggregates are the /24 IPv4 networks and the /48 IPv6 networks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// The requests per second of a single address, unlimited if `None`.
+    pub per_ip: Option<u32>,
+    /// The requests per second of a network aggregate, unlimited if `None`.
+    pub per_network: Option<u32>,
+    /// The prefix length of the IPv4 network aggregates.
+    pub ipv4_prefix: u8,
+    /// The prefix length of the IPv6 network aggregates.
+    pub ipv6_prefix: u8,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            per_ip: None,
+            per_network: None,
+            ipv4_prefix: 24,
+            ipv6_prefix: 48,
+        }
+    }
+}
+
+impl RateLimit {
+    /// Get the network aggregate of the address.
+    fn network(&self, ip: &IpAddr) -> IpAddr {
+        match ip.to_canonical() {
+            IpAddr::V4(ip) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.ipv4_prefix.min(32) as u32)
+                    .unwrap_or(0);
+                IpAddr::V4(Ipv4Addr::from(u32::from(ip) & mask))
+            }
+            IpAddr::V6(ip) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.ipv6_prefix.min(128) as u32)
+                    .unwrap_or(0);
+                IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask))
+            }
+        }
+    }
+}
+
+/// A token bucket, which is full after a second of inactivity.
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Get the tokens of the bucket at the time.
+    fn tokens(&self, rate: u32, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        (self.tokens + elapsed * rate as f64).min(rate as f64)
+    }
+}
+
+/// The token buckets of a kind of the keys of the rate limits.
+///
+/// The full buckets are the same as the missing ones, they are dropped when
+/// the table has doubled since it was last pruned. At the capacity, the table
+/// is pruned at most once a second, the time a bucket takes to refill, so
+/// that a flood of the new keys does not scan the table on every request.
+struct Buckets {
+    table: AHashMap<IpAddr, Bucket>,
+    /// The size to prune the table at, and the time it was last pruned.
+    prune: usize,
+    pruned: Instant,
+}
+
+impl Buckets {
+    fn new(capacity: usize) -> Self {
+        Self {
+            table: AHashMap::with_capacity(capacity.min(1024)),
+            prune: capacity.min(1024),
+            pruned: Instant::now(),
+        }
+    }
+
+    /// Make room for the bucket of the key, returns false if the table is at
+    /// the capacity and none of its buckets can be dropped.
+    fn reserve(&mut self, key: &IpAddr, rate: u32, now: Instant, capacity: usize) -> bool {
+        let len = self.table.len();
+        if len < self.prune || self.table.contains_key(key) {
+            return true;
+        }
+
+        if len < capacity || now.saturating_duration_since(self.pruned) >= Duration::from_secs(1) {
+            self.table.retain(|_, it| it.tokens(rate, now) < rate as f64);
+            self.prune = (self.table.len() * 2).clamp(capacity.min(1024), capacity);
+            self.pruned = now;
+        }
+
+        self.table.len() < capacity
+    }
+}
+
+/// The token buckets of the request rate limits.
+///
+/// A request is allowed when both the bucket of its address and the bucket
+/// of its network aggregate have a token, so that the most restrictive limit
+/// wins, and a denied request does not consume the tokens. The buckets that
+/// have been refilled are dropped as the number of the buckets grows, and
+/// the requests of the new addresses are denied while the buckets are at the
+/// capacity and none of them has been refilled, which bounds the memory used
+/// by the limiter.
+///
+/// # Test
+///
+/// ```
+/// use mycrl_turn::policy::{RateLimit, RateLimiter};
+///
+/// let limiter = RateLimiter::new(
+///     RateLimit {
+///         per_ip: Some(2),
+///         per_network: Some(5),
+///         ..Default::default()
+///     },
+///     1024,
+/// );
+///
+/// // A single address is throttled by the limit of the address.
+/// let ip = "10.0.0.1".parse().unwrap();
+/// assert!(limiter.allow(&ip));
+/// assert!(limiter.allow(&ip));
+/// assert!(!limiter.allow(&ip));
+///
+/// // Flooding from the other addresses of the /24 only gets the remaining
+/// // tokens of the network.
+/// let allowed = (2..=20)
+///     .filter(|it| limiter.allow(&format!("10.0.0.{}", it).parse().unwrap()))
+///     .count();
+///
+/// assert_eq!(allowed, 3);
+///
+/// // The other networks are not affected.
+/// assert!(limiter.allow(&"10.0.1.1".parse().unwrap()));
+/// assert!(limiter.allow(&"2001:db8::1".parse().unwrap()));
+///
+/// // A flood of the new addresses fills the buckets, the addresses beyond the
+/// // capacity are denied until the buckets have been refilled.
+/// let limiter = RateLimiter::new(
+///     RateLimit {
+///         per_ip: Some(1),
+///         ..Default::default()
+///     },
+///     4,
+/// );
+///
+/// let ips = (1..=5)
+///     .map(|it| format!("10.0.0.{}", it).parse().unwrap())
+///     .collect::<Vec<_>>();
+///
+/// assert_eq!(ips.iter().filter(|it| limiter.allow(it)).count(), 4);
+///
+/// std::thread::sleep(std::time::Duration::from_millis(1000));
+/// assert!(limiter.allow(&ips[4]));
+/// ```
+pub struct RateLimiter {
+    limit: RateLimit,
+    capacity: usize,
+    buckets: Mutex<(Buckets, Buckets)>,
+}
+
+impl RateLimiter {
+    /// Create a limiter, which keeps about capacity buckets of each kind.
+    pub fn new(limit: RateLimit, capacity: usize) -> Self {
+        Self {
+            buckets: Mutex::new((Buckets::new(capacity), Buckets::new(capacity))),
+            capacity,
+            limit,
+        }
+    }
+
+    /// Check if a request of the address is allowed, the tokens are consumed
+    /// when it is.
+    pub fn allow(&self, ip: &IpAddr) -> bool {
+        let now = Instant::now();
+        let ip = ip.to_canonical();
+        let network = self.limit.network(&ip);
+
+        let mut buckets = self.buckets.lock();
+        let (ips, networks) = &mut *buckets;
+
+        let mut limits = [
+            (ips, ip, self.limit.per_ip),
+            (networks, network, self.limit.per_network),
+        ];
+        for (buckets, key, rate) in limits.iter_mut() {
+            if let Some(rate) = *rate {
+                if buckets
+                    .table
+                    .get(key)
+                    .map(|it| it.tokens(rate, now))
+                    .unwrap_or(rate as f64)
+                    < 1.0
+                {
+                    return false;
+                }
+
+                if !buckets.reserve(key, rate, now, self.capacity) {
+                    return false;
+                }
+            }
+        }
+
+        for (buckets, key, rate) in limits {
+            if let Some(rate) = rate {
+                let tokens = buckets
+                    .table
+                    .get(&key)
+                    .map(|it| it.tokens(rate, now))
+                    .unwrap_or(rate as f64);
+                buckets.table.insert(
+                    key,
+                    Bucket {
+                        tokens: tokens - 1.0,
+                        updated: now,
+                    },
+                );
+            }
+        }
+
+        true
+    }
+}
+
+/// The token bucket of the rate of the new allocations of the server.
+///
+/// The bucket holds a second worth of allocations, so a burst up to the rate
+/// is allowed after a quiet second, and the allocation storms beyond it are
+/// shed. Only the new allocations take a token, the existing allocations
+/// are never affected.
+///
+/// # Test
+///
+/// ```
+/// use mycrl_turn::policy::AllocationLimiter;
+///
+/// let limiter = AllocationLimiter::new(3);
+/// assert!(limiter.allow());
+/// assert!(limiter.allow());
+/// assert!(limiter.allow());
+/// assert!(!limiter.allow());
+///
+/// std::thread::sleep(std::time::Duration::from_millis(400));
+/// assert!(limiter.allow());
+/// assert!(!limiter.allow());
+/// ```
+pub struct AllocationLimiter {
+    rate: u32,
+    bucket: Mutex<Bucket>,
+}
+
+impl AllocationLimiter {
+    /// Create a limiter of the rate in allocations per second.
+    pub fn new(rate: u32) -> Self {
+        Self {
+            bucket: Mutex::new(Bucket {
+                tokens: rate as f64,
+                updated: Instant::now(),
+            }),
+            rate,
+        }
+    }
+
+    /// Check if a new allocation is allowed, the token is consumed when it
+    /// is.
+    pub fn allow(&self) -> bool {
+        let now = Instant::now();
+        let mut bucket = self.bucket.lock();
+
+        let tokens = bucket.tokens(self.rate, now);
+        if tokens < 1.0 {
+            return false;
+        }
+
+        bucket.tokens = tokens - 1.0;
+        bucket.updated = now;
+        true
+    }
+}
+
+/// The requests in flight of the server, for the load shedding.
+///
+/// Every request is counted from its arrival to its response, so the count
+/// grows when the processing falls behind, as when the password lookups of
+/// the observer are slow. Above the threshold the server is overloaded and
+/// the new work is shed, the traffic of the existing allocations is never
+/// affected.
+///
+/// # Test
+///
+/// ```
+/// use mycrl_turn::policy::LoadShedder;
+///
+/// let shedder = LoadShedder::new(1);
+/// let first = shedder.enter();
+/// assert!(!shedder.is_overloaded());
+///
+/// let second = shedder.enter();
+/// assert_eq!(shedder.in_flight(), 2);
+/// assert!(shedder.is_overloaded());
+///
+/// drop(first);
+/// drop(second);
+/// assert_eq!(shedder.in_flight(), 0);
+/// assert!(!shedder.is_overloaded());
+/// ```
+pub struct LoadShedder {
+    threshold: usize,
+    in_flight: AtomicUsize,
+}
+
+impl LoadShedder {
+    /// Create a shedder that is overloaded above the threshold of the
+    /// requests in flight.
+    pub fn new(threshold: usize) -> Self {
+        Self {
+            in_flight: AtomicUsize::new(0),
+            threshold,
+        }
+    }
+
+    /// Count a request in flight until the guard is dropped.
+    pub fn enter(&self) -> InFlight<'_> {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight(self)
+    }
+
+    /// Get the number of the requests in flight.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Check if the requests in flight are above the threshold.
+    pub fn is_overloaded(&self) -> bool {
+        self.in_flight() > self.threshold
+    }
+}
+
+/// A request counted by [`LoadShedder::enter`].
+pub struct InFlight<'a>(&'a LoadShedder);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The relay ips pinned to the usernames.
+///
+/// A pattern is either a username, or a prefix of the usernames followed by
+/// `*`, such as `tenant:*`. A pinned username takes precedence over the
+/// prefixes, and a longer prefix over a shorter one, so the order of the pins
+/// does not matter.
+///
+/// # Test
+///
+/// ```
+/// use mycrl_turn::policy::RelayPins;
+///
+/// let pins = RelayPins::new([
+///     ("acme:*".to_string(), "203.0.113.1".parse().unwrap()),
+///     ("acme:eu:*".to_string(), "203.0.113.2".parse().unwrap()),
+///     ("acme:vip".to_string(), "203.0.113.3".parse().unwrap()),
+/// ]);
+///
+/// assert_eq!(pins.get("acme:alice"), Some("203.0.113.1".parse().unwrap()));
+/// assert_eq!(pins.get("acme:eu:bob"), Some("203.0.113.2".parse().unwrap()));
+/// assert_eq!(pins.get("acme:vip"), Some("203.0.113.3".parse().unwrap()));
+/// assert_eq!(pins.get("other"), None);
+/// assert!(RelayPins::default().is_empty());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct RelayPins(Vec<(String, bool, IpAddr)>);
+
+impl RelayPins {
+    pub fn new(pins: impl IntoIterator<Item = (String, IpAddr)>) -> Self {
+        let mut pins = pins
+            .into_iter()
+            .map(|(pattern, ip)| match pattern.strip_suffix('*') {
+                Some(prefix) => (prefix.to_string(), true, ip),
+                None => (pattern, false, ip),
+            })
+            .collect::<Vec<_>>();
+
+        // The exact usernames first, then the longest prefixes.
+        pins.sort_by(|a, b| a.1.cmp(&b.1).then(b.0.len().cmp(&a.0.len())));
+        Self(pins)
+    }
+
+    /// Check if there are no pins.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Get the relay ip pinned to the username.
+    pub fn get(&self, username: &str) -> Option<IpAddr> {
+        self.0
+            .iter()
+            .find(|(pattern, prefix, _)| {
+                if *prefix {
+                    username.starts_with(pattern.as_str())
+                } else {
+                    username == pattern
+                }
+            })
+            .map(|(_, _, ip)| *ip)
+    }
+}
+
+/// The listeners that are drained for maintenance.
+///
+/// A drained listener takes no new allocations, each of them is either
+/// redirected to the alternate server of the listener, or rejected when
+/// there is none. The existing allocations on the listener are kept.
+///
+/// # Test
+///
+/// ```
+/// use mycrl_turn::policy::Drains;
+///
+/// let first = "127.0.0.1:3478".parse().unwrap();
+/// let second = "127.0.0.1:3479".parse().unwrap();
+/// let alternate = "192.0.2.1:3478".parse().unwrap();
+///
+/// let drains = Drains::default();
+/// drains.drain(first, Some(alternate));
+/// drains.drain(second, None);
+///
+/// assert_eq!(drains.get(&first), Some(Some(alternate)));
+/// assert_eq!(drains.get(&second), Some(None));
+///
+/// assert!(drains.resume(&first));
+/// assert!(!drains.resume(&first));
+/// assert_eq!(drains.get(&first), None);
+/// ```
+#[derive(Default)]
+pub struct Drains(RwLock<AHashMap<SocketAddr, Option<SocketAddr>>>);
+
+impl Drains {
+    /// Drain the listener, the new allocations are redirected to the
+    /// alternate server if there is one.
+    pub fn drain(&self, interface: SocketAddr, alternate: Option<SocketAddr>) {
+        self.0.write().insert(interface, alternate);
+    }
+
+    /// Take new allocations on the listener again, returns false if it was
+    /// not drained.
+    pub fn resume(&self, interface: &SocketAddr) -> bool {
+        self.0.write().remove(interface).is_some()
+    }
+
+    /// Get the alternate server of the drained listener, or `None` if the
+    /// listener is not drained.
+    pub fn get(&self, interface: &SocketAddr) -> Option<Option<SocketAddr>> {
+        self.0.read().get(interface).copied()
+    }
+}
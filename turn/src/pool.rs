@@ -0,0 +1,82 @@
+//! The pool of the response buffers.
+//!
+//! Each operationer writes its responses into one buffer, which is reused
+//! for all the requests of the operationer. The buffer is allocated when the
+//! operationer is created, so every new tcp connection allocates one. The
+//! pool keeps the buffers of the closed connections for the next ones.
+
+use bytes::BytesMut;
+use parking_lot::Mutex;
+
+/// The size of the response buffer of the operationers.
+pub const BUFFER_SIZE: usize = 4096;
+
+/// A bounded free list of the response buffers.
+///
+/// # Example
+///
+/// ```
+/// use mycrl_turn::pool::*;
+///
+/// let pool = BufferPool::new(1);
+///
+/// // The pool is empty, the buffers are allocated.
+/// let first = pool.get();
+/// let second = pool.get();
+/// assert!(first.capacity() >= BUFFER_SIZE);
+///
+/// // Only one buffer is kept, the other one is released.
+/// pool.put(first);
+/// pool.put(second);
+/// assert_eq!(pool.len(), 1);
+///
+/// let buffer = pool.get();
+/// assert!(buffer.is_empty());
+/// assert_eq!(pool.len(), 0);
+/// ```
+pub struct BufferPool {
+    buffers: Mutex<Vec<BytesMut>>,
+    capacity: usize,
+}
+
+impl BufferPool {
+    /// Create a pool that keeps at most the capacity of buffers.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            buffers: Mutex::new(Vec::with_capacity(capacity)),
+            capacity,
+        }
+    }
+
+    /// Take a buffer from the pool, or allocate one if the pool is empty.
+    pub fn get(&self) -> BytesMut {
+        self.buffers
+            .lock()
+            .pop()
+            .unwrap_or_else(|| BytesMut::with_capacity(BUFFER_SIZE))
+    }
+
+    /// Return a buffer to the pool, the buffer is released if the pool is
+    /// full or the buffer has lost its capacity.
+    pub fn put(&self, mut bytes: BytesMut) {
+        if bytes.capacity() < BUFFER_SIZE {
+            return;
+        }
+
+        bytes.clear();
+
+        let mut buffers = self.buffers.lock();
+        if buffers.len() < self.capacity {
+            buffers.push(bytes);
+        }
+    }
+
+    /// Get the number of the buffers in the pool.
+    pub fn len(&self) -> usize {
+        self.buffers.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
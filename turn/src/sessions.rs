@@ -1,21 +1,26 @@
-use crate::Observer;
+use crate::{
+    auth::{derive_key, make_nonce},
+    Observer,
+};
 
 use std::{
     hash::Hash,
-    net::SocketAddr,
+    io,
+    net::{IpAddr, SocketAddr},
     ops::{Deref, DerefMut, Range},
     sync::{
-        atomic::{AtomicU64, Ordering},
+        atomic::{AtomicU64, AtomicUsize, Ordering},
         Arc,
     },
     thread::{self, sleep},
-    time::Duration,
+    time::{Duration, SystemTime},
 };
 
-use ahash::{HashMap, HashMapExt};
+use ahash::{HashMap, HashMapExt, HashSet, HashSetExt};
+use bytes::{BufMut, BytesMut};
 use parking_lot::{Mutex, RwLock, RwLockReadGuard};
-use rand::{distributions::Alphanumeric, thread_rng, Rng};
-use stun::util::long_term_credential_digest;
+use rand::{thread_rng, Rng};
+use stun::attribute::Transport;
 
 /// Authentication information for the session.
 ///
@@ -25,7 +30,11 @@ use stun::util::long_term_credential_digest;
 pub struct Auth {
     pub username: String,
     pub password: String,
+    pub realm: String,
     pub digest: [u8; 16],
+    /// The opaque tenant tag given by [`Observer::get_tenant`], so that the
+    /// sessions can be grouped per tenant.
+    pub tenant: Option<String>,
 }
 
 /// Assignment information for the session.
@@ -35,6 +44,179 @@ pub struct Auth {
 pub struct Allocate {
     pub port: Option<u16>,
     pub channels: Vec<u16>,
+    /// The relayed transport address given to the client, recorded by the
+    /// allocate processor.
+    pub relay: Option<SocketAddr>,
+}
+
+/// The allocation metadata given to the observer callbacks.
+///
+/// The callbacks receive the allocation alongside the session address, so
+/// that the integrators do not have to maintain a parallel map keyed by the
+/// session address.
+///
+/// # Test
+///
+/// ```
+/// use std::{net::SocketAddr, sync::Arc};
+///
+/// use bytes::BytesMut;
+/// use mycrl_turn::{sessions::AllocationContext, *};
+/// use parking_lot::Mutex;
+/// use stun::{
+///     attribute::{Lifetime, ReqeestedTransport, Transport, UserName, Realm, XorPeerAddress},
+///     util::long_term_credential_digest,
+///     Kind, MessageWriter, Method,
+/// };
+///
+/// #[derive(Clone, Default)]
+/// struct ObserverTest(Arc<Mutex<Vec<(&'static str, Option<AllocationContext>)>>>);
+///
+/// impl Observer for ObserverTest {
+///     async fn get_password(&self, _: &SessionAddr, _: &str) -> Option<String> {
+///         Some("test".to_string())
+///     }
+///
+///     fn allocated(&self, _: &SessionAddr, _: &str, _: u16, allocation: &AllocationContext) {
+///         self.0.lock().push(("allocated", Some(*allocation)));
+///     }
+///
+///     fn create_permission(
+///         &self,
+///         _: &SessionAddr,
+///         _: &str,
+///         _: &[u16],
+///         allocation: &AllocationContext,
+///     ) {
+///         self.0.lock().push(("create_permission", Some(*allocation)));
+///     }
+///
+///     fn refresh(&self, _: &SessionAddr, _: &str, _: u32, allocation: &AllocationContext) {
+///         self.0.lock().push(("refresh", Some(*allocation)));
+///     }
+///
+///     fn closed(&self, _: &SessionAddr, _: &str, allocation: Option<&AllocationContext>) {
+///         self.0.lock().push(("closed", allocation.copied()));
+///     }
+/// }
+///
+/// let interface = "127.0.0.1:3478".parse::<SocketAddr>().unwrap();
+/// let external = "192.0.2.1:3478".parse::<SocketAddr>().unwrap();
+/// let observer = ObserverTest::default();
+/// let service = Service::new(
+///     "localhost".to_string(),
+///     vec![external],
+///     ServiceOptions::default(),
+///     observer.clone(),
+/// );
+///
+/// let digest = long_term_credential_digest("test", "test", "localhost");
+/// let mut operationer = service.get_operationer(interface, external);
+/// let mut request = |client: &str, method: Method, lifetime: Option<u32>, peer: Option<SocketAddr>| {
+///     let mut bytes = BytesMut::with_capacity(1500);
+///     let mut message = MessageWriter::new(method, &[0u8; 12], &mut bytes);
+///     message.append::<ReqeestedTransport>(Transport::UDP);
+///     if let Some(lifetime) = lifetime {
+///         message.append::<Lifetime>(lifetime);
+///     }
+///
+///     if let Some(peer) = peer {
+///         message.append::<XorPeerAddress>(peer);
+///     }
+///
+///     message.append::<UserName>("test");
+///     message.append::<Realm>("localhost");
+///     message.flush(Some(&digest)).unwrap();
+///
+///     let client = client.parse().unwrap();
+///     pollster::block_on(operationer.route(&bytes, client)).unwrap().unwrap().method
+/// };
+///
+/// // The peer is another allocation of the service.
+/// request("127.0.0.1:10001", Method::Allocate(Kind::Request), None, None);
+/// let peer = observer.0.lock().pop().unwrap().1.unwrap().relay;
+///
+/// request("127.0.0.1:10000", Method::Allocate(Kind::Request), Some(1200), None);
+///
+/// let relay = observer.0.lock()[0].1.unwrap().relay;
+/// assert_eq!(relay.ip(), external.ip());
+///
+/// request(
+///     "127.0.0.1:10000",
+///     Method::CreatePermission(Kind::Request),
+///     None,
+///     Some(peer),
+/// );
+///
+/// request("127.0.0.1:10000", Method::Refresh(Kind::Request), Some(0), None);
+///
+/// let allocation = |lifetime| Some(AllocationContext { relay, lifetime });
+/// assert_eq!(
+///     observer.0.lock().as_slice(),
+///     &[
+///         ("allocated", allocation(1200)),
+///         ("create_permission", allocation(1200)),
+///         ("closed", allocation(1200)),
+///         ("refresh", allocation(0)),
+///     ]
+/// );
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocationContext {
+    /// The relayed transport address of the allocation.
+    pub relay: SocketAddr,
+    /// The remaining lifetime of the allocation in seconds.
+    pub lifetime: u32,
+}
+
+/// A port held in reserve by a reservation token, see
+/// [`Sessions::allocate_even`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReservationInfo {
+    /// The token given to the client in the RESERVATION-TOKEN attribute.
+    pub token: u64,
+    /// The reserved port.
+    pub port: u16,
+    /// The remaining lifetime of the reservation in seconds.
+    pub lifetime: u32,
+}
+
+/// A channel binding of a session, see [`Sessions::channels`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelInfo {
+    /// The channel number.
+    pub number: u16,
+    /// The relayed transport address of the peer the channel is bound to.
+    pub peer: SocketAddr,
+    /// The remaining lifetime of the binding in seconds, the bindings are
+    /// kept as long as the allocation.
+    pub lifetime: u32,
+}
+
+/// The relay path of an [`AuditEvent`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditKind {
+    /// A permission is installed or refreshed for the peer.
+    Permission,
+    /// The channel is bound or refreshed to the peer.
+    Channel(u16),
+}
+
+/// A relay 5-tuple installed by a client, see [`Observer::audit`](crate::Observer::audit).
+///
+/// The event only carries the addresses and the time, there are no
+/// credentials in it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuditEvent {
+    pub kind: AuditKind,
+    /// The address of the client and the interface the client reached.
+    pub client: SessionAddr,
+    /// The relayed transport address of the allocation.
+    pub relay: SocketAddr,
+    /// The address of the peer, after the translation of the observer.
+    pub peer: SocketAddr,
+    /// The time the path was installed.
+    pub time: SystemTime,
 }
 
 /// turn session information.
@@ -48,6 +230,18 @@ pub struct Session {
     pub allocate: Allocate,
     pub permissions: Vec<u16>,
     pub expires: u64,
+    /// The time the allocation was last allocated or refreshed, see
+    /// [`Sessions::migrate`].
+    pub refreshed: u64,
+    /// The peers authorized or denied by [`Observer::authorize_peer`].
+    pub authorizations: HashMap<SocketAddr, bool>,
+}
+
+impl Session {
+    /// The relay paths of the session, see [`Sessions::relay_paths`].
+    fn relay_paths(&self) -> usize {
+        self.permissions.len() + self.allocate.channels.len()
+    }
 }
 
 /// The identifier of the session or addr.
@@ -112,19 +306,50 @@ pub struct State {
     port_relay_table: RwLock<Table<SessionAddr, HashMap</* port */ u16, Endpoint>>>,
     // Indicates to which session the data sent by a session to a channel should be forwarded.
     channel_relay_table: RwLock<Table<SessionAddr, HashMap</* channel */ u16, Endpoint>>>,
+    // Records the last time each allocation relayed data, the value is updated in place so that
+    // the relay path only needs a read lock.
+    relay_activity_table: RwLock<Table<SessionAddr, AtomicU64>>,
+    // The ports held in reserve for a later allocation, a token is given to the client for each of
+    // them, and the reserved port is released back into the allocation pool if it is not redeemed
+    // before it expires.
+    reservation_table: Mutex<Table</* token */ u64, (/* port */ u16, /* expires */ u64)>>,
+    // The allocations of the disconnected clients that are kept for a grace period, so that the
+    // reconnecting client can reclaim them, with the expiry of the session before the disconnect
+    // and the transport the client was connected over.
+    detached_table: Mutex<Table<SessionAddr, (/* expires */ u64, Transport)>>,
+    // The allocations of each ip and username of the clients, so that a client whose source port
+    // has changed finds its previous allocation without scanning the sessions.
+    allocation_index_table: RwLock<Table<(IpAddr, /* username */ String), Vec<SessionAddr>>>,
+    // The permissions and the channels of all sessions, with the paths reserved by the requests
+    // that are installing them, so that the limit of the server is checked without scanning the
+    // sessions.
+    relay_paths: AtomicUsize,
 }
 
+/// The default lifetime of the nonces in seconds.
+pub const NONCE_LIFETIME: u64 = 600;
+
 pub struct Sessions<T> {
     timer: Timer,
     state: State,
+    nonce_lifetime: u64,
     observer: T,
 }
 
 impl<T: Observer + 'static> Sessions<T> {
     pub fn new(observer: T) -> Arc<Self> {
+        Self::with_nonce_lifetime(observer, NONCE_LIFETIME)
+    }
+
+    /// Create the sessions whose nonces expire after the lifetime in seconds.
+    ///
+    /// A nonce is never extended, once it has expired the requests carrying
+    /// it are stale, and a fresh nonce is issued to the address.
+    pub fn with_nonce_lifetime(observer: T, nonce_lifetime: u64) -> Arc<Self> {
         let this = Arc::new(Self {
             state: State::default(),
             timer: Timer::default(),
+            nonce_lifetime,
             observer,
         });
 
@@ -173,6 +398,9 @@ impl<T: Observer + 'static> Sessions<T> {
                     }
                 }
 
+                // The reserved ports that have not been redeemed in time are released.
+                this.prune_reservations();
+
                 // Fixing a second tick.
                 sleep(Duration::from_secs(1));
             }
@@ -187,21 +415,33 @@ impl<T: Observer + 'static> Sessions<T> {
         let mut port_mapping_table = self.state.port_mapping_table.write();
         let mut port_relay_table = self.state.port_relay_table.write();
         let mut channel_relay_table = self.state.channel_relay_table.write();
+        let mut relay_activity_table = self.state.relay_activity_table.write();
+        let mut detached_table = self.state.detached_table.lock();
+        let mut allocation_index_table = self.state.allocation_index_table.write();
 
         addrs.iter().for_each(|k| {
             port_relay_table.remove(k);
             channel_relay_table.remove(k);
+            relay_activity_table.remove(k);
+            detached_table.remove(k);
 
             if let Some(session) = sessions.remove(k) {
+                self.state
+                    .relay_paths
+                    .fetch_sub(session.relay_paths(), Ordering::Relaxed);
+
                 // Removes the session-bound port from the port binding table and
                 // releases the port back into the allocation pool.
                 if let Some(port) = session.allocate.port {
                     port_mapping_table.remove(&port);
                     port_allocate_pool.restore(port);
+                    unindex(&mut allocation_index_table, k, &session.auth.username);
                 }
 
                 // Notifies that the external session has been closed.
-                self.observer.closed(k, &session.auth.username);
+                let allocation = self.allocation_context(k, &session);
+                self.observer
+                    .closed(k, &session.auth.username, allocation.as_ref());
             }
         });
     }
@@ -273,6 +513,9 @@ impl<T: Observer + 'static> Sessions<T> {
 
     /// Get nonce for addr.
     ///
+    /// The expired nonce is replaced, so that the challenge always carries a
+    /// nonce that the authenticated retry can use.
+    ///
     /// # Test
     ///
     /// ```
@@ -297,28 +540,33 @@ impl<T: Observer + 'static> Sessions<T> {
     /// let b = sessions.get_nonce(&addr).get_ref().unwrap().clone();
     /// assert_eq!(a.0, b.0);
     /// assert!(b.1 == 600 || b.1 == 601 || b.1 == 602);
+    ///
+    /// // The nonce expires right away without a lifetime.
+    /// let sessions = Sessions::with_nonce_lifetime(ObserverTest, 0);
+    /// let a = sessions.get_nonce(&addr).get_ref().unwrap().clone();
+    /// let b = sessions.get_nonce(&addr).get_ref().unwrap().clone();
+    /// assert_ne!(a.0, b.0);
     /// ```
     pub fn get_nonce<'a, 'b>(
         &'a self,
         key: &'b SessionAddr,
     ) -> ReadLock<'b, 'a, SessionAddr, Table<SessionAddr, (String, u64)>> {
-        // If no nonce is created, create a new one.
+        // If no nonce is created or the nonce has expired, create a new one.
         {
-            if !self.state.address_nonce_tanle.read().contains_key(key) {
+            let now = self.timer.get();
+            let fresh = matches!(
+                self.state.address_nonce_tanle.read().get(key),
+                Some((_, expires)) if *expires > now
+            );
+
+            if !fresh {
                 self.state.address_nonce_tanle.write().insert(
                     *key,
                     (
                         // A random string of length 16.
-                        {
-                            let mut rng = thread_rng();
-                            std::iter::repeat(())
-                                .map(|_| rng.sample(Alphanumeric) as char)
-                                .take(16)
-                                .collect::<String>()
-                                .to_lowercase()
-                        },
-                        // Current time stacks for 600 seconds.
-                        self.timer.get() + 600,
+                        make_nonce(),
+                        // Current time stacks for the nonce lifetime.
+                        self.timer.get() + self.nonce_lifetime,
                     ),
                 );
             }
@@ -330,6 +578,54 @@ impl<T: Observer + 'static> Sessions<T> {
         }
     }
 
+    /// Check if the nonce is the current nonce of addr and has not expired.
+    ///
+    /// The expired nonce is dropped right away instead of waiting for the
+    /// background thread, so that the next challenge issues a fresh nonce.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use std::{thread::sleep, time::Duration};
+    ///
+    /// use mycrl_turn::*;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {}
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let sessions = Sessions::with_nonce_lifetime(ObserverTest, 3);
+    /// let nonce = sessions.get_nonce(&addr).get_ref().unwrap().0.clone();
+    /// assert!(!sessions.verify_nonce(&addr, "unknown"));
+    ///
+    /// // Just inside the window.
+    /// sleep(Duration::from_secs(1));
+    /// assert!(sessions.verify_nonce(&addr, &nonce));
+    ///
+    /// // Just outside the window.
+    /// sleep(Duration::from_secs(3));
+    /// assert!(!sessions.verify_nonce(&addr, &nonce));
+    /// assert_ne!(sessions.get_nonce(&addr).get_ref().unwrap().0, nonce);
+    /// ```
+    pub fn verify_nonce(&self, key: &SessionAddr, nonce: &str) -> bool {
+        {
+            match self.state.address_nonce_tanle.read().get(key) {
+                Some((it, expires)) if *expires > self.timer.get() => return it == nonce,
+                None => return false,
+                _ => (),
+            }
+        }
+
+        self.remove_nonce(&[*key]);
+        false
+    }
+
     /// Get digest for addr.
     ///
     /// # Test
@@ -390,6 +686,18 @@ impl<T: Observer + 'static> Sessions<T> {
         // Already authenticated, get the cached digest directly.
         {
             if let Some(it) = self.state.sessions.read().get(addr) {
+                if it.auth.realm == realm {
+                    return Some(it.auth.digest);
+                }
+            }
+        }
+
+        // The realm has been rotated since the session was authenticated, the
+        // digest is derived again from the password of the session.
+        {
+            if let Some(it) = self.state.sessions.write().get_mut(addr) {
+                it.auth.digest = derive_key(&it.auth.username, realm, &it.auth.password);
+                it.auth.realm = realm.to_string();
                 return Some(it.auth.digest);
             }
         }
@@ -397,23 +705,29 @@ impl<T: Observer + 'static> Sessions<T> {
         // Get the current user's password from an external observer and create a
         // digest.
         let password = self.observer.get_password(addr, username).await?;
-        let digest = long_term_credential_digest(&username, &password, realm);
+        let digest = derive_key(username, realm, &password);
+        let tenant = self.observer.get_tenant(addr, username);
 
         // Record a new session.
         {
             self.state.sessions.write().insert(
                 *addr,
                 Session {
+                    authorizations: HashMap::new(),
                     permissions: Vec::with_capacity(10),
                     expires: self.timer.get() + 600,
+                    refreshed: self.timer.get(),
                     auth: Auth {
                         username: username.to_string(),
+                        realm: realm.to_string(),
                         password,
                         digest,
+                        tenant,
                     },
                     allocate: Allocate {
                         channels: Vec::with_capacity(10),
                         port: None,
+                        relay: None,
                     },
                 },
             );
@@ -426,6 +740,101 @@ impl<T: Observer + 'static> Sessions<T> {
         self.state.port_allocate_pool.lock().len()
     }
 
+    /// Get the number of the relay paths, the permissions and the channels of
+    /// all sessions.
+    ///
+    /// The count is kept as the paths are installed and removed, the paths of
+    /// an expired session are released when the session is cleaned up, and
+    /// the paths reserved by [`Sessions::reserve_relay_paths`] are counted
+    /// until they are released.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use std::net::SocketAddr;
+    ///
+    /// use mycrl_turn::*;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(&self, _: &SessionAddr, _: &str) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let endpoint = "127.0.0.1:3478".parse::<SocketAddr>().unwrap();
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: endpoint,
+    /// };
+    ///
+    /// let peer_addr = SessionAddr {
+    ///     address: "127.0.0.1:8081".parse().unwrap(),
+    ///     interface: endpoint,
+    /// };
+    ///
+    /// let sessions = Sessions::new(ObserverTest);
+    /// pollster::block_on(sessions.get_digest(&addr, "test", "test"));
+    /// pollster::block_on(sessions.get_digest(&peer_addr, "test", "test"));
+    ///
+    /// sessions.allocate(&addr).unwrap();
+    /// let peer_port = sessions.allocate(&peer_addr).unwrap();
+    /// assert_eq!(sessions.relay_paths(), 0);
+    ///
+    /// assert!(sessions.create_permission(&addr, &endpoint, &[peer_port]));
+    /// assert!(sessions.bind_channel(&addr, &endpoint, peer_port, 0x4000));
+    /// assert_eq!(sessions.relay_paths(), 2);
+    ///
+    /// sessions.refresh(&addr, 0);
+    /// assert_eq!(sessions.relay_paths(), 0);
+    /// ```
+    pub fn relay_paths(&self) -> usize {
+        self.state.relay_paths.load(Ordering::Relaxed)
+    }
+
+    /// Reserve the new relay paths under the limit, returns false if they do
+    /// not fit.
+    ///
+    /// The check and the reservation are one atomic update, so that the
+    /// concurrent requests cannot all pass the limit. The installed paths are
+    /// counted on their own, the reservation is released with
+    /// [`Sessions::release_relay_paths`] once the paths are installed or
+    /// refused.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {}
+    ///
+    /// let sessions = Sessions::new(ObserverTest);
+    /// assert!(sessions.reserve_relay_paths(2, 3));
+    /// assert!(!sessions.reserve_relay_paths(2, 3));
+    /// assert_eq!(sessions.relay_paths(), 2);
+    ///
+    /// sessions.release_relay_paths(2);
+    /// assert!(sessions.reserve_relay_paths(3, 3));
+    /// ```
+    pub fn reserve_relay_paths(&self, new: usize, max: usize) -> bool {
+        self.state
+            .relay_paths
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |it| {
+                (it + new <= max).then_some(it + new)
+            })
+            .is_ok()
+    }
+
+    /// Release the relay paths reserved by [`Sessions::reserve_relay_paths`].
+    pub fn release_relay_paths(&self, new: usize) {
+        self.state.relay_paths.fetch_sub(new, Ordering::Relaxed);
+    }
+
     /// Assign a port number to the session.
     ///
     /// # Test
@@ -486,6 +895,192 @@ impl<T: Observer + 'static> Sessions<T> {
     /// assert!(sessions.allocate(&addr).is_none());
     /// ```
     pub fn allocate(&self, addr: &SessionAddr) -> Option<u16> {
+        self.allocate_with(addr, |pool| pool.alloc(None))
+    }
+
+    /// Assign an even port to the session.
+    ///
+    /// If the lifetime of the reservation is given, the next port is held in
+    /// reserve for this number of seconds, and the token of the reservation
+    /// is returned with the port, see [`Sessions::allocate_reserved`].
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use std::{thread::sleep, time::Duration};
+    ///
+    /// use mycrl_turn::*;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(&self, _: &SessionAddr, _: &str) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let addr = |port: u16| SessionAddr {
+    ///     address: format!("127.0.0.1:{}", port).parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let sessions = Sessions::new(ObserverTest);
+    /// for port in 8080..8086 {
+    ///     pollster::block_on(sessions.get_digest(&addr(port), "test", "test"));
+    /// }
+    ///
+    /// let (port, token) = sessions.allocate_even(&addr(8080), None).unwrap();
+    /// assert_eq!(port % 2, 0);
+    /// assert_eq!(token, None);
+    ///
+    /// // The reserved port is redeemed with the token, only once.
+    /// let (port, token) = sessions.allocate_even(&addr(8081), Some(30)).unwrap();
+    /// assert_eq!(port % 2, 0);
+    /// assert_eq!(sessions.allocated(), 3);
+    /// assert_eq!(sessions.allocate_reserved(&addr(8082), token.unwrap()), Some(port + 1));
+    /// assert_eq!(sessions.allocate_reserved(&addr(8083), token.unwrap()), None);
+    /// assert_eq!(sessions.allocated(), 3);
+    ///
+    /// // The unknown token.
+    /// assert_eq!(sessions.allocate_reserved(&addr(8083), token.unwrap() ^ 1), None);
+    ///
+    /// // The expired token, the reserved port is released.
+    /// let (_, token) = sessions.allocate_even(&addr(8084), Some(1)).unwrap();
+    /// assert_eq!(sessions.allocated(), 5);
+    ///
+    /// sleep(Duration::from_secs(3));
+    /// assert_eq!(sessions.allocate_reserved(&addr(8085), token.unwrap()), None);
+    /// assert_eq!(sessions.allocated(), 4);
+    /// ```
+    pub fn allocate_even(
+        &self,
+        addr: &SessionAddr,
+        reservation: Option<u64>,
+    ) -> Option<(u16, Option<u64>)> {
+        let mut token = None;
+        let port = self.allocate_with(addr, |pool| {
+            let port = pool.alloc_even(reservation.is_some())?;
+            if let Some(lifetime) = reservation {
+                let mut reservation_table = self.state.reservation_table.lock();
+
+                // The token uniquely identifies the reserved port.
+                let mut rng = thread_rng();
+                let mut it = rng.gen::<u64>();
+                while reservation_table.contains_key(&it) {
+                    it = rng.gen();
+                }
+
+                reservation_table.insert(it, (port + 1, self.timer.get() + lifetime));
+                token = Some(it);
+            }
+
+            Some(port)
+        })?;
+
+        Some((port, token))
+    }
+
+    /// Get the outstanding reservations, the expired reservations are not
+    /// listed.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(&self, _: &SessionAddr, _: &str) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let addr = |port: u16| SessionAddr {
+    ///     address: format!("127.0.0.1:{}", port).parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let sessions = Sessions::new(ObserverTest);
+    /// for port in 8080..8082 {
+    ///     pollster::block_on(sessions.get_digest(&addr(port), "test", "test"));
+    /// }
+    ///
+    /// assert!(sessions.reservations().is_empty());
+    ///
+    /// // The reservation expires right away without a lifetime, the reserved
+    /// // port is released by the prune, unless the sessions did it first.
+    /// sessions.allocate_even(&addr(8080), Some(0)).unwrap();
+    /// assert!(sessions.reservations().is_empty());
+    /// sessions.prune_reservations();
+    /// assert_eq!(sessions.prune_reservations(), 0);
+    /// assert_eq!(sessions.allocated(), 1);
+    ///
+    /// let (port, token) = sessions.allocate_even(&addr(8081), Some(30)).unwrap();
+    /// let reservations = sessions.reservations();
+    /// assert_eq!(reservations.len(), 1);
+    /// assert_eq!(reservations[0].token, token.unwrap());
+    /// assert_eq!(reservations[0].port, port + 1);
+    /// assert!(reservations[0].lifetime <= 30);
+    /// ```
+    pub fn reservations(&self) -> Vec<ReservationInfo> {
+        let now = self.timer.get();
+        self.state
+            .reservation_table
+            .lock()
+            .iter()
+            .filter(|(_, (_, expires))| *expires > now)
+            .map(|(token, (port, expires))| ReservationInfo {
+                lifetime: (expires - now) as u32,
+                token: *token,
+                port: *port,
+            })
+            .collect()
+    }
+
+    /// Release the reserved ports whose reservation has expired, returns the
+    /// number of the released ports. This is done every second by the
+    /// sessions, it only needs to be called to release them right away.
+    pub fn prune_reservations(&self) -> usize {
+        let now = self.timer.get();
+        let mut port_allocate_pool = self.state.port_allocate_pool.lock();
+        let mut reservation_table = self.state.reservation_table.lock();
+
+        let len = reservation_table.len();
+        reservation_table.retain(|_, (port, expires)| {
+            if *expires > now {
+                return true;
+            }
+
+            port_allocate_pool.restore(*port);
+            false
+        });
+
+        len - reservation_table.len()
+    }
+
+    /// Assign the port held in reserve by the token to the session.
+    ///
+    /// Returns `None` if the token is unknown or has expired, a token is
+    /// redeemed only once.
+    pub fn allocate_reserved(&self, addr: &SessionAddr, token: u64) -> Option<u16> {
+        self.allocate_with(addr, |pool| {
+            let (port, expires) = self.state.reservation_table.lock().remove(&token)?;
+            if expires <= self.timer.get() {
+                pool.restore(port);
+                return None;
+            }
+
+            Some(port)
+        })
+    }
+
+    fn allocate_with<F>(&self, addr: &SessionAddr, alloc: F) -> Option<u16>
+    where
+        F: FnOnce(&mut PortAllocatePools) -> Option<u16>,
+    {
         let mut lock = self.state.sessions.write();
         let session = lock.get_mut(addr)?;
 
@@ -495,15 +1090,107 @@ impl<T: Observer + 'static> Sessions<T> {
         }
 
         // Records the port assigned to the current session and resets the alive time.
-        let port = self.state.port_allocate_pool.lock().alloc(None)?;
+        let port = alloc(&mut self.state.port_allocate_pool.lock())?;
         session.expires = self.timer.get() + 600;
+        session.refreshed = self.timer.get();
         session.allocate.port = Some(port);
 
+        self.state
+            .allocation_index_table
+            .write()
+            .entry((addr.address.ip(), session.auth.username.clone()))
+            .or_default()
+            .push(*addr);
+
         // Write the allocation port binding table.
         self.state.port_mapping_table.write().insert(port, *addr);
+        self.state
+            .relay_activity_table
+            .write()
+            .insert(*addr, AtomicU64::new(self.timer.get()));
+
         Some(port)
     }
 
+    /// Record the relayed transport address of the allocation of the session.
+    ///
+    /// The sessions do not know the external address of the interfaces, so
+    /// the processor records the address given to the client, which is then
+    /// reported in the allocation context. Without a recorded address the
+    /// address of the interface is reported.
+    pub fn set_relay(&self, addr: &SessionAddr, relay: SocketAddr) {
+        if let Some(session) = self.state.sessions.write().get_mut(addr) {
+            session.allocate.relay = Some(relay);
+        }
+    }
+
+    /// Get the allocation context of the session.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use std::net::SocketAddr;
+    ///
+    /// use mycrl_turn::{sessions::AllocationContext, *};
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(&self, _: &SessionAddr, _: &str) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let sessions = Sessions::new(ObserverTest);
+    ///
+    /// pollster::block_on(sessions.get_digest(&addr, "test", "test"));
+    /// assert_eq!(sessions.get_allocation(&addr), None);
+    ///
+    /// let port = sessions.allocate(&addr).unwrap();
+    /// assert_eq!(
+    ///     sessions.get_allocation(&addr),
+    ///     Some(AllocationContext {
+    ///         relay: SocketAddr::new(addr.interface.ip(), port),
+    ///         lifetime: 600,
+    ///     })
+    /// );
+    ///
+    /// let relay = SocketAddr::new("192.0.2.1".parse().unwrap(), port);
+    /// sessions.set_relay(&addr, relay);
+    /// sessions.refresh(&addr, 1200);
+    /// assert_eq!(
+    ///     sessions.get_allocation(&addr),
+    ///     Some(AllocationContext {
+    ///         relay,
+    ///         lifetime: 1200,
+    ///     })
+    /// );
+    /// ```
+    pub fn get_allocation(&self, addr: &SessionAddr) -> Option<AllocationContext> {
+        self.allocation_context(addr, self.state.sessions.read().get(addr)?)
+    }
+
+    fn allocation_context(
+        &self,
+        addr: &SessionAddr,
+        session: &Session,
+    ) -> Option<AllocationContext> {
+        let port = session.allocate.port?;
+        Some(AllocationContext {
+            relay: session
+                .allocate
+                .relay
+                .unwrap_or_else(|| SocketAddr::new(addr.interface.ip(), port)),
+            lifetime: session.expires.saturating_sub(self.timer.get()) as u32,
+        })
+    }
+
     /// Create permission for session.
     ///
     /// # Test
@@ -590,7 +1277,7 @@ impl<T: Observer + 'static> Sessions<T> {
         // Each peer port must be present.
         let mut peers = Vec::with_capacity(15);
         for port in ports {
-            if let Some(it) = port_mapping_table.get(&port) {
+            if let Some(it) = port_mapping_table.get(port) {
                 peers.push((it, *port));
             } else {
                 return false;
@@ -613,13 +1300,107 @@ impl<T: Observer + 'static> Sessions<T> {
             // Do not store the same peer ports to the permission list over and over again.
             if !session.permissions.contains(&port) {
                 session.permissions.push(port);
+                self.state.relay_paths.fetch_add(1, Ordering::Relaxed);
             }
         }
 
         true
     }
 
-    /// Binding a channel to the session.
+    /// Check if the session has installed a permission for the peer.
+    ///
+    /// The peer is given as the relayed address of the peer allocation, the
+    /// lookup is O(1) and the permissions of an expired session are treated
+    /// as absent even before the session has been cleaned up. The lookup
+    /// does not refresh the permission.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use std::net::SocketAddr;
+    ///
+    /// use mycrl_turn::*;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(
+    ///         &self,
+    ///         addr: &SessionAddr,
+    ///         username: &str,
+    ///     ) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let endpoint = "127.0.0.1:3478".parse::<SocketAddr>().unwrap();
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let peer_addr = SessionAddr {
+    ///     address: "127.0.0.1:8081".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let sessions = Sessions::new(ObserverTest);
+    ///
+    /// pollster::block_on(sessions.get_digest(&addr, "test", "test"));
+    /// pollster::block_on(sessions.get_digest(&peer_addr, "test", "test"));
+    ///
+    /// let port = sessions.allocate(&addr).unwrap();
+    /// let peer_port = sessions.allocate(&peer_addr).unwrap();
+    /// let peer = SocketAddr::new(endpoint.ip(), peer_port);
+    ///
+    /// assert!(!sessions.has_permission(&addr, &peer));
+    /// assert!(sessions.create_permission(&addr, &endpoint, &[peer_port]));
+    /// assert!(sessions.has_permission(&addr, &peer));
+    ///
+    /// // The permissions are not installed in the other direction, and the
+    /// // peer must be relayed on the given ip.
+    /// let other_ip = SocketAddr::new("127.0.0.2".parse().unwrap(), peer_port);
+    /// let other_port = SocketAddr::new(endpoint.ip(), peer_port + 1);
+    /// assert!(!sessions.has_permission(&peer_addr, &SocketAddr::new(endpoint.ip(), port)));
+    /// assert!(!sessions.has_permission(&addr, &other_ip));
+    /// assert!(!sessions.has_permission(&addr, &other_port));
+    ///
+    /// sessions.refresh(&addr, 0);
+    /// assert!(!sessions.has_permission(&addr, &peer));
+    /// ```
+    pub fn has_permission(&self, addr: &SessionAddr, peer: &SocketAddr) -> bool {
+        let local_port = {
+            let sessions = self.state.sessions.read();
+            match sessions.get(addr) {
+                Some(it) if it.expires > self.timer.get() => match it.allocate.port {
+                    Some(port) => port,
+                    None => return false,
+                },
+                _ => return false,
+            }
+        };
+
+        // The peer allocation that owns the port, relayed on the peer ip.
+        let peer_addr = match self.state.port_mapping_table.read().get(&peer.port()) {
+            Some(it) => *it,
+            None => return false,
+        };
+
+        if self.get_relayed_address(peer.port()).map(|it| it.ip()) != Some(peer.ip()) {
+            return false;
+        }
+
+        self.state
+            .port_relay_table
+            .read()
+            .get(&peer_addr)
+            .and_then(|it| it.get(&local_port))
+            .map(|it| it.address == addr.address)
+            .unwrap_or(false)
+    }
+
+    /// Binding a channel to the session.
     ///
     /// # Test
     ///
@@ -733,10 +1514,11 @@ impl<T: Observer + 'static> Sessions<T> {
                 return false;
             };
 
+            // An existing channel is refreshed, the conflicting bindings are
+            // rejected by the processor with `Sessions::verify_channel`.
             if !session.allocate.channels.contains(&channel) {
                 session.allocate.channels.push(channel);
-            } else {
-                return false;
+                self.state.relay_paths.fetch_add(1, Ordering::Relaxed);
             }
         }
 
@@ -762,6 +1544,312 @@ impl<T: Observer + 'static> Sessions<T> {
         true
     }
 
+    /// Verify that the channel binding does not conflict with the existing
+    /// bindings of the session.
+    ///
+    /// [rfc8656](https://tools.ietf.org/html/rfc8656#section-11.2)
+    ///
+    /// The channel number must not be bound to a different peer, and the
+    /// peer must not be bound to a different channel number. Binding the
+    /// same channel to the same peer again is a refresh of the binding.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(&self, _: &SessionAddr, _: &str) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let endpoint = "127.0.0.1:3478".parse().unwrap();
+    /// let addrs = [8080, 8081, 8082].map(|port| SessionAddr {
+    ///     address: format!("127.0.0.1:{}", port).parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// });
+    ///
+    /// let sessions = Sessions::new(ObserverTest);
+    /// let ports = addrs.map(|addr| {
+    ///     pollster::block_on(sessions.get_digest(&addr, "test", "test"));
+    ///     sessions.allocate(&addr).unwrap()
+    /// });
+    ///
+    /// assert!(sessions.verify_channel(&addrs[0], ports[1], 0x4000));
+    /// assert!(sessions.bind_channel(&addrs[0], &endpoint, ports[1], 0x4000));
+    ///
+    /// // The refresh of the binding.
+    /// assert!(sessions.verify_channel(&addrs[0], ports[1], 0x4000));
+    /// assert!(sessions.bind_channel(&addrs[0], &endpoint, ports[1], 0x4000));
+    ///
+    /// // The channel is bound to another peer, the peer to another channel.
+    /// assert!(!sessions.verify_channel(&addrs[0], ports[2], 0x4000));
+    /// assert!(!sessions.verify_channel(&addrs[0], ports[1], 0x4001));
+    /// assert!(sessions.verify_channel(&addrs[0], ports[2], 0x4001));
+    /// ```
+    pub fn verify_channel(&self, addr: &SessionAddr, port: u16, channel: u16) -> bool {
+        let sessions = self.state.sessions.read();
+        let session = match sessions.get(addr) {
+            Some(it) => it,
+            None => return true,
+        };
+
+        let port_mapping_table = self.state.port_mapping_table.read();
+        let channel_relay_table = self.state.channel_relay_table.read();
+        let is_bound = |peer: &SessionAddr, channel: &u16| {
+            channel_relay_table
+                .get(peer)
+                .and_then(|it| it.get(channel))
+                .map(|it| it.address == addr.address)
+                .unwrap_or(false)
+        };
+
+        let peer = match port_mapping_table.get(&port) {
+            Some(it) => it,
+            None => return true,
+        };
+
+        for it in &session.allocate.channels {
+            if *it == channel {
+                return is_bound(peer, it);
+            }
+
+            if is_bound(peer, it) {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Remove a channel binding of the session, the permission of the peer is
+    /// kept until it expires.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(&self, _: &SessionAddr, _: &str) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let endpoint = "127.0.0.1:3478".parse().unwrap();
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let peer_addr = SessionAddr {
+    ///     address: "127.0.0.1:8081".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let sessions = Sessions::new(ObserverTest);
+    ///
+    /// pollster::block_on(sessions.get_digest(&addr, "test", "test"));
+    /// pollster::block_on(sessions.get_digest(&peer_addr, "test", "test"));
+    ///
+    /// let port = sessions.allocate(&addr).unwrap();
+    /// let peer_port = sessions.allocate(&peer_addr).unwrap();
+    ///
+    /// assert!(sessions.bind_channel(&addr, &endpoint, peer_port, 0x4000));
+    /// assert!(sessions.bind_channel(&peer_addr, &endpoint, port, 0x4000));
+    /// assert!(sessions.get_channel_relay_address(&peer_addr, 0x4000).is_some());
+    ///
+    /// assert!(sessions.unbind_channel(&addr, 0x4000));
+    /// assert!(!sessions.unbind_channel(&addr, 0x4000));
+    /// assert!(sessions.get_channel_relay_address(&peer_addr, 0x4000).is_none());
+    /// assert!(sessions.get_channel_relay_address(&addr, 0x4000).is_some());
+    ///
+    /// // The peer can be bound to another channel number.
+    /// assert!(sessions.verify_channel(&addr, peer_port, 0x4001));
+    /// ```
+    pub fn unbind_channel(&self, addr: &SessionAddr, channel: u16) -> bool {
+        let mut sessions = self.state.sessions.write();
+        let session = match sessions.get_mut(addr) {
+            Some(it) => it,
+            None => return false,
+        };
+
+        match session
+            .allocate
+            .channels
+            .iter()
+            .position(|it| *it == channel)
+        {
+            Some(index) => session.allocate.channels.swap_remove(index),
+            None => return false,
+        };
+
+        self.state.relay_paths.fetch_sub(1, Ordering::Relaxed);
+
+        // The peers of the channel are only reachable through the permissions.
+        let port_mapping_table = self.state.port_mapping_table.read();
+        let mut channel_relay_table = self.state.channel_relay_table.write();
+        for peer in session
+            .permissions
+            .iter()
+            .filter_map(|it| port_mapping_table.get(it))
+        {
+            if let Some(channels) = channel_relay_table.get_mut(peer) {
+                if channels.get(&channel).map(|it| it.address) == Some(addr.address) {
+                    channels.remove(&channel);
+                }
+            }
+        }
+
+        true
+    }
+
+    /// Get the channel bindings of the session, for the diagnostics of a
+    /// channel that points to a stale peer. A binding is removed with
+    /// [`Sessions::unbind_channel`].
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(&self, _: &SessionAddr, _: &str) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let endpoint = "127.0.0.1:3478".parse().unwrap();
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let peer_addr = SessionAddr {
+    ///     address: "127.0.0.1:8081".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let sessions = Sessions::new(ObserverTest);
+    /// assert!(sessions.channels(&addr).is_empty());
+    ///
+    /// pollster::block_on(sessions.get_digest(&addr, "test", "test"));
+    /// pollster::block_on(sessions.get_digest(&peer_addr, "test", "test"));
+    ///
+    /// sessions.allocate(&addr).unwrap();
+    /// let peer_port = sessions.allocate(&peer_addr).unwrap();
+    ///
+    /// assert!(sessions.bind_channel(&addr, &endpoint, peer_port, 0x4000));
+    ///
+    /// let channels = sessions.channels(&addr);
+    /// assert_eq!(channels.len(), 1);
+    /// assert_eq!(channels[0].number, 0x4000);
+    /// assert_eq!(channels[0].peer, sessions.get_relayed_address(peer_port).unwrap());
+    /// assert!(channels[0].lifetime <= 600);
+    ///
+    /// assert!(sessions.unbind_channel(&addr, 0x4000));
+    /// assert!(sessions.channels(&addr).is_empty());
+    /// ```
+    pub fn channels(&self, addr: &SessionAddr) -> Vec<ChannelInfo> {
+        let sessions = self.state.sessions.read();
+        let session = match sessions.get(addr) {
+            Some(it) => it,
+            None => return Vec::new(),
+        };
+
+        let now = self.timer.get();
+        let port_mapping_table = self.state.port_mapping_table.read();
+        let channel_relay_table = self.state.channel_relay_table.read();
+
+        // The channel is bound to the peer of one of the permissions, whose
+        // channel forwards to the session.
+        let bound = |channel: &u16| {
+            session.permissions.iter().find_map(|port| {
+                let peer = port_mapping_table.get(port)?;
+                channel_relay_table
+                    .get(peer)?
+                    .get(channel)
+                    .filter(|it| it.address == addr.address)?;
+
+                let relay = sessions.get(peer).and_then(|it| it.allocate.relay);
+                Some(relay.unwrap_or_else(|| SocketAddr::new(peer.interface.ip(), *port)))
+            })
+        };
+
+        session
+            .allocate
+            .channels
+            .iter()
+            .filter_map(|number| {
+                Some(ChannelInfo {
+                    lifetime: session.expires.saturating_sub(now) as u32,
+                    peer: bound(number)?,
+                    number: *number,
+                })
+            })
+            .collect()
+    }
+
+    /// Get the cached authorization of the peer for the session, see
+    /// [`Observer::authorize_peer`].
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(&self, _: &SessionAddr, _: &str) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let peer = "127.0.0.1:49152".parse().unwrap();
+    /// let sessions = Sessions::new(ObserverTest);
+    ///
+    /// sessions.set_authorization(&addr, &peer, true);
+    /// assert_eq!(sessions.get_authorization(&addr, &peer), None);
+    ///
+    /// pollster::block_on(sessions.get_digest(&addr, "test", "test"));
+    /// sessions.set_authorization(&addr, &peer, false);
+    /// assert_eq!(sessions.get_authorization(&addr, &peer), Some(false));
+    /// ```
+    pub fn get_authorization(&self, addr: &SessionAddr, peer: &SocketAddr) -> Option<bool> {
+        self.state
+            .sessions
+            .read()
+            .get(addr)?
+            .authorizations
+            .get(peer)
+            .copied()
+    }
+
+    /// Cache the authorization of the peer for the session, which is kept
+    /// until the session is closed.
+    pub fn set_authorization(&self, addr: &SessionAddr, peer: &SocketAddr, allowed: bool) {
+        if let Some(it) = self.state.sessions.write().get_mut(addr) {
+            it.authorizations.insert(*peer, allowed);
+        }
+    }
+
     /// Gets the peer of the current session bound channel.
     ///
     /// # Test
@@ -829,12 +1917,16 @@ impl<T: Observer + 'static> Sessions<T> {
     /// );
     /// ```
     pub fn get_channel_relay_address(&self, addr: &SessionAddr, channel: u16) -> Option<Endpoint> {
-        self.state
+        let endpoint = self
+            .state
             .channel_relay_table
             .read()
-            .get(&addr)?
+            .get(addr)?
             .get(&channel)
-            .copied()
+            .copied()?;
+
+        self.touch(addr);
+        Some(endpoint)
     }
 
     /// Get the address of the port binding.
@@ -872,14 +1964,866 @@ impl<T: Observer + 'static> Sessions<T> {
     ///     interface: "127.0.0.1:3478".parse().unwrap(),
     /// };
     ///
-    /// let digest = [
-    ///     174, 238, 187, 253, 117, 209, 73, 157, 36, 56, 143, 91, 155, 16, 224,
-    ///     239,
-    /// ];
-    ///
+    /// let digest = [
+    ///     174, 238, 187, 253, 117, 209, 73, 157, 36, 56, 143, 91, 155, 16, 224,
+    ///     239,
+    /// ];
+    ///
+    /// let sessions = Sessions::new(ObserverTest);
+    ///
+    /// pollster::block_on(sessions.get_digest(&addr, "test", "test"));
+    /// pollster::block_on(sessions.get_digest(&peer_addr, "test", "test"));
+    ///
+    /// let port = sessions.allocate(&addr).unwrap();
+    /// let peer_port = sessions.allocate(&peer_addr).unwrap();
+    ///
+    /// assert!(sessions.create_permission(&addr, &endpoint, &[peer_port]));
+    /// assert!(sessions.create_permission(&peer_addr, &endpoint, &[port]));
+    ///
+    /// assert_eq!(
+    ///     sessions
+    ///         .get_relay_address(&addr, peer_port)
+    ///         .unwrap()
+    ///         .endpoint,
+    ///     endpoint
+    /// );
+    ///
+    /// assert_eq!(
+    ///     sessions
+    ///         .get_relay_address(&peer_addr, port)
+    ///         .unwrap()
+    ///         .endpoint,
+    ///     endpoint
+    /// );
+    /// ```
+    pub fn get_relay_address(&self, addr: &SessionAddr, port: u16) -> Option<Endpoint> {
+        let endpoint = self
+            .state
+            .port_relay_table
+            .read()
+            .get(addr)?
+            .get(&port)
+            .copied()?;
+
+        self.touch(addr);
+        Some(endpoint)
+    }
+
+    /// Get the relayed address of the allocation that owns the port.
+    ///
+    /// Each allocation is relayed on the interface the session was allocated
+    /// on, so on a dual-stack server the family of the relayed address tells
+    /// which relay the peer is reachable through.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(
+    ///         &self,
+    ///         addr: &SessionAddr,
+    ///         username: &str,
+    ///     ) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let peer_addr = SessionAddr {
+    ///     address: "[::1]:8081".parse().unwrap(),
+    ///     interface: "[::1]:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let sessions = Sessions::new(ObserverTest);
+    ///
+    /// pollster::block_on(sessions.get_digest(&addr, "test", "test"));
+    /// pollster::block_on(sessions.get_digest(&peer_addr, "test", "test"));
+    ///
+    /// let port = sessions.allocate(&addr).unwrap();
+    /// let peer_port = sessions.allocate(&peer_addr).unwrap();
+    ///
+    /// assert_eq!(
+    ///     sessions.get_relayed_address(port),
+    ///     Some(format!("127.0.0.1:{}", port).parse().unwrap())
+    /// );
+    ///
+    /// assert_eq!(
+    ///     sessions.get_relayed_address(peer_port),
+    ///     Some(format!("[::1]:{}", peer_port).parse().unwrap())
+    /// );
+    /// ```
+    pub fn get_relayed_address(&self, port: u16) -> Option<SocketAddr> {
+        let addr = self.state.port_mapping_table.read().get(&port).copied()?;
+        let relay = self
+            .state
+            .sessions
+            .read()
+            .get(&addr)
+            .and_then(|it| it.allocate.relay);
+
+        Some(relay.unwrap_or_else(|| SocketAddr::new(addr.interface.ip(), port)))
+    }
+
+    /// Records that the allocation of the session has relayed data.
+    fn touch(&self, addr: &SessionAddr) {
+        if let Some(it) = self.state.relay_activity_table.read().get(addr) {
+            it.store(self.timer.get(), Ordering::Relaxed);
+        }
+    }
+
+    /// Get the number of seconds since the allocation of the session last
+    /// relayed data sent by the client.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(
+    ///         &self,
+    ///         addr: &SessionAddr,
+    ///         username: &str,
+    ///     ) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let endpoint = "127.0.0.1:3478".parse().unwrap();
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let peer_addr = SessionAddr {
+    ///     address: "127.0.0.1:8081".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let sessions = Sessions::new(ObserverTest);
+    ///
+    /// pollster::block_on(sessions.get_digest(&addr, "test", "test"));
+    /// pollster::block_on(sessions.get_digest(&peer_addr, "test", "test"));
+    /// assert_eq!(sessions.get_inactive_time(&addr), None);
+    ///
+    /// let port = sessions.allocate(&addr).unwrap();
+    /// let peer_port = sessions.allocate(&peer_addr).unwrap();
+    /// assert!(sessions.create_permission(&peer_addr, &endpoint, &[port]));
+    ///
+    /// std::thread::sleep(std::time::Duration::from_millis(2500));
+    ///
+    /// assert!(sessions.get_inactive_time(&addr).unwrap() >= 1);
+    /// assert!(sessions.get_relay_address(&addr, peer_port).is_some());
+    /// assert_eq!(sessions.get_inactive_time(&addr), Some(0));
+    /// ```
+    pub fn get_inactive_time(&self, addr: &SessionAddr) -> Option<u64> {
+        let active = self
+            .state
+            .relay_activity_table
+            .read()
+            .get(addr)?
+            .load(Ordering::Relaxed);
+
+        Some(self.timer.get().saturating_sub(active))
+    }
+
+    /// Refresh the session for addr.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(
+    ///         &self,
+    ///         addr: &SessionAddr,
+    ///         username: &str,
+    ///     ) -> Option<String> {
+    ///         if username == "test" {
+    ///             Some("test".to_string())
+    ///         } else {
+    ///             None
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let digest = [
+    ///     174, 238, 187, 253, 117, 209, 73, 157, 36, 56, 143, 91, 155, 16, 224,
+    ///     239,
+    /// ];
+    ///
+    /// let sessions = Sessions::new(ObserverTest);
+    ///
+    /// assert!(sessions.get_session(&addr).get_ref().is_none());
+    ///
+    /// pollster::block_on(sessions.get_digest(&addr, "test", "test"));
+    ///
+    /// let expires = sessions.get_session(&addr).get_ref().unwrap().expires;
+    /// assert!(expires == 600 || expires == 601 || expires == 602);
+    ///
+    /// assert!(sessions.refresh(&addr, 0));
+    ///
+    /// assert!(sessions.get_session(&addr).get_ref().is_none());
+    /// ```
+    pub fn refresh(&self, addr: &SessionAddr, lifetime: u32) -> bool {
+        if lifetime > 3600 {
+            return false;
+        }
+
+        if lifetime == 0 {
+            self.remove_session(&[*addr]);
+            self.remove_nonce(&[*addr]);
+        } else {
+            if let Some(session) = self.state.sessions.write().get_mut(addr) {
+                session.expires = self.timer.get() + lifetime as u64;
+                session.refreshed = self.timer.get();
+            } else {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Evict the session for addr.
+    ///
+    /// The session is closed at once, without a grace period, and the
+    /// observer learns of the eviction before the close. Returns false if
+    /// there is no session for addr.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// use mycrl_turn::*;
+    ///
+    /// #[derive(Clone, Default)]
+    /// struct ObserverTest(Arc<Mutex<Vec<String>>>);
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(&self, _: &SessionAddr, _: &str) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    ///
+    ///     fn evicted(&self, _: &SessionAddr, username: &str) {
+    ///         self.0.lock().unwrap().push(format!("evicted {}", username));
+    ///     }
+    ///
+    ///     fn closed(&self, _: &SessionAddr, username: &str, _: Option<&AllocationContext>) {
+    ///         self.0.lock().unwrap().push(format!("closed {}", username));
+    ///     }
+    /// }
+    ///
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let observer = ObserverTest::default();
+    /// let sessions = Sessions::new(observer.clone());
+    /// assert!(!sessions.evict(&addr));
+    ///
+    /// pollster::block_on(sessions.get_digest(&addr, "test", "test"));
+    /// sessions.allocate(&addr).unwrap();
+    ///
+    /// assert!(sessions.evict(&addr));
+    /// assert!(sessions.get_session(&addr).get_ref().is_none());
+    /// assert_eq!(*observer.0.lock().unwrap(), vec!["evicted test", "closed test"]);
+    /// ```
+    pub fn evict(&self, addr: &SessionAddr) -> bool {
+        let username = if let Some(it) = self.state.sessions.read().get(addr) {
+            it.auth.username.clone()
+        } else {
+            return false;
+        };
+
+        self.observer.evicted(addr, &username);
+        self.refresh(addr, 0)
+    }
+
+    /// Detach the allocation of a disconnected client.
+    ///
+    /// Instead of closing the session, the allocation, the permissions and
+    /// the channels are kept for the grace period in seconds, during which a
+    /// reconnecting client with the same credentials over the same transport
+    /// can reclaim them with [`Sessions::reclaim`]. The session is closed as
+    /// usual once the grace period has elapsed. Returns false if the session
+    /// has no allocation, in which case there is nothing to keep.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    /// use stun::attribute::Transport;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(&self, _: &SessionAddr, _: &str) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let interface = "127.0.0.1:3478".parse().unwrap();
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface,
+    /// };
+    ///
+    /// let reconnected = SessionAddr {
+    ///     address: "127.0.0.1:8081".parse().unwrap(),
+    ///     interface,
+    /// };
+    ///
+    /// let sessions = Sessions::new(ObserverTest);
+    /// let digest = pollster::block_on(sessions.get_digest(&addr, "test", "test")).unwrap();
+    /// assert!(!sessions.detach(&addr, 10, Transport::TCP));
+    ///
+    /// let port = sessions.allocate(&addr).unwrap();
+    /// assert!(sessions.detach(&addr, 10, Transport::TCP));
+    ///
+    /// // Another user cannot reclaim the allocation.
+    /// let other = SessionAddr {
+    ///     address: "127.0.0.1:8082".parse().unwrap(),
+    ///     interface,
+    /// };
+    ///
+    /// let other_digest = pollster::block_on(sessions.get_digest(&other, "other", "test")).unwrap();
+    /// assert!(!sessions.reclaim(&other, &other_digest, Transport::TCP));
+    ///
+    /// // Nor can the same user over another transport.
+    /// pollster::block_on(sessions.get_digest(&reconnected, "test", "test"));
+    /// assert!(!sessions.reclaim(&reconnected, &digest, Transport::UDP));
+    ///
+    /// assert!(sessions.reclaim(&reconnected, &digest, Transport::TCP));
+    /// assert!(sessions.get_session(&addr).get_ref().is_none());
+    /// assert_eq!(sessions.get_session(&reconnected).get_ref().unwrap().allocate.port, Some(port));
+    ///
+    /// // The allocation can only be reclaimed once.
+    /// assert!(!sessions.reclaim(&addr, &digest, Transport::TCP));
+    /// ```
+    pub fn detach(&self, addr: &SessionAddr, grace: u32, transport: Transport) -> bool {
+        let mut sessions = self.state.sessions.write();
+        let session = match sessions.get_mut(addr) {
+            Some(it) if it.allocate.port.is_some() => it,
+            _ => return false,
+        };
+
+        // The session may be detached again by another path of the same
+        // disconnect, the expiry before the first detach is the one to keep.
+        let mut detached_table = self.state.detached_table.lock();
+        detached_table
+            .entry(*addr)
+            .or_insert((session.expires, transport));
+        session.expires = session.expires.min(self.timer.get() + grace as u64);
+
+        true
+    }
+
+    /// Reclaim a detached allocation for the reconnected client.
+    ///
+    /// The message integrity of the request of addr must have been checked
+    /// with the digest. If addr has no allocation and a detached allocation
+    /// on the same interface and transport was authenticated with the same
+    /// username and digest, the detached session is moved to addr and gets
+    /// its lifetime back. The nonce of addr is kept, since the client already
+    /// uses it.
+    pub fn reclaim(&self, addr: &SessionAddr, digest: &[u8; 16], transport: Transport) -> bool {
+        // Most of the time nothing is detached, which is checked without
+        // taking the lock of the sessions.
+        if self.state.detached_table.lock().is_empty() {
+            return false;
+        }
+
+        let (detached, expires) = {
+            let mut sessions = self.state.sessions.write();
+            let mut detached_table = self.state.detached_table.lock();
+            let username = match sessions.get(addr) {
+                Some(it) if it.allocate.port.is_none() => it.auth.username.clone(),
+                _ => return false,
+            };
+
+            let detached = detached_table.iter().find_map(|(it, (_, kind))| {
+                (it.interface == addr.interface
+                    && *kind == transport
+                    && sessions.get(it).is_some_and(|it| {
+                        it.auth.username == username && &it.auth.digest == digest
+                    }))
+                .then_some(*it)
+            });
+
+            let detached = if let Some(it) = detached {
+                it
+            } else {
+                return false;
+            };
+
+            // The session of addr only carries the credentials of the request,
+            // it is replaced by the detached session.
+            self.forget(&mut sessions, addr);
+            (
+                detached,
+                detached_table
+                    .remove(&detached)
+                    .map(|(expires, _)| expires)
+                    .unwrap_or_default(),
+            )
+        };
+
+        self.remove_nonce(&[detached]);
+        let nonce = self.state.address_nonce_tanle.write().remove(addr);
+        if !self.rebind(&detached, addr) {
+            return false;
+        }
+
+        if let Some(it) = nonce {
+            self.state.address_nonce_tanle.write().insert(*addr, it);
+        }
+
+        if let Some(it) = self.state.sessions.write().get_mut(addr) {
+            it.expires = expires;
+        }
+
+        true
+    }
+
+    /// Migrate the allocation of a client whose source port has changed.
+    ///
+    /// The message integrity of the request of addr must have been checked
+    /// with the digest. If addr has no allocation and another session on the
+    /// same interface, from the same IP address with another port, holds an
+    /// allocation authenticated with the same username and digest, that
+    /// session is moved to addr. The allocation is only moved once it has
+    /// been idle, neither relaying the data of its client nor refreshed, for
+    /// the idle seconds, so that the live flows of the clients behind the
+    /// same NAT keep their own allocations. The nonce of addr is kept, since
+    /// the client already uses it.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(&self, _: &SessionAddr, _: &str) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let interface = "127.0.0.1:3478".parse().unwrap();
+    /// let flows = ["127.0.0.1:8080", "127.0.0.1:8081", "127.0.0.1:8082"].map(|it| SessionAddr {
+    ///     address: it.parse().unwrap(),
+    ///     interface,
+    /// });
+    ///
+    /// let sessions = Sessions::new(ObserverTest);
+    /// let digest = pollster::block_on(sessions.get_digest(&flows[0], "test", "test")).unwrap();
+    /// let port = sessions.allocate(&flows[0]).unwrap();
+    ///
+    /// // A second live flow from the same address authenticates before it
+    /// // allocates, the first flow has just allocated and keeps its allocation.
+    /// pollster::block_on(sessions.get_digest(&flows[1], "test", "test"));
+    /// assert!(!sessions.migrate(&flows[1], &digest, 60));
+    /// assert!(sessions.allocate(&flows[1]).is_some());
+    /// assert_eq!(sessions.get_session(&flows[0]).get_ref().unwrap().allocate.port, Some(port));
+    ///
+    /// // Without the idle window, a new port takes over an allocation.
+    /// pollster::block_on(sessions.get_digest(&flows[2], "test", "test"));
+    /// assert!(sessions.migrate(&flows[2], &digest, 0));
+    /// assert!(sessions.get_session(&flows[2]).get_ref().unwrap().allocate.port.is_some());
+    /// assert_eq!(sessions.allocated(), 2);
+    /// ```
+    pub fn migrate(&self, addr: &SessionAddr, digest: &[u8; 16], idle: u64) -> bool {
+        let previous = {
+            let mut sessions = self.state.sessions.write();
+            let username = match sessions.get(addr) {
+                Some(it) if it.allocate.port.is_none() => it.auth.username.clone(),
+                _ => return false,
+            };
+
+            let now = self.timer.get();
+            let relay_activity_table = self.state.relay_activity_table.read();
+            let previous = self
+                .state
+                .allocation_index_table
+                .read()
+                .get(&(addr.address.ip(), username))
+                .and_then(|addrs| {
+                    addrs.iter().copied().find(|k| {
+                        let session = match sessions.get(k) {
+                            Some(it) => it,
+                            None => return false,
+                        };
+
+                        let active = relay_activity_table
+                            .get(k)
+                            .map(|it| it.load(Ordering::Relaxed))
+                            .unwrap_or(0)
+                            .max(session.refreshed);
+
+                        k.interface == addr.interface
+                            && k.address.port() != addr.address.port()
+                            && &session.auth.digest == digest
+                            && now.saturating_sub(active) >= idle
+                    })
+                });
+
+            drop(relay_activity_table);
+            let previous = if let Some(it) = previous {
+                it
+            } else {
+                return false;
+            };
+
+            // The session of addr only carries the credentials of the request,
+            // it is replaced by the previous session.
+            self.forget(&mut sessions, addr);
+            previous
+        };
+
+        self.take_over(&previous, addr)
+    }
+
+    /// Move the allocation named by a mobility ticket to a new client address.
+    ///
+    /// The message integrity of the request of addr must have been checked
+    /// with the digest, and the previous address must have been taken from a
+    /// valid ticket. The allocation of the previous session is moved to addr
+    /// if addr has no allocation, both are on the same interface and the
+    /// previous session was authenticated with the same username and digest.
+    /// The nonce of addr is kept, since the client already uses it.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(&self, _: &SessionAddr, username: &str) -> Option<String> {
+    ///         Some(username.to_string())
+    ///     }
+    /// }
+    ///
+    /// let interface = "127.0.0.1:3478".parse().unwrap();
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface,
+    /// };
+    ///
+    /// let moved = SessionAddr {
+    ///     address: "192.168.1.2:9000".parse().unwrap(),
+    ///     interface,
+    /// };
+    ///
+    /// let sessions = Sessions::new(ObserverTest);
+    /// pollster::block_on(sessions.get_digest(&addr, "test", "test"));
+    /// let port = sessions.allocate(&addr).unwrap();
+    ///
+    /// // The credentials of the request must match the allocation.
+    /// let other = SessionAddr {
+    ///     address: "192.168.1.3:9000".parse().unwrap(),
+    ///     interface,
+    /// };
+    ///
+    /// let digest = pollster::block_on(sessions.get_digest(&other, "other", "other")).unwrap();
+    /// assert!(!sessions.relocate(&addr, &other, &digest));
+    ///
+    /// let digest = pollster::block_on(sessions.get_digest(&moved, "test", "test")).unwrap();
+    /// assert!(sessions.relocate(&addr, &moved, &digest));
+    ///
+    /// assert!(sessions.get_session(&addr).get_ref().is_none());
+    /// assert_eq!(sessions.get_session(&moved).get_ref().unwrap().allocate.port, Some(port));
+    /// assert!(!sessions.relocate(&addr, &moved, &digest));
+    /// ```
+    pub fn relocate(&self, previous: &SessionAddr, addr: &SessionAddr, digest: &[u8; 16]) -> bool {
+        if previous == addr || previous.interface != addr.interface {
+            return false;
+        }
+
+        {
+            let mut sessions = self.state.sessions.write();
+            let username = match sessions.get(addr) {
+                Some(it) if it.allocate.port.is_none() => it.auth.username.clone(),
+                _ => return false,
+            };
+
+            match sessions.get(previous) {
+                Some(it)
+                    if it.allocate.port.is_some()
+                        && it.auth.username == username
+                        && &it.auth.digest == digest => {}
+                _ => return false,
+            }
+
+            self.forget(&mut sessions, addr);
+        }
+
+        self.take_over(previous, addr)
+    }
+
+    /// Remove the session of addr that only carries the credentials of a
+    /// request, which is replaced by another session.
+    fn forget(&self, sessions: &mut Table<SessionAddr, Session>, addr: &SessionAddr) {
+        if let Some(session) = sessions.remove(addr) {
+            self.state
+                .relay_paths
+                .fetch_sub(session.relay_paths(), Ordering::Relaxed);
+        }
+    }
+
+    /// Move the session of previous to addr, whose own session has been
+    /// removed, keeping the nonce of addr.
+    fn take_over(&self, previous: &SessionAddr, addr: &SessionAddr) -> bool {
+        self.remove_nonce(&[*previous]);
+        let nonce = self.state.address_nonce_tanle.write().remove(addr);
+        if !self.rebind(previous, addr) {
+            return false;
+        }
+
+        if let Some(it) = nonce {
+            self.state.address_nonce_tanle.write().insert(*addr, it);
+        }
+
+        true
+    }
+
+    /// Move the session of addr to a new client address.
+    ///
+    /// When the client migrates to another network, the allocation, the
+    /// permissions, the channels and the nonce of the session follow the
+    /// client, and the routes of the peers to the session are moved to the
+    /// new address in the same critical section, so the relay never sees a
+    /// half moved session. The route whose endpoint is the old address, as
+    /// the tcp connections are routed by the address of the client, follows
+    /// the new address too. Returns false if there is no session for addr or
+    /// the new address already has a session.
+    ///
+    /// This is the single entry point of the rebinding, the moved event of
+    /// the observer is triggered in the same critical section, so that the
+    /// observer moves the state it keeps by the address, such as the route
+    /// and the statistics of the session, together with the session. The
+    /// observer must not call back into the sessions from the event.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(
+    ///         &self,
+    ///         addr: &SessionAddr,
+    ///         username: &str,
+    ///     ) -> Option<String> {
+    ///         Some("test".to_string())
+    ///     }
+    /// }
+    ///
+    /// let endpoint = "127.0.0.1:3478".parse().unwrap();
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let peer_addr = SessionAddr {
+    ///     address: "127.0.0.1:8081".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let moved = SessionAddr {
+    ///     address: "127.0.0.1:9090".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let sessions = Sessions::new(ObserverTest);
+    /// pollster::block_on(sessions.get_digest(&addr, "test", "test"));
+    /// pollster::block_on(sessions.get_digest(&peer_addr, "test", "test"));
+    ///
+    /// let port = sessions.allocate(&addr).unwrap();
+    /// let peer_port = sessions.allocate(&peer_addr).unwrap();
+    /// assert!(sessions.bind_channel(&addr, &endpoint, peer_port, 0x4000));
+    /// assert!(sessions.create_permission(&peer_addr, &endpoint, &[port]));
+    ///
+    /// let nonce = sessions.get_nonce(&addr).get_ref().unwrap().0.clone();
+    /// assert!(sessions.rebind(&addr, &moved));
+    ///
+    /// assert!(sessions.get_session(&addr).get_ref().is_none());
+    /// assert_eq!(sessions.get_session(&moved).get_ref().unwrap().allocate.port, Some(port));
+    /// assert!(sessions.verify_nonce(&moved, &nonce));
+    ///
+    /// // The data of the peer is relayed to the new address.
+    /// let relay = sessions.get_relay_address(&peer_addr, port).unwrap();
+    /// assert_eq!(relay.address, moved.address);
+    /// let relay = sessions.get_channel_relay_address(&peer_addr, 0x4000).unwrap();
+    /// assert_eq!(relay.address, moved.address);
+    /// assert_eq!(sessions.get_relay_address(&moved, peer_port).unwrap().address, peer_addr.address);
+    ///
+    /// assert!(!sessions.rebind(&addr, &moved));
+    /// assert!(!sessions.rebind(&moved, &peer_addr));
+    /// ```
+    pub fn rebind(&self, addr: &SessionAddr, new: &SessionAddr) -> bool {
+        {
+            let mut sessions = self.state.sessions.write();
+            let mut port_mapping_table = self.state.port_mapping_table.write();
+            let mut port_relay_table = self.state.port_relay_table.write();
+            let mut channel_relay_table = self.state.channel_relay_table.write();
+            let mut relay_activity_table = self.state.relay_activity_table.write();
+
+            if sessions.contains_key(new) {
+                return false;
+            }
+
+            let session = if let Some(it) = sessions.remove(addr) {
+                it
+            } else {
+                return false;
+            };
+
+            let rebind = |it: &mut Endpoint| {
+                if it.address == addr.address {
+                    it.address = new.address;
+                    if it.endpoint == addr.address {
+                        it.endpoint = new.address;
+                    }
+                }
+            };
+
+            // The peers that have a permission for the session reach it through
+            // the port of the allocation and the bound channels.
+            if let Some(port) = session.allocate.port {
+                port_mapping_table.insert(port, *new);
+
+                for peer in session
+                    .permissions
+                    .iter()
+                    .filter_map(|it| port_mapping_table.get(it))
+                {
+                    if let Some(it) = port_relay_table
+                        .get_mut(peer)
+                        .and_then(|it| it.get_mut(&port))
+                    {
+                        rebind(it);
+                    }
+
+                    if let Some(channels) = channel_relay_table.get_mut(peer) {
+                        for channel in &session.allocate.channels {
+                            if let Some(it) = channels.get_mut(channel) {
+                                rebind(it);
+                            }
+                        }
+                    }
+                }
+            }
+
+            if let Some(it) = port_relay_table.remove(addr) {
+                port_relay_table.insert(*new, it);
+            }
+
+            if let Some(it) = channel_relay_table.remove(addr) {
+                channel_relay_table.insert(*new, it);
+            }
+
+            if let Some(it) = relay_activity_table.remove(addr) {
+                relay_activity_table.insert(*new, it);
+            }
+
+            if session.allocate.port.is_some() {
+                let mut allocation_index_table = self.state.allocation_index_table.write();
+                unindex(&mut allocation_index_table, addr, &session.auth.username);
+                allocation_index_table
+                    .entry((new.address.ip(), session.auth.username.clone()))
+                    .or_default()
+                    .push(*new);
+            }
+
+            self.observer.moved(addr, new, &session.auth.username);
+            sessions.insert(*new, session);
+        }
+
+        let mut address_nonce_tanle = self.state.address_nonce_tanle.write();
+        if let Some(it) = address_nonce_tanle.remove(addr) {
+            address_nonce_tanle.insert(*new, it);
+        }
+
+        true
+    }
+
+    /// Export the state of the sessions.
+    ///
+    /// For in-place upgrades, the sessions, allocations, permissions, channels
+    /// and nonces are exported with their remaining lifetime, so that a new
+    /// process can import them with [`Sessions::import_state`]. The
+    /// passwords and the keys are never exported, the importer derives them
+    /// again from the observer.
+    ///
+    /// The turn-server binary does not call this, the embedder doing the
+    /// upgrade exports the state once the old process has stopped serving,
+    /// and imports it in the new process before its listeners are started.
+    /// Handing over the sockets is also left to the embedder.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::*;
+    ///
+    /// #[derive(Clone)]
+    /// struct ObserverTest;
+    ///
+    /// impl Observer for ObserverTest {
+    ///     async fn get_password(&self, _: &SessionAddr, username: &str) -> Option<String> {
+    ///         if username == "test" {
+    ///             Some("test".to_string())
+    ///         } else {
+    ///             None
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let endpoint = "127.0.0.1:3478".parse().unwrap();
+    /// let addr = SessionAddr {
+    ///     address: "127.0.0.1:8080".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
+    /// let peer_addr = SessionAddr {
+    ///     address: "127.0.0.1:8081".parse().unwrap(),
+    ///     interface: "127.0.0.1:3478".parse().unwrap(),
+    /// };
+    ///
     /// let sessions = Sessions::new(ObserverTest);
-    ///
-    /// pollster::block_on(sessions.get_digest(&addr, "test", "test"));
+    /// let digest = pollster::block_on(sessions.get_digest(&addr, "test", "test")).unwrap();
     /// pollster::block_on(sessions.get_digest(&peer_addr, "test", "test"));
     ///
     /// let port = sessions.allocate(&addr).unwrap();
@@ -887,100 +2831,482 @@ impl<T: Observer + 'static> Sessions<T> {
     ///
     /// assert!(sessions.create_permission(&addr, &endpoint, &[peer_port]));
     /// assert!(sessions.create_permission(&peer_addr, &endpoint, &[port]));
+    /// assert!(sessions.bind_channel(&addr, &endpoint, peer_port, 0x4000));
+    /// assert!(sessions.bind_channel(&peer_addr, &endpoint, port, 0x4000));
+    /// assert!(sessions.refresh(&addr, 1200));
     ///
-    /// assert_eq!(
-    ///     sessions
-    ///         .get_relay_address(&addr, peer_port)
-    ///         .unwrap()
-    ///         .endpoint,
-    ///     endpoint
-    /// );
-    ///
-    /// assert_eq!(
-    ///     sessions
-    ///         .get_relay_address(&peer_addr, port)
-    ///         .unwrap()
-    ///         .endpoint,
-    ///     endpoint
-    /// );
-    /// ```
-    pub fn get_relay_address(&self, addr: &SessionAddr, port: u16) -> Option<Endpoint> {
-        self.state
-            .port_relay_table
-            .read()
-            .get(&addr)?
-            .get(&port)
-            .copied()
-    }
-
-    /// Refresh the session for addr.
-    ///
-    /// # Test
+    /// let state = sessions.export_state();
     ///
-    /// ```
-    /// use mycrl_turn::*;
+    /// let imported = Sessions::new(ObserverTest);
+    /// assert_eq!(pollster::block_on(imported.import_state(&state)).unwrap(), 2);
+    /// assert_eq!(imported.allocated(), 2);
     ///
-    /// #[derive(Clone)]
-    /// struct ObserverTest;
+    /// {
+    ///     let lock = sessions.get_session(&addr);
+    ///     let expires = lock.get_ref().unwrap().expires;
     ///
-    /// impl Observer for ObserverTest {
-    ///     async fn get_password(
-    ///         &self,
-    ///         addr: &SessionAddr,
-    ///         username: &str,
-    ///     ) -> Option<String> {
-    ///         if username == "test" {
-    ///             Some("test".to_string())
-    ///         } else {
-    ///             None
-    ///         }
-    ///     }
+    ///     let lock = imported.get_session(&addr);
+    ///     let session = lock.get_ref().unwrap();
+    ///     assert_eq!(session.auth.username, "test");
+    ///     assert_eq!(session.auth.digest, digest);
+    ///     assert_eq!(session.allocate.port, Some(port));
+    ///     assert_eq!(session.allocate.channels, vec![0x4000]);
+    ///     assert!(session.expires.abs_diff(expires) <= 1);
     /// }
     ///
-    /// let addr = SessionAddr {
-    ///     address: "127.0.0.1:8080".parse().unwrap(),
-    ///     interface: "127.0.0.1:3478".parse().unwrap(),
-    /// };
-    ///
-    /// let digest = [
-    ///     174, 238, 187, 253, 117, 209, 73, 157, 36, 56, 143, 91, 155, 16, 224,
-    ///     239,
-    /// ];
+    /// assert_eq!(
+    ///     imported.get_allocation(&addr).map(|it| it.relay),
+    ///     sessions.get_allocation(&addr).map(|it| it.relay)
+    /// );
+    /// assert_eq!(
+    ///     imported.get_relay_address(&addr, peer_port),
+    ///     sessions.get_relay_address(&addr, peer_port)
+    /// );
     ///
-    /// let sessions = Sessions::new(ObserverTest);
+    /// assert_eq!(
+    ///     imported.get_channel_relay_address(&peer_addr, 0x4000),
+    ///     sessions.get_channel_relay_address(&peer_addr, 0x4000)
+    /// );
     ///
-    /// assert!(sessions.get_session(&addr).get_ref().is_none());
+    /// // The allocated ports are reserved in the new process.
+    /// assert_ne!(imported.allocate(&addr), Some(port));
+    /// assert!(pollster::block_on(imported.import_state(&state[..10])).is_err());
     ///
-    /// pollster::block_on(sessions.get_digest(&addr, "test", "test"));
+    /// // A corrupt count is refused before anything is reserved for it.
+    /// let mut corrupt = state[..5].to_vec();
+    /// corrupt.extend_from_slice(&u32::MAX.to_be_bytes());
+    /// let err = pollster::block_on(imported.import_state(&corrupt)).unwrap_err();
+    /// assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
     ///
-    /// let expires = sessions.get_session(&addr).get_ref().unwrap().expires;
-    /// assert!(expires == 600 || expires == 601 || expires == 602);
+    /// // The peer address is already in use, so the peer is skipped, and the
+    /// // relays, permissions and channels between the two are dropped.
+    /// let imported = Sessions::new(ObserverTest);
+    /// pollster::block_on(imported.get_digest(&peer_addr, "test", "test"));
+    /// let nonce = imported.get_nonce(&peer_addr).get_ref().unwrap().0.clone();
+    /// assert_eq!(pollster::block_on(imported.import_state(&state)).unwrap(), 1);
     ///
-    /// assert!(sessions.refresh(&addr, 0));
+    /// {
+    ///     let lock = imported.get_session(&addr);
+    ///     let session = lock.get_ref().unwrap();
+    ///     assert!(session.permissions.is_empty());
+    ///     assert!(session.allocate.channels.is_empty());
+    /// }
     ///
-    /// assert!(sessions.get_session(&addr).get_ref().is_none());
+    /// assert!(imported.get_relay_address(&addr, peer_port).is_none());
+    /// assert!(imported.get_relay_address(&peer_addr, port).is_none());
+    /// assert!(imported.get_channel_relay_address(&addr, 0x4000).is_none());
+    /// assert!(imported.get_channel_relay_address(&peer_addr, 0x4000).is_none());
+    /// assert_eq!(imported.get_nonce(&peer_addr).get_ref().unwrap().0, nonce);
     /// ```
-    pub fn refresh(&self, addr: &SessionAddr, lifetime: u32) -> bool {
-        if lifetime > 3600 {
-            return false;
+    pub fn export_state(&self) -> Vec<u8> {
+        let now = self.timer.get();
+        let mut bytes = BytesMut::with_capacity(4096);
+        bytes.put_slice(STATE_MAGIC);
+        bytes.put_u8(STATE_VERSION);
+
+        {
+            let sessions = self.state.sessions.read();
+            let port_relay_table = self.state.port_relay_table.read();
+            let channel_relay_table = self.state.channel_relay_table.read();
+
+            bytes.put_u32(sessions.len() as u32);
+            for (addr, session) in sessions.iter() {
+                put_session_addr(&mut bytes, addr);
+                put_str(&mut bytes, &session.auth.username);
+                put_str(&mut bytes, &session.auth.realm);
+                bytes.put_u32(session.expires.saturating_sub(now) as u32);
+                bytes.put_u16(session.allocate.port.unwrap_or(0));
+
+                match session.allocate.relay {
+                    Some(relay) => {
+                        bytes.put_u8(1);
+                        put_addr(&mut bytes, &relay);
+                    }
+                    None => bytes.put_u8(0),
+                }
+
+                for items in [&session.permissions, &session.allocate.channels] {
+                    bytes.put_u16(items.len() as u16);
+                    items.iter().for_each(|it| bytes.put_u16(*it));
+                }
+            }
+
+            for table in [&port_relay_table, &channel_relay_table] {
+                bytes.put_u32(table.len() as u32);
+                for (addr, relays) in table.iter() {
+                    put_session_addr(&mut bytes, addr);
+                    bytes.put_u16(relays.len() as u16);
+                    for (key, endpoint) in relays {
+                        bytes.put_u16(*key);
+                        put_addr(&mut bytes, &endpoint.address);
+                        put_addr(&mut bytes, &endpoint.endpoint);
+                    }
+                }
+            }
         }
 
-        if lifetime == 0 {
-            self.remove_session(&[*addr]);
-            self.remove_nonce(&[*addr]);
-        } else {
-            if let Some(session) = self.state.sessions.write().get_mut(addr) {
-                session.expires = self.timer.get() + lifetime as u64;
-            } else {
-                return false;
+        {
+            let address_nonce_tanle = self.state.address_nonce_tanle.read();
+
+            bytes.put_u32(address_nonce_tanle.len() as u32);
+            for (addr, (nonce, expires)) in address_nonce_tanle.iter() {
+                put_session_addr(&mut bytes, addr);
+                put_str(&mut bytes, nonce);
+                bytes.put_u32(expires.saturating_sub(now) as u32);
+            }
+        }
+
+        bytes.to_vec()
+    }
+
+    /// Import the state exported by [`Sessions::export_state`].
+    ///
+    /// The keys of the sessions are derived again with the passwords given by
+    /// the observer, the sessions whose password is no longer available, or
+    /// whose address or port is already in use, are skipped together with
+    /// their nonces. The relays, permissions and channels are only kept when
+    /// the sessions at both of their ends have been imported. Returns the
+    /// number of the imported sessions, a state that is truncated or counts
+    /// more entries than it holds is refused.
+    pub async fn import_state(&self, bytes: &[u8]) -> io::Result<usize> {
+        let mut reader = StateReader(bytes);
+        if reader.bytes(STATE_MAGIC.len())? != STATE_MAGIC || reader.u8()? != STATE_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid state header",
+            ));
+        }
+
+        // The whole state is parsed before anything is imported.
+        let size = reader.count(STATE_SESSION_SIZE)?;
+        let mut sessions = Vec::with_capacity(size);
+        for _ in 0..size {
+            let addr = reader.session_addr()?;
+            let username = reader.str()?;
+            let realm = reader.str()?;
+            let lifetime = reader.u32()?;
+            let port = Some(reader.u16()?).filter(|it| *it != 0);
+            let relay = match reader.u8()? {
+                0 => None,
+                _ => Some(reader.addr()?),
+            };
+
+            let mut lists = [Vec::new(), Vec::new()];
+            for items in lists.iter_mut() {
+                for _ in 0..reader.u16()? {
+                    items.push(reader.u16()?);
+                }
             }
 
-            if let Some(nonce) = self.state.address_nonce_tanle.write().get_mut(addr) {
-                nonce.1 = self.timer.get() + lifetime as u64;
+            let [permissions, channels] = lists;
+            sessions.push((
+                addr,
+                username,
+                realm,
+                lifetime,
+                port,
+                relay,
+                permissions,
+                channels,
+            ));
+        }
+
+        let mut tables = [Vec::new(), Vec::new()];
+        for table in tables.iter_mut() {
+            for _ in 0..reader.count(STATE_RELAYS_SIZE)? {
+                let addr = reader.session_addr()?;
+                let size = reader.short_count(STATE_RELAY_SIZE)?;
+                let mut relays = HashMap::with_capacity(size);
+                for _ in 0..size {
+                    let key = reader.u16()?;
+                    let address = reader.addr()?;
+                    let endpoint = reader.addr()?;
+                    relays.insert(key, Endpoint { address, endpoint });
+                }
+
+                table.push((addr, relays));
             }
         }
 
-        true
+        let size = reader.count(STATE_NONCE_SIZE)?;
+        let mut nonces = Vec::with_capacity(size);
+        for _ in 0..size {
+            nonces.push((reader.session_addr()?, reader.str()?, reader.u32()?));
+        }
+
+        let mut imported = HashSet::with_capacity(sessions.len());
+        for (addr, username, realm, lifetime, port, relay, permissions, channels) in sessions {
+            let password = match self.observer.get_password(&addr, &username).await {
+                Some(it) => it,
+                None => continue,
+            };
+
+            let now = self.timer.get();
+            let digest = derive_key(&username, &realm, &password);
+            let tenant = self.observer.get_tenant(&addr, &username);
+
+            let mut sessions = self.state.sessions.write();
+            if sessions.contains_key(&addr) {
+                continue;
+            }
+
+            if let Some(port) = port {
+                if !self.state.port_allocate_pool.lock().reserve(port) {
+                    continue;
+                }
+
+                self.state.port_mapping_table.write().insert(port, addr);
+                self.state
+                    .relay_activity_table
+                    .write()
+                    .insert(addr, AtomicU64::new(now));
+                self.state
+                    .allocation_index_table
+                    .write()
+                    .entry((addr.address.ip(), username.clone()))
+                    .or_default()
+                    .push(addr);
+            }
+
+            sessions.insert(
+                addr,
+                Session {
+                    expires: now + lifetime as u64,
+                    refreshed: now,
+                    authorizations: HashMap::new(),
+                    permissions,
+                    auth: Auth {
+                        username,
+                        realm,
+                        password,
+                        digest,
+                        tenant,
+                    },
+                    allocate: Allocate {
+                        channels,
+                        relay,
+                        port,
+                    },
+                },
+            );
+
+            imported.insert(addr);
+        }
+
+        // A relay connects the session it is keyed by to the session of its
+        // endpoint, which is told by the port or the channel it holds, and is
+        // only kept if both of the sessions have been imported.
+        {
+            let mut sessions = self.state.sessions.write();
+            let mut ports = HashSet::with_capacity(imported.len());
+            let mut channels = HashSet::with_capacity(imported.len());
+            for addr in &imported {
+                if let Some(session) = sessions.get(addr) {
+                    if let Some(port) = session.allocate.port {
+                        ports.insert((addr.address, port));
+                    }
+
+                    for channel in &session.allocate.channels {
+                        channels.insert((addr.address, *channel));
+                    }
+                }
+            }
+
+            let keep = |relays: Vec<(SessionAddr, HashMap<u16, Endpoint>)>,
+                        held: &HashSet<(SocketAddr, u16)>| {
+                relays
+                    .into_iter()
+                    .filter(|(addr, _)| imported.contains(addr))
+                    .map(|(addr, mut relays)| {
+                        relays.retain(|key, endpoint| held.contains(&(endpoint.address, *key)));
+                        (addr, relays)
+                    })
+                    .filter(|(_, relays)| !relays.is_empty())
+                    .collect::<Vec<_>>()
+            };
+
+            let [port_relays, channel_relays] = tables;
+            let port_relays = keep(port_relays, &ports);
+            let channel_relays = keep(channel_relays, &channels);
+
+            let bound = channel_relays
+                .iter()
+                .flat_map(|(_, relays)| relays.iter().map(|(key, it)| (it.address, *key)))
+                .collect::<HashSet<_>>();
+
+            for (table, relays) in [
+                (&self.state.port_relay_table, port_relays),
+                (&self.state.channel_relay_table, channel_relays),
+            ] {
+                table.write().extend(relays);
+            }
+
+            // The permissions for the ports and the channels to the sessions
+            // that have not been imported are dropped along with the relays.
+            let ports = ports.iter().map(|(_, port)| *port).collect::<HashSet<_>>();
+            for addr in &imported {
+                if let Some(session) = sessions.get_mut(addr) {
+                    session.permissions.retain(|it| ports.contains(it));
+                    session
+                        .allocate
+                        .channels
+                        .retain(|it| bound.contains(&(addr.address, *it)));
+
+                    self.state
+                        .relay_paths
+                        .fetch_add(session.relay_paths(), Ordering::Relaxed);
+                }
+            }
+        }
+
+        // Only the nonces of the imported sessions are kept, the other
+        // addresses are challenged again.
+        {
+            let now = self.timer.get();
+            let mut address_nonce_tanle = self.state.address_nonce_tanle.write();
+            for (addr, nonce, lifetime) in nonces {
+                if imported.contains(&addr) {
+                    address_nonce_tanle.insert(addr, (nonce, now + lifetime as u64));
+                }
+            }
+        }
+
+        Ok(imported.len())
+    }
+}
+
+/// Remove the allocation of addr from the index of its ip and username.
+fn unindex(
+    table: &mut Table<(IpAddr, String), Vec<SessionAddr>>,
+    addr: &SessionAddr,
+    username: &str,
+) {
+    let key = (addr.address.ip(), username.to_string());
+    if let Some(addrs) = table.get_mut(&key) {
+        addrs.retain(|it| it != addr);
+        if addrs.is_empty() {
+            table.remove(&key);
+        }
+    }
+}
+
+static STATE_MAGIC: &[u8] = b"TURN";
+const STATE_VERSION: u8 = 1;
+
+// The smallest encoded size of each entry of the state, an ipv4 session
+// address takes 14 bytes.
+const STATE_SESSION_SIZE: usize = 14 + 2 + 2 + 4 + 2 + 1 + 2 + 2;
+const STATE_RELAYS_SIZE: usize = 14 + 2;
+const STATE_RELAY_SIZE: usize = 2 + 7 + 7;
+const STATE_NONCE_SIZE: usize = 14 + 2 + 4;
+
+fn put_addr(bytes: &mut BytesMut, addr: &SocketAddr) {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            bytes.put_u8(4);
+            bytes.put_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            bytes.put_u8(6);
+            bytes.put_slice(&ip.octets());
+        }
+    }
+
+    bytes.put_u16(addr.port());
+}
+
+fn put_session_addr(bytes: &mut BytesMut, addr: &SessionAddr) {
+    put_addr(bytes, &addr.address);
+    put_addr(bytes, &addr.interface);
+}
+
+fn put_str(bytes: &mut BytesMut, value: &str) {
+    bytes.put_u16(value.len() as u16);
+    bytes.put_slice(value.as_bytes());
+}
+
+/// The reader of the exported state, every read is checked against the
+/// remaining bytes.
+struct StateReader<'a>(&'a [u8]);
+
+impl<'a> StateReader<'a> {
+    fn bytes(&mut self, size: usize) -> io::Result<&'a [u8]> {
+        if self.0.len() < size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "truncated state",
+            ));
+        }
+
+        let (bytes, remaining) = self.0.split_at(size);
+        self.0 = remaining;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Read the count of the entries of at least the size, a count that the
+    /// remaining bytes cannot hold is invalid, so that a corrupt state does
+    /// not reserve more than it holds.
+    fn count(&mut self, size: usize) -> io::Result<usize> {
+        let count = self.u32()? as usize;
+        self.check(count, size)
+    }
+
+    fn short_count(&mut self, size: usize) -> io::Result<usize> {
+        let count = self.u16()? as usize;
+        self.check(count, size)
+    }
+
+    fn check(&self, count: usize, size: usize) -> io::Result<usize> {
+        if count.saturating_mul(size) > self.0.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid state count",
+            ));
+        }
+
+        Ok(count)
+    }
+
+    fn str(&mut self) -> io::Result<String> {
+        let size = self.u16()? as usize;
+        String::from_utf8(self.bytes(size)?.to_vec())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid string"))
+    }
+
+    fn addr(&mut self) -> io::Result<SocketAddr> {
+        let ip = match self.u8()? {
+            4 => IpAddr::from(<[u8; 4]>::try_from(self.bytes(4)?).unwrap()),
+            6 => IpAddr::from(<[u8; 16]>::try_from(self.bytes(16)?).unwrap()),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "invalid address",
+                ))
+            }
+        };
+
+        Ok(SocketAddr::new(ip, self.u16()?))
+    }
+
+    fn session_addr(&mut self) -> io::Result<SessionAddr> {
+        Ok(SessionAddr {
+            address: self.addr()?,
+            interface: self.addr()?,
+        })
     }
 }
 
@@ -1314,4 +3640,81 @@ impl PortAllocatePools {
         self.set_bit(bucket, index, Bit::Low);
         self.allocated -= 1;
     }
+
+    /// allocate an even port, if reserve_next is true the next port is
+    /// allocated too, so that it can be held in reserve.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::sessions::PortAllocatePools;
+    ///
+    /// let mut pool = PortAllocatePools::default();
+    ///
+    /// let port = pool.alloc_even(false).unwrap();
+    /// assert_eq!(port % 2, 0);
+    /// assert_eq!(pool.len(), 1);
+    ///
+    /// let port = pool.alloc_even(true).unwrap();
+    /// assert_eq!(port % 2, 0);
+    /// assert_eq!(pool.len(), 3);
+    /// assert!(!pool.reserve(port + 1));
+    /// ```
+    pub fn alloc_even(&mut self, reserve_next: bool) -> Option<u16> {
+        let range = Self::port_range();
+        let count = (range.end - range.start) / 2;
+        let start = thread_rng().gen_range(0..count);
+
+        for i in 0..count {
+            let port = range.start + ((start + i) % count) * 2;
+            if !self.reserve(port) {
+                continue;
+            }
+
+            if reserve_next && !self.reserve(port + 1) {
+                self.restore(port);
+                continue;
+            }
+
+            return Some(port);
+        }
+
+        None
+    }
+
+    /// reserve the port in the buckets, returns false if the port is out of
+    /// range or is already allocated.
+    ///
+    /// # Test
+    ///
+    /// ```
+    /// use mycrl_turn::sessions::PortAllocatePools;
+    ///
+    /// let mut pool = PortAllocatePools::default();
+    ///
+    /// assert!(pool.reserve(49153));
+    /// assert!(!pool.reserve(49153));
+    /// assert!(!pool.reserve(1024));
+    /// assert_eq!(pool.len(), 1);
+    ///
+    /// assert_eq!(pool.alloc(Some(0)), Some(49152));
+    /// assert_eq!(pool.alloc(Some(0)), Some(49154));
+    /// ```
+    pub fn reserve(&mut self, port: u16) -> bool {
+        if !Self::port_range().contains(&port) {
+            return false;
+        }
+
+        let offset = (port - Self::port_range().start) as usize;
+        let bucket = offset / 64;
+        let index = offset - (bucket * 64);
+
+        if self.buckets[bucket] & (1 << (63 - index)) != 0 {
+            return false;
+        }
+
+        self.set_bit(bucket, index, Bit::High);
+        self.allocated += 1;
+        true
+    }
 }